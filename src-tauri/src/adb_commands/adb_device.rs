@@ -0,0 +1,289 @@
+use adb_client::{ADBDeviceExt, ADBListItemType, AdbStatResponse};
+use std::io::{Read, Write};
+
+use super::command::ShellCommand;
+use super::device::{Device, DeviceTransport};
+use super::server_protocol;
+use super::shell::{ExitMarkerWriter, ShellOutput, with_exit_marker};
+use super::sync;
+use crate::error::DroidKitError;
+
+/// File type bits of a sync `STAT` mode.
+pub(crate) const S_IFMT: u32 = 0o170000;
+pub(crate) const S_IFDIR: u32 = 0o040000;
+pub(crate) const S_IFREG: u32 = 0o100000;
+pub(crate) const S_IFLNK: u32 = 0o120000;
+pub(crate) const S_IFCHR: u32 = 0o020000;
+pub(crate) const S_IFBLK: u32 = 0o060000;
+pub(crate) const S_IFSOCK: u32 = 0o140000;
+pub(crate) const S_IFIFO: u32 = 0o010000;
+
+/// One entry of a sync `LIST`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct DirEntry {
+    pub name: String,
+    /// Permission bits and the file type bits of a directory, regular file
+    /// or symlink.
+    pub mode: u32,
+    pub size: u32,
+    pub mod_time: u32,
+}
+
+/// The operations the rest of the app needs from a device, independent of
+/// how it is reached. Parsers take `&mut dyn AdbDevice` so they can be tested
+/// against recorded output.
+pub(crate) trait AdbDevice {
+    fn transport(&self) -> DeviceTransport;
+
+    /// The name the transport addresses the device by, when that may differ
+    /// from `ro.serialno` (emulators behind an adb server).
+    fn transport_serial(&self) -> Option<String>;
+
+    /// adb features this app can use on the device through this transport.
+    fn features(&mut self) -> Vec<String>;
+
+    /// Runs `command` through the device shell. Returns the exit code where
+    /// the transport reports one; stderr is merged into `output`.
+    fn shell(&mut self, command: &str, output: &mut dyn Write)
+    -> Result<Option<u8>, DroidKitError>;
+
+    /// Runs `command` through the device shell, writing stdout and stderr
+    /// as they arrive. Transports without shell v2 merge stderr into stdout.
+    fn shell_streams(
+        &mut self,
+        command: &str,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<Option<u8>, DroidKitError>;
+
+    /// Runs `command` through the device shell, keeping stdout, stderr and
+    /// the exit code apart where the transport allows.
+    fn shell_output(&mut self, command: &str) -> Result<ShellOutput, DroidKitError> {
+        let mut output = ShellOutput::default();
+        output.exit_code = self.shell_streams(command, &mut output.stdout, &mut output.stderr)?;
+        Ok(output)
+    }
+
+    /// Runs `command` without a shell and copies its stdout byte for byte,
+    /// like `adb exec-out`. Use this for binary output.
+    fn exec_out(&mut self, command: &str, output: &mut dyn Write) -> Result<(), DroidKitError>;
+
+    /// Sync protocol `STAT` of `remote_path`. A missing path comes back with
+    /// an all-zero mode rather than an error.
+    fn stat(&mut self, remote_path: &str) -> Result<AdbStatResponse, DroidKitError>;
+
+    /// Sync protocol `LIST` of the directory `remote_path`, without `.` and
+    /// `..`. Symlinks are listed as links, not followed.
+    fn list(&mut self, remote_path: &str) -> Result<Vec<DirEntry>, DroidKitError>;
+
+    /// Sync protocol `RECV` of `remote_path`, written to `output` as it arrives.
+    fn pull(&mut self, remote_path: &str, output: &mut dyn Write) -> Result<(), DroidKitError>;
+
+    /// Sync protocol `SEND` of `input` to `remote_path`, created with `mode`
+    /// (including the file type bits) and `mod_time`.
+    fn push(
+        &mut self,
+        input: &mut dyn Read,
+        remote_path: &str,
+        mode: u32,
+        mod_time: u32,
+    ) -> Result<(), DroidKitError>;
+}
+
+impl Device {
+    pub(crate) fn as_ext(&mut self) -> &mut dyn ADBDeviceExt {
+        match self {
+            Device::USB(device) => device,
+            Device::TCP(device) => device,
+            Device::Server(device) => device,
+        }
+    }
+}
+
+impl AdbDevice for Device {
+    fn transport(&self) -> DeviceTransport {
+        match self {
+            Device::USB(_) => DeviceTransport::USB,
+            Device::TCP(_) => DeviceTransport::TCP,
+            Device::Server(_) => DeviceTransport::Server,
+        }
+    }
+
+    fn transport_serial(&self) -> Option<String> {
+        match self {
+            Device::Server(device) => device.identifier.clone(),
+            Device::USB(_) | Device::TCP(_) => None,
+        }
+    }
+
+    fn features(&mut self) -> Vec<String> {
+        match self {
+            Device::Server(device) => {
+                server_protocol::device_features(device.identifier.as_deref()).unwrap_or_default()
+            }
+            // adb_client does not expose what adbd announced on connecting,
+            // and only speaks the legacy protocols to it anyway
+            Device::USB(_) | Device::TCP(_) => Vec::new(),
+        }
+    }
+
+    fn shell(
+        &mut self,
+        command: &str,
+        output: &mut dyn Write,
+    ) -> Result<Option<u8>, DroidKitError> {
+        Ok(self.shell_command(&command, output)?)
+    }
+
+    fn shell_streams(
+        &mut self,
+        command: &str,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<Option<u8>, DroidKitError> {
+        match self {
+            // adb_client asks the server for shell v2 whenever it is available
+            Device::Server(device) => {
+                Ok(device.shell_command(&command, Some(stdout), Some(stderr))?)
+            }
+            // adb_client only speaks the legacy shell protocol to adbd, which
+            // merges both streams and drops the exit status
+            Device::USB(_) | Device::TCP(_) => {
+                let mut stdout = ExitMarkerWriter::new(stdout);
+                self.shell_command(&with_exit_marker(command), &mut stdout)?;
+                Ok(stdout.finish()?)
+            }
+        }
+    }
+
+    fn exec_out(&mut self, command: &str, output: &mut dyn Write) -> Result<(), DroidKitError> {
+        match self {
+            Device::Server(device) => {
+                let mut stream = server_protocol::open_device_service(
+                    device.identifier.as_deref(),
+                    &format!("exec:{}", command),
+                )?;
+                std::io::copy(&mut stream, output)?;
+                Ok(())
+            }
+            // adb_client's exec returns before the output has been read, but
+            // `shell:<command>` runs without a PTY on these transports and
+            // only carries stdout, which amounts to the same thing.
+            Device::USB(_) | Device::TCP(_) => {
+                self.shell_command(&command, output)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn stat(&mut self, remote_path: &str) -> Result<AdbStatResponse, DroidKitError> {
+        Ok(self.as_ext().stat(&remote_path)?)
+    }
+
+    fn list(&mut self, remote_path: &str) -> Result<Vec<DirEntry>, DroidKitError> {
+        let items = self.as_ext().list(&remote_path)?;
+        Ok(items
+            .into_iter()
+            .map(|item| match item {
+                ADBListItemType::Directory(item) => (S_IFDIR, item),
+                ADBListItemType::File(item) => (S_IFREG, item),
+                ADBListItemType::Symlink(item) => (S_IFLNK, item),
+            })
+            .filter(|(_, item)| item.name != "." && item.name != "..")
+            .map(|(file_type, item)| DirEntry {
+                name: item.name,
+                mode: file_type | item.permissions,
+                size: item.size,
+                mod_time: item.time,
+            })
+            .collect())
+    }
+
+    fn pull(&mut self, remote_path: &str, output: &mut dyn Write) -> Result<(), DroidKitError> {
+        Ok(self.as_ext().pull(&remote_path, output)?)
+    }
+
+    fn push(
+        &mut self,
+        input: &mut dyn Read,
+        remote_path: &str,
+        mode: u32,
+        mod_time: u32,
+    ) -> Result<(), DroidKitError> {
+        match self {
+            Device::Server(device) => {
+                let mut stream =
+                    server_protocol::open_device_service(device.identifier.as_deref(), "sync:")?;
+                sync::send_file(&mut stream, input, remote_path, mode, mod_time)
+            }
+            // adb_client pushes with mode 0777 and the current time, so fix
+            // both up afterwards. Toolbox `touch` before Android 6 has no
+            // `-d`, only `-t` in local time, hence the UTC zone.
+            Device::USB(_) | Device::TCP(_) => {
+                self.as_ext().push(input, &remote_path)?;
+                let command = format!(
+                    "{} && {{ {} 2>/dev/null || TZ=UTC0 {}; }}",
+                    ShellCommand::new("chmod")
+                        .arg(format!("{:o}", mode & 0o7777))
+                        .arg(remote_path),
+                    ShellCommand::new("touch")
+                        .args(["-m", "-d"])
+                        .arg(format!("@{}", mod_time))
+                        .arg(remote_path),
+                    ShellCommand::new("touch")
+                        .args(["-m", "-t"])
+                        .arg(toolbox_touch_time(mod_time))
+                        .arg(remote_path)
+                );
+                self.shell_output(&command)
+                    .and_then(|output| output.into_result(&command))
+                    .map_err(|e| {
+                        e.context(&format!(
+                            "Pushed {} but failed to set its mode and time",
+                            remote_path
+                        ))
+                    })?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// `secs` since the epoch as toolbox `touch -t` takes it, `YYYYMMDD.hhmmss`
+/// in UTC.
+fn toolbox_touch_time(secs: u32) -> String {
+    let secs = u64::from(secs);
+    let (days, time) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01, after Howard Hinnant
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}.{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toolbox_touch_time_is_utc() {
+        assert_eq!(toolbox_touch_time(0), "19700101.000000");
+        assert_eq!(toolbox_touch_time(951_782_400), "20000229.000000");
+        assert_eq!(toolbox_touch_time(1_717_171_717), "20240531.160837");
+    }
+}
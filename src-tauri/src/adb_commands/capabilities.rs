@@ -0,0 +1,187 @@
+//! What a device's shell and adbd can do, probed once per device so commands
+//! can be picked to suit it instead of assuming the newest Android.
+
+use serde::Serialize;
+
+use super::adb_device::AdbDevice;
+use crate::error::DroidKitError;
+
+/// Every check is one line of a single script, so probing costs one round
+/// trip. Each prints `key=value`; checks that fail print nothing.
+const PROBE_SCRIPT: &str = "\
+echo sdk=$(getprop ro.build.version.sdk)
+echo abi=$(getprop ro.product.cpu.abi)
+echo toybox=$(toybox --version 2>/dev/null)
+echo ls=$(readlink \"$(command -v ls)\" 2>/dev/null)
+echo id=$(id)
+echo debuggable=$(getprop ro.debuggable)
+command -v su >/dev/null && echo su=$(su -v 2>/dev/null)
+cmd -l 2>/dev/null | while read service; do [ \"$service\" = package ] && echo cmd_package=1; done
+ls --full-time -d / >/dev/null 2>&1 && echo ls_full_time=1
+stat -c %s / >/dev/null 2>&1 && echo stat_format=1
+true";
+
+/// Which `ls` the device has; they print different columns.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum LsFlavor {
+    /// Android 6 and later, coreutils-like output
+    #[default]
+    Toybox,
+    /// Android 5 and older: no link count, no size for directories
+    Toolbox,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub(crate) struct DeviceCapabilities {
+    pub sdk: Option<u32>,
+    pub abi: Option<String>,
+    /// `None` when toybox is missing or too old to report its version.
+    pub toybox_version: Option<String>,
+    pub ls: LsFlavor,
+    /// adbd itself runs as root, after `adb root` on a userdebug build.
+    pub adbd_root: bool,
+    /// `ro.debuggable` is set, so adbd may be restarted as root.
+    pub adb_root_available: bool,
+    /// An `su` binary is on the `PATH`.
+    pub su: bool,
+    /// That `su` takes `-c <command>`. Magisk, KernelSU and SuperSU answer
+    /// `su -v` with `<version>:<name>`; AOSP's `su` has no such option.
+    pub su_command: bool,
+    /// Stdout, stderr and the exit code arrive separately.
+    pub shell_v2: bool,
+    /// `cmd package`, which answers much faster than `pm`.
+    pub cmd_package: bool,
+    pub ls_full_time: bool,
+    /// `stat -c` with a format string.
+    pub stat_format: bool,
+}
+
+fn parse_probe(output: &str) -> DeviceCapabilities {
+    let mut capabilities = DeviceCapabilities::default();
+    let mut ls_target = String::new();
+
+    for line in output.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match key {
+            "sdk" => capabilities.sdk = value.parse().ok(),
+            "abi" if !value.is_empty() => capabilities.abi = Some(value.to_string()),
+            "toybox" => {
+                capabilities.toybox_version = value
+                    .strip_prefix("toybox ")
+                    .map(|version| version.trim().to_string())
+            }
+            "ls" => ls_target = value.to_string(),
+            "id" => capabilities.adbd_root = value.starts_with("uid=0("),
+            "debuggable" => capabilities.adb_root_available = value == "1",
+            "su" => {
+                capabilities.su = true;
+                capabilities.su_command = value.contains(':');
+            }
+            "cmd_package" => capabilities.cmd_package = true,
+            "ls_full_time" => capabilities.ls_full_time = true,
+            "stat_format" => capabilities.stat_format = true,
+            _ => {}
+        }
+    }
+
+    // Without `readlink` fall back to the release that switched to toybox
+    let toolbox_ls = if ls_target.is_empty() {
+        capabilities.sdk.is_some_and(|sdk| sdk < 23)
+    } else {
+        ls_target.ends_with("toolbox")
+    };
+    if toolbox_ls {
+        capabilities.ls = LsFlavor::Toolbox;
+    }
+
+    capabilities
+}
+
+/// Runs the probe script and asks the transport for its features.
+pub(crate) fn probe_capabilities(
+    device: &mut dyn AdbDevice,
+) -> Result<DeviceCapabilities, DroidKitError> {
+    let mut buf: Vec<u8> = Vec::new();
+    device
+        .shell(PROBE_SCRIPT, &mut buf)
+        .map_err(|e| e.context("Failed to probe device capabilities"))?;
+
+    let mut capabilities = parse_probe(&String::from_utf8_lossy(&buf));
+    capabilities.shell_v2 = device
+        .features()
+        .iter()
+        .any(|feature| feature == "shell_v2");
+    Ok(capabilities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::FakeDevice;
+
+    #[test]
+    fn android_5_has_toolbox() {
+        let mut device = FakeDevice::new().with_shell(
+            PROBE_SCRIPT,
+            "sdk=22\nabi=armeabi-v7a\ntoybox=\nls=toolbox\nid=uid=2000(shell) gid=2000(shell) groups=1004(input),1007(log),1011(adb),1015(sdcard_rw),1028(sdcard_r),3001(net_bt_admin),3002(net_bt),3003(inet),3006(net_bw_stats) context=u:r:shell:s0\n",
+        );
+        let capabilities = probe_capabilities(&mut device).unwrap();
+
+        assert_eq!(capabilities.sdk, Some(22));
+        assert_eq!(capabilities.abi.as_deref(), Some("armeabi-v7a"));
+        assert_eq!(capabilities.toybox_version, None);
+        assert_eq!(capabilities.ls, LsFlavor::Toolbox);
+        assert!(!capabilities.adbd_root);
+        assert!(!capabilities.cmd_package);
+        assert!(!capabilities.stat_format);
+        assert!(!capabilities.shell_v2);
+    }
+
+    #[test]
+    fn android_14_userdebug_after_adb_root() {
+        let mut device = FakeDevice::new()
+            .with_shell(
+                PROBE_SCRIPT,
+                "sdk=34\nabi=arm64-v8a\ntoybox=toybox 0.8.9-android\nls=toybox\nid=uid=0(root) gid=0(root) groups=0(root) context=u:r:su:s0\ndebuggable=1\nsu=\ncmd_package=1\nls_full_time=1\nstat_format=1\n",
+            )
+            .with_features(&["shell_v2", "cmd", "stat_v2", "ls_v2"]);
+        let capabilities = probe_capabilities(&mut device).unwrap();
+
+        assert_eq!(capabilities.sdk, Some(34));
+        assert_eq!(
+            capabilities.toybox_version.as_deref(),
+            Some("0.8.9-android")
+        );
+        assert_eq!(capabilities.ls, LsFlavor::Toybox);
+        assert!(capabilities.adbd_root);
+        assert!(capabilities.adb_root_available);
+        assert!(capabilities.su);
+        assert!(!capabilities.su_command);
+        assert!(capabilities.cmd_package);
+        assert!(capabilities.ls_full_time);
+        assert!(capabilities.stat_format);
+        assert!(capabilities.shell_v2);
+    }
+
+    #[test]
+    fn magisk_su_takes_a_command() {
+        let capabilities = parse_probe("sdk=33\ndebuggable=0\nsu=26.4:MAGISK:R\n");
+        assert!(capabilities.su);
+        assert!(capabilities.su_command);
+        assert!(!capabilities.adb_root_available);
+    }
+
+    #[test]
+    fn ls_flavor_falls_back_to_the_sdk() {
+        let capabilities = parse_probe("sdk=21\nls=\n");
+        assert_eq!(capabilities.ls, LsFlavor::Toolbox);
+
+        // Toybox on Android 6 predates `--version`
+        let capabilities = parse_probe("sdk=23\ntoybox=\nls=\n");
+        assert_eq!(capabilities.ls, LsFlavor::Toybox);
+        assert_eq!(capabilities.toybox_version, None);
+    }
+}
@@ -0,0 +1,350 @@
+//! Digests of files on this computer and on the device, for checking that a
+//! copy arrived intact and for telling apart files whose size and time agree.
+
+use md5::Md5;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use super::adb_device::AdbDevice;
+use super::command::ShellCommand;
+use super::shell::ShellOutput;
+use crate::error::DroidKitError;
+
+/// Paths per invocation of a digest tool, to stay well inside the shell's
+/// command line limit.
+const PATHS_PER_COMMAND: usize = 64;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ChecksumAlgorithm {
+    Sha256,
+    Md5,
+}
+
+impl ChecksumAlgorithm {
+    fn hex_len(self) -> usize {
+        match self {
+            ChecksumAlgorithm::Sha256 => 64,
+            ChecksumAlgorithm::Md5 => 32,
+        }
+    }
+}
+
+/// Device tools tried in turn. Toybox had `md5sum` before `sha256sum`, and
+/// toolbox before Android 6 only `md5`, which prints the same way.
+const DEVICE_TOOLS: [(&str, ChecksumAlgorithm); 3] = [
+    ("sha256sum", ChecksumAlgorithm::Sha256),
+    ("md5sum", ChecksumAlgorithm::Md5),
+    ("md5", ChecksumAlgorithm::Md5),
+];
+
+/// A local file compared with one on the device.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct ChecksumComparison {
+    pub local_path: String,
+    pub remote_path: String,
+    pub algorithm: ChecksumAlgorithm,
+    /// Lowercase hex.
+    pub local_digest: String,
+    pub remote_digest: String,
+    pub matches: bool,
+    /// The device had none of the tools, so its copy was read back over the
+    /// sync protocol and hashed here.
+    pub read_back: bool,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hash_file<D: Digest + Write>(path: &Path) -> Result<String, DroidKitError> {
+    let mut file = File::open(path).map_err(|e| {
+        DroidKitError::from(e).context(&format!("Failed to open {}", path.display()))
+    })?;
+    let mut hasher = D::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// The lowercase hex digest of a local file.
+pub(crate) fn local_digest(
+    path: &Path,
+    algorithm: ChecksumAlgorithm,
+) -> Result<String, DroidKitError> {
+    match algorithm {
+        ChecksumAlgorithm::Sha256 => hash_file::<Sha256>(path),
+        ChecksumAlgorithm::Md5 => hash_file::<Md5>(path),
+    }
+}
+
+/// Whether `output`, with `text` its stdout, is the shell saying there is no
+/// such command.
+fn tool_missing(output: &ShellOutput, text: &str) -> bool {
+    // Without shell v2 there is no exit code, only the shell's message
+    output.exit_code == Some(127) || (output.exit_code.is_none() && text.contains("not found"))
+}
+
+/// The digest of `remote_path` from the first tool the device has, or, with
+/// none, SHA-256 of the file read back over sync. Also says which.
+fn remote_digest(
+    device: &mut dyn AdbDevice,
+    remote_path: &str,
+) -> Result<(ChecksumAlgorithm, String, bool), DroidKitError> {
+    for (tool, algorithm) in DEVICE_TOOLS {
+        let command = ShellCommand::new(tool).arg(remote_path).to_string();
+        let output = device.shell_output(&command)?;
+        let text = String::from_utf8_lossy(&output.stdout).into_owned();
+        let digest = text
+            .split_whitespace()
+            .next()
+            .filter(|digest| {
+                digest.len() == algorithm.hex_len()
+                    && digest.bytes().all(|byte| byte.is_ascii_hexdigit())
+            })
+            .map(str::to_ascii_lowercase);
+        if let Some(digest) = digest {
+            return Ok((algorithm, digest, false));
+        }
+        if !tool_missing(&output, &text) {
+            output.into_result(&command)?;
+            return Err(DroidKitError::Parse(format!(
+                "`{}` printed no digest: {}",
+                command,
+                text.trim()
+            )));
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    device
+        .pull(remote_path, &mut hasher)
+        .map_err(|e| e.context("Failed to read back file"))?;
+    Ok((ChecksumAlgorithm::Sha256, to_hex(&hasher.finalize()), true))
+}
+
+/// Hashes `local_path` and `remote_path` with the same algorithm, the best
+/// the device offers.
+pub(crate) fn compare_file(
+    device: &mut dyn AdbDevice,
+    local_path: &str,
+    remote_path: &str,
+) -> Result<ChecksumComparison, DroidKitError> {
+    // So a missing local file fails before any device work
+    std::fs::metadata(local_path)
+        .map_err(|e| DroidKitError::from(e).context(&format!("Failed to read {}", local_path)))?;
+    let (algorithm, remote_digest, read_back) = remote_digest(device, remote_path)?;
+    let local_digest = local_digest(Path::new(local_path), algorithm)?;
+    Ok(ChecksumComparison {
+        local_path: local_path.to_string(),
+        remote_path: remote_path.to_string(),
+        algorithm,
+        matches: local_digest == remote_digest,
+        local_digest,
+        remote_digest,
+        read_back,
+    })
+}
+
+/// Fails with [`DroidKitError::ChecksumMismatch`] unless the two copies are
+/// the same, for checking a transfer just made.
+pub(crate) fn verify_copy(
+    device: &mut dyn AdbDevice,
+    local_path: &str,
+    remote_path: &str,
+) -> Result<(), DroidKitError> {
+    let comparison = compare_file(device, local_path, remote_path)
+        .map_err(|e| e.context("Failed to verify transfer"))?;
+    if comparison.matches {
+        return Ok(());
+    }
+    Err(DroidKitError::ChecksumMismatch {
+        path: remote_path.to_string(),
+        local: comparison.local_digest,
+        remote: comparison.remote_digest,
+    })
+}
+
+/// Runs `tool` on `paths` and adds each digest it prints to `digests`.
+/// False when the device has no `tool`.
+fn hash_chunk(
+    device: &mut dyn AdbDevice,
+    (tool, algorithm): (&str, ChecksumAlgorithm),
+    paths: &[String],
+    digests: &mut HashMap<String, String>,
+) -> Result<bool, DroidKitError> {
+    let command = ShellCommand::new(tool).args(paths).to_string();
+    let output = device.shell_output(&command)?;
+    let text = String::from_utf8_lossy(&output.stdout).into_owned();
+    // `hash  path` per file; unreadable ones only show on stderr
+    let mut found = 0;
+    for line in text.lines() {
+        if let Some((digest, path)) = line.split_once("  ")
+            && digest.len() == algorithm.hex_len()
+            && digest.bytes().all(|byte| byte.is_ascii_hexdigit())
+        {
+            digests.insert(path.to_string(), digest.to_ascii_lowercase());
+            found += 1;
+        }
+    }
+    if found == 0 {
+        if tool_missing(&output, &text) {
+            return Ok(false);
+        }
+        output.into_result(&command)?;
+    }
+    Ok(true)
+}
+
+/// The lowercase hex digest of each of `paths` on the device that could be
+/// read, by path, and the algorithm they share. The first tool the device
+/// has hashes them all, [`PATHS_PER_COMMAND`] at a time; with none, each
+/// file is read back over sync and hashed with SHA-256.
+pub(crate) fn remote_digests(
+    device: &mut dyn AdbDevice,
+    paths: &[String],
+) -> Result<(ChecksumAlgorithm, HashMap<String, String>), DroidKitError> {
+    let mut chunks = paths.chunks(PATHS_PER_COMMAND);
+    let Some(first) = chunks.next() else {
+        return Ok((ChecksumAlgorithm::Sha256, HashMap::new()));
+    };
+    for tool in DEVICE_TOOLS {
+        let mut digests = HashMap::new();
+        if !hash_chunk(device, tool, first, &mut digests)? {
+            continue;
+        }
+        for chunk in chunks.by_ref() {
+            hash_chunk(device, tool, chunk, &mut digests)?;
+        }
+        return Ok((tool.1, digests));
+    }
+
+    let mut digests = HashMap::new();
+    for path in paths {
+        let mut hasher = Sha256::new();
+        if device.pull(path, &mut hasher).is_ok() {
+            digests.insert(path.clone(), to_hex(&hasher.finalize()));
+        }
+    }
+    Ok((ChecksumAlgorithm::Sha256, digests))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::{FakeDevice, unique_temp_dir};
+    use std::path::PathBuf;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const ABC_MD5: &str = "900150983cd24fb0d6963f7d28e17f72";
+
+    fn local_file(name: &str, data: &[u8]) -> PathBuf {
+        let path = unique_temp_dir(name).join("local");
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn compares_with_sha256sum() {
+        let local = local_file("checksum-sha256", b"abc");
+        let mut device = FakeDevice::new().with_shell(
+            "sha256sum /sdcard/abc.txt",
+            format!("{}  /sdcard/abc.txt\n", ABC_SHA256.to_uppercase()),
+        );
+
+        let comparison =
+            compare_file(&mut device, &local.to_string_lossy(), "/sdcard/abc.txt").unwrap();
+        assert_eq!(comparison.algorithm, ChecksumAlgorithm::Sha256);
+        assert_eq!(comparison.local_digest, ABC_SHA256);
+        assert_eq!(comparison.remote_digest, ABC_SHA256);
+        assert!(comparison.matches);
+        assert!(!comparison.read_back);
+        let _ = std::fs::remove_file(&local);
+    }
+
+    #[test]
+    fn falls_back_to_md5_on_older_devices() {
+        let local = local_file("checksum-md5", b"abc");
+        // No sha256sum, and toolbox `md5` as on Android 5
+        let mut device = FakeDevice::new().with_shell(
+            "md5 /sdcard/abc.txt",
+            format!("{}  /sdcard/abc.txt\n", ABC_MD5),
+        );
+
+        let comparison =
+            compare_file(&mut device, &local.to_string_lossy(), "/sdcard/abc.txt").unwrap();
+        assert_eq!(comparison.algorithm, ChecksumAlgorithm::Md5);
+        assert_eq!(comparison.local_digest, ABC_MD5);
+        assert!(comparison.matches);
+        assert_eq!(
+            device.calls,
+            [
+                "sha256sum /sdcard/abc.txt",
+                "md5sum /sdcard/abc.txt",
+                "md5 /sdcard/abc.txt"
+            ]
+        );
+        let _ = std::fs::remove_file(&local);
+    }
+
+    #[test]
+    fn reads_back_when_the_device_cannot_hash() {
+        let local = local_file("checksum-read-back", b"abc");
+        let mut device =
+            FakeDevice::new().with_file_data("/sdcard/abc.txt", 0o660, b"abd".to_vec(), 0);
+
+        let comparison =
+            compare_file(&mut device, &local.to_string_lossy(), "/sdcard/abc.txt").unwrap();
+        assert!(comparison.read_back);
+        assert!(!comparison.matches);
+
+        let err =
+            verify_copy(&mut device, &local.to_string_lossy(), "/sdcard/abc.txt").unwrap_err();
+        assert_eq!(err.kind(), "ChecksumMismatch");
+        let _ = std::fs::remove_file(&local);
+    }
+
+    #[test]
+    fn hashes_many_files_with_the_first_tool_found() {
+        let paths = ["/sdcard/a.txt".to_string(), "/sdcard/b.txt".to_string()];
+        let mut device = FakeDevice::new().with_shell(
+            "md5sum /sdcard/a.txt /sdcard/b.txt",
+            format!("{}  /sdcard/a.txt\n", ABC_MD5),
+        );
+        let (algorithm, digests) = remote_digests(&mut device, &paths).unwrap();
+        assert_eq!(algorithm, ChecksumAlgorithm::Md5);
+        assert_eq!(digests.get("/sdcard/a.txt").unwrap(), ABC_MD5);
+        assert!(!digests.contains_key("/sdcard/b.txt"));
+        assert_eq!(
+            device.calls,
+            [
+                "sha256sum /sdcard/a.txt /sdcard/b.txt",
+                "md5sum /sdcard/a.txt /sdcard/b.txt",
+            ]
+        );
+
+        // No tool at all: what can be read is read back
+        let mut device =
+            FakeDevice::new().with_file_data("/sdcard/a.txt", 0o660, b"abc".to_vec(), 0);
+        let (algorithm, digests) = remote_digests(&mut device, &paths).unwrap();
+        assert_eq!(algorithm, ChecksumAlgorithm::Sha256);
+        assert_eq!(digests.get("/sdcard/a.txt").unwrap(), ABC_SHA256);
+        assert_eq!(digests.len(), 1);
+    }
+
+    #[test]
+    fn reports_unreadable_remote_files() {
+        let local = local_file("checksum-unreadable", b"abc");
+        let mut device = FakeDevice::new().with_shell_failure(
+            "sha256sum /data/secret",
+            "sha256sum: /data/secret: Permission denied\n",
+            1,
+        );
+
+        let err = compare_file(&mut device, &local.to_string_lossy(), "/data/secret").unwrap_err();
+        assert_eq!(err.kind(), "CommandFailed");
+        let _ = std::fs::remove_file(&local);
+    }
+}
@@ -0,0 +1,159 @@
+//! Building command lines for the device's `/system/bin/sh` without letting
+//! arguments be reinterpreted by it.
+
+use std::fmt;
+
+/// A program and its arguments, each quoted for the device shell.
+///
+/// ```ignore
+/// let command = ShellCommand::new("ls").arg("-la").arg(path);
+/// device.shell(&command.to_string(), &mut output)?;
+/// ```
+#[derive(Clone, Debug)]
+pub(crate) struct ShellCommand {
+    words: Vec<String>,
+}
+
+impl ShellCommand {
+    pub fn new(program: &str) -> Self {
+        ShellCommand {
+            words: vec![quote(program)],
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<str>) -> Self {
+        self.words.push(quote(arg.as_ref()));
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.words
+            .extend(args.into_iter().map(|arg| quote(arg.as_ref())));
+        self
+    }
+}
+
+impl fmt::Display for ShellCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.words.join(" "))
+    }
+}
+
+fn is_safe(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"_@%+=:,./-".contains(&byte)
+}
+
+/// Quotes `arg` as a single word for a POSIX shell. Plain words are left as
+/// they are so logged commands stay readable.
+pub(crate) fn quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.bytes().all(is_safe) {
+        return arg.to_string();
+    }
+    // Nothing is special inside single quotes, so only the quote itself needs
+    // handling: close, add an escaped quote, reopen
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE_NAMES: &[&str] = &[
+        "my notes.txt",
+        "it's here.txt",
+        "\"double\".txt",
+        "$HOME",
+        "$(reboot)",
+        "`id`",
+        "a; rm -rf /",
+        "a && reboot",
+        "a | sh",
+        "back\\slash",
+        "new\nline",
+        "tab\there",
+        "*",
+        "~",
+        "#hash",
+        "!bang",
+        "{a,b}",
+        "> out",
+        "'",
+        "''",
+        "",
+        "ünïcode 文件",
+    ];
+
+    #[test]
+    fn plain_words_are_left_alone() {
+        assert_eq!(
+            ShellCommand::new("ls")
+                .arg("-la")
+                .arg("/sdcard/")
+                .to_string(),
+            "ls -la /sdcard/"
+        );
+        assert_eq!(quote("ro.build.version.sdk"), "ro.build.version.sdk");
+        assert_eq!(quote("user@host:1,2=3%+"), "user@host:1,2=3%+");
+    }
+
+    #[test]
+    fn everything_else_is_single_quoted() {
+        assert_eq!(quote("my notes.txt"), "'my notes.txt'");
+        assert_eq!(quote("$(reboot)"), "'$(reboot)'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote(""), "''");
+        assert_eq!(
+            ShellCommand::new("cat")
+                .arg("/sdcard/a; rm -rf /")
+                .to_string(),
+            "cat '/sdcard/a; rm -rf /'"
+        );
+    }
+
+    #[test]
+    fn hostile_names_stay_inside_their_quotes() {
+        for name in HOSTILE_NAMES {
+            let quoted = quote(name);
+            if quoted == *name {
+                continue;
+            }
+            // Outside of the quotes there may only be escaped single quotes
+            let mut chars = quoted.chars();
+            let mut inside = false;
+            while let Some(c) = chars.next() {
+                match (inside, c) {
+                    (_, '\'') => inside = !inside,
+                    (false, '\\') => assert_eq!(chars.next(), Some('\''), "{:?}", quoted),
+                    (false, c) => panic!("{:?} quoted as {:?} leaves {:?} bare", name, quoted, c),
+                    (true, _) => {}
+                }
+            }
+            assert!(!inside, "{:?} quoted as {:?} is unterminated", name, quoted);
+        }
+    }
+
+    /// The device shell is mksh, which treats single quotes as any POSIX sh
+    /// does, so the host's shell gives a faithful round trip.
+    #[cfg(unix)]
+    #[test]
+    fn hostile_names_round_trip_through_sh() {
+        for name in HOSTILE_NAMES {
+            let command = ShellCommand::new("printf").arg("%s").arg(name);
+            let output = std::process::Command::new("/bin/sh")
+                .arg("-c")
+                .arg(command.to_string())
+                .output()
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                *name,
+                "ran {}",
+                command
+            );
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! Deadlines and cancellation for device operations.
+//!
+//! adb_client blocks on its sockets without a timeout, so a deadline is
+//! enforced on both ends: the remote command runs under `timeout`, which ends
+//! the stream when it fires, and output is refused locally once the deadline
+//! or a cancellation hits, which ends chatty commands early.
+//!
+//! Sync transfers are bounded by inactivity instead: every chunk they move
+//! pushes the deadline back, so a large pull, push or mirror only times out
+//! once it stalls.
+
+use adb_client::AdbStatResponse;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use super::adb_device::{AdbDevice, DirEntry};
+use super::command::ShellCommand;
+use super::device::DeviceTransport;
+use crate::error::DroidKitError;
+
+/// Broad kinds of device operation, each with its own default deadline.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum CommandCategory {
+    SystemInfo,
+    /// Anything that waits on the device's network, like `ping`
+    Network,
+    Packages,
+    Files,
+    Logcat,
+    /// Commands typed by the user
+    Shell,
+}
+
+/// Deadline per [`CommandCategory`] in seconds; 0 means none.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct CommandTimeouts {
+    pub system_info_secs: u64,
+    pub network_secs: u64,
+    pub packages_secs: u64,
+    /// For transfers, how long they may go without moving a chunk.
+    pub files_secs: u64,
+    pub logcat_secs: u64,
+    pub shell_secs: u64,
+}
+
+impl Default for CommandTimeouts {
+    fn default() -> Self {
+        CommandTimeouts {
+            system_info_secs: 30,
+            network_secs: 30,
+            packages_secs: 60,
+            files_secs: 120,
+            logcat_secs: 30,
+            shell_secs: 120,
+        }
+    }
+}
+
+impl CommandTimeouts {
+    fn get(&self, category: CommandCategory) -> Option<Duration> {
+        let secs = match category {
+            CommandCategory::SystemInfo => self.system_info_secs,
+            CommandCategory::Network => self.network_secs,
+            CommandCategory::Packages => self.packages_secs,
+            CommandCategory::Files => self.files_secs,
+            CommandCategory::Logcat => self.logcat_secs,
+            CommandCategory::Shell => self.shell_secs,
+        };
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub(crate) struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Deadline {
+    expires_at: Arc<Mutex<Option<Instant>>>,
+    timeout: Option<Duration>,
+    cancel: CancelToken,
+}
+
+impl Deadline {
+    pub fn after(timeout: Option<Duration>, cancel: CancelToken) -> Self {
+        Deadline {
+            expires_at: Arc::new(Mutex::new(timeout.map(|timeout| Instant::now() + timeout))),
+            timeout,
+            cancel,
+        }
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        lock(&self.expires_at)
+            .map(|expires_at| expires_at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Pushes the deadline back to a full timeout from now, for progress made.
+    fn extend(&self) {
+        if let Some(timeout) = self.timeout {
+            *lock(&self.expires_at) = Some(Instant::now() + timeout);
+        }
+    }
+
+    /// Fails with `Cancelled` or `Timeout` once either has happened.
+    pub fn check(&self) -> Result<(), DroidKitError> {
+        if self.cancel.is_cancelled() {
+            return Err(DroidKitError::Cancelled(
+                "The operation was cancelled".to_string(),
+            ));
+        }
+        match (self.remaining(), self.timeout) {
+            (Some(remaining), Some(timeout)) if remaining.is_zero() => Err(DroidKitError::Timeout(
+                format!("No result within {}s", timeout.as_secs()),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs `f` against `device` under this deadline. Parsers that swallow
+    /// errors would otherwise return partial results after a timeout.
+    pub fn run<T>(
+        &self,
+        device: &mut dyn AdbDevice,
+        f: impl FnOnce(&mut dyn AdbDevice) -> T,
+    ) -> Result<T, DroidKitError> {
+        self.check()?;
+        let value = f(&mut DeadlineDevice {
+            inner: device,
+            deadline: self,
+        });
+        self.check()?;
+        Ok(value)
+    }
+
+    /// [`Deadline::run`] for operations that can fail on their own.
+    pub fn try_run<T>(
+        &self,
+        device: &mut dyn AdbDevice,
+        f: impl FnOnce(&mut dyn AdbDevice) -> Result<T, DroidKitError>,
+    ) -> Result<T, DroidKitError> {
+        self.run(device, f)?
+    }
+
+    /// Wraps `command` so the device kills it when the deadline passes.
+    /// Devices without `timeout` (before Android 6) run it unbounded.
+    fn wrap_command(&self, command: &str) -> String {
+        match self.remaining() {
+            Some(remaining) => {
+                let secs = remaining.as_secs() + 1;
+                let bounded = ShellCommand::new("timeout")
+                    .args(["-s", "KILL"])
+                    .arg(secs.to_string())
+                    .args(["sh", "-c", command]);
+                format!(
+                    "if command -v timeout >/dev/null; then {}; else {}\nfi",
+                    bounded, command
+                )
+            }
+            None => command.to_string(),
+        }
+    }
+}
+
+/// Refuses output once the deadline passes or the operation is cancelled,
+/// which makes adb_client stop reading.
+struct DeadlineWriter<'a> {
+    inner: &'a mut dyn Write,
+    deadline: &'a Deadline,
+    /// Each chunk extends the deadline, as for sync transfers.
+    extends: bool,
+}
+
+impl Write for DeadlineWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.deadline.check().map_err(io::Error::other)?;
+        let written = self.inner.write(buf)?;
+        if self.extends {
+            self.deadline.extend();
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The same for upload input, each chunk of which extends the deadline.
+struct DeadlineReader<'a> {
+    inner: &'a mut dyn Read,
+    deadline: &'a Deadline,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.deadline.check().map_err(io::Error::other)?;
+        let read = self.inner.read(buf)?;
+        self.deadline.extend();
+        Ok(read)
+    }
+}
+
+struct DeadlineDevice<'a> {
+    inner: &'a mut dyn AdbDevice,
+    deadline: &'a Deadline,
+}
+
+impl DeadlineDevice<'_> {
+    /// The deadline's error takes precedence over whatever it caused.
+    fn finish<T>(&self, result: Result<T, DroidKitError>) -> Result<T, DroidKitError> {
+        self.deadline.check()?;
+        result
+    }
+}
+
+impl AdbDevice for DeadlineDevice<'_> {
+    fn transport(&self) -> DeviceTransport {
+        self.inner.transport()
+    }
+
+    fn transport_serial(&self) -> Option<String> {
+        self.inner.transport_serial()
+    }
+
+    fn features(&mut self) -> Vec<String> {
+        self.inner.features()
+    }
+
+    fn shell(
+        &mut self,
+        command: &str,
+        output: &mut dyn Write,
+    ) -> Result<Option<u8>, DroidKitError> {
+        self.deadline.check()?;
+        let mut output = DeadlineWriter {
+            inner: output,
+            deadline: self.deadline,
+            extends: false,
+        };
+        let result = self
+            .inner
+            .shell(&self.deadline.wrap_command(command), &mut output);
+        self.finish(result)
+    }
+
+    fn shell_streams(
+        &mut self,
+        command: &str,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<Option<u8>, DroidKitError> {
+        self.deadline.check()?;
+        let mut stdout = DeadlineWriter {
+            inner: stdout,
+            deadline: self.deadline,
+            extends: false,
+        };
+        let mut stderr = DeadlineWriter {
+            inner: stderr,
+            deadline: self.deadline,
+            extends: false,
+        };
+        let result = self.inner.shell_streams(
+            &self.deadline.wrap_command(command),
+            &mut stdout,
+            &mut stderr,
+        );
+        self.finish(result)
+    }
+
+    fn exec_out(&mut self, command: &str, output: &mut dyn Write) -> Result<(), DroidKitError> {
+        self.deadline.check()?;
+        // adbd runs `exec:` commands through `sh -c` too
+        let mut output = DeadlineWriter {
+            inner: output,
+            deadline: self.deadline,
+            extends: false,
+        };
+        let result = self
+            .inner
+            .exec_out(&self.deadline.wrap_command(command), &mut output);
+        self.finish(result)
+    }
+
+    fn stat(&mut self, remote_path: &str) -> Result<AdbStatResponse, DroidKitError> {
+        self.deadline.check()?;
+        let result = self.inner.stat(remote_path);
+        self.finish(result)
+    }
+
+    fn list(&mut self, remote_path: &str) -> Result<Vec<DirEntry>, DroidKitError> {
+        self.deadline.check()?;
+        let result = self.inner.list(remote_path);
+        self.finish(result)
+    }
+
+    fn pull(&mut self, remote_path: &str, output: &mut dyn Write) -> Result<(), DroidKitError> {
+        self.deadline.check()?;
+        let mut output = DeadlineWriter {
+            inner: output,
+            deadline: self.deadline,
+            extends: true,
+        };
+        let result = self.inner.pull(remote_path, &mut output);
+        self.finish(result)
+    }
+
+    fn push(
+        &mut self,
+        input: &mut dyn Read,
+        remote_path: &str,
+        mode: u32,
+        mod_time: u32,
+    ) -> Result<(), DroidKitError> {
+        self.deadline.check()?;
+        let mut input = DeadlineReader {
+            inner: input,
+            deadline: self.deadline,
+        };
+        let result = self.inner.push(&mut input, remote_path, mode, mod_time);
+        self.finish(result)
+    }
+}
+
+/// Configured timeouts plus a cancel token per device, held in Tauri state.
+#[derive(Clone, Default)]
+pub(crate) struct DeviceOperations {
+    timeouts: Arc<Mutex<CommandTimeouts>>,
+    cancel_tokens: Arc<Mutex<HashMap<String, CancelToken>>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl DeviceOperations {
+    pub fn timeouts(&self) -> CommandTimeouts {
+        lock(&self.timeouts).clone()
+    }
+
+    pub fn set_timeouts(&self, timeouts: CommandTimeouts) {
+        *lock(&self.timeouts) = timeouts;
+    }
+
+    /// A deadline for a new `category` operation on `serial_no`.
+    pub fn deadline(&self, serial_no: &str, category: CommandCategory) -> Deadline {
+        let cancel = lock(&self.cancel_tokens)
+            .entry(serial_no.to_string())
+            .or_default()
+            .clone();
+        Deadline::after(lock(&self.timeouts).get(category), cancel)
+    }
+
+    /// Cancels every operation in flight on `serial_no`. Later ones are unaffected.
+    pub fn cancel_all(&self, serial_no: &str) {
+        if let Some(token) = lock(&self.cancel_tokens).remove(serial_no) {
+            token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::FakeDevice;
+
+    #[test]
+    fn command_runs_under_remote_timeout() {
+        let deadline = Deadline::after(Some(Duration::from_secs(5)), CancelToken::default());
+        let wrapped = deadline.wrap_command("dumpsys 'battery'");
+        assert!(wrapped.contains("timeout -s KILL 5 sh -c 'dumpsys '\\''battery'\\'''"));
+        assert!(wrapped.ends_with("else dumpsys 'battery'\nfi"));
+
+        let unbounded = Deadline::after(None, CancelToken::default());
+        assert_eq!(unbounded.wrap_command("ls"), "ls");
+    }
+
+    #[test]
+    fn expired_deadline_is_a_timeout() {
+        let deadline = Deadline::after(Some(Duration::ZERO), CancelToken::default());
+        let mut device = FakeDevice::new();
+        let err = deadline
+            .run(&mut device, |device| device.shell("ls", &mut Vec::new()))
+            .unwrap_err();
+        assert_eq!(err.kind(), "Timeout");
+        assert!(device.calls.is_empty());
+    }
+
+    #[test]
+    fn transfer_chunks_extend_the_deadline() {
+        let deadline = Deadline::after(Some(Duration::from_millis(200)), CancelToken::default());
+        let mut data = Vec::new();
+        let mut output = DeadlineWriter {
+            inner: &mut data,
+            deadline: &deadline,
+            extends: true,
+        };
+
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(120));
+            output.write_all(b"chunk").unwrap();
+        }
+        assert!(deadline.check().is_ok());
+
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(deadline.check().unwrap_err().kind(), "Timeout");
+    }
+
+    #[test]
+    fn cancellation_refuses_further_output() {
+        let token = CancelToken::default();
+        let deadline = Deadline::after(None, token.clone());
+        let mut device = FakeDevice::new().with_shell("getprop ro.product.model", "Pixel 7\n");
+
+        let model = deadline
+            .try_run(&mut device, |device| {
+                let mut buf = Vec::new();
+                device.shell("getprop ro.product.model", &mut buf)?;
+                Ok(String::from_utf8(buf).unwrap())
+            })
+            .unwrap();
+        assert_eq!(model, "Pixel 7\n");
+
+        token.cancel();
+        let err = deadline
+            .run(&mut device, |device| {
+                device.shell("getprop ro.product.model", &mut Vec::new())
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), "Cancelled");
+    }
+
+    #[test]
+    fn cancel_all_only_affects_operations_in_flight() {
+        let operations = DeviceOperations::default();
+        let before = operations.deadline("emulator-5554", CommandCategory::SystemInfo);
+        let other = operations.deadline("R58M123ABC", CommandCategory::SystemInfo);
+
+        operations.cancel_all("emulator-5554");
+
+        assert_eq!(before.check().unwrap_err().kind(), "Cancelled");
+        assert!(other.check().is_ok());
+        assert!(
+            operations
+                .deadline("emulator-5554", CommandCategory::SystemInfo)
+                .check()
+                .is_ok()
+        );
+    }
+}
@@ -1,29 +1,41 @@
 use adb_client::{
-    ADBDeviceExt, RustADBError, server::ADBServer, tcp::ADBTcpDevice, usb::ADBUSBDevice,
+    ADBDeviceExt, RustADBError,
+    server::{ADBServer, DeviceState},
+    server_device::ADBServerDevice,
+    tcp::ADBTcpDevice,
+    usb::{ADBUSBDevice, USBTransport},
 };
+use rusb::{GlobalContext, UsbContext, constants::LIBUSB_CLASS_VENDOR_SPEC};
 use serde::{Deserialize, Serialize};
-use std::net::{IpAddr, SocketAddr, SocketAddrV4};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpStream};
+use std::time::Duration;
 
 use super::logcat::get_device_info;
+use crate::error::DroidKitError;
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub(crate) enum DeviceTransport {
     USB,
     TCP,
+    Server,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub(crate) struct DeviceInfo {
     pub transport: DeviceTransport,
     pub serial_no: String,
     pub model: String,
     pub android_version: String,
     pub sdk_version: String,
+    /// adb connection state: "device" once usable, otherwise e.g. "offline" or "unauthorized"
+    pub state: String,
 }
 
 pub(crate) enum Device {
     USB(ADBUSBDevice),
     TCP(ADBTcpDevice),
+    /// Routed through a running `adb` server, which owns the actual transport.
+    Server(ADBServerDevice),
 }
 
 impl Device {
@@ -35,25 +47,258 @@ impl Device {
         match self {
             Device::USB(device) => device.shell_command(cmd, Some(output), None),
             Device::TCP(device) => device.shell_command(cmd, Some(output), None),
+            Device::Server(device) => {
+                // The server speaks shell v2 and splits stderr out; fold it back
+                // in so callers see the same merged stream as the other transports.
+                let mut stderr: Vec<u8> = Vec::new();
+                let status = device.shell_command(cmd, Some(&mut *output), Some(&mut stderr))?;
+                output.write_all(&stderr)?;
+                Ok(status)
+            }
+        }
+    }
+}
+
+pub(crate) const ADB_SERVER_PORT: u16 = 5037;
+
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct ServerDeviceEntry {
+    pub serial_no: String,
+    pub state: String,
+    pub usb: String,
+    pub product: String,
+    pub model: String,
+    pub device: String,
+    pub transport_id: u32,
+}
+
+/// Whether an `adb` server is already listening locally. `ADBServer` would
+/// otherwise spawn one, which then claims every USB device we want to open directly.
+pub(crate) fn adb_server_available() -> bool {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, ADB_SERVER_PORT));
+    TcpStream::connect_timeout(&addr, Duration::from_millis(300)).is_ok()
+}
+
+/// Lists the devices known to a running `adb` server (`host:devices-l`).
+pub(crate) fn list_server_devices() -> Result<Vec<ServerDeviceEntry>, DroidKitError> {
+    if !adb_server_available() {
+        return Ok(Vec::new());
+    }
+
+    let devices = ADBServer::default()
+        .devices_long()
+        .map_err(|e| DroidKitError::from(e).context("Failed to list adb server devices"))?;
+
+    Ok(devices
+        .into_iter()
+        .map(|device| ServerDeviceEntry {
+            serial_no: device.identifier,
+            state: device.state.to_string(),
+            usb: device.usb,
+            product: device.product,
+            model: device.model,
+            device: device.device,
+            transport_id: device.transport_id,
+        })
+        .collect())
+}
+
+/// Selects the adb server transport for `serial_no`.
+pub(crate) fn open_server_device(serial_no: &str) -> Result<ADBServerDevice, DroidKitError> {
+    if !adb_server_available() {
+        return Err(DroidKitError::NoDevice(
+            "No adb server is running".to_string(),
+        ));
+    }
+
+    let devices = ADBServer::default()
+        .devices()
+        .map_err(|e| DroidKitError::from(e).context("Failed to list adb server devices"))?;
+
+    match devices.iter().find(|device| device.identifier == serial_no) {
+        Some(device) => match device.state {
+            DeviceState::Device => Ok(ADBServerDevice::new(serial_no.to_string(), None)),
+            DeviceState::Unauthorized => Err(DroidKitError::Unauthorized(format!(
+                "Device {} has not authorized this computer; accept the prompt on the device",
+                serial_no
+            ))),
+            ref state => Err(DroidKitError::Offline(format!(
+                "Device {} is {} according to the adb server",
+                serial_no, state
+            ))),
+        },
+        None => Err(DroidKitError::NoDevice(format!(
+            "Device {} is not known to the adb server",
+            serial_no
+        ))),
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct UsbDeviceEntry {
+    pub serial_no: Option<String>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bus_number: u8,
+    pub address: u8,
+    pub description: String,
+}
+
+fn is_adb_interface(device: &rusb::Device<GlobalContext>, des: &rusb::DeviceDescriptor) -> bool {
+    const ADB_SUBCLASS: u8 = 0x42;
+    const ADB_PROTOCOL: u8 = 0x1;
+    // Some devices only expose ADB once the "file transfer" USB mode is selected
+    const BULK_CLASS: u8 = 0xdc;
+    const BULK_ADB_SUBCLASS: u8 = 2;
+
+    (0..des.num_configurations())
+        .filter_map(|n| device.config_descriptor(n).ok())
+        .any(|config| {
+            config.interfaces().any(|interface| {
+                interface.descriptors().any(|interface_des| {
+                    let class = interface_des.class_code();
+                    let subclass = interface_des.sub_class_code();
+                    interface_des.protocol_code() == ADB_PROTOCOL
+                        && ((class == LIBUSB_CLASS_VENDOR_SPEC && subclass == ADB_SUBCLASS)
+                            || (class == BULK_CLASS && subclass == BULK_ADB_SUBCLASS))
+                })
+            })
+        })
+}
+
+pub(crate) fn read_usb_entry(
+    device: &rusb::Device<GlobalContext>,
+    des: &rusb::DeviceDescriptor,
+) -> UsbDeviceEntry {
+    let mut entry = UsbDeviceEntry {
+        serial_no: None,
+        vendor_id: des.vendor_id(),
+        product_id: des.product_id(),
+        bus_number: device.bus_number(),
+        address: device.address(),
+        description: "Unknown device".to_string(),
+    };
+
+    // String descriptors can be read without claiming the interface, so this
+    // works even while another process (e.g. an adb server) owns the device.
+    if let Ok(handle) = device.open() {
+        let timeout = Duration::from_millis(500);
+        let language = handle
+            .read_languages(timeout)
+            .ok()
+            .and_then(|languages| languages.first().copied());
+
+        if let Some(language) = language {
+            entry.serial_no = handle
+                .read_serial_number_string(language, des, timeout)
+                .ok()
+                .map(|serial| serial.trim().to_string())
+                .filter(|serial| !serial.is_empty());
+
+            let manufacturer = handle
+                .read_manufacturer_string(language, des, timeout)
+                .unwrap_or_else(|_| "Unknown".to_string());
+            let product = handle
+                .read_product_string(language, des, timeout)
+                .unwrap_or_else(|_| "Unknown".to_string());
+            entry.description = format!("{} {}", manufacturer, product);
         }
     }
+
+    entry
+}
+
+fn find_adb_usb_devices()
+-> Result<Vec<(rusb::Device<GlobalContext>, UsbDeviceEntry)>, DroidKitError> {
+    let devices = GlobalContext::default()
+        .devices()
+        .map_err(|e| DroidKitError::Io(format!("Failed to enumerate USB devices: {}", e)))?;
+
+    Ok(devices
+        .iter()
+        .filter_map(|device| {
+            let des = device.device_descriptor().ok()?;
+            if !is_adb_interface(&device, &des) {
+                return None;
+            }
+            let entry = read_usb_entry(&device, &des);
+            Some((device, entry))
+        })
+        .collect())
+}
+
+/// Lists every attached USB device exposing an ADB interface, with its serial
+/// number when the descriptor could be read.
+pub(crate) fn list_usb_devices() -> Result<Vec<UsbDeviceEntry>, DroidKitError> {
+    find_adb_usb_devices().map(|devices| devices.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// Opens the USB device whose serial number is exactly `serial_no`.
+pub(crate) fn open_usb_device(serial_no: &str) -> Result<ADBUSBDevice, DroidKitError> {
+    let devices = find_adb_usb_devices()?;
+
+    let known_serials: Vec<String> = devices
+        .iter()
+        .map(|(_, entry)| {
+            entry.serial_no.clone().unwrap_or_else(|| {
+                format!(
+                    "<unreadable {:04x}:{:04x}>",
+                    entry.vendor_id, entry.product_id
+                )
+            })
+        })
+        .collect();
+
+    let usb_device = devices
+        .into_iter()
+        .find(|(_, entry)| entry.serial_no.as_deref() == Some(serial_no))
+        .map(|(device, _)| device)
+        .ok_or_else(|| {
+            DroidKitError::NoDevice(if known_serials.is_empty() {
+                format!("No USB device with serial {} is attached", serial_no)
+            } else {
+                format!(
+                    "No USB device with serial {} is attached (found: {})",
+                    serial_no,
+                    known_serials.join(", ")
+                )
+            })
+        })?;
+
+    ADBUSBDevice::new_from_transport(USBTransport::new_from_device(usb_device), None).map_err(|e| {
+        DroidKitError::from(e).context(&format!("Failed to open USB device {}", serial_no))
+    })
 }
 
 pub(crate) fn get_connected_device() -> Option<Device> {
-    let autodetect = ADBUSBDevice::autodetect();
-    match autodetect {
-        Ok(device) => Some(Device::USB(device)),
+    let devices = match find_adb_usb_devices() {
+        Ok(devices) => devices,
         Err(what) => {
-            println!("Error: {:?}", what);
-            None
+            println!("Error: {}", what);
+            return None;
+        }
+    };
+
+    for (usb_device, entry) in devices {
+        match ADBUSBDevice::new_from_transport(USBTransport::new_from_device(usb_device), None) {
+            Ok(device) => return Some(Device::USB(device)),
+            Err(what) => println!("Error opening USB device {:?}: {:?}", entry.serial_no, what),
         }
     }
+
+    // USB devices claimed by a running adb server are only reachable through it
+    list_server_devices()
+        .ok()?
+        .into_iter()
+        .find(|device| device.state == DeviceState::Device.to_string())
+        .map(|device| Device::Server(ADBServerDevice::new(device.serial_no, None)))
 }
 
 #[derive(Serialize, Deserialize)]
 pub(crate) enum ConnectionMethod {
     USB { serial_number: String },
     TCP { socket_address: String },
+    Server { serial_number: String },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -65,81 +310,134 @@ pub(crate) struct DiscoveredDevice {
     pub is_connected: bool,
 }
 
-pub(crate) fn list_discovered_devices() -> Result<Vec<DiscoveredDevice>, String> {
+pub(crate) fn list_discovered_devices() -> Result<Vec<DiscoveredDevice>, DroidKitError> {
     let mut discovered_devices = Vec::new();
 
-    if let Ok(usb_device) = ADBUSBDevice::autodetect() {
-        if let Ok(device_info) = get_device_info(&mut Device::USB(usb_device)) {
-            discovered_devices.push(DiscoveredDevice {
+    let server_devices = list_server_devices().unwrap_or_else(|e| {
+        println!("Error: {}", e);
+        Vec::new()
+    });
+
+    for entry in &server_devices {
+        let device_info = open_server_device(&entry.serial_no)
+            .ok()
+            .and_then(|server_device| get_device_info(&mut Device::Server(server_device)).ok());
+
+        discovered_devices.push(DiscoveredDevice {
+            connection_method: ConnectionMethod::Server {
+                serial_number: entry.serial_no.clone(),
+            },
+            model: device_info
+                .as_ref()
+                .map(|info| info.model.clone())
+                .or_else(|| Some(entry.model.replace('_', " "))),
+            android_version: device_info
+                .as_ref()
+                .map(|info| info.android_version.clone()),
+            sdk_version: device_info.as_ref().map(|info| info.sdk_version.clone()),
+            is_connected: device_info.is_some(),
+        });
+    }
+
+    for entry in list_usb_devices()? {
+        let Some(serial_no) = entry.serial_no else {
+            println!(
+                "Skipping USB device {:04x}:{:04x} with unreadable serial",
+                entry.vendor_id, entry.product_id
+            );
+            continue;
+        };
+
+        // Already listed above; the server owns the interface, so opening it would fail
+        if server_devices
+            .iter()
+            .any(|device| device.serial_no == serial_no)
+        {
+            continue;
+        }
+
+        let device_info = open_usb_device(&serial_no)
+            .ok()
+            .and_then(|usb_device| get_device_info(&mut Device::USB(usb_device)).ok());
+
+        discovered_devices.push(match device_info {
+            Some(device_info) => DiscoveredDevice {
                 connection_method: ConnectionMethod::USB {
-                    serial_number: device_info.serial_no,
+                    serial_number: serial_no,
                 },
                 model: Some(device_info.model),
                 android_version: Some(device_info.android_version),
                 sdk_version: Some(device_info.sdk_version),
                 is_connected: true,
-            });
-        }
+            },
+            None => DiscoveredDevice {
+                connection_method: ConnectionMethod::USB {
+                    serial_number: serial_no,
+                },
+                model: None,
+                android_version: None,
+                sdk_version: None,
+                is_connected: false,
+            },
+        });
     }
 
     Ok(discovered_devices)
 }
 
-pub(crate) fn connect_to_discovered_device(device: &DiscoveredDevice) -> Result<Device, String> {
+pub(crate) fn connect_to_discovered_device(
+    device: &DiscoveredDevice,
+) -> Result<Device, DroidKitError> {
     match &device.connection_method {
-        ConnectionMethod::USB { serial_number } => match ADBUSBDevice::autodetect() {
-            Ok(device) => Ok(Device::USB(device)),
-            Err(e) => Err(format!(
-                "Failed to connect to USB device {}: {:?}",
-                serial_number, e
-            )),
-        },
+        ConnectionMethod::USB { serial_number } => open_usb_device(serial_number).map(Device::USB),
+        ConnectionMethod::Server { serial_number } => {
+            open_server_device(serial_number).map(Device::Server)
+        }
         ConnectionMethod::TCP { socket_address } => {
             if socket_address.contains(':') {
                 let parts: Vec<&str> = socket_address.split(':').collect();
                 if parts.len() == 2 {
                     if let Ok(ip) = parts[0].parse::<IpAddr>() {
                         if let Ok(port) = parts[1].parse::<u16>() {
-                            return connect_tcp_device(ip, port)
-                                .ok_or_else(|| "Failed to connect to TCP device".to_string());
+                            return connect_tcp_device(ip, port);
                         }
                     }
                 }
             }
-            Err(format!("Invalid socket address format: {}", socket_address))
+            Err(DroidKitError::InvalidInput(format!(
+                "Invalid socket address format: {}",
+                socket_address
+            )))
         }
     }
 }
 
-pub(crate) fn reconnect_device(serial_no: &str) -> Option<Device> {
-    if serial_no.contains(':') {
-        let parts: Vec<&str> = serial_no.split(':').collect();
-        if parts.len() == 2 {
-            if let Ok(ip) = parts[0].parse::<IpAddr>() {
-                if let Ok(port) = parts[1].parse::<u16>() {
-                    let socket_addr = SocketAddr::new(ip, port);
-                    if let Ok(device) = ADBTcpDevice::new(socket_addr) {
-                        return Some(Device::TCP(device));
-                    }
-                }
-            }
-        }
+/// Reopens the device identified by `serial_no`. Devices listed by a running
+/// adb server (including emulators) are routed through it; otherwise `ip:port`
+/// serials are reached over TCP and anything else must match an attached USB device.
+pub(crate) fn reconnect_device(serial_no: &str) -> Result<Device, DroidKitError> {
+    if let Ok(server_device) = open_server_device(serial_no) {
+        return Ok(Device::Server(server_device));
     }
 
-    if let Ok(device) = ADBUSBDevice::autodetect() {
-        return Some(Device::USB(device));
+    if let Ok(socket_addr) = serial_no.parse::<SocketAddr>() {
+        return ADBTcpDevice::new(socket_addr)
+            .map(Device::TCP)
+            .map_err(|e| {
+                DroidKitError::from(e).context(&format!("Failed to connect to {}", serial_no))
+            });
     }
 
-    None
+    open_usb_device(serial_no).map(Device::USB)
 }
 
-pub(crate) fn connect_tcp_device(ip: IpAddr, port: u16) -> Option<Device> {
+pub(crate) fn connect_tcp_device(ip: IpAddr, port: u16) -> Result<Device, DroidKitError> {
     let socket_addr = SocketAddr::new(ip, port);
     match ADBTcpDevice::new(socket_addr) {
-        Ok(device) => Some(Device::TCP(device)),
+        Ok(device) => Ok(Device::TCP(device)),
         Err(what) => {
             println!("Error connecting to TCP device: {:?}", what);
-            None
+            Err(DroidKitError::from(what).context(&format!("Failed to connect to {}", socket_addr)))
         }
     }
 }
@@ -148,19 +446,27 @@ pub(crate) fn pair_device_with_code(
     ip: IpAddr,
     port: u16,
     pairing_code: &str,
-) -> Result<(), String> {
+) -> Result<(), DroidKitError> {
     let ipv4 = match ip {
         IpAddr::V4(ipv4) => ipv4,
-        IpAddr::V6(_) => return Err("IPv6 addresses are not supported for pairing".to_string()),
+        IpAddr::V6(_) => {
+            return Err(DroidKitError::InvalidInput(
+                "IPv6 addresses are not supported for pairing".to_string(),
+            ));
+        }
     };
 
     let trimmed_code = pairing_code.trim();
     if trimmed_code.len() != 6 {
-        return Err("Pairing code must be exactly 6 digits".to_string());
+        return Err(DroidKitError::InvalidInput(
+            "Pairing code must be exactly 6 digits".to_string(),
+        ));
     }
 
     if !trimmed_code.chars().all(|c| c.is_ascii_digit()) {
-        return Err("Pairing code must contain only digits".to_string());
+        return Err(DroidKitError::InvalidInput(
+            "Pairing code must contain only digits".to_string(),
+        ));
     }
 
     println!(
@@ -183,29 +489,31 @@ pub(crate) fn pair_device_with_code(
         }
         Err(e) => {
             println!("Detailed pairing error: {:#?}", e);
+            Err(DroidKitError::PairingFailed {
+                reason: pairing_failure_reason(&e),
+            })
+        }
+    }
+}
 
-            let error_str = format!("{:?}", e);
-
-            if error_str.contains("ParseIntError") {
-                Err(
-                    "Pairing failed due to a protocol parsing error. This might happen if:\n\
-                     1. The device is not in pairing mode\n\
-                     2. The pairing port is incorrect\n\
-                     3. The pairing code has expired\n\
-                     Please check your Android device's wireless debugging screen and try again."
-                        .to_string(),
-                )
-            } else if error_str.contains("ConnectionRefused") {
-                Err("Connection refused. Please ensure:\n\
-                     1. The device is in pairing mode\n\
-                     2. The IP address and port are correct\n\
-                     3. Both devices are on the same network"
-                    .to_string())
-            } else if error_str.contains("TimedOut") {
-                Err("Pairing timed out. Please try again with a fresh pairing code.".to_string())
-            } else {
-                Err(format!("Pairing failed with error: {}", error_str))
-            }
+fn pairing_failure_reason(e: &RustADBError) -> String {
+    match e {
+        RustADBError::ParseIntError(_) => "Protocol parsing error. This might happen if:\n\
+             1. The device is not in pairing mode\n\
+             2. The pairing port is incorrect\n\
+             3. The pairing code has expired\n\
+             Please check your Android device's wireless debugging screen and try again."
+            .to_string(),
+        RustADBError::IOError(io) if io.kind() == std::io::ErrorKind::ConnectionRefused => {
+            "Connection refused. Please ensure:\n\
+             1. The device is in pairing mode\n\
+             2. The IP address and port are correct\n\
+             3. Both devices are on the same network"
+                .to_string()
+        }
+        RustADBError::IOError(io) if io.kind() == std::io::ErrorKind::TimedOut => {
+            "Timed out. Please try again with a fresh pairing code.".to_string()
         }
+        other => other.to_string(),
     }
 }
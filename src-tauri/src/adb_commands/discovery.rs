@@ -6,6 +6,7 @@ use std::thread;
 use std::time::Duration;
 
 use super::device::connect_tcp_device;
+use crate::error::DroidKitError;
 
 #[derive(Serialize)]
 pub(crate) struct DiscoveredWirelessDevice {
@@ -18,15 +19,15 @@ pub(crate) struct DiscoveredWirelessDevice {
     pub is_connected: bool,
 }
 
-pub(crate) fn discover_wireless_devices() -> Result<Vec<DiscoveredWirelessDevice>, String> {
+pub(crate) fn discover_wireless_devices() -> Result<Vec<DiscoveredWirelessDevice>, DroidKitError> {
     let (tx, rx) = mpsc::channel();
 
     let mut discovery_service = MDNSDiscoveryService::new()
-        .map_err(|e| format!("Failed to create mDNS discovery service: {:?}", e))?;
+        .map_err(|e| DroidKitError::from(e).context("Failed to create mDNS discovery service"))?;
 
     discovery_service
         .start(tx)
-        .map_err(|e| format!("Failed to start mDNS discovery: {:?}", e))?;
+        .map_err(|e| DroidKitError::from(e).context("Failed to start mDNS discovery"))?;
 
     let mut devices = Vec::new();
     let timeout = Duration::from_secs(5);
@@ -92,7 +93,7 @@ pub(crate) fn discover_wireless_devices() -> Result<Vec<DiscoveredWirelessDevice
 
     discovery_service
         .shutdown()
-        .map_err(|e| format!("Failed to shutdown mDNS discovery: {:?}", e))?;
+        .map_err(|e| DroidKitError::from(e).context("Failed to shutdown mDNS discovery"))?;
 
     println!(
         "Discovery completed. Found {} wireless devices",
@@ -101,8 +102,8 @@ pub(crate) fn discover_wireless_devices() -> Result<Vec<DiscoveredWirelessDevice
     Ok(devices)
 }
 
-pub(crate) fn discover_wireless_devices_detailed() -> Result<Vec<DiscoveredWirelessDevice>, String>
-{
+pub(crate) fn discover_wireless_devices_detailed()
+-> Result<Vec<DiscoveredWirelessDevice>, DroidKitError> {
     discover_wireless_devices()
 }
 
@@ -128,7 +129,7 @@ pub(crate) fn get_connection_port_for_device(ip: &str) -> u16 {
             test_port, ip
         );
         if let Ok(ip_addr) = ip.parse::<IpAddr>() {
-            if connect_tcp_device(ip_addr, test_port).is_some() {
+            if connect_tcp_device(ip_addr, test_port).is_ok() {
                 println!("Successfully connected to device at {}:{}", ip, test_port);
                 return test_port;
             }
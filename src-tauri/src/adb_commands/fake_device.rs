@@ -0,0 +1,298 @@
+//! A scripted [`AdbDevice`] that replays recorded command output, so parsers
+//! can be tested against real dumps without a phone attached.
+
+use adb_client::AdbStatResponse;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::adb_device::{AdbDevice, DirEntry, S_IFDIR, S_IFMT, S_IFREG};
+use super::device::DeviceTransport;
+use crate::error::DroidKitError;
+
+struct Recording {
+    output: Vec<u8>,
+    stderr: Vec<u8>,
+    exit_code: Option<u8>,
+}
+
+pub(crate) struct FakeDevice {
+    transport: DeviceTransport,
+    features: Vec<String>,
+    shell: HashMap<String, Recording>,
+    exec_out: HashMap<String, Vec<u8>>,
+    files: HashMap<String, AdbStatResponse>,
+    contents: HashMap<String, Vec<u8>>,
+    /// Every command and sync request received, in order.
+    pub calls: Vec<String>,
+}
+
+/// Reads `tests/fixtures/<android_version>/<name>`.
+pub(crate) fn fixture(android_version: &str, name: &str) -> String {
+    let path: PathBuf = [
+        env!("CARGO_MANIFEST_DIR"),
+        "tests",
+        "fixtures",
+        android_version,
+        name,
+    ]
+    .iter()
+    .collect();
+    std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("missing fixture {}: {}", path.display(), e))
+}
+
+/// A new empty directory under the system temp dir, named after `name`, this
+/// process and a counter, so tests running in parallel never share one.
+pub(crate) fn unique_temp_dir(name: &str) -> PathBuf {
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "droidkit-{}-{}-{}",
+        name,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+impl FakeDevice {
+    pub fn new() -> Self {
+        FakeDevice {
+            transport: DeviceTransport::USB,
+            features: Vec::new(),
+            shell: HashMap::new(),
+            exec_out: HashMap::new(),
+            files: HashMap::new(),
+            contents: HashMap::new(),
+            calls: Vec::new(),
+        }
+    }
+
+    pub fn with_transport(mut self, transport: DeviceTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn with_features(mut self, features: &[&str]) -> Self {
+        self.features = features.iter().map(|feature| feature.to_string()).collect();
+        self
+    }
+
+    /// Answers `command` with `output` and exit code 0.
+    pub fn with_shell(self, command: &str, output: impl Into<Vec<u8>>) -> Self {
+        self.with_shell_status(command, output, Some(0))
+    }
+
+    pub fn with_shell_status(
+        mut self,
+        command: &str,
+        output: impl Into<Vec<u8>>,
+        exit_code: Option<u8>,
+    ) -> Self {
+        self.shell.insert(
+            command.to_string(),
+            Recording {
+                output: output.into(),
+                stderr: Vec::new(),
+                exit_code,
+            },
+        );
+        self
+    }
+
+    /// Answers `command` with nothing on stdout, `stderr` and `exit_code`.
+    pub fn with_shell_failure(
+        mut self,
+        command: &str,
+        stderr: impl Into<Vec<u8>>,
+        exit_code: u8,
+    ) -> Self {
+        self.shell.insert(
+            command.to_string(),
+            Recording {
+                output: Vec::new(),
+                stderr: stderr.into(),
+                exit_code: Some(exit_code),
+            },
+        );
+        self
+    }
+
+    pub fn with_exec_out(mut self, command: &str, output: impl Into<Vec<u8>>) -> Self {
+        self.exec_out.insert(command.to_string(), output.into());
+        self
+    }
+
+    /// Adds a path visible to the sync protocol. `mode` includes the type bits.
+    pub fn with_file(mut self, path: &str, mode: u32, size: u32, mod_time: u32) -> Self {
+        self.files.insert(
+            path.to_string(),
+            AdbStatResponse {
+                file_perm: mode,
+                file_size: size,
+                mod_time,
+            },
+        );
+        self
+    }
+
+    /// Adds a regular file with `data` as its contents, for `RECV`.
+    pub fn with_file_data(
+        mut self,
+        path: &str,
+        mode: u32,
+        data: impl Into<Vec<u8>>,
+        mod_time: u32,
+    ) -> Self {
+        let data = data.into();
+        self = self.with_file(path, S_IFREG | mode, data.len() as u32, mod_time);
+        self.contents.insert(path.to_string(), data);
+        self
+    }
+
+    fn missing(&self, what: &str) -> DroidKitError {
+        DroidKitError::Adb(format!("FakeDevice has no recording for {}", what))
+    }
+}
+
+impl AdbDevice for FakeDevice {
+    fn transport(&self) -> DeviceTransport {
+        self.transport.clone()
+    }
+
+    fn transport_serial(&self) -> Option<String> {
+        None
+    }
+
+    fn features(&mut self) -> Vec<String> {
+        self.features.clone()
+    }
+
+    fn shell(
+        &mut self,
+        command: &str,
+        output: &mut dyn Write,
+    ) -> Result<Option<u8>, DroidKitError> {
+        let mut stderr = Vec::new();
+        let exit_code = self.shell_streams(command, output, &mut stderr)?;
+        output.write_all(&stderr)?;
+        Ok(exit_code)
+    }
+
+    fn shell_streams(
+        &mut self,
+        command: &str,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<Option<u8>, DroidKitError> {
+        self.calls.push(command.to_string());
+        match self.shell.get(command) {
+            Some(recording) => {
+                stdout.write_all(&recording.output)?;
+                stderr.write_all(&recording.stderr)?;
+                Ok(recording.exit_code)
+            }
+            None => {
+                // What `sh` prints for an unknown command
+                let program = command.split_whitespace().next().unwrap_or_default();
+                writeln!(
+                    stderr,
+                    "/system/bin/sh: {}: inaccessible or not found",
+                    program
+                )?;
+                Ok(Some(127))
+            }
+        }
+    }
+
+    fn exec_out(&mut self, command: &str, output: &mut dyn Write) -> Result<(), DroidKitError> {
+        self.calls.push(format!("exec:{}", command));
+        let recording = self
+            .exec_out
+            .get(command)
+            .ok_or_else(|| self.missing(command))?;
+        output.write_all(recording)?;
+        Ok(())
+    }
+
+    fn stat(&mut self, remote_path: &str) -> Result<AdbStatResponse, DroidKitError> {
+        self.calls.push(format!("STAT {}", remote_path));
+        // A trailing slash only resolves to a directory
+        let found = self.files.get(remote_path).or_else(|| {
+            let dir = self.files.get(remote_path.strip_suffix('/')?)?;
+            (dir.file_perm & S_IFMT == S_IFDIR).then_some(dir)
+        });
+        Ok(match found {
+            Some(stat) => AdbStatResponse {
+                file_perm: stat.file_perm,
+                file_size: stat.file_size,
+                mod_time: stat.mod_time,
+            },
+            // adbd answers a missing path with an all-zero stat
+            None => AdbStatResponse {
+                file_perm: 0,
+                file_size: 0,
+                mod_time: 0,
+            },
+        })
+    }
+
+    /// Every added path directly under `remote_path`, by name.
+    fn list(&mut self, remote_path: &str) -> Result<Vec<DirEntry>, DroidKitError> {
+        self.calls.push(format!("LIST {}", remote_path));
+        let dir = remote_path.trim_end_matches('/');
+        let mut entries: Vec<DirEntry> = self
+            .files
+            .iter()
+            .filter_map(|(path, stat)| {
+                let name = path.strip_prefix(dir)?.strip_prefix('/')?;
+                (!name.is_empty() && !name.contains('/')).then(|| DirEntry {
+                    name: name.to_string(),
+                    mode: stat.file_perm,
+                    size: stat.file_size,
+                    mod_time: stat.mod_time,
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn pull(&mut self, remote_path: &str, output: &mut dyn Write) -> Result<(), DroidKitError> {
+        self.calls.push(format!("RECV {}", remote_path));
+        let data = self
+            .contents
+            .get(remote_path)
+            .ok_or_else(|| self.missing(remote_path))?;
+        // In pieces the size of sync DATA packets
+        for chunk in data.chunks(64 * 1024) {
+            output.write_all(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn push(
+        &mut self,
+        input: &mut dyn Read,
+        remote_path: &str,
+        mode: u32,
+        mod_time: u32,
+    ) -> Result<(), DroidKitError> {
+        self.calls.push(format!("SEND {},{:o}", remote_path, mode));
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        self.files.insert(
+            remote_path.to_string(),
+            AdbStatResponse {
+                file_perm: mode,
+                file_size: data.len() as u32,
+                mod_time,
+            },
+        );
+        self.contents.insert(remote_path.to_string(), data);
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+//! File management for the explorer. Each operation is one shell command
+//! with every path passed as a single quoted word, and paths must be
+//! absolute so none can be taken for an option.
+
+use serde::Serialize;
+
+use super::adb_device::{AdbDevice, S_IFDIR, S_IFMT};
+use super::command::ShellCommand;
+use crate::error::DroidKitError;
+
+/// What a file operation did or, in a dry run, would do.
+#[derive(Serialize, Debug)]
+pub(crate) struct FileOpResult {
+    /// The shell command, as it ran or would run.
+    pub command: String,
+    pub dry_run: bool,
+    /// The paths affected. A dry-run delete of a directory lists everything
+    /// in it, deepest first.
+    pub paths: Vec<String>,
+    /// Whether an existing destination was, or would be, replaced.
+    pub replaced: bool,
+}
+
+fn absolute(path: &str) -> Result<&str, DroidKitError> {
+    if path.starts_with('/') {
+        Ok(path)
+    } else {
+        Err(DroidKitError::InvalidInput(format!(
+            "{:?} is not an absolute path",
+            path
+        )))
+    }
+}
+
+/// The `S_IFMT` bits of `path`, refusing paths that do not exist.
+fn existing_type(device: &mut dyn AdbDevice, path: &str) -> Result<u32, DroidKitError> {
+    match device.stat(path)?.file_perm & S_IFMT {
+        0 => Err(DroidKitError::InvalidInput(format!(
+            "{} does not exist on the device",
+            path
+        ))),
+        file_type => Ok(file_type),
+    }
+}
+
+fn run(
+    device: &mut dyn AdbDevice,
+    command: String,
+    dry_run: bool,
+    paths: Vec<String>,
+    replaced: bool,
+) -> Result<FileOpResult, DroidKitError> {
+    if !dry_run {
+        device.shell_output(&command)?.into_result(&command)?;
+    }
+    Ok(FileOpResult {
+        command,
+        dry_run,
+        paths,
+        replaced,
+    })
+}
+
+/// Deletes a file, link or, when `recursive`, a directory and everything in
+/// it. A link is removed, never what it points to.
+pub(crate) fn delete_path(
+    device: &mut dyn AdbDevice,
+    path: &str,
+    recursive: bool,
+    dry_run: bool,
+) -> Result<FileOpResult, DroidKitError> {
+    let path = absolute(path)?.trim_end_matches('/');
+    if path.is_empty() {
+        return Err(DroidKitError::InvalidInput(
+            "Refusing to delete /".to_string(),
+        ));
+    }
+    let is_dir = match existing_type(device, path)? {
+        S_IFDIR if !recursive => {
+            return Err(DroidKitError::InvalidInput(format!(
+                "{} is a directory",
+                path
+            )));
+        }
+        file_type => file_type == S_IFDIR,
+    };
+
+    let command = ShellCommand::new("rm")
+        .arg(if is_dir { "-rf" } else { "-f" })
+        .arg(path)
+        .to_string();
+    let paths = if dry_run && is_dir {
+        let find = ShellCommand::new("find")
+            .arg(path)
+            .arg("-depth")
+            .to_string();
+        let output = device.shell_output(&find)?.into_result(&find)?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    } else {
+        vec![path.to_string()]
+    };
+    run(device, command, dry_run, paths, false)
+}
+
+/// Where `source` goes when moved or copied to `dest`: under its own name
+/// when `dest` is a directory or ends in `/`. Returns the target and whether
+/// it already exists, refusing to replace it unless `overwrite`.
+fn resolve_target(
+    device: &mut dyn AdbDevice,
+    source: &str,
+    dest: &str,
+    overwrite: bool,
+) -> Result<(String, bool), DroidKitError> {
+    let source = absolute(source)?;
+    let dest = absolute(dest)?;
+    existing_type(device, source)?;
+
+    let mut target = dest.to_string();
+    if dest.ends_with('/') || device.stat(dest)?.file_perm & S_IFMT == S_IFDIR {
+        let name = source
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        if name.is_empty() {
+            return Err(DroidKitError::InvalidInput(format!(
+                "{} has no name",
+                source
+            )));
+        }
+        target = format!("{}/{}", dest.trim_end_matches('/'), name);
+    }
+
+    match device.stat(&target)?.file_perm & S_IFMT {
+        0 => Ok((target, false)),
+        S_IFDIR => Err(DroidKitError::InvalidInput(format!(
+            "{} is a directory",
+            target
+        ))),
+        _ if !overwrite => Err(DroidKitError::AlreadyExists(format!(
+            "{} already exists on the device",
+            target
+        ))),
+        _ => Ok((target, true)),
+    }
+}
+
+/// Renames or moves `source` to `dest`. See [`resolve_target`] for where it
+/// ends up.
+pub(crate) fn move_path(
+    device: &mut dyn AdbDevice,
+    source: &str,
+    dest: &str,
+    overwrite: bool,
+    dry_run: bool,
+) -> Result<FileOpResult, DroidKitError> {
+    let (target, replaced) = resolve_target(device, source, dest, overwrite)?;
+    let command = ShellCommand::new("mv").arg(source).arg(&target).to_string();
+    run(
+        device,
+        command,
+        dry_run,
+        vec![source.to_string(), target],
+        replaced,
+    )
+}
+
+/// Copies `source`, recursively if it is a directory, keeping modes and
+/// times. See [`resolve_target`] for where the copy ends up.
+pub(crate) fn copy_path(
+    device: &mut dyn AdbDevice,
+    source: &str,
+    dest: &str,
+    overwrite: bool,
+    dry_run: bool,
+) -> Result<FileOpResult, DroidKitError> {
+    let (target, replaced) = resolve_target(device, source, dest, overwrite)?;
+    let command = ShellCommand::new("cp")
+        .arg("-Rp")
+        .arg(source)
+        .arg(&target)
+        .to_string();
+    run(
+        device,
+        command,
+        dry_run,
+        vec![source.to_string(), target],
+        replaced,
+    )
+}
+
+/// `mkdir -p`: creates `path` and any missing parents.
+pub(crate) fn make_directory(
+    device: &mut dyn AdbDevice,
+    path: &str,
+) -> Result<FileOpResult, DroidKitError> {
+    let path = absolute(path)?;
+    let command = ShellCommand::new("mkdir").arg("-p").arg(path).to_string();
+    run(device, command, false, vec![path.to_string()], false)
+}
+
+/// An octal mode, or symbolic clauses such as `u+x,go-w`.
+fn is_valid_mode(mode: &str) -> bool {
+    let octal = (3..=4).contains(&mode.len()) && mode.bytes().all(|b| (b'0'..=b'7').contains(&b));
+    let symbolic = !mode.starts_with('-')
+        && mode.split(',').all(|clause| {
+            let action = clause.trim_start_matches(['u', 'g', 'o', 'a']);
+            action.starts_with(['+', '-', '=']) && action.bytes().all(|b| b"+-=rwxXst".contains(&b))
+        });
+    octal || symbolic
+}
+
+pub(crate) fn change_mode(
+    device: &mut dyn AdbDevice,
+    path: &str,
+    mode: &str,
+    recursive: bool,
+) -> Result<FileOpResult, DroidKitError> {
+    let path = absolute(path)?;
+    if !is_valid_mode(mode) {
+        return Err(DroidKitError::InvalidInput(format!(
+            "{:?} is not a file mode",
+            mode
+        )));
+    }
+    let mut command = ShellCommand::new("chmod");
+    if recursive {
+        command = command.arg("-R");
+    }
+    let command = command.arg(mode).arg(path).to_string();
+    run(device, command, false, vec![path.to_string()], false)
+}
+
+/// Creates `path` if missing and sets its times to `mod_time`, or to now.
+pub(crate) fn touch_path(
+    device: &mut dyn AdbDevice,
+    path: &str,
+    mod_time: Option<u32>,
+) -> Result<FileOpResult, DroidKitError> {
+    let path = absolute(path)?;
+    let mut command = ShellCommand::new("touch");
+    if let Some(mod_time) = mod_time {
+        command = command.arg("-d").arg(format!("@{}", mod_time));
+    }
+    let command = command.arg(path).to_string();
+    run(device, command, false, vec![path.to_string()], false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::FakeDevice;
+
+    fn ran(device: &FakeDevice) -> Vec<&str> {
+        device
+            .calls
+            .iter()
+            .filter(|call| !call.starts_with("STAT "))
+            .map(String::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn delete_dry_run_lists_without_deleting() {
+        let mut device = FakeDevice::new()
+            .with_file("/sdcard/Old Photos", 0o040771, 4096, 0)
+            .with_shell(
+                "find '/sdcard/Old Photos' -depth",
+                "/sdcard/Old Photos/a.jpg\n/sdcard/Old Photos/trip/b.jpg\n\
+                 /sdcard/Old Photos/trip\n/sdcard/Old Photos\n",
+            )
+            .with_shell("rm -rf '/sdcard/Old Photos'", "");
+
+        let plan = delete_path(&mut device, "/sdcard/Old Photos/", true, true).unwrap();
+        assert!(plan.dry_run);
+        assert_eq!(plan.command, "rm -rf '/sdcard/Old Photos'");
+        assert_eq!(plan.paths.len(), 4);
+        assert_eq!(plan.paths[3], "/sdcard/Old Photos");
+        assert_eq!(ran(&device), ["find '/sdcard/Old Photos' -depth"]);
+
+        let done = delete_path(&mut device, "/sdcard/Old Photos", true, false).unwrap();
+        assert_eq!(done.paths, ["/sdcard/Old Photos"]);
+        assert_eq!(ran(&device).last(), Some(&"rm -rf '/sdcard/Old Photos'"));
+    }
+
+    #[test]
+    fn delete_refuses_what_it_should_not_touch() {
+        let mut device = FakeDevice::new().with_file("/sdcard/DCIM", 0o040771, 4096, 0);
+
+        let err = delete_path(&mut device, "/sdcard/DCIM", false, false).unwrap_err();
+        assert_eq!(err.kind(), "InvalidInput");
+        let err = delete_path(&mut device, "//", true, false).unwrap_err();
+        assert_eq!(err.kind(), "InvalidInput");
+        let err = delete_path(&mut device, "-rf", true, false).unwrap_err();
+        assert_eq!(err.kind(), "InvalidInput");
+        let err = delete_path(&mut device, "/sdcard/missing", true, false).unwrap_err();
+        assert_eq!(err.kind(), "InvalidInput");
+        assert!(ran(&device).is_empty());
+    }
+
+    #[test]
+    fn move_into_a_directory_keeps_the_name() {
+        let mut device = FakeDevice::new()
+            .with_file("/sdcard/it's; reboot.txt", 0o100660, 3, 0)
+            .with_file("/sdcard/Documents", 0o040771, 4096, 0)
+            .with_file("/sdcard/Documents/it's; reboot.txt", 0o100660, 5, 0)
+            .with_shell(
+                r"mv '/sdcard/it'\''s; reboot.txt' '/sdcard/Documents/it'\''s; reboot.txt'",
+                "",
+            );
+
+        let err = move_path(
+            &mut device,
+            "/sdcard/it's; reboot.txt",
+            "/sdcard/Documents",
+            false,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), "AlreadyExists");
+
+        let plan = move_path(
+            &mut device,
+            "/sdcard/it's; reboot.txt",
+            "/sdcard/Documents",
+            true,
+            true,
+        )
+        .unwrap();
+        assert!(plan.replaced);
+        assert_eq!(plan.paths[1], "/sdcard/Documents/it's; reboot.txt");
+        assert!(ran(&device).is_empty());
+
+        move_path(
+            &mut device,
+            "/sdcard/it's; reboot.txt",
+            "/sdcard/Documents/",
+            true,
+            false,
+        )
+        .unwrap();
+        assert_eq!(ran(&device).len(), 1);
+    }
+
+    #[test]
+    fn copy_keeps_modes_and_times() {
+        let mut device = FakeDevice::new()
+            .with_file("/sdcard/Music", 0o040771, 4096, 0)
+            .with_shell("cp -Rp /sdcard/Music /sdcard/Music.bak", "");
+
+        let result = copy_path(
+            &mut device,
+            "/sdcard/Music",
+            "/sdcard/Music.bak",
+            false,
+            false,
+        )
+        .unwrap();
+        assert!(!result.replaced);
+        assert_eq!(ran(&device), ["cp -Rp /sdcard/Music /sdcard/Music.bak"]);
+    }
+
+    #[test]
+    fn chmod_only_takes_modes() {
+        let mut device = FakeDevice::new()
+            .with_shell("chmod -R 0755 /data/local/tmp/bin", "")
+            .with_shell("chmod u+x,go-w /data/local/tmp/run.sh", "");
+
+        change_mode(&mut device, "/data/local/tmp/bin", "0755", true).unwrap();
+        change_mode(&mut device, "/data/local/tmp/run.sh", "u+x,go-w", false).unwrap();
+        for mode in ["999", "--reference=/x", "u+x; reboot", "", "77777"] {
+            let err = change_mode(&mut device, "/data/local/tmp/bin", mode, false).unwrap_err();
+            assert_eq!(err.kind(), "InvalidInput", "{:?}", mode);
+        }
+        assert_eq!(ran(&device).len(), 2);
+    }
+
+    #[test]
+    fn mkdir_and_touch_report_failure() {
+        let mut device = FakeDevice::new()
+            .with_shell("mkdir -p /sdcard/a/b/c", "")
+            .with_shell_failure(
+                "touch -d @1717171717 /system/x",
+                "touch: '/system/x': Read-only file system\n",
+                1,
+            );
+
+        make_directory(&mut device, "/sdcard/a/b/c").unwrap();
+        let err = touch_path(&mut device, "/system/x", Some(1717171717)).unwrap_err();
+        assert_eq!(err.kind(), "CommandFailed");
+    }
+}
@@ -1,6 +1,16 @@
+use adb_client::AdbStatResponse;
 use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
 
-use super::device::Device;
+use super::adb_device::{
+    AdbDevice, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK,
+};
+use super::capabilities::{DeviceCapabilities, LsFlavor};
+use super::command::{ShellCommand, quote};
+use crate::error::DroidKitError;
 
 #[derive(Serialize, Clone, Debug)]
 #[serde(tag = "type")]
@@ -8,102 +18,1002 @@ pub enum FileType {
     File,
     Directory,
     Symlink { target: String },
+    BlockDevice,
+    CharDevice,
+    Socket,
+    Fifo,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone, Debug)]
 pub(crate) struct FileInfo {
-    name: String,
-    dir: String,
-    file_type: FileType,
-    size: Option<u64>,
-    permissions: String,
-}
-
-pub(crate) fn list_files(device: &mut Device, path: &str) -> Result<Vec<FileInfo>, String> {
-    let mut buf: Vec<u8> = Vec::new();
-
-    let result = device.shell_command(&format!("ls -la {}", path), &mut buf);
-
-    match result {
-        Ok(_) => {
-            let output = String::from_utf8_lossy(&buf);
-            let mut files = Vec::new();
-
-            for line in output.lines() {
-                let line = line.trim();
-
-                if line.is_empty() || line.starts_with("total ") {
-                    continue;
-                }
-
-                let parts: Vec<&str> = line.split_whitespace().collect();
-
-                if parts.len() < 8 {
-                    continue;
-                }
-
-                let permissions = parts[0];
-                let size_str = parts[4];
-                let name_part = parts[7..].join(" ");
-
-                let file_info = match permissions.chars().next() {
-                    Some('d') => Some((FileType::Directory, name_part.clone(), None)),
-                    Some('-') => Some((FileType::File, name_part.clone(), size_str.parse().ok())),
-                    Some('l') => {
-                        if let Some(arrow_pos) = name_part.find(" -> ") {
-                            let link_name = name_part[..arrow_pos].to_string();
-                            let target = name_part[arrow_pos + 4..].to_string();
-                            Some((
-                                FileType::Symlink { target },
-                                link_name,
-                                size_str.parse().ok(),
-                            ))
-                        } else {
-                            Some((
-                                FileType::Symlink {
-                                    target: String::new(),
-                                },
-                                name_part.clone(),
-                                size_str.parse().ok(),
-                            ))
-                        }
-                    }
-                    _ => None,
-                };
-
-                if let Some((file_type, name, size)) = file_info {
-                    if name != "." && name != ".." {
-                        files.push(FileInfo {
-                            name,
-                            dir: path.to_string(),
-                            file_type,
-                            size,
-                            permissions: permissions.to_string(),
-                        });
-                    }
-                }
+    pub name: String,
+    pub dir: String,
+    pub file_type: FileType,
+    /// For files and links only.
+    pub size: Option<u64>,
+    pub permissions: String,
+    /// Unix seconds. Missing from toolbox `ls`, which prints the device's
+    /// local time to the minute without saying which zone that is.
+    pub modified: Option<u64>,
+    pub owner: String,
+    pub group: String,
+}
+
+/// `stat -c` format of one listing line. The path goes last, as the only
+/// field that may contain spaces.
+const STAT_FORMAT: &str = "%f %s %Y %U %G %n";
+
+/// `ls -l` style permissions, like `drwxrwx--x` or `-rwsr-x---`.
+fn permission_string(mode: u32) -> String {
+    let mut permissions = String::with_capacity(10);
+    permissions.push(match mode & S_IFMT {
+        S_IFDIR => 'd',
+        S_IFLNK => 'l',
+        S_IFCHR => 'c',
+        S_IFBLK => 'b',
+        S_IFSOCK => 's',
+        S_IFIFO => 'p',
+        _ => '-',
+    });
+    for (shift, special_bit, special) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        permissions.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        permissions.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        permissions.push(match (bits & 0o1 != 0, mode & special_bit != 0) {
+            (true, true) => special,
+            (false, true) => special.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    permissions
+}
+
+/// The [`FileType`] for a type letter of `ls -l`, with an empty link target.
+fn file_type_of(type_char: char) -> Option<FileType> {
+    Some(match type_char {
+        '-' => FileType::File,
+        'd' => FileType::Directory,
+        'l' => FileType::Symlink {
+            target: String::new(),
+        },
+        'b' => FileType::BlockDevice,
+        'c' => FileType::CharDevice,
+        's' => FileType::Socket,
+        'p' => FileType::Fifo,
+        _ => return None,
+    })
+}
+
+/// One parsed `stat -c` [`STAT_FORMAT`] line.
+fn parse_stat_line(line: &str, dir: &str) -> Option<FileInfo> {
+    let mut fields = line.splitn(6, ' ');
+    let mode = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let size: u64 = fields.next()?.parse().ok()?;
+    let modified = fields.next()?.parse().ok()?;
+    let owner = fields.next()?.to_string();
+    let group = fields.next()?.to_string();
+    let name = fields.next()?.rsplit('/').next()?.to_string();
+
+    let permissions = permission_string(mode);
+    let file_type = file_type_of(permissions.chars().next()?)?;
+    let size = matches!(file_type, FileType::File | FileType::Symlink { .. }).then_some(size);
+    Some(FileInfo {
+        name,
+        dir: dir.to_string(),
+        file_type,
+        size,
+        permissions,
+        modified: Some(modified),
+        owner,
+        group,
+    })
+}
+
+/// Lists `path` with `stat`, then reads the targets of its links in one
+/// more round trip.
+fn list_with_stat(device: &mut dyn AdbDevice, path: &str) -> Result<Vec<FileInfo>, DroidKitError> {
+    // -H so a link such as /sdcard is listed rather than returned as itself
+    let command = ShellCommand::new("find")
+        .args(["-H", path, "-mindepth", "1", "-maxdepth", "1"])
+        .args(["-exec", "stat", "-c", STAT_FORMAT, "{}", "+"])
+        .to_string();
+    let output = device.shell_output(&command)?;
+
+    // Unreadable entries are reported on stderr, or mixed into stdout
+    // without shell v2, while the rest are still listed
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut files: Vec<FileInfo> = text
+        .lines()
+        .filter_map(|line| parse_stat_line(line, path))
+        .collect();
+    if files.is_empty() {
+        output.into_result(&command)?;
+        return Ok(files);
+    }
+    read_link_targets(device, path, &mut files)?;
+    Ok(files)
+}
+
+/// Fills in the target of every link among `files`, listed from `dir`, with
+/// one `readlink` per link in a single shell loop.
+fn read_link_targets(
+    device: &mut dyn AdbDevice,
+    dir: &str,
+    files: &mut [FileInfo],
+) -> Result<(), DroidKitError> {
+    let mut links: Vec<&mut FileInfo> = files
+        .iter_mut()
+        .filter(|file| matches!(file.file_type, FileType::Symlink { .. }))
+        .collect();
+    if links.is_empty() {
+        return Ok(());
+    }
+
+    let paths: Vec<String> = links
+        .iter()
+        .map(|link| quote(&format!("{}/{}", dir.trim_end_matches('/'), link.name)))
+        .collect();
+    // One line per link, in order, even where `readlink` fails
+    let command = format!(
+        "for f in {}; do readlink \"$f\" || echo; done",
+        paths.join(" ")
+    );
+    let output = device.shell_output(&command)?.into_result(&command)?;
+    let targets = String::from_utf8_lossy(&output.stdout);
+    for (link, target) in links.iter_mut().zip(targets.lines()) {
+        link.file_type = FileType::Symlink {
+            target: target.to_string(),
+        };
+    }
+    Ok(())
+}
+
+/// The columns of one `ls -la` line. `name` runs to the end of the line, so
+/// only leading spaces are lost.
+struct LsLine<'a> {
+    permissions: &'a str,
+    owner: &'a str,
+    group: &'a str,
+    size: Option<&'a str>,
+    name: &'a str,
+}
+
+/// The rest of `line` from its `n`th whitespace-separated column on.
+fn from_column(line: &str, n: usize) -> Option<&str> {
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        let end = rest.find(char::is_whitespace)?;
+        rest = rest[end..].trim_start();
+    }
+    (!rest.is_empty()).then_some(rest)
+}
+
+/// Splits an `ls -la` line into its columns.
+fn split_ls_line(line: &str, flavor: LsFlavor) -> Option<LsLine<'_>> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let type_char = parts.first()?.chars().next()?;
+    // Devices show `major, minor` where the size would be
+    let is_device = matches!(type_char, 'b' | 'c');
+
+    match flavor {
+        // drwxrwx--x  2 root sdcard_rw    4096 2021-03-01 09:12 Alarms
+        // crw-rw-rw-  1 root root      1,   3 2024-05-12 09:13 null
+        LsFlavor::Toybox => {
+            let name_at = if is_device { 8 } else { 7 };
+            if parts.len() <= name_at {
+                return None;
+            }
+            Some(LsLine {
+                permissions: parts[0],
+                owner: parts[2],
+                group: parts[3],
+                size: (!is_device).then(|| parts[4]),
+                name: from_column(line, name_at)?,
+            })
+        }
+        // drwxrwx--- root     sdcard_r          2015-06-01 10:00 Alarms
+        // -rw-rw---- root     sdcard_r     1234 2015-06-01 10:00 notes.txt
+        // crw-rw-rw- root     root       1,   3 2015-06-01 10:00 null
+        LsFlavor::Toolbox => {
+            let has_size = matches!(type_char, '-' | 'l');
+            let name_at = if is_device {
+                7
+            } else if has_size {
+                6
+            } else {
+                5
+            };
+            if parts.len() <= name_at {
+                return None;
             }
+            Some(LsLine {
+                permissions: parts[0],
+                owner: parts[1],
+                group: parts[2],
+                size: has_size.then(|| parts[3]),
+                name: from_column(line, name_at)?,
+            })
+        }
+    }
+}
+
+/// Lists `path` from `ls -la`, for devices whose `stat` takes no format.
+fn list_with_ls(
+    device: &mut dyn AdbDevice,
+    ls: LsFlavor,
+    path: &str,
+) -> Result<Vec<FileInfo>, DroidKitError> {
+    let command = ShellCommand::new("ls").arg("-la").arg(path).to_string();
+    let output = device.shell_output(&command)?;
+
+    // `ls` exits 1 when any one entry cannot be read, so like `stat` above,
+    // only fail when nothing was listed
+    let text = String::from_utf8_lossy(&output.stdout).into_owned();
+    let mut files = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with("total ") {
+            continue;
+        }
+        let Some(columns) = split_ls_line(line, ls) else {
+            continue;
+        };
+        let Some(mut file_type) = columns.permissions.chars().next().and_then(file_type_of) else {
+            continue;
+        };
 
-            Ok(files)
+        let mut name = columns.name.to_string();
+        if let FileType::Symlink { target } = &mut file_type
+            && let Some((link_name, link_target)) = columns.name.split_once(" -> ")
+        {
+            name = link_name.to_string();
+            *target = link_target.to_string();
         }
-        Err(e) => Err(format!("Failed to list files: {:?}", e)),
+        if name == "." || name == ".." {
+            continue;
+        }
+
+        let size = match file_type {
+            FileType::File | FileType::Symlink { .. } => {
+                columns.size.and_then(|size| size.parse().ok())
+            }
+            _ => None,
+        };
+        files.push(FileInfo {
+            name,
+            dir: path.to_string(),
+            file_type,
+            size,
+            permissions: columns.permissions.to_string(),
+            modified: None,
+            owner: columns.owner.to_string(),
+            group: columns.group.to_string(),
+        });
     }
+    if files.is_empty() {
+        output.into_result(&command)?;
+    }
+    Ok(files)
+}
+
+/// Lists the directory `path`, following it if it is a link, without `.`
+/// and `..`.
+pub(crate) fn list_files(
+    device: &mut dyn AdbDevice,
+    capabilities: &DeviceCapabilities,
+    path: &str,
+) -> Result<Vec<FileInfo>, DroidKitError> {
+    let files = if capabilities.stat_format {
+        list_with_stat(device, path)
+    } else {
+        list_with_ls(device, capabilities.ls, path)
+    };
+    files.map_err(|e| e.context("Failed to list files"))
 }
 
+/// Progress of one file being copied to or from the device.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct TransferProgress {
+    pub remote_path: String,
+    pub bytes_transferred: u64,
+    /// For downloads from `STAT`, which reports sizes modulo 4 GiB.
+    pub total_bytes: u64,
+}
+
+const PROGRESS_EVERY: u64 = 1024 * 1024;
+
+/// Counts the bytes of one transfer, reporting every [`PROGRESS_EVERY`] of them.
+struct Progress<'a, F: FnMut(TransferProgress)> {
+    remote_path: &'a str,
+    bytes_transferred: u64,
+    reported: u64,
+    total_bytes: u64,
+    on_progress: F,
+}
+
+impl<'a, F: FnMut(TransferProgress)> Progress<'a, F> {
+    fn new(remote_path: &'a str, total_bytes: u64, on_progress: F) -> Self {
+        Progress {
+            remote_path,
+            bytes_transferred: 0,
+            reported: 0,
+            total_bytes,
+            on_progress,
+        }
+    }
+
+    fn report(&mut self) {
+        self.reported = self.bytes_transferred;
+        (self.on_progress)(TransferProgress {
+            remote_path: self.remote_path.to_string(),
+            bytes_transferred: self.bytes_transferred,
+            total_bytes: self.total_bytes,
+        });
+    }
+
+    fn add(&mut self, bytes: usize) {
+        self.bytes_transferred += bytes as u64;
+        if self.bytes_transferred - self.reported >= PROGRESS_EVERY {
+            self.report();
+        }
+    }
+
+    /// Reports the final count, unless that was the last report.
+    fn finish(&mut self) {
+        if self.reported != self.bytes_transferred || self.bytes_transferred == 0 {
+            self.report();
+        }
+    }
+}
+
+struct ProgressWriter<'a, W: Write, F: FnMut(TransferProgress)> {
+    inner: W,
+    progress: Progress<'a, F>,
+}
+
+impl<W: Write, F: FnMut(TransferProgress)> Write for ProgressWriter<'_, W, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.progress.add(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct ProgressReader<'a, R: Read, F: FnMut(TransferProgress)> {
+    inner: R,
+    progress: Progress<'a, F>,
+}
+
+impl<R: Read, F: FnMut(TransferProgress)> Read for ProgressReader<'_, R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.add(read);
+        Ok(read)
+    }
+}
+
+/// Gives the local copy the device file's modification time and, where the
+/// platform has them, its permission bits.
+fn copy_metadata(file: &File, stat: &AdbStatResponse) -> std::io::Result<()> {
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(u64::from(stat.mod_time)))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(stat.file_perm & 0o7777))?;
+    }
+    Ok(())
+}
+
+/// Added to the name of a file being pulled until it is complete.
+const PARTIAL_SUFFIX: &str = ".droidkit-partial";
+
+/// Copies `remote_path` to `local_path` over the sync protocol, streaming it
+/// to disk. A failed copy leaves no partial file behind, and an existing
+/// `local_path` untouched.
 pub(crate) fn pull_file(
-    device: &mut Device,
+    device: &mut dyn AdbDevice,
     remote_path: &str,
     local_path: &str,
-) -> Result<(), String> {
-    let mut buf: Vec<u8> = Vec::new();
+    on_progress: impl FnMut(TransferProgress),
+) -> Result<(), DroidKitError> {
+    let stat = device.stat(remote_path)?;
+    match stat.file_perm & S_IFMT {
+        S_IFREG => {}
+        0 => {
+            return Err(DroidKitError::InvalidInput(format!(
+                "{} does not exist on the device",
+                remote_path
+            )));
+        }
+        S_IFDIR => {
+            return Err(DroidKitError::InvalidInput(format!(
+                "{} is a directory",
+                remote_path
+            )));
+        }
+        _ => {
+            return Err(DroidKitError::InvalidInput(format!(
+                "{} is not a regular file",
+                remote_path
+            )));
+        }
+    }
+
+    // Written next to the target and renamed over it once complete, so a
+    // failed pull leaves any previous copy as it was
+    let partial_path = format!("{}{}", local_path, PARTIAL_SUFFIX);
+    let file = File::create(&partial_path)
+        .map_err(|e| DroidKitError::Io(format!("Failed to create file: {}", e)))?;
+    let mut writer = ProgressWriter {
+        inner: BufWriter::new(file),
+        progress: Progress::new(remote_path, u64::from(stat.file_size), on_progress),
+    };
 
-    let result = device.shell_command(&format!("cat {}", remote_path), &mut buf);
+    let result = device
+        .pull(remote_path, &mut writer)
+        .map_err(|e| e.context("Failed to pull file"))
+        .and_then(|()| {
+            writer.progress.finish();
+            let file = writer
+                .inner
+                .into_inner()
+                .map_err(|e| DroidKitError::Io(format!("Failed to write file: {}", e)))?;
+            copy_metadata(&file, &stat).map_err(|e| {
+                DroidKitError::Io(format!("Failed to set file time and mode: {}", e))
+            })?;
+            drop(file);
+            std::fs::rename(&partial_path, local_path)
+                .map_err(|e| DroidKitError::Io(format!("Failed to move file into place: {}", e)))
+        });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial_path);
+    }
+    result
+}
+
+/// The permission bits of a local file or directory.
+#[cfg(unix)]
+pub(crate) fn local_permissions(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
 
-    match result {
-        Ok(_) => {
-            std::fs::write(local_path, buf).map_err(|e| format!("Failed to write file: {}", e))?;
-            Ok(())
+/// Read-write, or read-only, for everyone where there are no permission bits.
+#[cfg(not(unix))]
+pub(crate) fn local_permissions(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o666
+    }
+}
+
+/// The modification time of a local file in the sync protocol's seconds, or
+/// 0 where it has none that fits.
+pub(crate) fn local_mod_time(metadata: &std::fs::Metadata) -> u32 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .and_then(|since_epoch| u32::try_from(since_epoch.as_secs()).ok())
+        .unwrap_or_default()
+}
+
+/// Copies `local_path` to `remote_path` over the sync protocol with the local
+/// file's mode and modification time. A `remote_path` that is a directory,
+/// or ends in `/`, receives the file under its own name. Refuses to replace
+/// an existing file unless `overwrite`. Returns where the file went.
+pub(crate) fn push_file(
+    device: &mut dyn AdbDevice,
+    local_path: &str,
+    remote_path: &str,
+    overwrite: bool,
+    on_progress: impl FnMut(TransferProgress),
+) -> Result<String, DroidKitError> {
+    let metadata = std::fs::metadata(local_path)
+        .map_err(|e| DroidKitError::from(e).context(&format!("Failed to read {}", local_path)))?;
+    if !metadata.is_file() {
+        return Err(DroidKitError::InvalidInput(format!(
+            "{} is not a regular file",
+            local_path
+        )));
+    }
+
+    let mut target = remote_path.to_string();
+    if target.ends_with('/') || device.stat(&target)?.file_perm & S_IFMT == S_IFDIR {
+        let name = Path::new(local_path)
+            .file_name()
+            .ok_or_else(|| DroidKitError::InvalidInput(format!("{} has no name", local_path)))?;
+        target = format!(
+            "{}/{}",
+            target.trim_end_matches('/'),
+            name.to_string_lossy()
+        );
+    }
+    push_file_as(
+        device,
+        local_path,
+        &metadata,
+        &target,
+        overwrite,
+        on_progress,
+    )?;
+    Ok(target)
+}
+
+/// [`push_file`] to exactly `target`, for a file already known to be regular.
+pub(crate) fn push_file_as(
+    device: &mut dyn AdbDevice,
+    local_path: &str,
+    metadata: &std::fs::Metadata,
+    target: &str,
+    overwrite: bool,
+    on_progress: impl FnMut(TransferProgress),
+) -> Result<(), DroidKitError> {
+    match device.stat(target)?.file_perm & S_IFMT {
+        0 => {}
+        S_IFDIR => {
+            return Err(DroidKitError::InvalidInput(format!(
+                "{} is a directory",
+                target
+            )));
+        }
+        _ if !overwrite => {
+            return Err(DroidKitError::AlreadyExists(format!(
+                "{} already exists on the device",
+                target
+            )));
+        }
+        _ => {}
+    }
+
+    let file = File::open(local_path)
+        .map_err(|e| DroidKitError::from(e).context(&format!("Failed to open {}", local_path)))?;
+    let mut reader = ProgressReader {
+        inner: file,
+        progress: Progress::new(target, metadata.len(), on_progress),
+    };
+
+    device
+        .push(
+            &mut reader,
+            target,
+            S_IFREG | local_permissions(metadata),
+            local_mod_time(metadata),
+        )
+        .map_err(|e| e.context("Failed to push file"))?;
+    reader.progress.finish();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::{FakeDevice, fixture, unique_temp_dir};
+
+    fn names(files: &[FileInfo]) -> Vec<&str> {
+        files.iter().map(|file| file.name.as_str()).collect()
+    }
+
+    fn file<'a>(files: &'a [FileInfo], name: &str) -> &'a FileInfo {
+        files
+            .iter()
+            .find(|file| file.name == name)
+            .unwrap_or_else(|| panic!("no entry {}", name))
+    }
+
+    #[test]
+    fn list_files_android_9_sdcard() {
+        let mut device = FakeDevice::new()
+            .with_shell("ls -la /sdcard/", fixture("android-9", "ls_la_sdcard.txt"));
+        let files = list_files(&mut device, &DeviceCapabilities::default(), "/sdcard/").unwrap();
+
+        assert_eq!(files.len(), 12);
+        assert!(!names(&files).contains(&"."));
+        assert!(!names(&files).contains(&".."));
+
+        let dcim = file(&files, "DCIM");
+        assert!(matches!(dcim.file_type, FileType::Directory));
+        assert_eq!(dcim.size, None);
+        assert_eq!(dcim.dir, "/sdcard/");
+
+        let bugreport = file(
+            &files,
+            "bugreport-walleye-PQ3A.190801.002-2021-06-12-14-02-11.zip",
+        );
+        assert!(matches!(bugreport.file_type, FileType::File));
+        assert_eq!(bugreport.size, Some(1532087));
+        assert_eq!(bugreport.permissions, "-rw-rw----");
+
+        assert_eq!(file(&files, "my notes.txt").size, Some(48));
+    }
+
+    #[test]
+    fn list_files_android_11_scoped_storage() {
+        let mut device = FakeDevice::new()
+            .with_shell("ls -la /sdcard/", fixture("android-11", "ls_la_sdcard.txt"));
+        let files = list_files(&mut device, &DeviceCapabilities::default(), "/sdcard/").unwrap();
+
+        assert_eq!(files.len(), 13);
+        assert_eq!(file(&files, "Android").permissions, "drwxrws--x");
+        assert_eq!(file(&files, "app-debug.apk").size, Some(3145728));
+    }
+
+    #[test]
+    fn list_files_android_14_root_symlinks() {
+        let mut device =
+            FakeDevice::new().with_shell("ls -la /", fixture("android-14", "ls_la_root.txt"));
+        let files = list_files(&mut device, &DeviceCapabilities::default(), "/").unwrap();
+
+        assert_eq!(files.len(), 20);
+
+        let sdcard = file(&files, "sdcard");
+        match &sdcard.file_type {
+            FileType::Symlink { target } => assert_eq!(target, "/storage/self/primary"),
+            other => panic!("sdcard is {:?}", other),
+        }
+
+        let init_rc = file(&files, "init.environ.rc");
+        assert!(matches!(init_rc.file_type, FileType::File));
+        assert_eq!(init_rc.size, Some(2244));
+
+        assert!(matches!(
+            file(&files, "proc").file_type,
+            FileType::Directory
+        ));
+    }
+
+    #[test]
+    fn list_files_android_5_toolbox() {
+        let capabilities = DeviceCapabilities {
+            ls: LsFlavor::Toolbox,
+            ..Default::default()
+        };
+        let mut device = FakeDevice::new()
+            .with_shell("ls -la /sdcard/", fixture("android-5", "ls_la_sdcard.txt"));
+        let files = list_files(&mut device, &capabilities, "/sdcard/").unwrap();
+
+        assert_eq!(files.len(), 12);
+
+        let dcim = file(&files, "DCIM");
+        assert!(matches!(dcim.file_type, FileType::Directory));
+        assert_eq!(dcim.size, None);
+
+        let apk = file(&files, "app-release.apk");
+        assert!(matches!(apk.file_type, FileType::File));
+        assert_eq!(apk.size, Some(412876));
+        assert_eq!(apk.permissions, "-rw-rw----");
+
+        assert_eq!(file(&files, "my notes.txt").size, Some(48));
+    }
+
+    #[test]
+    fn hostile_paths_are_passed_as_one_argument() {
+        let mut device = FakeDevice::new();
+        let _ = list_files(
+            &mut device,
+            &DeviceCapabilities::default(),
+            "/sdcard/it's; reboot",
+        );
+        let _ = list_files(
+            &mut device,
+            &DeviceCapabilities::default(),
+            "/sdcard/$(id) `id`",
+        );
+        assert_eq!(
+            device.calls,
+            [
+                r"ls -la '/sdcard/it'\''s; reboot'",
+                "ls -la '/sdcard/$(id) `id`'"
+            ]
+        );
+    }
+
+    #[test]
+    fn pull_file_streams_over_sync() {
+        // Line endings a shell could have translated, and more than one
+        // progress report's worth
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend((0..1_500_000u32).map(|i| (i % 251) as u8));
+        let remote_path = "/sdcard/DCIM/Camera/my \"photo\"; rm -rf ~.png";
+        let mut device =
+            FakeDevice::new().with_file_data(remote_path, 0o640, data.clone(), 1717171717);
+        let local = unique_temp_dir("pull-sync").join("photo.png");
+
+        let mut progress = Vec::new();
+        pull_file(&mut device, remote_path, &local.to_string_lossy(), |p| {
+            progress.push(p.bytes_transferred)
+        })
+        .unwrap();
+
+        assert_eq!(std::fs::read(&local).unwrap(), data);
+        assert_eq!(progress, [1_048_576, data.len() as u64]);
+        assert_eq!(
+            device.calls,
+            [
+                format!("STAT {}", remote_path),
+                format!("RECV {}", remote_path)
+            ]
+        );
+
+        let metadata = std::fs::metadata(&local).unwrap();
+        assert_eq!(
+            metadata.modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1717171717)
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
         }
-        Err(e) => Err(format!("Failed to pull file: {:?}", e)),
+        let _ = std::fs::remove_file(&local);
+    }
+
+    #[test]
+    fn pull_file_removes_a_partial_copy() {
+        // Listed by STAT, but the fake has nothing to send
+        let mut device = FakeDevice::new().with_file("/sdcard/big.bin", 0o100660, 4096, 0);
+        let dir = unique_temp_dir("pull-partial");
+        let local = dir.join("big.bin");
+
+        let err = pull_file(
+            &mut device,
+            "/sdcard/big.bin",
+            &local.to_string_lossy(),
+            |_| {},
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("Failed to pull file"));
+        assert!(!local.exists());
+
+        // A copy already there survives a failed pull over it
+        std::fs::write(&local, b"previous copy").unwrap();
+        pull_file(
+            &mut device,
+            "/sdcard/big.bin",
+            &local.to_string_lossy(),
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(std::fs::read(&local).unwrap(), b"previous copy");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn push_file_keeps_mode_and_time() {
+        let local = unique_temp_dir("push").join("droidkit-push-test.txt");
+        std::fs::write(&local, b"line one\r\nline two\n").unwrap();
+        let file = File::options().write(true).open(&local).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(1717171717))
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o640))
+                .unwrap();
+        }
+        let mut device = FakeDevice::new().with_file("/sdcard/Download", 0o040771, 4096, 0);
+
+        let mut progress = Vec::new();
+        let target = push_file(
+            &mut device,
+            &local.to_string_lossy(),
+            "/sdcard/Download",
+            false,
+            |p| progress.push((p.bytes_transferred, p.total_bytes)),
+        )
+        .unwrap();
+        let _ = std::fs::remove_file(&local);
+
+        assert_eq!(target, "/sdcard/Download/droidkit-push-test.txt");
+        assert_eq!(progress, [(19, 19)]);
+        let stat = device.stat(&target).unwrap();
+        assert_eq!(stat.mod_time, 1717171717);
+        assert_eq!(stat.file_size, 19);
+        #[cfg(unix)]
+        assert_eq!(stat.file_perm, 0o100640);
+
+        let mut data = Vec::new();
+        device.pull(&target, &mut data).unwrap();
+        assert_eq!(data, b"line one\r\nline two\n");
+    }
+
+    #[test]
+    fn push_file_refuses_to_overwrite() {
+        let local = unique_temp_dir("push-overwrite").join("notes.txt");
+        std::fs::write(&local, b"new").unwrap();
+        let local_path = local.to_string_lossy();
+        let mut device =
+            FakeDevice::new().with_file_data("/sdcard/notes.txt", 0o660, b"old".to_vec(), 0);
+
+        let err =
+            push_file(&mut device, &local_path, "/sdcard/notes.txt", false, |_| {}).unwrap_err();
+        assert_eq!(err.kind(), "AlreadyExists");
+        assert!(!device.calls.iter().any(|call| call.starts_with("SEND ")));
+
+        push_file(&mut device, &local_path, "/sdcard/notes.txt", true, |_| {}).unwrap();
+        let mut data = Vec::new();
+        device.pull("/sdcard/notes.txt", &mut data).unwrap();
+        assert_eq!(data, b"new");
+        let _ = std::fs::remove_file(&local);
+    }
+
+    fn stat_capable() -> DeviceCapabilities {
+        DeviceCapabilities {
+            stat_format: true,
+            ..Default::default()
+        }
+    }
+
+    fn stat_listing(dir: &str) -> String {
+        format!(
+            "find -H {} -mindepth 1 -maxdepth 1 -exec stat -c '%f %s %Y %U %G %n' '{{}}' +",
+            dir
+        )
+    }
+
+    #[test]
+    fn list_files_android_9_stat_sdcard() {
+        let mut device = FakeDevice::new().with_shell(
+            &stat_listing("/sdcard"),
+            fixture("android-9", "stat_sdcard.txt"),
+        );
+        let files = list_files(&mut device, &stat_capable(), "/sdcard").unwrap();
+
+        assert_eq!(files.len(), 13);
+        let dcim = file(&files, "DCIM");
+        assert!(matches!(dcim.file_type, FileType::Directory));
+        assert_eq!(dcim.permissions, "drwxrwx--x");
+        assert_eq!(dcim.modified, Some(1624361201));
+        assert_eq!(
+            (dcim.owner.as_str(), dcim.group.as_str()),
+            ("root", "sdcard_rw")
+        );
+        assert_eq!(dcim.size, None);
+        assert_eq!(dcim.dir, "/sdcard");
+
+        assert_eq!(file(&files, "  two leading spaces.txt").size, Some(12));
+        assert!(matches!(
+            file(&files, "a  b -> c.txt").file_type,
+            FileType::File
+        ));
+        assert_eq!(file(&files, ".nomedia").permissions, "-rw-rw----");
+        // No links, so no second round trip
+        assert_eq!(device.calls.len(), 1);
+    }
+
+    #[test]
+    fn list_files_android_14_stat_dev() {
+        let mut device = FakeDevice::new()
+            .with_shell(&stat_listing("/dev"), fixture("android-14", "stat_dev.txt"))
+            .with_shell(
+                "for f in /dev/fd; do readlink \"$f\" || echo; done",
+                "/proc/self/fd\n",
+            );
+        let files = list_files(&mut device, &stat_capable(), "/dev").unwrap();
+
+        assert_eq!(files.len(), 15);
+        let null = file(&files, "null");
+        assert!(matches!(null.file_type, FileType::CharDevice));
+        assert_eq!(null.permissions, "crw-rw-rw-");
+        assert_eq!(null.size, None);
+        assert!(matches!(
+            file(&files, "loop-control").file_type,
+            FileType::BlockDevice
+        ));
+        assert_eq!(file(&files, "kmsg").group, "system");
+        match &file(&files, "fd").file_type {
+            FileType::Symlink { target } => assert_eq!(target, "/proc/self/fd"),
+            other => panic!("fd is {:?}", other),
+        }
+        assert_eq!(file(&files, "__properties__").permissions, "drwx--x--x");
+    }
+
+    #[test]
+    fn list_files_android_11_stat_sockets() {
+        let mut device = FakeDevice::new().with_shell(
+            &stat_listing("/dev/socket"),
+            fixture("android-11", "stat_dev_socket.txt"),
+        );
+        let files = list_files(&mut device, &stat_capable(), "/dev/socket").unwrap();
+
+        assert_eq!(files.len(), 7);
+        let logdw = file(&files, "logdw");
+        assert!(matches!(logdw.file_type, FileType::Socket));
+        assert_eq!(logdw.permissions, "srw-rw-rw-");
+        assert_eq!(logdw.owner, "logd");
+        assert!(matches!(
+            file(&files, ".event-pipe").file_type,
+            FileType::Fifo
+        ));
+        assert_eq!(file(&files, "qemud").group, "radio");
+    }
+
+    #[test]
+    fn list_files_stat_reports_unreadable_directories() {
+        let mut device = FakeDevice::new().with_shell_failure(
+            &stat_listing("/data"),
+            "find: /data: Permission denied\n",
+            1,
+        );
+        let err = list_files(&mut device, &stat_capable(), "/data").unwrap_err();
+        assert_eq!(err.kind(), "CommandFailed");
+    }
+
+    #[test]
+    fn list_files_android_5_toolbox_devices() {
+        let capabilities = DeviceCapabilities {
+            ls: LsFlavor::Toolbox,
+            ..Default::default()
+        };
+        let mut device =
+            FakeDevice::new().with_shell("ls -la /dev", fixture("android-5", "ls_la_dev.txt"));
+        let files = list_files(&mut device, &capabilities, "/dev").unwrap();
+
+        assert_eq!(files.len(), 8);
+        let ashmem = file(&files, "ashmem");
+        assert!(matches!(ashmem.file_type, FileType::CharDevice));
+        assert_eq!(ashmem.size, None);
+        assert_eq!(ashmem.modified, None);
+        assert_eq!(file(&files, "kmsg").group, "system");
+        assert!(matches!(
+            file(&files, "socket").file_type,
+            FileType::Directory
+        ));
+    }
+
+    #[test]
+    fn permission_strings_show_special_bits() {
+        assert_eq!(permission_string(0o041777), "drwxrwxrwt");
+        assert_eq!(permission_string(0o106750), "-rwsr-s---");
+        assert_eq!(permission_string(0o104644), "-rwSr--r--");
+        assert_eq!(permission_string(0o120777), "lrwxrwxrwx");
+    }
+
+    #[test]
+    fn list_files_keeps_what_ls_listed_when_an_entry_is_unreadable() {
+        let capabilities = DeviceCapabilities {
+            ls: LsFlavor::Toolbox,
+            ..Default::default()
+        };
+        // Toolbox `ls` exits 1 for one unreadable entry but lists the rest
+        let mut device = FakeDevice::new().with_shell_status(
+            "ls -la /dev",
+            fixture("android-5", "ls_la_dev.txt"),
+            Some(1),
+        );
+        let files = list_files(&mut device, &capabilities, "/dev").unwrap();
+        assert_eq!(files.len(), 8);
+    }
+
+    #[test]
+    fn list_files_fails_when_ls_does() {
+        let mut device = FakeDevice::new().with_shell_failure(
+            "ls -la /data",
+            "ls: /data: Permission denied\n",
+            1,
+        );
+        let err = list_files(&mut device, &DeviceCapabilities::default(), "/data").unwrap_err();
+        assert_eq!(err.kind(), "CommandFailed");
+    }
+
+    #[test]
+    fn pull_file_rejects_directories_and_missing_paths() {
+        let mut device = FakeDevice::new().with_file("/sdcard/DCIM", 0o040771, 4096, 0);
+        let local = unique_temp_dir("pull-rejects").join("pulled");
+        let local = local.to_string_lossy();
+
+        let err = pull_file(&mut device, "/sdcard/DCIM", &local, |_| {}).unwrap_err();
+        assert_eq!(err.kind(), "InvalidInput");
+
+        let err = pull_file(&mut device, "/sdcard/missing.txt", &local, |_| {}).unwrap_err();
+        assert_eq!(err.kind(), "InvalidInput");
+        assert!(!device.calls.iter().any(|call| call.starts_with("RECV ")));
     }
 }
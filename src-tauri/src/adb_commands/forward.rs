@@ -0,0 +1,393 @@
+//! `adb forward` and `adb reverse` rules, remembered per device so they
+//! survive the device dropping off and coming back.
+//!
+//! Only devices reached through the adb server can forward: the server is
+//! what listens on this computer and relays each connection. Devices opened
+//! directly over USB or TCP fail with [`DroidKitError::InvalidInput`].
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::device::Device;
+use super::server_protocol;
+use crate::error::DroidKitError;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ForwardDirection {
+    /// Connections to `local` on this computer reach `remote` on the device.
+    Forward,
+    /// Connections to `remote` on the device reach `local` on this computer.
+    Reverse,
+}
+
+/// One rule, with both ends as adb socket specs such as `tcp:8081` or
+/// `localabstract:chrome_devtools_remote`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct PortRule {
+    pub direction: ForwardDirection,
+    pub local: String,
+    pub remote: String,
+}
+
+const NAMED_SOCKETS: &[&str] = &[
+    "localabstract:",
+    "localreserved:",
+    "localfilesystem:",
+    "dev:",
+];
+
+fn check_socket_spec(spec: &str, on_device: bool) -> Result<(), DroidKitError> {
+    let invalid = |why: &str| {
+        Err(DroidKitError::InvalidInput(format!(
+            "Invalid socket {:?}: {}",
+            spec, why
+        )))
+    };
+
+    if spec.contains(';') {
+        return invalid("it may not contain ';'");
+    }
+    if let Some(port) = spec.strip_prefix("tcp:") {
+        return match port.parse::<u16>() {
+            Ok(_) => Ok(()),
+            Err(_) => invalid("expected a port number"),
+        };
+    }
+    if let Some(pid) = spec.strip_prefix("jdwp:") {
+        if !on_device {
+            return invalid("jdwp sockets only exist on the device");
+        }
+        return match pid.parse::<u32>() {
+            Ok(_) => Ok(()),
+            Err(_) => invalid("expected a process id"),
+        };
+    }
+    match NAMED_SOCKETS
+        .iter()
+        .find_map(|prefix| spec.strip_prefix(prefix))
+    {
+        Some(name) if !name.is_empty() => Ok(()),
+        Some(_) => invalid("expected a name after the prefix"),
+        None => invalid(
+            "expected tcp:, localabstract:, localreserved:, localfilesystem:, dev: or jdwp:",
+        ),
+    }
+}
+
+impl PortRule {
+    fn validate(&self) -> Result<(), DroidKitError> {
+        check_socket_spec(&self.local, false)?;
+        check_socket_spec(&self.remote, true)
+    }
+}
+
+/// Parses `list-forward` output, `<serial> <from> <to>` per line. For
+/// reverse rules `from` is the device's end.
+fn parse_rule_list(
+    output: &str,
+    direction: ForwardDirection,
+    serial_no: Option<&str>,
+) -> Vec<PortRule> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (serial, from, to) = (fields.next()?, fields.next()?, fields.next()?);
+            // The server lists the forwards of every device
+            if direction == ForwardDirection::Forward
+                && serial_no.is_some_and(|serial_no| serial_no != serial)
+            {
+                return None;
+            }
+            let (local, remote) = match direction {
+                ForwardDirection::Forward => (from, to),
+                ForwardDirection::Reverse => (to, from),
+            };
+            Some(PortRule {
+                direction,
+                local: local.to_string(),
+                remote: remote.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The port picked for a `tcp:0` end, sent after the final OKAY.
+fn read_allocated_port(stream: &mut impl Read) -> Result<u16, DroidKitError> {
+    let block = server_protocol::read_hex_block(stream)?;
+    let port = String::from_utf8_lossy(&block);
+    port.trim()
+        .parse()
+        .map_err(|_| DroidKitError::Parse(format!("Allocated port {:?}", port)))
+}
+
+fn host_request(serial_no: Option<&str>, request: &str) -> Result<TcpStream, DroidKitError> {
+    let service = match serial_no {
+        Some(serial_no) => format!("host-serial:{}:{}", serial_no, request),
+        None => format!("host:{}", request),
+    };
+    let mut stream = server_protocol::connect()?;
+    server_protocol::send_request(&mut stream, &service)?;
+    Ok(stream)
+}
+
+impl Device {
+    /// The serial to address the adb server with. Direct USB and TCP
+    /// connections have no server to listen on this computer for us.
+    fn forwarding_serial(&self) -> Result<Option<String>, DroidKitError> {
+        match self {
+            Device::Server(device) => Ok(device.identifier.clone()),
+            Device::USB(_) | Device::TCP(_) => Err(DroidKitError::InvalidInput(
+                "Port forwarding needs the device to be connected through the adb server; \
+                 start `adb start-server` and reconnect it"
+                    .to_string(),
+            )),
+        }
+    }
+
+    /// Creates `rule`, replacing any rule on the same listening end, and
+    /// returns it with a `tcp:0` listening end resolved to the port picked.
+    pub fn add_port_rule(&mut self, rule: &PortRule) -> Result<PortRule, DroidKitError> {
+        rule.validate()?;
+        let serial_no = self.forwarding_serial()?;
+        let mut rule = rule.clone();
+
+        let (mut stream, listening_end) = match rule.direction {
+            ForwardDirection::Forward => {
+                let request = format!("forward:{};{}", rule.local, rule.remote);
+                (
+                    host_request(serial_no.as_deref(), &request)?,
+                    &mut rule.local,
+                )
+            }
+            ForwardDirection::Reverse => {
+                let request = format!("reverse:forward:{};{}", rule.remote, rule.local);
+                let stream = server_protocol::open_device_service(serial_no.as_deref(), &request)?;
+                (stream, &mut rule.remote)
+            }
+        };
+        server_protocol::read_status(&mut stream, "forward")?;
+        if listening_end == "tcp:0" {
+            *listening_end = format!("tcp:{}", read_allocated_port(&mut stream)?);
+        }
+
+        Ok(rule)
+    }
+
+    pub fn list_port_rules(&mut self) -> Result<Vec<PortRule>, DroidKitError> {
+        let serial_no = self.forwarding_serial()?;
+
+        let mut stream = host_request(serial_no.as_deref(), "list-forward")?;
+        let forwards = server_protocol::read_hex_block(&mut stream)?;
+        let mut stream =
+            server_protocol::open_device_service(serial_no.as_deref(), "reverse:list-forward")?;
+        // adbd answers for itself once the server has connected us
+        server_protocol::read_status(&mut stream, "reverse:list-forward")?;
+        let reverses = server_protocol::read_hex_block(&mut stream)?;
+
+        let mut rules = parse_rule_list(
+            &String::from_utf8_lossy(&forwards),
+            ForwardDirection::Forward,
+            serial_no.as_deref(),
+        );
+        rules.extend(parse_rule_list(
+            &String::from_utf8_lossy(&reverses),
+            ForwardDirection::Reverse,
+            serial_no.as_deref(),
+        ));
+        Ok(rules)
+    }
+
+    /// Removes the rule listening where `rule` does.
+    pub fn remove_port_rule(&mut self, rule: &PortRule) -> Result<(), DroidKitError> {
+        let serial_no = self.forwarding_serial()?;
+
+        let mut stream = match rule.direction {
+            ForwardDirection::Forward => {
+                host_request(serial_no.as_deref(), &format!("killforward:{}", rule.local))?
+            }
+            ForwardDirection::Reverse => server_protocol::open_device_service(
+                serial_no.as_deref(),
+                &format!("reverse:killforward:{}", rule.remote),
+            )?,
+        };
+        server_protocol::read_status(&mut stream, "killforward")?;
+        Ok(())
+    }
+}
+
+/// Rules created through DroidKit keyed by device serial, so they can be put
+/// back after a reconnect.
+#[derive(Clone, Default)]
+pub(crate) struct PortRules {
+    rules: Arc<Mutex<HashMap<String, Vec<PortRule>>>>,
+}
+
+impl PortRules {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Vec<PortRule>>> {
+        self.rules.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn listens_on_same_end(a: &PortRule, b: &PortRule) -> bool {
+        a.direction == b.direction
+            && match a.direction {
+                ForwardDirection::Forward => a.local == b.local,
+                ForwardDirection::Reverse => a.remote == b.remote,
+            }
+    }
+
+    pub fn record(&self, serial_no: &str, rule: PortRule) {
+        let mut rules = self.lock();
+        let rules = rules.entry(serial_no.to_string()).or_default();
+        rules.retain(|existing| !Self::listens_on_same_end(existing, &rule));
+        rules.push(rule);
+    }
+
+    pub fn forget(&self, serial_no: &str, rule: &PortRule) {
+        if let Some(rules) = self.lock().get_mut(serial_no) {
+            rules.retain(|existing| !Self::listens_on_same_end(existing, rule));
+        }
+    }
+
+    pub fn rules(&self, serial_no: &str) -> Vec<PortRule> {
+        self.lock().get(serial_no).cloned().unwrap_or_default()
+    }
+
+    /// Re-creates every rule recorded for `serial_no` on a fresh connection.
+    /// Failures are logged rather than failing the connection.
+    pub fn reapply(&self, serial_no: &str, device: &mut Device) {
+        for rule in self.rules(serial_no) {
+            match device.add_port_rule(&rule) {
+                Ok(_) => println!(
+                    "Re-applied {:?} {} -> {} on {}",
+                    rule.direction, rule.local, rule.remote, serial_no
+                ),
+                Err(e) => println!(
+                    "Failed to re-apply {:?} {} -> {} on {}: {}",
+                    rule.direction, rule.local, rule.remote, serial_no, e
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(direction: ForwardDirection, local: &str, remote: &str) -> PortRule {
+        PortRule {
+            direction,
+            local: local.to_string(),
+            remote: remote.to_string(),
+        }
+    }
+
+    #[test]
+    fn socket_specs() {
+        assert!(
+            rule(ForwardDirection::Reverse, "tcp:8081", "tcp:8081")
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            rule(
+                ForwardDirection::Forward,
+                "tcp:9222",
+                "localabstract:chrome_devtools_remote"
+            )
+            .validate()
+            .is_ok()
+        );
+        assert!(
+            rule(ForwardDirection::Forward, "tcp:0", "jdwp:4242")
+                .validate()
+                .is_ok()
+        );
+
+        for (local, remote) in [
+            ("tcp:80800", "tcp:8081"),
+            ("tcp:8081", "localabstract:"),
+            ("jdwp:4242", "tcp:8700"),
+            ("tcp:8081", "tcp:8081;host:kill"),
+            ("8081", "tcp:8081"),
+        ] {
+            let err = rule(ForwardDirection::Forward, local, remote)
+                .validate()
+                .unwrap_err();
+            assert_eq!(err.kind(), "InvalidInput", "{} {}", local, remote);
+        }
+    }
+
+    #[test]
+    fn forward_list_is_filtered_by_serial() {
+        let output = "emulator-5554 tcp:9222 localabstract:chrome_devtools_remote\n\
+                      R58M123ABC tcp:8700 jdwp:4242\n";
+        let rules = parse_rule_list(output, ForwardDirection::Forward, Some("emulator-5554"));
+        assert_eq!(
+            rules,
+            [rule(
+                ForwardDirection::Forward,
+                "tcp:9222",
+                "localabstract:chrome_devtools_remote"
+            )]
+        );
+    }
+
+    #[test]
+    fn reverse_list_puts_the_device_end_remote() {
+        let output = "UsbFfs tcp:8081 tcp:8081\nUsbFfs localabstract:metro tcp:19000\n";
+        let rules = parse_rule_list(output, ForwardDirection::Reverse, Some("emulator-5554"));
+        assert_eq!(
+            rules,
+            [
+                rule(ForwardDirection::Reverse, "tcp:8081", "tcp:8081"),
+                rule(
+                    ForwardDirection::Reverse,
+                    "tcp:19000",
+                    "localabstract:metro"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn recording_replaces_rules_on_the_same_end() {
+        let rules = PortRules::default();
+        rules.record(
+            "emulator-5554",
+            rule(ForwardDirection::Reverse, "tcp:8081", "tcp:8081"),
+        );
+        rules.record(
+            "emulator-5554",
+            rule(ForwardDirection::Reverse, "tcp:8082", "tcp:8081"),
+        );
+        rules.record(
+            "emulator-5554",
+            rule(ForwardDirection::Forward, "tcp:8081", "tcp:8081"),
+        );
+        assert_eq!(
+            rules.rules("emulator-5554"),
+            [
+                rule(ForwardDirection::Reverse, "tcp:8082", "tcp:8081"),
+                rule(ForwardDirection::Forward, "tcp:8081", "tcp:8081"),
+            ]
+        );
+
+        rules.forget(
+            "emulator-5554",
+            &rule(ForwardDirection::Reverse, "tcp:8082", "tcp:8081"),
+        );
+        assert_eq!(rules.rules("emulator-5554").len(), 1);
+        assert!(rules.rules("R58M123ABC").is_empty());
+    }
+
+    #[test]
+    fn allocated_port_follows_the_okay() {
+        let mut reply: &[u8] = b"00045037";
+        assert_eq!(read_allocated_port(&mut reply).unwrap(), 5037);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use tauri::{AppHandle, Emitter};
+
+use super::device::{DeviceInfo, DeviceTransport, adb_server_available, list_usb_devices};
+use super::logcat::get_device_info;
+use super::server_protocol;
+use super::session::DeviceSessions;
+
+pub(crate) const DEVICE_CONNECTED_EVENT: &str = "device-connected";
+pub(crate) const DEVICE_DISCONNECTED_EVENT: &str = "device-disconnected";
+pub(crate) const DEVICE_STATE_CHANGED_EVENT: &str = "device-state-changed";
+
+const USB_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const SERVER_RETRY_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Source {
+    Usb,
+    Server,
+}
+
+struct TrackedDevice {
+    info: DeviceInfo,
+    source: Source,
+    /// The state `source` last reported, before [`DeviceWatcher::describe`]
+    /// turned it into `info.state`. Compared as-is, so a device that fails to
+    /// open is not re-opened on every poll.
+    reported_state: String,
+}
+
+#[derive(Clone)]
+struct DeviceWatcher {
+    app: AppHandle,
+    sessions: DeviceSessions,
+    known: Arc<Mutex<HashMap<String, TrackedDevice>>>,
+}
+
+/// Starts background threads that keep track of attached devices and emit
+/// `device-connected`, `device-disconnected` and `device-state-changed` events.
+///
+/// USB devices are picked up by polling the bus; when an adb server is running,
+/// its `host:track-devices` stream additionally reports emulators, network
+/// devices and authorization state changes.
+pub(crate) fn start_device_watcher(app: AppHandle, sessions: DeviceSessions) {
+    let watcher = DeviceWatcher {
+        app,
+        sessions,
+        known: Arc::default(),
+    };
+
+    let usb_watcher = watcher.clone();
+    thread::spawn(move || usb_watcher.poll_usb());
+    thread::spawn(move || watcher.track_server());
+}
+
+fn placeholder_info(serial_no: &str, transport: DeviceTransport, state: &str) -> DeviceInfo {
+    DeviceInfo {
+        transport,
+        serial_no: serial_no.to_string(),
+        model: String::new(),
+        android_version: String::new(),
+        sdk_version: String::new(),
+        state: state.to_string(),
+    }
+}
+
+impl DeviceWatcher {
+    fn emit(&self, event: &str, info: &DeviceInfo) {
+        if let Err(e) = self.app.emit(event, info.clone()) {
+            println!("Failed to emit {}: {:?}", event, e);
+        }
+    }
+
+    fn describe(&self, serial_no: &str, transport: DeviceTransport, state: &str) -> DeviceInfo {
+        if state != "device" {
+            return placeholder_info(serial_no, transport, state);
+        }
+
+        match self
+            .sessions
+            .with_device(serial_no, |device| get_device_info(device).ok())
+        {
+            Ok(Some(mut info)) => {
+                // Keep the name we were told about so the UI can reconnect with it
+                info.serial_no = serial_no.to_string();
+                info
+            }
+            // Listed but not answering yet (e.g. still waiting for the RSA prompt)
+            _ => placeholder_info(serial_no, transport, "offline"),
+        }
+    }
+
+    /// [`describe`](Self::describe) for a device just connected or back
+    /// online. Its old handle is dropped first, so the fresh connection
+    /// re-applies its port rules, which the adb server forgets along with the
+    /// transport.
+    fn describe_reconnected(
+        &self,
+        serial_no: &str,
+        transport: DeviceTransport,
+        state: &str,
+    ) -> DeviceInfo {
+        if state == "device" {
+            self.sessions.evict(serial_no);
+        }
+        self.describe(serial_no, transport, state)
+    }
+
+    /// Applies a full snapshot of what `source` currently sees. The lock is
+    /// held throughout, so the USB and server threads cannot both announce
+    /// the same device.
+    fn apply_snapshot(&self, source: Source, current: &HashMap<String, String>) {
+        let transport = || match source {
+            Source::Usb => DeviceTransport::USB,
+            Source::Server => DeviceTransport::Server,
+        };
+        let mut known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let gone: Vec<String> = known
+            .iter()
+            .filter(|(serial_no, tracked)| {
+                tracked.source == source && !current.contains_key(*serial_no)
+            })
+            .map(|(serial_no, _)| serial_no.clone())
+            .collect();
+        for serial_no in gone {
+            if let Some(tracked) = known.remove(&serial_no) {
+                self.sessions.evict(&serial_no);
+                self.emit(DEVICE_DISCONNECTED_EVENT, &tracked.info);
+            }
+        }
+
+        for (serial_no, state) in current {
+            let tracked = |info: DeviceInfo| TrackedDevice {
+                info,
+                source,
+                reported_state: state.clone(),
+            };
+            match known.get(serial_no) {
+                Some(previous)
+                    if previous.source == source && previous.reported_state == *state => {}
+                Some(previous) if previous.source == source => {
+                    let previous_state = previous.info.state.clone();
+                    let info = self.describe_reconnected(serial_no, transport(), state);
+                    // A device that still refuses to open keeps its old state
+                    if info.state != previous_state {
+                        self.emit(DEVICE_STATE_CHANGED_EVENT, &info);
+                    }
+                    known.insert(serial_no.clone(), tracked(info));
+                }
+                // The adb server takes precedence: once it owns a USB device,
+                // our direct handle is useless and commands go through the server
+                Some(previous) if previous.source == Source::Usb && source == Source::Server => {
+                    self.sessions.evict(serial_no);
+                    let info = self.describe(serial_no, transport(), state);
+                    self.emit(DEVICE_STATE_CHANGED_EVENT, &info);
+                    known.insert(serial_no.clone(), tracked(info));
+                }
+                Some(_) => {}
+                None => {
+                    let info = self.describe_reconnected(serial_no, transport(), state);
+                    self.emit(DEVICE_CONNECTED_EVENT, &info);
+                    known.insert(serial_no.clone(), tracked(info));
+                }
+            }
+        }
+    }
+
+    fn poll_usb(&self) {
+        loop {
+            match list_usb_devices() {
+                Ok(entries) => {
+                    let current: HashMap<String, String> = entries
+                        .into_iter()
+                        .filter_map(|entry| entry.serial_no)
+                        .map(|serial_no| (serial_no, "device".to_string()))
+                        .collect();
+                    self.apply_snapshot(Source::Usb, &current);
+                }
+                Err(e) => println!("USB polling failed: {}", e),
+            }
+            thread::sleep(USB_POLL_INTERVAL);
+        }
+    }
+
+    fn track_server(&self) {
+        loop {
+            if adb_server_available() {
+                if let Err(e) = self.follow_track_devices() {
+                    println!("Lost adb server device tracking: {}", e);
+                }
+                // The server went away, so everything it reported did too
+                self.apply_snapshot(Source::Server, &HashMap::new());
+            }
+            thread::sleep(SERVER_RETRY_INTERVAL);
+        }
+    }
+
+    fn follow_track_devices(&self) -> std::io::Result<()> {
+        let mut stream = server_protocol::connect()?;
+        server_protocol::send_request(&mut stream, "host:track-devices")?;
+
+        loop {
+            let block = server_protocol::read_hex_block(&mut stream)?;
+            let current: HashMap<String, String> = server_protocol::parse_device_list(&block)
+                .into_iter()
+                .collect();
+            self.apply_snapshot(Source::Server, &current);
+        }
+    }
+}
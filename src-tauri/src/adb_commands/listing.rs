@@ -0,0 +1,416 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::adb_device::AdbDevice;
+use super::capabilities::DeviceCapabilities;
+use super::files::{FileInfo, FileType, list_files};
+use crate::error::DroidKitError;
+
+/// Entries sent to the webview per [`ListingBatch`].
+pub(crate) const BATCH_SIZE: usize = 500;
+
+/// What a listing is ordered by. Ties are broken by name.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum SortKey {
+    /// Case-insensitive.
+    #[default]
+    Name,
+    /// Entries without a size, such as directories, count as empty.
+    Size,
+    /// Entries without a time sort before the oldest.
+    Modified,
+    /// Directories, links, files, then special files.
+    Type,
+}
+
+/// Which part of a directory to send, and in what order.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub(crate) struct ListingQuery {
+    pub sort: SortKey,
+    pub descending: bool,
+    /// Keeps directories ahead of everything else, whichever way the rest is
+    /// sorted.
+    pub directories_first: bool,
+    /// Case-insensitive part of the name.
+    pub filter: Option<String>,
+    /// Index of the first entry to send, after filtering and sorting.
+    pub offset: usize,
+    /// Entries to send at most, or all the rest.
+    pub limit: Option<usize>,
+}
+
+impl Default for ListingQuery {
+    fn default() -> Self {
+        Self {
+            sort: SortKey::Name,
+            descending: false,
+            directories_first: true,
+            filter: None,
+            offset: 0,
+            limit: None,
+        }
+    }
+}
+
+/// Consecutive entries of a page, starting at `offset` in the whole sorted
+/// listing.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct ListingBatch {
+    pub offset: usize,
+    pub entries: Vec<FileInfo>,
+}
+
+/// What was sent once every batch of a page is.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct ListingPage {
+    /// Entries matching the filter.
+    pub total: usize,
+    /// Entries in the directory.
+    pub unfiltered: usize,
+    pub offset: usize,
+    /// Entries sent in batches.
+    pub returned: usize,
+    pub has_more: bool,
+}
+
+fn compare_names(a: &str, b: &str) -> Ordering {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .cmp(b.chars().flat_map(char::to_lowercase))
+        .then_with(|| a.cmp(b))
+}
+
+fn type_rank(file_type: &FileType) -> u8 {
+    match file_type {
+        FileType::Directory => 0,
+        FileType::Symlink { .. } => 1,
+        FileType::File => 2,
+        _ => 3,
+    }
+}
+
+/// Orders `files` as `query` asks.
+fn sort_files(files: &mut [FileInfo], query: &ListingQuery) {
+    files.sort_by(|a, b| {
+        let directories = if query.directories_first {
+            let not_dir = |file: &FileInfo| !matches!(file.file_type, FileType::Directory);
+            not_dir(a).cmp(&not_dir(b))
+        } else {
+            Ordering::Equal
+        };
+        let key = match query.sort {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a.size.unwrap_or(0).cmp(&b.size.unwrap_or(0)),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+            SortKey::Type => type_rank(&a.file_type).cmp(&type_rank(&b.file_type)),
+        }
+        .then_with(|| compare_names(&a.name, &b.name));
+        directories.then(if query.descending { key.reverse() } else { key })
+    });
+}
+
+/// The directory each device is being browsed in, sorted for the last page
+/// asked of it, held in Tauri state.
+#[derive(Clone, Default)]
+pub(crate) struct ListingCache {
+    browses: Arc<Mutex<HashMap<String, Browse>>>,
+}
+
+/// Who a page is listed for: a listing made as root is only reused for
+/// pages that ask for root too.
+pub(crate) struct BrowseKey {
+    pub serial_no: String,
+    pub elevated: bool,
+}
+
+struct Browse {
+    path: String,
+    elevated: bool,
+    /// Sort key, descending and directories first, as `files` is ordered.
+    order: (SortKey, bool, bool),
+    files: Vec<FileInfo>,
+}
+
+impl ListingCache {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Browse>> {
+        self.browses.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Drops what is cached for `serial_no`, after its files changed, so
+    /// the next page lists its directory afresh.
+    pub fn forget(&self, serial_no: &str) {
+        self.lock().remove(serial_no);
+    }
+}
+
+/// Sends the page of `path` that `query` selects to `on_batch`,
+/// [`BATCH_SIZE`] entries at a time, so that a directory of thousands of
+/// entries never crosses to the webview whole.
+///
+/// A page at offset 0 starts a browse and lists `path` afresh. Later pages
+/// are cut from that listing in `cache`, re-sorted if the order changed.
+pub(crate) fn list_page(
+    device: &mut dyn AdbDevice,
+    capabilities: &DeviceCapabilities,
+    cache: &ListingCache,
+    key: &BrowseKey,
+    path: &str,
+    query: &ListingQuery,
+    mut on_batch: impl FnMut(ListingBatch),
+) -> Result<ListingPage, DroidKitError> {
+    let order = (query.sort, query.descending, query.directories_first);
+    let cached = match query.offset {
+        0 => None,
+        _ => cache
+            .lock()
+            .remove(&key.serial_no)
+            .filter(|browse| browse.path == path && browse.elevated == key.elevated),
+    };
+    let mut browse = match cached {
+        Some(browse) => browse,
+        None => {
+            let mut files = list_files(device, capabilities, path)?;
+            sort_files(&mut files, query);
+            Browse {
+                path: path.to_string(),
+                elevated: key.elevated,
+                order,
+                files,
+            }
+        }
+    };
+    if browse.order != order {
+        sort_files(&mut browse.files, query);
+        browse.order = order;
+    }
+
+    let filter = query
+        .filter
+        .as_deref()
+        .filter(|filter| !filter.is_empty())
+        .map(str::to_lowercase);
+    let matching = || {
+        browse.files.iter().filter(|file| match &filter {
+            Some(filter) => file.name.to_lowercase().contains(filter),
+            None => true,
+        })
+    };
+    let unfiltered = browse.files.len();
+    let total = matching().count();
+
+    let limit = query.limit.unwrap_or(usize::MAX);
+    let mut page = matching().skip(query.offset).take(limit).peekable();
+    let mut offset = query.offset;
+    while page.peek().is_some() {
+        let entries: Vec<FileInfo> = page.by_ref().take(BATCH_SIZE).cloned().collect();
+        let sent = entries.len();
+        on_batch(ListingBatch { offset, entries });
+        offset += sent;
+    }
+
+    cache.lock().insert(key.serial_no.clone(), browse);
+    let returned = offset.saturating_sub(query.offset);
+    Ok(ListingPage {
+        total,
+        unfiltered,
+        offset: query.offset,
+        returned,
+        has_more: offset < total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::{FakeDevice, fixture};
+
+    fn sdcard() -> FakeDevice {
+        FakeDevice::new().with_shell(
+            "find -H /sdcard -mindepth 1 -maxdepth 1 -exec stat -c '%f %s %Y %U %G %n' '{}' +",
+            fixture("android-9", "stat_sdcard.txt"),
+        )
+    }
+
+    fn stat_capable() -> DeviceCapabilities {
+        DeviceCapabilities {
+            stat_format: true,
+            ..Default::default()
+        }
+    }
+
+    fn page_of(
+        device: &mut FakeDevice,
+        cache: &ListingCache,
+        query: ListingQuery,
+    ) -> (ListingPage, Vec<ListingBatch>) {
+        let mut batches = Vec::new();
+        let page = list_page(
+            device,
+            &stat_capable(),
+            cache,
+            &BrowseKey {
+                serial_no: "emulator-5554".to_string(),
+                elevated: false,
+            },
+            "/sdcard",
+            &query,
+            |batch| batches.push(batch),
+        )
+        .unwrap();
+        (page, batches)
+    }
+
+    fn page(query: ListingQuery) -> (ListingPage, Vec<ListingBatch>) {
+        page_of(&mut sdcard(), &ListingCache::default(), query)
+    }
+
+    fn names(batches: &[ListingBatch]) -> Vec<&str> {
+        batches
+            .iter()
+            .flat_map(|batch| batch.entries.iter().map(|file| file.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn directories_first_then_names_ignoring_case() {
+        let (page, batches) = page(ListingQuery::default());
+        assert_eq!(page.total, 13);
+        assert_eq!(page.returned, 13);
+        assert!(!page.has_more);
+        assert_eq!(batches.len(), 1);
+
+        let names = names(&batches);
+        assert_eq!(names[0], "Alarms");
+        assert_eq!(names[7], "Pictures");
+        // Leading spaces sort first among the files
+        assert_eq!(
+            &names[8..],
+            [
+                "  two leading spaces.txt",
+                ".nomedia",
+                "a  b -> c.txt",
+                "bugreport-walleye-PQ3A.190801.002-2021-06-12-14-02-11.zip",
+                "my notes.txt",
+            ]
+        );
+    }
+
+    #[test]
+    fn sorts_by_size_descending_without_directories_first() {
+        let (_, batches) = page(ListingQuery {
+            sort: SortKey::Size,
+            descending: true,
+            directories_first: false,
+            ..Default::default()
+        });
+        let names = names(&batches);
+        assert_eq!(
+            names[0],
+            "bugreport-walleye-PQ3A.190801.002-2021-06-12-14-02-11.zip"
+        );
+        assert_eq!(names[1], "my notes.txt");
+        // Reversed ties too, so the empty entries end with .nomedia
+        assert_eq!(names[12], ".nomedia");
+    }
+
+    #[test]
+    fn filters_and_pages_on_the_backend() {
+        let (page, batches) = page(ListingQuery {
+            filter: Some("TXT".to_string()),
+            offset: 1,
+            limit: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(page.unfiltered, 13);
+        assert_eq!(page.total, 3);
+        assert_eq!(page.offset, 1);
+        assert_eq!(page.returned, 1);
+        assert!(page.has_more);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].offset, 1);
+        assert_eq!(names(&batches), ["a  b -> c.txt"]);
+    }
+
+    #[test]
+    fn later_pages_come_from_the_listing_of_the_first() {
+        let mut device = sdcard();
+        let cache = ListingCache::default();
+        let first = ListingQuery {
+            limit: Some(5),
+            ..Default::default()
+        };
+        page_of(&mut device, &cache, first.clone());
+        let (page, batches) = page_of(
+            &mut device,
+            &cache,
+            ListingQuery {
+                offset: 5,
+                ..first.clone()
+            },
+        );
+        assert_eq!(device.calls.len(), 1);
+        assert_eq!(page.total, 13);
+        assert_eq!(names(&batches)[0], "Music");
+
+        // Another order re-sorts what was listed rather than listing again
+        let (_, batches) = page_of(
+            &mut device,
+            &cache,
+            ListingQuery {
+                offset: 5,
+                descending: true,
+                ..first.clone()
+            },
+        );
+        assert_eq!(device.calls.len(), 1);
+        assert_eq!(names(&batches)[0], "DCIM");
+
+        // Starting over lists the directory again, as does a change of files
+        page_of(&mut device, &cache, first.clone());
+        assert_eq!(device.calls.len(), 2);
+        cache.forget("emulator-5554");
+        page_of(&mut device, &cache, ListingQuery { offset: 5, ..first });
+        assert_eq!(device.calls.len(), 3);
+    }
+
+    #[test]
+    fn offset_past_the_end_sends_nothing() {
+        let (page, batches) = page(ListingQuery {
+            offset: 50,
+            ..Default::default()
+        });
+        assert_eq!(page.returned, 0);
+        assert!(!page.has_more);
+        assert!(batches.is_empty());
+    }
+
+    #[test]
+    fn sorts_by_time_and_type() {
+        let mut files: Vec<FileInfo> = page(ListingQuery::default())
+            .1
+            .into_iter()
+            .flat_map(|batch| batch.entries)
+            .collect();
+        let query = ListingQuery {
+            sort: SortKey::Modified,
+            directories_first: false,
+            ..Default::default()
+        };
+        sort_files(&mut files, &query);
+        let times: Vec<Option<u64>> = files.iter().map(|file| file.modified).collect();
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        sort_files(
+            &mut files,
+            &ListingQuery {
+                sort: SortKey::Type,
+                ..query
+            },
+        );
+        assert!(matches!(files[0].file_type, FileType::Directory));
+        assert!(matches!(files[12].file_type, FileType::File));
+    }
+}
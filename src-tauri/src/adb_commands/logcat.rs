@@ -1,100 +1,138 @@
 use std::str::from_utf8;
 
-use super::device::{Device, DeviceInfo, DeviceTransport};
+use super::adb_device::AdbDevice;
+use super::command::ShellCommand;
+use super::device::DeviceInfo;
+use crate::error::DroidKitError;
 
 pub(crate) fn get_logcat_output(
-    device: &mut Device,
+    device: &mut dyn AdbDevice,
     lines: u32,
     log_level: Option<String>,
-) -> Result<String, String> {
+) -> Result<String, DroidKitError> {
     let mut buf: Vec<u8> = Vec::new();
 
-    let lines_str = lines.to_string();
-    let mut args = vec!["logcat", "-d", "-t", lines_str.as_str()];
-
-    let filter_arg;
+    let mut command = ShellCommand::new("logcat")
+        .args(["-d", "-t"])
+        .arg(lines.to_string());
     if let Some(level) = log_level.as_ref() {
-        filter_arg = format!("*:{}", level);
-        args.push(filter_arg.as_str());
+        command = command.arg(format!("*:{}", level));
     }
 
-    let shell_result = device.shell_command(&args.join(" ").as_str(), &mut buf);
+    let shell_result = device.exec_out(&command.to_string(), &mut buf);
 
     match shell_result {
         Ok(_) => {
             let output = String::from_utf8_lossy(&buf);
             Ok(output.to_string())
         }
-        Err(e) => Err(format!("Failed to get logcat: {:?}", e)),
+        Err(e) => Err(e.context("Failed to get logcat")),
     }
 }
 
-fn getprop_from_device(device: &mut Device, property: &str) -> Option<String> {
+pub(crate) fn getprop_from_device(
+    device: &mut dyn AdbDevice,
+    property: &str,
+) -> Result<String, DroidKitError> {
     let mut buf: Vec<u8> = Vec::new();
 
-    match device.shell_command(&format!("getprop {}", property), &mut buf) {
-        Ok(..) => match from_utf8(buf.as_slice()) {
-            Ok(data) => Some(data.trim().to_string()),
-            Err(..) => None,
-        },
-        Err(..) => None,
+    let command = ShellCommand::new("getprop").arg(property);
+    device.shell(&command.to_string(), &mut buf)?;
+    match from_utf8(buf.as_slice()) {
+        Ok(data) => Ok(data.trim().to_string()),
+        Err(e) => Err(DroidKitError::Parse(format!(
+            "getprop {} returned invalid UTF-8: {}",
+            property, e
+        ))),
     }
 }
 
-pub(crate) fn get_device_serial(device: &mut Device) -> Option<String> {
+pub(crate) fn get_device_serial(device: &mut dyn AdbDevice) -> Result<String, DroidKitError> {
     getprop_from_device(device, "ro.serialno")
 }
 
-pub(crate) fn get_device_model(device: &mut Device) -> Option<String> {
+pub(crate) fn get_device_model(device: &mut dyn AdbDevice) -> Result<String, DroidKitError> {
     getprop_from_device(device, "ro.product.model")
 }
 
-pub(crate) fn get_device_android_version(device: &mut Device) -> Option<String> {
+pub(crate) fn get_device_android_version(
+    device: &mut dyn AdbDevice,
+) -> Result<String, DroidKitError> {
     getprop_from_device(device, "ro.build.version.release")
 }
 
-pub(crate) fn get_device_sdk_version(device: &mut Device) -> Option<String> {
+pub(crate) fn get_device_sdk_version(device: &mut dyn AdbDevice) -> Result<String, DroidKitError> {
     getprop_from_device(device, "ro.build.version.sdk")
 }
 
-pub(crate) fn execute_shell_command(device: &mut Device, command: &str) -> Result<String, String> {
+pub(crate) fn execute_shell_command(
+    device: &mut dyn AdbDevice,
+    command: &str,
+) -> Result<String, DroidKitError> {
     let mut buf: Vec<u8> = Vec::new();
 
     let command_parts: Vec<&str> = command.trim().split_whitespace().collect();
     if command_parts.is_empty() {
-        return Err("Empty command".to_string());
+        return Err(DroidKitError::InvalidInput("Empty command".to_string()));
     }
 
-    let result = device.shell_command(&command, &mut buf);
+    let result = device.shell(command, &mut buf);
 
     match result {
         Ok(_) => {
             let output = String::from_utf8_lossy(&buf);
             Ok(output.to_string())
         }
-        Err(e) => Err(format!("Command execution failed: {:?}", e)),
+        Err(e) => Err(e.context("Command execution failed")),
     }
 }
 
-pub(crate) fn get_device_info(device: &mut Device) -> Result<DeviceInfo, ()> {
-    if let Some(serial_no) = get_device_serial(device) {
-        if let Some(model) = get_device_model(device) {
-            if let Some(android_version) = get_device_android_version(device) {
-                if let Some(sdk_version) = get_device_sdk_version(device) {
-                    let transport = match device {
-                        Device::USB(_) => DeviceTransport::USB,
-                        Device::TCP(_) => DeviceTransport::TCP,
-                    };
-                    return Ok(DeviceInfo {
-                        transport,
-                        serial_no,
-                        model,
-                        android_version,
-                        sdk_version,
-                    });
-                }
-            }
-        }
+pub(crate) fn get_device_info(device: &mut dyn AdbDevice) -> Result<DeviceInfo, DroidKitError> {
+    let serial_no = get_device_serial(device)?;
+    let model = get_device_model(device)?;
+    let android_version = get_device_android_version(device)?;
+    let sdk_version = get_device_sdk_version(device)?;
+
+    Ok(DeviceInfo {
+        transport: device.transport(),
+        // Emulators report a different ro.serialno than the name
+        // the server knows them by, and only the latter reconnects
+        serial_no: device.transport_serial().unwrap_or(serial_no),
+        model,
+        android_version,
+        sdk_version,
+        state: "device".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::device::DeviceTransport;
+    use crate::adb_commands::fake_device::FakeDevice;
+
+    #[test]
+    fn logcat_filters_by_level_without_a_shell() {
+        let mut device =
+            FakeDevice::new().with_exec_out("logcat -d -t 100 '*:E'", "E/AndroidRuntime: boom\n");
+        let output = get_logcat_output(&mut device, 100, Some("E".to_string())).unwrap();
+        assert_eq!(output, "E/AndroidRuntime: boom\n");
+        assert_eq!(device.calls, ["exec:logcat -d -t 100 '*:E'"]);
+    }
+
+    #[test]
+    fn device_info_from_getprop() {
+        let mut device = FakeDevice::new()
+            .with_transport(DeviceTransport::TCP)
+            .with_shell("getprop ro.serialno", "HT7A1B2C3D4E\n")
+            .with_shell("getprop ro.product.model", "Pixel 2\n")
+            .with_shell("getprop ro.build.version.release", "9\n")
+            .with_shell("getprop ro.build.version.sdk", "28\n");
+        let info = get_device_info(&mut device).unwrap();
+        assert!(matches!(info.transport, DeviceTransport::TCP));
+        assert_eq!(info.serial_no, "HT7A1B2C3D4E");
+        assert_eq!(info.model, "Pixel 2");
+        assert_eq!(info.android_version, "9");
+        assert_eq!(info.sdk_version, "28");
     }
-    Err(())
 }
@@ -0,0 +1,879 @@
+//! Keeping a local directory and one on the device the same, like `rsync
+//! --delete`, in two steps: a plan of what would change, for preview, and
+//! then carrying that plan out over the sync protocol.
+//!
+//! Regular files are compared by size and modification time, or by their
+//! digests when asked. Links, sockets and the like are left alone on both sides.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::adb_device::{AdbDevice, S_IFDIR, S_IFMT, S_IFREG};
+use super::checksum::{local_digest, remote_digests};
+use super::command::ShellCommand;
+use super::file_ops::delete_path;
+use super::files::{TransferProgress, local_mod_time, pull_file, push_file_as};
+use super::transfer::{TreeProgress, TreeTransferSummary, join_remote};
+use crate::error::DroidKitError;
+
+/// Which side is copied onto the other.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SyncDirection {
+    /// This computer to the device.
+    Push,
+    /// The device to this computer.
+    Pull,
+}
+
+/// How two regular files of the same size are told apart.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum CompareBy {
+    /// Modification time, to the second, as copies keep it.
+    #[default]
+    Time,
+    /// Digests of the contents, read in full on both sides: SHA-256, or MD5
+    /// on devices with only `md5sum` or `md5`.
+    Checksum,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MirrorAction {
+    /// Missing from the destination.
+    Add,
+    /// Differs on the destination.
+    Update,
+    /// Only on the destination. A directory goes with everything in it.
+    Delete,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct MirrorEntry {
+    /// Relative to both roots, with `/` between names.
+    pub path: String,
+    pub action: MirrorAction,
+    pub is_dir: bool,
+    /// Bytes to copy, for files being added or updated.
+    pub size: u64,
+}
+
+/// What a mirror would change, in the order it would change it: deletions
+/// first, then each directory before what goes in it.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct MirrorPlan {
+    /// What to pass to [`MirrorPlans::take`] to run it. Set once stored.
+    pub id: u32,
+    pub direction: SyncDirection,
+    pub local_dir: String,
+    pub remote_dir: String,
+    pub entries: Vec<MirrorEntry>,
+    /// Files already the same on both sides.
+    pub unchanged: u64,
+    pub bytes_to_copy: u64,
+    /// Links and special files on the source side, which are not copied.
+    pub skipped: Vec<String>,
+    /// Paths that are a file on one side and a directory on the other. They
+    /// are left alone rather than deleted to make room.
+    pub conflicts: Vec<String>,
+    /// The destination as planned against, to tell what changed since.
+    #[serde(skip)]
+    dest: BTreeMap<String, Node>,
+}
+
+/// Plans made by [`plan_mirror`] and not yet run, held in Tauri state. The
+/// webview only ever gets a copy to preview, and runs a plan by its id, so
+/// what is deleted or overwritten is only ever what was planned here.
+#[derive(Clone, Default)]
+pub(crate) struct MirrorPlans {
+    next_id: Arc<AtomicU32>,
+    plans: Arc<Mutex<HashMap<u32, (String, MirrorPlan)>>>,
+}
+
+impl MirrorPlans {
+    fn lock(&self) -> MutexGuard<'_, HashMap<u32, (String, MirrorPlan)>> {
+        self.plans.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Keeps `plan` for `serial_no` under a new id, in place of any earlier
+    /// plan for that device, and returns it with the id set.
+    pub fn insert(&self, serial_no: &str, mut plan: MirrorPlan) -> MirrorPlan {
+        plan.id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut plans = self.lock();
+        plans.retain(|_, (planned_for, _)| planned_for != serial_no);
+        plans.insert(plan.id, (serial_no.to_string(), plan.clone()));
+        plan
+    }
+
+    /// Removes plan `id` for running on `serial_no`. Each plan runs once.
+    pub fn take(&self, serial_no: &str, id: u32) -> Result<MirrorPlan, DroidKitError> {
+        let mut plans = self.lock();
+        match plans.get(&id) {
+            Some((planned_for, _)) if planned_for == serial_no => Ok(plans.remove(&id).unwrap().1),
+            _ => Err(DroidKitError::InvalidInput(format!(
+                "No mirror plan {} for {}; plan it again",
+                id, serial_no
+            ))),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub(crate) struct MirrorSummary {
+    #[serde(flatten)]
+    pub transfer: TreeTransferSummary,
+    /// Files and directories removed from the destination.
+    pub deleted: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Node {
+    Dir,
+    File { size: u64, mod_time: u64 },
+}
+
+/// One side's tree, by path relative to its root.
+#[derive(Default)]
+struct Tree {
+    nodes: BTreeMap<String, Node>,
+    skipped: Vec<String>,
+}
+
+fn join_relative(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Whether `relative` stays inside the tree it is relative to: no leading
+/// `/` and no empty, `.` or `..` names.
+fn is_inside(relative: &str) -> bool {
+    !relative.starts_with('/')
+        && relative
+            .split('/')
+            .all(|name| !name.is_empty() && name != "." && name != "..")
+}
+
+/// `path` and everything under it in `nodes`.
+fn subtree<'a>(
+    nodes: &'a BTreeMap<String, Node>,
+    path: &'a str,
+) -> impl Iterator<Item = (&'a String, &'a Node)> {
+    // Whatever starts with `path` sorts together, `path/...` among the rest
+    nodes
+        .range::<str, _>((Bound::Included(path), Bound::Unbounded))
+        .take_while(move |(other, _)| other.starts_with(path))
+        .filter(move |(other, _)| other.len() == path.len() || other[path.len()..].starts_with('/'))
+}
+
+fn local_path(root: &Path, relative: &str) -> PathBuf {
+    relative
+        .split('/')
+        .fold(root.to_path_buf(), |path, name| path.join(name))
+}
+
+/// Walks the device's `root` with `LIST`, which does not follow links. An
+/// empty tree when `root` does not exist.
+fn walk_remote(device: &mut dyn AdbDevice, root: &str) -> Result<Tree, DroidKitError> {
+    let mut tree = Tree::default();
+    // `STAT` does not follow links, except through a trailing slash
+    match device
+        .stat(&format!("{}/", root.trim_end_matches('/')))?
+        .file_perm
+        & S_IFMT
+    {
+        0 => return Ok(tree),
+        S_IFDIR => {}
+        _ => {
+            return Err(DroidKitError::InvalidInput(format!(
+                "{} is not a directory on the device",
+                root
+            )));
+        }
+    }
+
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        let remote_dir = if dir.is_empty() {
+            root.to_string()
+        } else {
+            join_remote(root, &dir)
+        };
+        let entries = device
+            .list(&remote_dir)
+            .map_err(|e| e.context(&format!("Failed to list {}", remote_dir)))?;
+        for entry in entries {
+            let relative = join_relative(&dir, &entry.name);
+            match entry.mode & S_IFMT {
+                S_IFDIR => {
+                    tree.nodes.insert(relative.clone(), Node::Dir);
+                    pending.push(relative);
+                }
+                S_IFREG => {
+                    tree.nodes.insert(
+                        relative,
+                        Node::File {
+                            size: u64::from(entry.size),
+                            mod_time: u64::from(entry.mod_time),
+                        },
+                    );
+                }
+                _ => tree.skipped.push(join_remote(root, &relative)),
+            }
+        }
+    }
+    Ok(tree)
+}
+
+/// Walks the local `root` without following links. An empty tree when
+/// `root` does not exist.
+fn walk_local(root: &Path) -> Result<Tree, DroidKitError> {
+    let mut tree = Tree::default();
+    match std::fs::metadata(root) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) => {
+            return Err(DroidKitError::InvalidInput(format!(
+                "{} is not a directory",
+                root.display()
+            )));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(tree),
+        Err(e) => {
+            return Err(
+                DroidKitError::from(e).context(&format!("Failed to read {}", root.display()))
+            );
+        }
+    }
+
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        let local_dir = if dir.is_empty() {
+            root.to_path_buf()
+        } else {
+            local_path(root, &dir)
+        };
+        let entries = std::fs::read_dir(&local_dir)
+            .and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>())
+            .map_err(|e| {
+                DroidKitError::from(e).context(&format!("Failed to list {}", local_dir.display()))
+            })?;
+        for entry in entries {
+            let relative = join_relative(&dir, &entry.file_name().to_string_lossy());
+            // Of the entry itself, not what a link points to
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                tree.nodes.insert(relative.clone(), Node::Dir);
+                pending.push(relative);
+            } else if metadata.is_file() {
+                tree.nodes.insert(
+                    relative,
+                    Node::File {
+                        size: metadata.len(),
+                        mod_time: u64::from(local_mod_time(&metadata)),
+                    },
+                );
+            } else {
+                tree.skipped
+                    .push(entry.path().to_string_lossy().into_owned());
+            }
+        }
+    }
+    Ok(tree)
+}
+
+/// Sizes from `LIST` wrap at 4 GiB, so only the low 32 bits can be compared.
+fn same_size(a: u64, b: u64) -> bool {
+    a as u32 == b as u32
+}
+
+/// Compares the two trees and works out what copying `direction` would
+/// change. Nothing is changed yet; the plan is for [`run_mirror`].
+pub(crate) fn plan_mirror(
+    device: &mut dyn AdbDevice,
+    local_dir: &str,
+    remote_dir: &str,
+    direction: SyncDirection,
+    compare: CompareBy,
+    delete: bool,
+) -> Result<MirrorPlan, DroidKitError> {
+    let local = walk_local(Path::new(local_dir))?;
+    let remote = walk_remote(device, remote_dir)?;
+    let (source, dest) = match direction {
+        SyncDirection::Push => (local, remote),
+        SyncDirection::Pull => (remote, local),
+    };
+
+    let mut plan = MirrorPlan {
+        id: 0,
+        direction,
+        local_dir: local_dir.to_string(),
+        remote_dir: remote_dir.to_string(),
+        entries: Vec::new(),
+        unchanged: 0,
+        bytes_to_copy: 0,
+        skipped: source.skipped,
+        conflicts: Vec::new(),
+        dest: BTreeMap::new(),
+    };
+
+    // Files whose size and time agree, when contents are to be compared
+    let mut to_hash = Vec::new();
+    let mut copies = Vec::new();
+    for (path, node) in &source.nodes {
+        let action = match (node, dest.nodes.get(path)) {
+            (_, None) => MirrorAction::Add,
+            (Node::Dir, Some(Node::Dir)) => continue,
+            (Node::Dir, Some(Node::File { .. })) | (Node::File { .. }, Some(Node::Dir)) => {
+                plan.conflicts.push(path.clone());
+                continue;
+            }
+            (
+                Node::File { size, mod_time },
+                Some(Node::File {
+                    size: dest_size,
+                    mod_time: dest_mod_time,
+                }),
+            ) => {
+                if !same_size(*size, *dest_size) {
+                    MirrorAction::Update
+                } else if compare == CompareBy::Checksum {
+                    to_hash.push((path.clone(), *size));
+                    continue;
+                } else if mod_time != dest_mod_time {
+                    MirrorAction::Update
+                } else {
+                    plan.unchanged += 1;
+                    continue;
+                }
+            }
+        };
+        copies.push((path.clone(), action, *node));
+    }
+
+    if !to_hash.is_empty() {
+        let remote_paths: Vec<String> = to_hash
+            .iter()
+            .map(|(path, _)| join_remote(remote_dir, path))
+            .collect();
+        let (algorithm, remote_digests) = remote_digests(device, &remote_paths)?;
+        for ((path, size), remote_path) in to_hash.into_iter().zip(&remote_paths) {
+            let local_digest = local_digest(&local_path(Path::new(local_dir), &path), algorithm)?;
+            if remote_digests.get(remote_path) == Some(&local_digest) {
+                plan.unchanged += 1;
+            } else {
+                copies.push((path, MirrorAction::Update, Node::File { size, mod_time: 0 }));
+            }
+        }
+        copies.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    if delete {
+        // Only the topmost of what goes, as a directory takes its contents
+        let mut deleted_dirs: HashSet<&str> = HashSet::new();
+        for (path, node) in &dest.nodes {
+            if source.nodes.contains_key(path) {
+                continue;
+            }
+            let inside_deleted = path
+                .match_indices('/')
+                .any(|(at, _)| deleted_dirs.contains(&path[..at]));
+            if inside_deleted {
+                continue;
+            }
+            let is_dir = matches!(node, Node::Dir);
+            if is_dir {
+                deleted_dirs.insert(path);
+            }
+            plan.entries.push(MirrorEntry {
+                path: path.clone(),
+                action: MirrorAction::Delete,
+                is_dir,
+                size: 0,
+            });
+        }
+    }
+
+    for (path, action, node) in copies {
+        let (is_dir, size) = match node {
+            Node::Dir => (true, 0),
+            Node::File { size, .. } => (false, size),
+        };
+        plan.bytes_to_copy += size;
+        plan.entries.push(MirrorEntry {
+            path,
+            action,
+            is_dir,
+            size,
+        });
+    }
+    plan.dest = dest.nodes;
+    Ok(plan)
+}
+
+fn delete_local(path: &Path, is_dir: bool) -> Result<(), DroidKitError> {
+    let result = if is_dir {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+    result.map_err(|e| {
+        DroidKitError::from(e).context(&format!("Failed to delete {}", path.display()))
+    })
+}
+
+/// Carries out a plan from [`plan_mirror`]. Like a tree transfer, a path
+/// that fails is recorded in the summary and the rest carry on.
+pub(crate) fn run_mirror(
+    device: &mut dyn AdbDevice,
+    plan: &MirrorPlan,
+    mut on_progress: impl FnMut(TreeProgress),
+) -> Result<MirrorSummary, DroidKitError> {
+    if let Some(entry) = plan.entries.iter().find(|entry| !is_inside(&entry.path)) {
+        return Err(DroidKitError::InvalidInput(format!(
+            "Mirror plan path escapes its directory: {}",
+            entry.path
+        )));
+    }
+
+    let local_root = Path::new(&plan.local_dir);
+    // What is on the destination now, so nothing that changed since the
+    // plan is deleted or overwritten on the strength of it
+    let current = match plan.direction {
+        SyncDirection::Push => walk_remote(device, &plan.remote_dir)?,
+        SyncDirection::Pull => walk_local(local_root)?,
+    }
+    .nodes;
+    let mut summary = MirrorSummary::default();
+    let mut progress = TreeProgress {
+        files_done: 0,
+        total_files: plan
+            .entries
+            .iter()
+            .filter(|entry| entry.action != MirrorAction::Delete && !entry.is_dir)
+            .count() as u64,
+        bytes_done: 0,
+        total_bytes: plan.bytes_to_copy,
+        current_file: String::new(),
+    };
+
+    // The destination root itself, which may not exist yet
+    let created = match plan.direction {
+        SyncDirection::Push => {
+            let command = ShellCommand::new("mkdir")
+                .arg("-p")
+                .arg(&plan.remote_dir)
+                .to_string();
+            device
+                .shell_output(&command)
+                .and_then(|output| output.into_result(&command))
+                .map(|_| ())
+        }
+        SyncDirection::Pull => std::fs::create_dir_all(local_root)
+            .map_err(|e| DroidKitError::from(e).context("Failed to create directory")),
+    };
+    created?;
+
+    for entry in &plan.entries {
+        let local = local_path(local_root, &entry.path);
+        let local_str = local.to_string_lossy().into_owned();
+        let remote = join_remote(&plan.remote_dir, &entry.path);
+        let (source, dest) = match plan.direction {
+            SyncDirection::Push => (&local_str, &remote),
+            SyncDirection::Pull => (&remote, &local_str),
+        };
+
+        if subtree(&plan.dest, &entry.path).ne(subtree(&current, &entry.path)) {
+            let error = DroidKitError::InvalidInput(format!(
+                "{} changed since the mirror was planned",
+                dest
+            ));
+            summary.transfer.fail(dest, error)?;
+            continue;
+        }
+
+        if entry.action == MirrorAction::Delete {
+            let result = match plan.direction {
+                SyncDirection::Push => delete_path(device, &remote, true, false).map(|_| ()),
+                SyncDirection::Pull => delete_local(&local, entry.is_dir),
+            };
+            match result {
+                Ok(()) => summary.deleted += 1,
+                Err(e) => summary.transfer.fail(dest, e)?,
+            }
+            continue;
+        }
+
+        if entry.is_dir {
+            let result = match plan.direction {
+                SyncDirection::Push => {
+                    let command = ShellCommand::new("mkdir")
+                        .arg("-p")
+                        .arg(&remote)
+                        .to_string();
+                    device
+                        .shell_output(&command)
+                        .and_then(|output| output.into_result(&command))
+                        .map(|_| ())
+                }
+                SyncDirection::Pull => std::fs::create_dir_all(&local)
+                    .map_err(|e| DroidKitError::from(e).context("Failed to create directory")),
+            };
+            match result {
+                Ok(()) => summary.transfer.directories += 1,
+                Err(e) => summary.transfer.fail(dest, e)?,
+            }
+            continue;
+        }
+
+        progress.current_file = source.clone();
+        let report = |file: TransferProgress| TreeProgress {
+            bytes_done: progress.bytes_done + file.bytes_transferred,
+            ..progress.clone()
+        };
+        let result = match plan.direction {
+            SyncDirection::Push => std::fs::metadata(&local)
+                .map_err(|e| {
+                    DroidKitError::from(e).context(&format!("Failed to read {}", local_str))
+                })
+                .and_then(|metadata| {
+                    push_file_as(device, &local_str, &metadata, &remote, true, |file| {
+                        on_progress(report(file))
+                    })
+                }),
+            SyncDirection::Pull => pull_file(device, &remote, &local_str, |file| {
+                on_progress(report(file))
+            }),
+        };
+        progress.files_done += 1;
+        progress.bytes_done += entry.size;
+        match result {
+            Ok(()) => {
+                summary.transfer.files_transferred += 1;
+                summary.transfer.bytes_transferred += entry.size;
+            }
+            Err(e) => summary.transfer.fail(source, e)?,
+        }
+        on_progress(progress.clone());
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::checksum::ChecksumAlgorithm;
+    use crate::adb_commands::fake_device::{FakeDevice, unique_temp_dir};
+    use std::fs::File;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn fresh_dir(name: &str) -> PathBuf {
+        let dir = unique_temp_dir(name);
+        let _ = std::fs::remove_dir(&dir);
+        dir
+    }
+
+    fn write(path: &Path, data: &[u8], mod_time: u64) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(mod_time))
+            .unwrap();
+    }
+
+    fn actions(plan: &MirrorPlan) -> Vec<(&str, MirrorAction)> {
+        plan.entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.action))
+            .collect()
+    }
+
+    /// The same files on both sides, except where a name says otherwise.
+    fn test_data(local: &Path) -> FakeDevice {
+        write(&local.join("same.jpg"), b"same", 1700000000);
+        write(&local.join("newer.jpg"), b"new!", 1700000100);
+        write(&local.join("resized.jpg"), b"longer", 1700000000);
+        write(&local.join("Clips/only_local.mp4"), b"clip", 1700000000);
+        write(&local.join("conflict"), b"file", 1700000000);
+
+        FakeDevice::new()
+            .with_file("/sdcard/TestData", 0o040770, 4096, 1700000000)
+            .with_file_data(
+                "/sdcard/TestData/same.jpg",
+                0o660,
+                b"same".to_vec(),
+                1700000000,
+            )
+            .with_file_data(
+                "/sdcard/TestData/newer.jpg",
+                0o660,
+                b"old!".to_vec(),
+                1700000000,
+            )
+            .with_file_data(
+                "/sdcard/TestData/resized.jpg",
+                0o660,
+                b"short".to_vec(),
+                1700000000,
+            )
+            .with_file("/sdcard/TestData/conflict", 0o040770, 4096, 1700000000)
+            .with_file("/sdcard/TestData/Old", 0o040770, 4096, 1700000000)
+            .with_file_data("/sdcard/TestData/Old/gone.jpg", 0o660, b"gone".to_vec(), 0)
+            .with_file_data("/sdcard/TestData/stale.jpg", 0o660, b"x".to_vec(), 0)
+    }
+
+    #[test]
+    fn plans_a_push_by_size_and_time() {
+        let local = fresh_dir("mirror-plan");
+        let mut device = test_data(&local);
+
+        let plan = plan_mirror(
+            &mut device,
+            &local.to_string_lossy(),
+            "/sdcard/TestData",
+            SyncDirection::Push,
+            CompareBy::Time,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            actions(&plan),
+            [
+                ("Old", MirrorAction::Delete),
+                ("stale.jpg", MirrorAction::Delete),
+                ("Clips", MirrorAction::Add),
+                ("Clips/only_local.mp4", MirrorAction::Add),
+                ("newer.jpg", MirrorAction::Update),
+                ("resized.jpg", MirrorAction::Update),
+            ]
+        );
+        assert_eq!(plan.unchanged, 1);
+        assert_eq!(plan.bytes_to_copy, 14);
+        assert_eq!(plan.conflicts, ["conflict"]);
+        let _ = std::fs::remove_dir_all(&local);
+    }
+
+    #[test]
+    fn checksums_catch_changes_that_keep_size_and_time() {
+        let local = fresh_dir("mirror-checksum");
+        write(&local.join("a.bin"), b"same", 1700000000);
+        write(&local.join("b.bin"), b"diff", 1700000000);
+        let mut device = FakeDevice::new()
+            .with_file("/sdcard/TestData", 0o040770, 4096, 0)
+            .with_file_data(
+                "/sdcard/TestData/a.bin",
+                0o660,
+                b"same".to_vec(),
+                1700000000,
+            )
+            .with_file_data(
+                "/sdcard/TestData/b.bin",
+                0o660,
+                b"DIFF".to_vec(),
+                1700000000,
+            )
+            .with_shell(
+                "sha256sum /sdcard/TestData/a.bin /sdcard/TestData/b.bin",
+                format!(
+                    "{}  /sdcard/TestData/a.bin\n{}  /sdcard/TestData/b.bin\n",
+                    local_digest(&local.join("a.bin"), ChecksumAlgorithm::Sha256).unwrap(),
+                    "0".repeat(64),
+                ),
+            );
+
+        let by_time = plan_mirror(
+            &mut device,
+            &local.to_string_lossy(),
+            "/sdcard/TestData",
+            SyncDirection::Pull,
+            CompareBy::Time,
+            false,
+        )
+        .unwrap();
+        assert!(by_time.entries.is_empty());
+
+        let by_checksum = plan_mirror(
+            &mut device,
+            &local.to_string_lossy(),
+            "/sdcard/TestData",
+            SyncDirection::Pull,
+            CompareBy::Checksum,
+            false,
+        )
+        .unwrap();
+        assert_eq!(actions(&by_checksum), [("b.bin", MirrorAction::Update)]);
+        assert_eq!(by_checksum.unchanged, 1);
+        let _ = std::fs::remove_dir_all(&local);
+    }
+
+    #[test]
+    fn runs_a_push_plan() {
+        let local = fresh_dir("mirror-push");
+        let mut device = test_data(&local)
+            .with_shell("mkdir -p /sdcard/TestData", "")
+            .with_shell("mkdir -p /sdcard/TestData/Clips", "")
+            .with_shell("rm -rf /sdcard/TestData/Old", "")
+            .with_shell("rm -f /sdcard/TestData/stale.jpg", "");
+        let plan = plan_mirror(
+            &mut device,
+            &local.to_string_lossy(),
+            "/sdcard/TestData",
+            SyncDirection::Push,
+            CompareBy::Time,
+            true,
+        )
+        .unwrap();
+
+        let mut progress = Vec::new();
+        let summary = run_mirror(&mut device, &plan, |p| progress.push(p)).unwrap();
+
+        assert!(summary.transfer.failures.is_empty());
+        assert_eq!(summary.deleted, 2);
+        assert_eq!(summary.transfer.directories, 1);
+        assert_eq!(summary.transfer.files_transferred, 3);
+        assert_eq!(summary.transfer.bytes_transferred, 14);
+        let last = progress.last().unwrap();
+        assert_eq!((last.files_done, last.total_files), (3, 3));
+        assert!(
+            device
+                .calls
+                .iter()
+                .any(|call| call == "rm -rf /sdcard/TestData/Old")
+        );
+
+        // What was pushed now matches
+        let again = plan_mirror(
+            &mut device,
+            &local.to_string_lossy(),
+            "/sdcard/TestData",
+            SyncDirection::Push,
+            CompareBy::Time,
+            false,
+        )
+        .unwrap();
+        assert_eq!(again.unchanged, 3);
+        let _ = std::fs::remove_dir_all(&local);
+    }
+
+    #[test]
+    fn runs_a_pull_plan_into_a_new_directory() {
+        let local = fresh_dir("mirror-pull");
+        let mut device = FakeDevice::new()
+            .with_file("/sdcard/TestData", 0o040770, 4096, 0)
+            .with_file("/sdcard/TestData/Sub", 0o040770, 4096, 0)
+            .with_file_data(
+                "/sdcard/TestData/Sub/c.txt",
+                0o660,
+                b"ccc".to_vec(),
+                1700000000,
+            )
+            .with_file("/sdcard/TestData/link", 0o120777, 5, 0);
+        let plan = plan_mirror(
+            &mut device,
+            &local.to_string_lossy(),
+            "/sdcard/TestData",
+            SyncDirection::Pull,
+            CompareBy::Time,
+            true,
+        )
+        .unwrap();
+        assert_eq!(plan.skipped, ["/sdcard/TestData/link"]);
+
+        let summary = run_mirror(&mut device, &plan, |_| {}).unwrap();
+        assert_eq!(summary.transfer.files_transferred, 1);
+        assert_eq!(std::fs::read(local.join("Sub/c.txt")).unwrap(), b"ccc");
+        let _ = std::fs::remove_dir_all(&local);
+    }
+
+    fn delete_plan(local: &Path, path: &str) -> MirrorPlan {
+        MirrorPlan {
+            id: 0,
+            direction: SyncDirection::Pull,
+            local_dir: local.to_string_lossy().into_owned(),
+            remote_dir: "/sdcard/TestData".to_string(),
+            entries: vec![MirrorEntry {
+                path: path.to_string(),
+                action: MirrorAction::Delete,
+                is_dir: true,
+                size: 0,
+            }],
+            unchanged: 0,
+            bytes_to_copy: 0,
+            skipped: Vec::new(),
+            conflicts: Vec::new(),
+            dest: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn refuses_plans_that_escape_their_directories() {
+        let local = fresh_dir("mirror-escape");
+        for path in ["../outside", "/etc/hosts", "Sub//c.txt", "./c.txt", ""] {
+            let mut device = FakeDevice::new();
+            let err = run_mirror(&mut device, &delete_plan(&local, path), |_| {})
+                .map(|_| ())
+                .unwrap_err();
+            assert_eq!(err.kind(), "InvalidInput", "{:?}", path);
+            assert!(device.calls.is_empty());
+        }
+        assert!(!local.exists());
+    }
+
+    #[test]
+    fn leaves_alone_what_changed_since_the_plan() {
+        let local = fresh_dir("mirror-changed");
+        write(&local.join("Extra/old.txt"), b"old", 1700000000);
+        write(&local.join("stale.txt"), b"stale", 1700000000);
+        let mut device = FakeDevice::new().with_file("/sdcard/TestData", 0o040770, 4096, 0);
+        let plan = plan_mirror(
+            &mut device,
+            &local.to_string_lossy(),
+            "/sdcard/TestData",
+            SyncDirection::Pull,
+            CompareBy::Time,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            actions(&plan),
+            [
+                ("Extra", MirrorAction::Delete),
+                ("stale.txt", MirrorAction::Delete)
+            ]
+        );
+
+        write(&local.join("Extra/new.txt"), b"new", 1700000000);
+        let summary = run_mirror(&mut device, &plan, |_| {}).unwrap();
+        assert_eq!(summary.deleted, 1);
+        assert_eq!(summary.transfer.failures.len(), 1);
+        assert!(local.join("Extra/new.txt").exists());
+        assert!(!local.join("stale.txt").exists());
+        let _ = std::fs::remove_dir_all(&local);
+    }
+
+    #[test]
+    fn plans_run_once_and_only_on_their_device() {
+        let plans = MirrorPlans::default();
+        let first = plans.insert("emulator-5554", delete_plan(Path::new("/tmp"), "a"));
+        let plan = plans.insert("emulator-5554", delete_plan(Path::new("/tmp"), "b"));
+        assert_ne!(first.id, plan.id);
+
+        // A new plan for the device replaces the one before
+        let err = plans.take("emulator-5554", first.id).unwrap_err();
+        assert_eq!(err.kind(), "InvalidInput");
+        let err = plans.take("192.168.1.20:5555", plan.id).unwrap_err();
+        assert_eq!(err.kind(), "InvalidInput");
+        assert_eq!(
+            plans.take("emulator-5554", plan.id).unwrap().entries[0].path,
+            "b"
+        );
+        assert!(plans.take("emulator-5554", plan.id).is_err());
+    }
+}
@@ -1,6 +1,28 @@
+pub mod adb_device;
+pub mod capabilities;
+pub mod checksum;
+pub mod command;
+pub mod deadline;
 pub mod device;
 pub mod discovery;
+#[cfg(test)]
+pub mod fake_device;
+pub mod file_ops;
 pub mod files;
+pub mod forward;
+pub mod hotplug;
+pub mod listing;
 pub mod logcat;
+pub mod mirror;
 pub mod packages;
 pub mod pairing;
+pub mod pty;
+pub mod reboot;
+pub mod root;
+pub mod server_protocol;
+pub mod session;
+pub mod shell;
+pub mod shell_stream;
+pub mod sync;
+pub mod tcpip;
+pub mod transfer;
@@ -1,9 +1,20 @@
-use super::device::Device;
+use super::adb_device::AdbDevice;
+use super::capabilities::DeviceCapabilities;
+use crate::error::DroidKitError;
 
-pub(crate) fn get_installed_packages(device: &mut Device) -> Result<Vec<String>, String> {
+pub(crate) fn get_installed_packages(
+    device: &mut dyn AdbDevice,
+    capabilities: &DeviceCapabilities,
+) -> Result<Vec<String>, DroidKitError> {
     let mut buf: Vec<u8> = Vec::new();
 
-    let result = device.shell_command(&"pm list packages", &mut buf);
+    // `pm` starts a Java process for every call, `cmd` talks to the running service
+    let command = if capabilities.cmd_package {
+        "cmd package list packages"
+    } else {
+        "pm list packages"
+    };
+    let result = device.shell(command, &mut buf);
 
     match result {
         Ok(_) => {
@@ -20,6 +31,36 @@ pub(crate) fn get_installed_packages(device: &mut Device) -> Result<Vec<String>,
                 .collect();
             Ok(packages)
         }
-        Err(e) => Err(format!("Failed to get packages: {:?}", e)),
+        Err(e) => Err(e.context("Failed to get packages")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::FakeDevice;
+
+    const PACKAGES: &str = "package:com.android.settings\npackage:com.google.android.gm\n";
+
+    #[test]
+    fn prefers_cmd_package() {
+        let capabilities = DeviceCapabilities {
+            cmd_package: true,
+            ..Default::default()
+        };
+        let mut device = FakeDevice::new().with_shell("cmd package list packages", PACKAGES);
+
+        let packages = get_installed_packages(&mut device, &capabilities).unwrap();
+        assert_eq!(packages, ["com.android.settings", "com.google.android.gm"]);
+        assert_eq!(device.calls, ["cmd package list packages"]);
+    }
+
+    #[test]
+    fn falls_back_to_pm() {
+        let mut device = FakeDevice::new().with_shell("pm list packages", PACKAGES);
+
+        let packages = get_installed_packages(&mut device, &DeviceCapabilities::default()).unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(device.calls, ["pm list packages"]);
     }
 }
@@ -1,3 +1,4 @@
+use crate::error::DroidKitError;
 use crate::utils::get_local_ip_address;
 use adb_client::server::ADBServer;
 use mdns_sd::{ServiceDaemon, ServiceEvent};
@@ -20,7 +21,7 @@ pub(crate) struct PairingResult {
     pub device_port: Option<u16>,
 }
 
-pub(crate) fn generate_pairing_data() -> Result<PairingData, String> {
+pub(crate) fn generate_pairing_data() -> Result<PairingData, DroidKitError> {
     let local_ip = get_local_ip_address().unwrap_or_else(|| "192.168.1.100".to_string());
 
     let pairing_port = 5555;
@@ -50,16 +51,16 @@ pub(crate) fn generate_pairing_data() -> Result<PairingData, String> {
 pub(crate) fn start_pairing_listener(
     pairing_code: String,
     timeout_secs: u64,
-) -> Result<PairingResult, String> {
+) -> Result<PairingResult, DroidKitError> {
     println!("Starting mDNS pairing listener with code: {}", pairing_code);
 
-    let mdns =
-        ServiceDaemon::new().map_err(|e| format!("Failed to create mDNS daemon: {:?}", e))?;
+    let mdns = ServiceDaemon::new()
+        .map_err(|e| DroidKitError::Io(format!("Failed to create mDNS daemon: {}", e)))?;
 
     let service_name = "_adb-tls-pairing._tcp.local.";
     let receiver = mdns
         .browse(service_name)
-        .map_err(|e| format!("Failed to browse mDNS service: {:?}", e))?;
+        .map_err(|e| DroidKitError::Io(format!("Failed to browse mDNS service: {}", e)))?;
 
     println!("Listening for pairing devices...");
 
@@ -0,0 +1,310 @@
+//! Interactive shell sessions on a device PTY, over shell protocol v2.
+//!
+//! Each session has its own connection to the adb server, so it can stay open
+//! indefinitely without holding the device's session in [`DeviceSessions`].
+//! Devices connected directly over USB or TCP are not supported: adb_client
+//! only speaks the legacy shell protocol to adbd, which has no PTY size or
+//! exit status, and the direct handle cannot be shared with a long-lived
+//! session. Opening one fails with [`DroidKitError::InvalidInput`].
+//!
+//! [`DeviceSessions`]: super::session::DeviceSessions
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::device::open_server_device;
+use super::server_protocol;
+use super::shell_stream::complete_utf8_len;
+use crate::error::DroidKitError;
+
+// Packet ids from adb's shell_protocol.h
+const ID_STDIN: u8 = 0;
+const ID_STDOUT: u8 = 1;
+const ID_STDERR: u8 = 2;
+const ID_EXIT: u8 = 3;
+const ID_WINDOW_SIZE_CHANGE: u8 = 5;
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub(crate) enum PtyEvent {
+    Output {
+        data: String,
+    },
+    /// The shell exited or the connection closed; always the last event.
+    Exit {
+        exit_code: Option<u8>,
+    },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct PtySessionInfo {
+    pub id: u32,
+    pub serial_no: String,
+    pub rows: u16,
+    pub cols: u16,
+}
+
+struct PtySession {
+    info: PtySessionInfo,
+    stream: TcpStream,
+}
+
+/// Open PTY sessions keyed by id, held in Tauri state.
+#[derive(Clone, Default)]
+pub(crate) struct PtySessions {
+    next_id: Arc<AtomicU32>,
+    sessions: Arc<Mutex<HashMap<u32, PtySession>>>,
+}
+
+fn write_packet(output: &mut impl Write, id: u8, data: &[u8]) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(5 + data.len());
+    packet.push(id);
+    packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    packet.extend_from_slice(data);
+    output.write_all(&packet)
+}
+
+/// Reads one packet, or `None` once the other side has closed the stream.
+fn read_packet(input: &mut impl Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut header = [0u8; 5];
+    match input.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    let mut data = vec![0u8; len];
+    input.read_exact(&mut data)?;
+    Ok(Some((header[0], data)))
+}
+
+/// Payload of a window size change, as `adb shell` sends it.
+fn window_size(rows: u16, cols: u16) -> Vec<u8> {
+    format!("{}x{},0x0\0", rows, cols).into_bytes()
+}
+
+/// Forwards everything the shell prints until it exits or the stream closes.
+fn pump_output(input: &mut impl Read, on_event: &dyn Fn(PtyEvent)) {
+    let mut pending: Vec<u8> = Vec::new();
+    let mut exit_code = None;
+
+    loop {
+        match read_packet(input) {
+            Ok(Some((ID_STDOUT | ID_STDERR, data))) => {
+                pending.extend_from_slice(&data);
+                let complete = complete_utf8_len(&pending);
+                if complete > 0 {
+                    let data = String::from_utf8_lossy(&pending[..complete]).to_string();
+                    pending.drain(..complete);
+                    on_event(PtyEvent::Output { data });
+                }
+            }
+            Ok(Some((ID_EXIT, data))) => exit_code = data.first().copied(),
+            // Nothing else is sent towards the client
+            Ok(Some(_)) => {}
+            // A local close shows up as an error on some platforms
+            Ok(None) | Err(_) => break,
+        }
+    }
+
+    if !pending.is_empty() {
+        on_event(PtyEvent::Output {
+            data: String::from_utf8_lossy(&pending).to_string(),
+        });
+    }
+    on_event(PtyEvent::Exit { exit_code });
+}
+
+impl PtySessions {
+    fn lock(&self) -> MutexGuard<'_, HashMap<u32, PtySession>> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Starts a login shell on a PTY of `rows`x`cols` and streams its output
+    /// through `on_event` until it exits.
+    pub fn open(
+        &self,
+        serial_no: &str,
+        rows: u16,
+        cols: u16,
+        on_event: impl Fn(PtyEvent) + Send + 'static,
+    ) -> Result<u32, DroidKitError> {
+        // Gives a useful error when the device is offline or unauthorized
+        match open_server_device(serial_no) {
+            Ok(_) => {}
+            Err(DroidKitError::NoDevice(_)) => {
+                return Err(DroidKitError::InvalidInput(format!(
+                    "Interactive shells need {} to be reachable through the adb server; \
+                     devices connected directly over USB or TCP only run one-off commands",
+                    serial_no
+                )));
+            }
+            Err(e) => return Err(e.context("Failed to open a shell")),
+        }
+
+        let mut stream = server_protocol::open_device_service(
+            Some(serial_no),
+            "shell,v2,TERM=xterm-256color,pty:",
+        )
+        .map_err(|e| DroidKitError::from(e).context("Failed to open a shell"))?;
+        write_packet(&mut stream, ID_WINDOW_SIZE_CHANGE, &window_size(rows, cols))?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut input = stream.try_clone()?;
+        self.lock().insert(
+            id,
+            PtySession {
+                info: PtySessionInfo {
+                    id,
+                    serial_no: serial_no.to_string(),
+                    rows,
+                    cols,
+                },
+                stream,
+            },
+        );
+
+        let sessions = self.clone();
+        std::thread::spawn(move || {
+            pump_output(&mut input, &on_event);
+            sessions.lock().remove(&id);
+        });
+
+        Ok(id)
+    }
+
+    /// Updates session `id` with `f` and hands back its own handle to the
+    /// stream, so that a write blocked on a slow device holds up no other
+    /// session.
+    fn with_session(
+        &self,
+        id: u32,
+        f: impl FnOnce(&mut PtySession),
+    ) -> Result<TcpStream, DroidKitError> {
+        match self.lock().get_mut(&id) {
+            Some(session) => {
+                f(session);
+                Ok(session.stream.try_clone()?)
+            }
+            None => Err(DroidKitError::InvalidInput(format!(
+                "No shell session {}",
+                id
+            ))),
+        }
+    }
+
+    /// Sends keystrokes or pasted text to the shell.
+    pub fn write(&self, id: u32, data: &[u8]) -> Result<(), DroidKitError> {
+        let mut stream = self.with_session(id, |_| {})?;
+        Ok(write_packet(&mut stream, ID_STDIN, data)?)
+    }
+
+    pub fn resize(&self, id: u32, rows: u16, cols: u16) -> Result<(), DroidKitError> {
+        let mut stream = self.with_session(id, |session| {
+            session.info.rows = rows;
+            session.info.cols = cols;
+        })?;
+        Ok(write_packet(
+            &mut stream,
+            ID_WINDOW_SIZE_CHANGE,
+            &window_size(rows, cols),
+        )?)
+    }
+
+    /// Hangs up the shell; its output stream then ends with an exit event.
+    pub fn close(&self, id: u32) -> Result<(), DroidKitError> {
+        let stream = self.with_session(id, |_| {})?;
+        self.lock().remove(&id);
+        stream.shutdown(Shutdown::Both)?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<PtySessionInfo> {
+        let mut sessions: Vec<PtySessionInfo> = self
+            .lock()
+            .values()
+            .map(|session| session.info.clone())
+            .collect();
+        sessions.sort_by_key(|session| session.id);
+        sessions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn packets(packets: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (id, data) in packets {
+            write_packet(&mut bytes, *id, data).unwrap();
+        }
+        bytes
+    }
+
+    fn pump(bytes: Vec<u8>) -> Vec<PtyEvent> {
+        let events = RefCell::new(Vec::new());
+        pump_output(&mut io::Cursor::new(bytes), &|event| {
+            events.borrow_mut().push(event)
+        });
+        events.into_inner()
+    }
+
+    #[test]
+    fn packet_layout() {
+        assert_eq!(
+            packets(&[(ID_STDIN, b"ls\r")]),
+            [0, 3, 0, 0, 0, b'l', b's', b'\r']
+        );
+        assert_eq!(window_size(24, 80), b"24x80,0x0\0");
+    }
+
+    #[test]
+    fn output_then_exit_code() {
+        let events = pump(packets(&[
+            (ID_STDOUT, b"generic_x86:/ $ "),
+            (ID_STDOUT, b"exit\r\n"),
+            (ID_EXIT, &[0]),
+        ]));
+
+        let output: String = events
+            .iter()
+            .filter_map(|event| match event {
+                PtyEvent::Output { data } => Some(data.as_str()),
+                PtyEvent::Exit { .. } => None,
+            })
+            .collect();
+        assert_eq!(output, "generic_x86:/ $ exit\r\n");
+        assert!(matches!(
+            events.last(),
+            Some(PtyEvent::Exit { exit_code: Some(0) })
+        ));
+    }
+
+    #[test]
+    fn utf8_split_across_packets() {
+        let text = "ünïcode".as_bytes();
+        let events = pump(packets(&[(ID_STDOUT, &text[..1]), (ID_STDOUT, &text[1..])]));
+
+        assert!(matches!(&events[0], PtyEvent::Output { data } if data == "ünïcode"));
+    }
+
+    #[test]
+    fn dropped_connection_ends_without_exit_code() {
+        let mut bytes = packets(&[(ID_STDOUT, b"top - 12:00:01")]);
+        // Cut off in the middle of the next header
+        bytes.extend_from_slice(&[ID_STDOUT, 10]);
+        let events = pump(bytes);
+
+        assert!(matches!(
+            events.last(),
+            Some(PtyEvent::Exit { exit_code: None })
+        ));
+    }
+}
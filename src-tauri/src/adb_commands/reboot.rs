@@ -0,0 +1,372 @@
+//! Rebooting into the system or one of the boot modes, and `adb sideload`.
+
+use adb_client::RebootType;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::device::{
+    Device, UsbDeviceEntry, adb_server_available, list_server_devices, list_usb_devices,
+};
+use super::logcat::getprop_from_device;
+use super::server_protocol;
+use super::session::DeviceSessions;
+use crate::error::DroidKitError;
+use crate::fastboot::usb::list_fastboot_usb_devices;
+
+const BOOT_TIMEOUT: Duration = Duration::from_secs(300);
+/// Recovery and the bootloader come up well before Android would.
+const MODE_TIMEOUT: Duration = Duration::from_secs(120);
+const BOOT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Recovery asks for the package in blocks of this size, by index.
+const SIDELOAD_BLOCK_SIZE: usize = 64 * 1024;
+/// Sent instead of a block index once the device has everything it needs.
+const SIDELOAD_DONE: &[u8; 8] = b"DONEDONE";
+const SIDELOAD_PROGRESS_EVERY: u64 = 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RebootTarget {
+    System,
+    Bootloader,
+    Recovery,
+    /// Recovery, waiting for `adb sideload`.
+    Sideload,
+    /// fastbootd, the userspace fastboot of Android 10 and later.
+    Fastboot,
+}
+
+impl RebootTarget {
+    fn reboot_type(self) -> RebootType {
+        match self {
+            RebootTarget::System => RebootType::System,
+            RebootTarget::Bootloader => RebootType::Bootloader,
+            RebootTarget::Recovery => RebootType::Recovery,
+            RebootTarget::Sideload => RebootType::Sideload,
+            RebootTarget::Fastboot => RebootType::Fastboot,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub(crate) enum RebootEvent {
+    Rebooting {
+        target: RebootTarget,
+    },
+    /// The device dropped off adb.
+    Offline,
+    /// The device answers again but Android is still starting.
+    Booting,
+    /// The last event of a reboot into the system that did not fail.
+    Booted,
+    /// The last event of a reboot into any other mode, once the device
+    /// shows up in it: on adb for recovery and sideload, on fastboot for the
+    /// bootloader and fastbootd.
+    Left {
+        target: RebootTarget,
+    },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct SideloadProgress {
+    /// Recovery reads parts of the package more than once, verifying it
+    /// before installing, so this may pass `total_bytes`.
+    pub bytes_sent: u64,
+    pub total_bytes: u64,
+}
+
+impl Device {
+    pub fn reboot_into(&mut self, target: RebootTarget) -> Result<(), DroidKitError> {
+        self.as_ext()
+            .reboot(target.reboot_type())
+            .map_err(|e| DroidKitError::from(e).context("Failed to reboot"))
+    }
+}
+
+/// Where `serial_no` is listed for a reboot into `target`: `None` while it
+/// is nowhere, `Some(true)` once it is certainly in the new mode, and
+/// `Some(false)` when it is listed in a mode that cannot be told apart, like
+/// a device attached directly, which may not have gone down yet.
+fn listed_in_mode(serial_no: &str, target: RebootTarget) -> Option<bool> {
+    let has_serial = |entry: &UsbDeviceEntry| entry.serial_no.as_deref() == Some(serial_no);
+    let state = match target {
+        RebootTarget::System => return None,
+        RebootTarget::Bootloader | RebootTarget::Fastboot => {
+            return list_fastboot_usb_devices()
+                .is_ok_and(|devices| devices.iter().any(has_serial))
+                .then_some(true);
+        }
+        RebootTarget::Recovery => "recovery",
+        RebootTarget::Sideload => "sideload",
+    };
+
+    if let Ok(devices) = list_server_devices()
+        && let Some(device) = devices.iter().find(|device| device.serial_no == serial_no)
+    {
+        return Some(device.state == state);
+    }
+    list_usb_devices()
+        .is_ok_and(|devices| devices.iter().any(has_serial))
+        .then_some(false)
+}
+
+/// Waits for `serial_no` to show up in `target` after a reboot into it.
+fn wait_for_mode(
+    serial_no: &str,
+    target: RebootTarget,
+    on_event: &impl Fn(RebootEvent),
+) -> Result<(), DroidKitError> {
+    let started = Instant::now();
+    let mut offline = false;
+    while started.elapsed() < MODE_TIMEOUT {
+        thread::sleep(BOOT_POLL_INTERVAL);
+        match listed_in_mode(serial_no, target) {
+            None if !offline => {
+                offline = true;
+                on_event(RebootEvent::Offline);
+            }
+            None => {}
+            // Only counts once it has been seen gone
+            Some(false) if !offline => {}
+            Some(_) => {
+                on_event(RebootEvent::Left { target });
+                return Ok(());
+            }
+        }
+    }
+
+    Err(DroidKitError::Timeout(format!(
+        "{} did not show up in {:?} mode within {} seconds",
+        serial_no,
+        target,
+        MODE_TIMEOUT.as_secs()
+    )))
+}
+
+/// Reboots `serial_no` into `target` and waits for it to get there,
+/// reporting each step through `on_event`: for the system until
+/// `sys.boot_completed` is set again, for any other mode until the device is
+/// listed in it.
+pub(crate) fn reboot_and_wait(
+    sessions: &DeviceSessions,
+    serial_no: &str,
+    target: RebootTarget,
+    on_event: impl Fn(RebootEvent),
+) -> Result<(), DroidKitError> {
+    sessions.try_with_device(serial_no, |device| device.reboot_into(target))?;
+    sessions.evict(serial_no);
+    on_event(RebootEvent::Rebooting { target });
+
+    if target != RebootTarget::System {
+        return wait_for_mode(serial_no, target, &on_event);
+    }
+
+    // The device answers for a moment after the reboot is sent, with the
+    // previous boot's properties, so nothing counts until it has gone away
+    let started = Instant::now();
+    let mut offline = false;
+    let mut booting = false;
+    while started.elapsed() < BOOT_TIMEOUT {
+        thread::sleep(BOOT_POLL_INTERVAL);
+        let boot_completed = sessions.try_with_device(serial_no, |device| {
+            getprop_from_device(device, "sys.boot_completed")
+        });
+        match boot_completed {
+            Err(_) if !offline => {
+                offline = true;
+                on_event(RebootEvent::Offline);
+            }
+            Err(_) => {}
+            Ok(_) if !offline => {}
+            Ok(value) if value == "1" => {
+                on_event(RebootEvent::Booted);
+                return Ok(());
+            }
+            Ok(_) if !booting => {
+                booting = true;
+                on_event(RebootEvent::Booting);
+            }
+            Ok(_) => {}
+        }
+    }
+
+    Err(DroidKitError::Timeout(format!(
+        "{} did not finish booting within {} seconds",
+        serial_no,
+        BOOT_TIMEOUT.as_secs()
+    )))
+}
+
+/// Answers recovery's block requests from `package` until it says it is done.
+fn serve_sideload(
+    stream: &mut (impl Read + Write),
+    package: &mut (impl Read + Seek),
+    total_bytes: u64,
+    on_progress: &impl Fn(SideloadProgress),
+) -> Result<(), DroidKitError> {
+    let mut request = [0u8; 8];
+    let mut block = vec![0u8; SIDELOAD_BLOCK_SIZE];
+    let mut bytes_sent = 0;
+    let mut reported = 0;
+
+    loop {
+        if let Err(e) = stream.read_exact(&mut request) {
+            if e.kind() == ErrorKind::UnexpectedEof {
+                return Err(DroidKitError::Adb(
+                    "Recovery stopped the sideload; check the device screen".to_string(),
+                ));
+            }
+            return Err(e.into());
+        }
+        if &request == SIDELOAD_DONE {
+            on_progress(SideloadProgress {
+                bytes_sent,
+                total_bytes,
+            });
+            return Ok(());
+        }
+
+        let index: u64 = std::str::from_utf8(&request)
+            .ok()
+            .and_then(|index| index.trim_end_matches('\0').parse().ok())
+            .ok_or_else(|| {
+                DroidKitError::Parse(format!("Invalid sideload request: {:?}", request))
+            })?;
+        let offset = index * SIDELOAD_BLOCK_SIZE as u64;
+        if offset >= total_bytes {
+            return Err(DroidKitError::Adb(format!(
+                "Recovery asked for block {} past the end of the package",
+                index
+            )));
+        }
+
+        let len = (total_bytes - offset).min(SIDELOAD_BLOCK_SIZE as u64) as usize;
+        package.seek(SeekFrom::Start(offset))?;
+        package.read_exact(&mut block[..len])?;
+        stream.write_all(&block[..len])?;
+
+        bytes_sent += len as u64;
+        if bytes_sent - reported >= SIDELOAD_PROGRESS_EVERY {
+            reported = bytes_sent;
+            on_progress(SideloadProgress {
+                bytes_sent,
+                total_bytes,
+            });
+        }
+    }
+}
+
+/// `adb sideload`: sends the OTA package at `path` to `serial_no`, which must
+/// be in recovery waiting for it. Only the adb server can: adb_client does
+/// not open the `sideload-host` service on devices it holds directly, so
+/// without a running server this fails with [`DroidKitError::InvalidInput`].
+pub(crate) fn sideload_package(
+    serial_no: &str,
+    path: &Path,
+    on_progress: impl Fn(SideloadProgress),
+) -> Result<(), DroidKitError> {
+    if !adb_server_available() {
+        return Err(DroidKitError::InvalidInput(
+            "Sideloading goes through the adb server, which is not running; \
+             start it with `adb start-server`"
+                .to_string(),
+        ));
+    }
+
+    let mut package = File::open(path).map_err(|e| {
+        DroidKitError::from(e).context(&format!("Failed to open {}", path.display()))
+    })?;
+    let total_bytes = package.metadata()?.len();
+
+    let service = format!("sideload-host:{}:{}", total_bytes, SIDELOAD_BLOCK_SIZE);
+    let mut stream = server_protocol::open_device_service(Some(serial_no), &service)
+        .map_err(|e| DroidKitError::from(e).context("Failed to start sideload"))?;
+    println!("Sideloading {} to {}", path.display(), serial_no);
+
+    serve_sideload(&mut stream, &mut package, total_bytes, &on_progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+
+    /// Recovery's side of the connection: scripted requests in, blocks out.
+    struct FakeRecovery {
+        requests: Cursor<Vec<u8>>,
+        received: Vec<u8>,
+    }
+
+    impl FakeRecovery {
+        fn new(requests: &[&str]) -> Self {
+            FakeRecovery {
+                requests: Cursor::new(requests.concat().into_bytes()),
+                received: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for FakeRecovery {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.requests.read(buf)
+        }
+    }
+
+    impl Write for FakeRecovery {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.received.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn package(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn serves_requested_blocks() {
+        let data = package(150_000);
+        let mut recovery = FakeRecovery::new(&["00000002", "00000000", "DONEDONE"]);
+        let progress = RefCell::new(Vec::new());
+
+        serve_sideload(
+            &mut recovery,
+            &mut Cursor::new(&data),
+            data.len() as u64,
+            &|p: SideloadProgress| progress.borrow_mut().push(p.bytes_sent),
+        )
+        .unwrap();
+
+        // The last block is short
+        assert_eq!(recovery.received.len(), 18_928 + 65_536);
+        assert_eq!(recovery.received[..18_928], data[131_072..]);
+        assert_eq!(recovery.received[18_928..], data[..65_536]);
+        assert_eq!(progress.into_inner(), [84_464]);
+    }
+
+    #[test]
+    fn rejects_blocks_past_the_end() {
+        let data = package(1000);
+        let mut recovery = FakeRecovery::new(&["00000001"]);
+        let err =
+            serve_sideload(&mut recovery, &mut Cursor::new(&data), 1000, &|_| {}).unwrap_err();
+        assert_eq!(err.kind(), "Adb");
+    }
+
+    #[test]
+    fn recovery_hanging_up_is_an_error() {
+        let data = package(1000);
+        let mut recovery = FakeRecovery::new(&["00000000"]);
+        let err =
+            serve_sideload(&mut recovery, &mut Cursor::new(&data), 1000, &|_| {}).unwrap_err();
+        assert!(err.to_string().contains("stopped the sideload"));
+    }
+}
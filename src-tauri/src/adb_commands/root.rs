@@ -0,0 +1,402 @@
+//! Running device commands as root, through whichever route the device offers.
+
+use adb_client::AdbStatResponse;
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::adb_device::{AdbDevice, DirEntry};
+use super::capabilities::DeviceCapabilities;
+use super::command::{ShellCommand, quote};
+use super::device::DeviceTransport;
+use crate::error::DroidKitError;
+
+/// Writable by the `shell` user on every Android release.
+const PUSH_STAGING_DIR: &str = "/data/local/tmp";
+
+/// Numbers the staged copies of one process's uploads.
+static NEXT_STAGING_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Who a command ran as, reported back with its result.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RootMode {
+    /// The unprivileged `shell` user.
+    Shell,
+    /// adbd itself runs as root, after `adb root` on a userdebug or eng build.
+    AdbdRoot,
+    /// `su -c <command>`, as Magisk, KernelSU and SuperSU take it.
+    SuCommand,
+    /// `su 0 sh -c <command>`, for the `su` of AOSP userdebug builds.
+    SuUid,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Elevated<T> {
+    pub root_mode: RootMode,
+    pub value: T,
+}
+
+impl RootMode {
+    /// The mode to run in when the user asked for `elevated` or not. A device
+    /// whose adbd already runs as root runs everything as root.
+    pub fn choose(
+        capabilities: &DeviceCapabilities,
+        elevated: bool,
+    ) -> Result<RootMode, DroidKitError> {
+        if capabilities.adbd_root {
+            return Ok(RootMode::AdbdRoot);
+        }
+        if !elevated {
+            return Ok(RootMode::Shell);
+        }
+        if capabilities.su_command {
+            return Ok(RootMode::SuCommand);
+        }
+        if capabilities.su {
+            return Ok(RootMode::SuUid);
+        }
+
+        let hint = if capabilities.adb_root_available {
+            "; this build allows restarting adbd as root"
+        } else {
+            ""
+        };
+        Err(DroidKitError::NotRooted(format!(
+            "The device has no su binary and adbd is not running as root{}",
+            hint
+        )))
+    }
+
+    fn wrap(&self, command: &str) -> String {
+        match self {
+            RootMode::Shell | RootMode::AdbdRoot => command.to_string(),
+            RootMode::SuCommand => format!("su -c {}", quote(command)),
+            RootMode::SuUid => format!("su 0 sh -c {}", quote(command)),
+        }
+    }
+
+    /// Runs `f` against `device` in this mode and reports the mode with its result.
+    pub fn run<T>(
+        self,
+        device: &mut dyn AdbDevice,
+        f: impl FnOnce(&mut dyn AdbDevice) -> Result<T, DroidKitError>,
+    ) -> Result<Elevated<T>, DroidKitError> {
+        let value = match self {
+            // Nothing to wrap; adbd's sync service already runs as root
+            RootMode::Shell | RootMode::AdbdRoot => f(device)?,
+            RootMode::SuCommand | RootMode::SuUid => f(&mut SuDevice {
+                inner: device,
+                mode: self,
+            })?,
+        };
+        Ok(Elevated {
+            root_mode: self,
+            value,
+        })
+    }
+}
+
+/// Routes every command through `su`.
+struct SuDevice<'a> {
+    inner: &'a mut dyn AdbDevice,
+    mode: RootMode,
+}
+
+impl AdbDevice for SuDevice<'_> {
+    fn transport(&self) -> DeviceTransport {
+        self.inner.transport()
+    }
+
+    fn transport_serial(&self) -> Option<String> {
+        self.inner.transport_serial()
+    }
+
+    fn features(&mut self) -> Vec<String> {
+        self.inner.features()
+    }
+
+    fn shell(
+        &mut self,
+        command: &str,
+        output: &mut dyn Write,
+    ) -> Result<Option<u8>, DroidKitError> {
+        self.inner.shell(&self.mode.wrap(command), output)
+    }
+
+    fn shell_streams(
+        &mut self,
+        command: &str,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<Option<u8>, DroidKitError> {
+        self.inner
+            .shell_streams(&self.mode.wrap(command), stdout, stderr)
+    }
+
+    fn exec_out(&mut self, command: &str, output: &mut dyn Write) -> Result<(), DroidKitError> {
+        // adbd hands exec commands to `sh -c` as well
+        self.inner.exec_out(&self.mode.wrap(command), output)
+    }
+
+    /// The sync service runs as adbd's user, which cannot see into the paths
+    /// root is wanted for, so ask `stat` instead.
+    fn stat(&mut self, remote_path: &str) -> Result<AdbStatResponse, DroidKitError> {
+        let command = ShellCommand::new("stat")
+            .args(["-c", "%f %s %Y"])
+            .arg(remote_path)
+            .to_string();
+        let output = self.shell_output(&command)?;
+
+        if output.exit_code != Some(0) {
+            let message = [&output.stderr[..], &output.stdout[..]].concat();
+            // Like the sync service, report a missing path as an all-zero mode
+            if String::from_utf8_lossy(&message).contains("No such file") {
+                return Ok(AdbStatResponse {
+                    file_perm: 0,
+                    file_size: 0,
+                    mod_time: 0,
+                });
+            }
+        }
+        let output = output.into_result(&command)?;
+
+        let text = String::from_utf8_lossy(&output.stdout);
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let parsed = match fields[..] {
+            [mode, size, mod_time] => u32::from_str_radix(mode, 16)
+                .ok()
+                .zip(size.parse().ok())
+                .zip(mod_time.parse().ok()),
+            _ => None,
+        };
+        let ((file_perm, file_size), mod_time) = parsed
+            .ok_or_else(|| DroidKitError::Parse(format!("`{}` printed {:?}", command, text)))?;
+        Ok(AdbStatResponse {
+            file_perm,
+            file_size,
+            mod_time,
+        })
+    }
+
+    /// The same goes for `LIST`, so `stat` every entry instead.
+    fn list(&mut self, remote_path: &str) -> Result<Vec<DirEntry>, DroidKitError> {
+        let command = ShellCommand::new("find")
+            .arg(remote_path)
+            .args(["-mindepth", "1", "-maxdepth", "1", "-exec", "stat"])
+            .args(["-c", "%f %s %Y %n", "{}", "+"])
+            .to_string();
+        let output = self.shell_output(&command)?.into_result(&command)?;
+
+        let text = String::from_utf8_lossy(&output.stdout);
+        text.lines()
+            .map(|line| {
+                let fields: Vec<&str> = line.splitn(4, ' ').collect();
+                let parsed = match fields[..] {
+                    [mode, size, mod_time, path] => u32::from_str_radix(mode, 16)
+                        .ok()
+                        .zip(size.parse().ok())
+                        .zip(mod_time.parse().ok())
+                        .map(|((mode, size), mod_time)| DirEntry {
+                            name: path.rsplit('/').next().unwrap_or(path).to_string(),
+                            mode,
+                            size,
+                            mod_time,
+                        }),
+                    _ => None,
+                };
+                parsed.ok_or_else(|| {
+                    DroidKitError::Parse(format!("`{}` printed {:?}", command, line))
+                })
+            })
+            .collect()
+    }
+
+    /// For the same reason, copy the file out with `cat`. `exec` carries it
+    /// byte for byte, unlike a shell with a PTY.
+    fn pull(&mut self, remote_path: &str, output: &mut dyn Write) -> Result<(), DroidKitError> {
+        let command = ShellCommand::new("cat").arg(remote_path).to_string();
+        self.exec_out(&command, output)
+    }
+
+    /// Sends the file somewhere adbd's user can write, then moves it into
+    /// place as root. It stays owned by the `shell` user. The staged copy is
+    /// named after this process and upload, so concurrent uploads of the same
+    /// name do not collide, and removed if either step fails.
+    fn push(
+        &mut self,
+        input: &mut dyn Read,
+        remote_path: &str,
+        mode: u32,
+        mod_time: u32,
+    ) -> Result<(), DroidKitError> {
+        let name = remote_path.rsplit('/').next().unwrap_or_default();
+        let staging = format!(
+            "{}/.droidkit-{}-{}-{}",
+            PUSH_STAGING_DIR,
+            std::process::id(),
+            NEXT_STAGING_ID.fetch_add(1, Ordering::SeqCst),
+            name
+        );
+
+        let command = ShellCommand::new("mv")
+            .arg(&staging)
+            .arg(remote_path)
+            .to_string();
+        let result = self
+            .inner
+            .push(input, &staging, mode, mod_time)
+            .and_then(|()| {
+                self.shell_output(&command)
+                    .and_then(|output| output.into_result(&command))
+            });
+        if let Err(e) = result {
+            let cleanup = ShellCommand::new("rm").args(["-f", &staging]).to_string();
+            let _ = self.inner.shell(&cleanup, &mut std::io::sink());
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::FakeDevice;
+    use crate::adb_commands::logcat::execute_shell_command;
+
+    fn capabilities(su: bool, su_command: bool) -> DeviceCapabilities {
+        DeviceCapabilities {
+            su,
+            su_command,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chooses_the_best_route() {
+        let magisk = capabilities(true, true);
+        assert_eq!(RootMode::choose(&magisk, false).unwrap(), RootMode::Shell);
+        assert_eq!(
+            RootMode::choose(&magisk, true).unwrap(),
+            RootMode::SuCommand
+        );
+        assert_eq!(
+            RootMode::choose(&capabilities(true, false), true).unwrap(),
+            RootMode::SuUid
+        );
+
+        let adbd_root = DeviceCapabilities {
+            adbd_root: true,
+            su: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            RootMode::choose(&adbd_root, false).unwrap(),
+            RootMode::AdbdRoot
+        );
+
+        let err = RootMode::choose(&capabilities(false, false), true).unwrap_err();
+        assert_eq!(err.kind(), "NotRooted");
+    }
+
+    #[test]
+    fn commands_go_through_su() {
+        let mut device = FakeDevice::new()
+            .with_shell("su -c 'ls /data/data'", "com.android.settings\n")
+            .with_shell("su 0 sh -c 'setenforce 0'", "");
+
+        let listing = RootMode::SuCommand
+            .run(&mut device, |device| {
+                execute_shell_command(device, "ls /data/data")
+            })
+            .unwrap();
+        assert_eq!(listing.root_mode, RootMode::SuCommand);
+        assert_eq!(listing.value, "com.android.settings\n");
+
+        RootMode::SuUid
+            .run(&mut device, |device| {
+                execute_shell_command(device, "setenforce 0")
+            })
+            .unwrap();
+        RootMode::Shell
+            .run(&mut device, |device| execute_shell_command(device, "id"))
+            .unwrap();
+        assert_eq!(
+            device.calls,
+            ["su -c 'ls /data/data'", "su 0 sh -c 'setenforce 0'", "id"]
+        );
+    }
+
+    #[test]
+    fn stat_through_su() {
+        let mut device = FakeDevice::new()
+            .with_shell(
+                r"su -c 'stat -c '\''%f %s %Y'\'' /data/data/com.example/databases/app.db'",
+                "81b0 20480 1717171717\n",
+            )
+            .with_shell_failure(
+                r"su -c 'stat -c '\''%f %s %Y'\'' /data/missing'",
+                "stat: '/data/missing': No such file or directory\n",
+                1,
+            );
+
+        let stat = RootMode::SuCommand
+            .run(&mut device, |device| {
+                device.stat("/data/data/com.example/databases/app.db")
+            })
+            .unwrap()
+            .value;
+        assert_eq!(stat.file_perm, 0o100660);
+        assert_eq!(stat.file_size, 20480);
+        assert_eq!(stat.mod_time, 1717171717);
+
+        let missing = RootMode::SuCommand
+            .run(&mut device, |device| device.stat("/data/missing"))
+            .unwrap()
+            .value;
+        assert_eq!(missing.file_perm, 0);
+    }
+
+    #[test]
+    fn pull_through_su() {
+        let mut device = FakeDevice::new().with_exec_out(
+            "su -c 'cat /data/data/com.example/databases/app.db'",
+            b"SQLite format 3\0\r\n".to_vec(),
+        );
+
+        let mut data = Vec::new();
+        RootMode::SuCommand
+            .run(&mut device, |device| {
+                device.pull("/data/data/com.example/databases/app.db", &mut data)
+            })
+            .unwrap();
+        assert_eq!(data, b"SQLite format 3\0\r\n");
+    }
+
+    #[test]
+    fn failed_push_removes_its_unique_staged_copy() {
+        let mut device = FakeDevice::new();
+        for _ in 0..2 {
+            let err = RootMode::SuCommand
+                .run(&mut device, |device| {
+                    device.push(&mut &b"data"[..], "/data/app.db", 0o100660, 0)
+                })
+                .map(|elevated| elevated.value)
+                .unwrap_err();
+            assert_eq!(err.kind(), "CommandFailed");
+        }
+
+        let staged: Vec<&str> = device
+            .calls
+            .iter()
+            .filter_map(|call| call.strip_prefix("SEND "))
+            .collect();
+        assert_eq!(staged.len(), 2);
+        assert_ne!(staged[0], staged[1]);
+        for staged in staged {
+            let path = staged.split(',').next().unwrap();
+            assert!(path.ends_with("-app.db"));
+            assert!(device.calls.contains(&format!("rm -f {}", path)));
+        }
+    }
+}
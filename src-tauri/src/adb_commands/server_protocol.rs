@@ -0,0 +1,104 @@
+//! Minimal client for the adb server's smart-socket protocol, for the services
+//! `adb_client` does not expose (device tracking, raw transport streams).
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use super::device::ADB_SERVER_PORT;
+
+pub(crate) fn connect() -> std::io::Result<TcpStream> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, ADB_SERVER_PORT));
+    let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(2))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+fn read_exact_string(stream: &mut impl Read, len: usize) -> std::io::Result<String> {
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+/// Reads a block prefixed with its length as four hex digits.
+pub(crate) fn read_hex_block(stream: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let len_str = read_exact_string(stream, 4)?;
+    let len = usize::from_str_radix(&len_str, 16).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid length prefix from adb server: {:?}", len_str),
+        )
+    })?;
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Sends one service request and waits for the server's OKAY.
+pub(crate) fn send_request(stream: &mut TcpStream, service: &str) -> std::io::Result<()> {
+    stream.write_all(format!("{:04x}{}", service.len(), service).as_bytes())?;
+    read_status(stream, service)
+}
+
+/// Reads an OKAY, or turns a FAIL into an error. Some services answer twice:
+/// once on accepting the request and once when it is done.
+pub(crate) fn read_status(stream: &mut impl Read, service: &str) -> std::io::Result<()> {
+    match read_exact_string(stream, 4)?.as_str() {
+        "OKAY" => Ok(()),
+        "FAIL" => {
+            let message = String::from_utf8_lossy(&read_hex_block(stream)?).to_string();
+            Err(std::io::Error::other(format!(
+                "adb server rejected {}: {}",
+                service, message
+            )))
+        }
+        other => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Unexpected adb server reply to {}: {:?}", service, other),
+        )),
+    }
+}
+
+/// Switches a fresh connection to `serial_no` (any single device when `None`)
+/// and opens `service` on it, e.g. `exec:cat /proc/version`.
+pub(crate) fn open_device_service(
+    serial_no: Option<&str>,
+    service: &str,
+) -> std::io::Result<TcpStream> {
+    let mut stream = connect()?;
+    match serial_no {
+        Some(serial_no) => send_request(&mut stream, &format!("host:transport:{}", serial_no))?,
+        None => send_request(&mut stream, "host:transport-any")?,
+    }
+    send_request(&mut stream, service)?;
+    Ok(stream)
+}
+
+/// Features that both the adb server and `serial_no` (any single device when
+/// `None`) support, e.g. `shell_v2`.
+pub(crate) fn device_features(serial_no: Option<&str>) -> std::io::Result<Vec<String>> {
+    let mut stream = connect()?;
+    match serial_no {
+        Some(serial_no) => {
+            send_request(&mut stream, &format!("host-serial:{}:features", serial_no))?
+        }
+        None => send_request(&mut stream, "host:features")?,
+    }
+    let block = read_hex_block(&mut stream)?;
+    Ok(String::from_utf8_lossy(&block)
+        .split(',')
+        .map(|feature| feature.trim().to_string())
+        .filter(|feature| !feature.is_empty())
+        .collect())
+}
+
+/// Parses a `host:devices`/`host:track-devices` block into `(serial, state)` pairs.
+pub(crate) fn parse_device_list(block: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(block)
+        .lines()
+        .filter_map(|line| {
+            let (serial, state) = line.split_once('\t')?;
+            Some((serial.trim().to_string(), state.trim().to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use super::adb_device::AdbDevice;
+use super::capabilities::{DeviceCapabilities, probe_capabilities};
+use super::device::{Device, reconnect_device};
+use super::forward::PortRules;
+use crate::error::DroidKitError;
+
+/// Handles that sat idle for longer than this are probed before being reused,
+/// since a cable pull or `adb kill-server` leaves them dead without notice.
+const IDLE_PROBE_AFTER: Duration = Duration::from_secs(10);
+
+struct DeviceSession {
+    device: Device,
+    last_used: Instant,
+}
+
+type SessionSlot = Arc<Mutex<Option<DeviceSession>>>;
+
+/// Registry of open device handles keyed by serial, held in Tauri state.
+///
+/// Each serial gets its own slot, so commands for different devices run in
+/// parallel while commands for the same device are serialized on its handle.
+///
+/// Probed capabilities are kept until the handle is dropped, since a reboot
+/// or `adb root` in between disconnects the device anyway. Port rules
+/// outlive it and are re-applied on every new connection.
+#[derive(Clone, Default)]
+pub(crate) struct DeviceSessions {
+    slots: Arc<Mutex<HashMap<String, SessionSlot>>>,
+    capabilities: Arc<Mutex<HashMap<String, DeviceCapabilities>>>,
+    port_rules: PortRules,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A panic inside a command must not wedge the device for the rest of the session
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Device {
+    fn is_alive(&mut self) -> bool {
+        self.shell_command(&"true", &mut std::io::sink()).is_ok()
+    }
+}
+
+impl DeviceSessions {
+    fn slot(&self, serial_no: &str) -> SessionSlot {
+        lock(&self.slots)
+            .entry(serial_no.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Runs `f` against the open handle for `serial_no`, connecting first if
+    /// there is none or the previous one has died.
+    pub fn with_device<T>(
+        &self,
+        serial_no: &str,
+        f: impl FnOnce(&mut Device) -> T,
+    ) -> Result<T, DroidKitError> {
+        let slot = self.slot(serial_no);
+        let mut guard = lock(&slot);
+
+        if let Some(session) = guard.as_mut()
+            && session.last_used.elapsed() > IDLE_PROBE_AFTER
+            && !session.device.is_alive()
+        {
+            println!("Dropping dead session for {}", serial_no);
+            *guard = None;
+            self.forget_capabilities(serial_no);
+        }
+
+        let session = match guard.as_mut() {
+            Some(session) => session,
+            None => {
+                let mut device = reconnect_device(serial_no)?;
+                self.port_rules.reapply(serial_no, &mut device);
+                guard.insert(DeviceSession {
+                    device,
+                    last_used: Instant::now(),
+                })
+            }
+        };
+
+        let result = f(&mut session.device);
+        session.last_used = Instant::now();
+        Ok(result)
+    }
+
+    /// Like [`with_device`](Self::with_device) for fallible work: when `f`
+    /// fails and the handle no longer answers, it is evicted so the next call reconnects.
+    pub fn try_with_device<T>(
+        &self,
+        serial_no: &str,
+        f: impl FnOnce(&mut Device) -> Result<T, DroidKitError>,
+    ) -> Result<T, DroidKitError> {
+        let slot = self.slot(serial_no);
+        let result = self.with_device(serial_no, f)?;
+
+        if result.is_err() {
+            let mut guard = lock(&slot);
+            if guard
+                .as_mut()
+                .is_some_and(|session| !session.device.is_alive())
+            {
+                println!("Evicting dead session for {}", serial_no);
+                *guard = None;
+                self.forget_capabilities(serial_no);
+            }
+        }
+
+        result
+    }
+
+    /// Adopts an already connected handle, e.g. right after a wireless connect.
+    pub fn insert(&self, serial_no: &str, device: Device) {
+        let slot = self.slot(serial_no);
+        *lock(&slot) = Some(DeviceSession {
+            device,
+            last_used: Instant::now(),
+        });
+        self.forget_capabilities(serial_no);
+    }
+
+    /// Drops the handle for `serial_no`, waiting for any command using it to finish.
+    pub fn evict(&self, serial_no: &str) {
+        let slot = lock(&self.slots).remove(serial_no);
+        if let Some(slot) = slot {
+            *lock(&slot) = None;
+        }
+        self.forget_capabilities(serial_no);
+    }
+
+    pub fn port_rules(&self) -> &PortRules {
+        &self.port_rules
+    }
+
+    /// Capabilities of `serial_no`, probed through `device` on first use.
+    /// Call it from inside [`with_device`](Self::with_device).
+    pub fn capabilities(
+        &self,
+        serial_no: &str,
+        device: &mut dyn AdbDevice,
+    ) -> Result<DeviceCapabilities, DroidKitError> {
+        if let Some(capabilities) = lock(&self.capabilities).get(serial_no) {
+            return Ok(capabilities.clone());
+        }
+
+        let capabilities = probe_capabilities(device)?;
+        println!("Probed {}: {:?}", serial_no, capabilities);
+        lock(&self.capabilities).insert(serial_no.to_string(), capabilities.clone());
+        Ok(capabilities)
+    }
+
+    fn forget_capabilities(&self, serial_no: &str) {
+        lock(&self.capabilities).remove(serial_no);
+    }
+}
@@ -0,0 +1,209 @@
+use serde::{Serialize, Serializer};
+use std::io::{self, Write};
+
+use super::adb_device::AdbDevice;
+use crate::error::DroidKitError;
+
+/// Printed after the command on transports without shell v2, followed by `$?`.
+const EXIT_MARKER: &str = "\u{1e}droidkit-exit:";
+
+/// Result of a shell command with its streams kept apart.
+#[derive(Serialize, Debug, Default)]
+pub(crate) struct ShellOutput {
+    #[serde(serialize_with = "lossy_utf8")]
+    pub stdout: Vec<u8>,
+    /// Always empty when the transport merges stderr into stdout (no shell v2).
+    #[serde(serialize_with = "lossy_utf8")]
+    pub stderr: Vec<u8>,
+    /// `None` when the device never reported one, e.g. the connection dropped.
+    pub exit_code: Option<u8>,
+}
+
+fn lossy_utf8<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from_utf8_lossy(bytes))
+}
+
+impl ShellOutput {
+    /// Turns a nonzero exit code into [`DroidKitError::CommandFailed`].
+    pub fn into_result(self, command: &str) -> Result<Self, DroidKitError> {
+        match self.exit_code {
+            Some(code) if code != 0 => {
+                // Without shell v2 the error message ends up on stdout
+                let stderr = if self.stderr.is_empty() {
+                    &self.stdout
+                } else {
+                    &self.stderr
+                };
+                Err(DroidKitError::CommandFailed {
+                    command: command.to_string(),
+                    exit_code: Some(code),
+                    stderr: String::from_utf8_lossy(stderr).to_string(),
+                })
+            }
+            _ => Ok(self),
+        }
+    }
+}
+
+/// Appends a trailer that reports the exit status of `command` on stdout, for
+/// transports that only carry a single stream. The newline ends any trailing
+/// comment in `command` so the trailer still runs.
+pub(crate) fn with_exit_marker(command: &str) -> String {
+    format!("{}\nprintf '%s%d' '{}' $?", command, EXIT_MARKER)
+}
+
+/// Strips the trailer added by [`with_exit_marker`] from a stdout stream,
+/// passing everything else through as it arrives.
+pub(crate) struct ExitMarkerWriter<'a> {
+    inner: &'a mut dyn Write,
+    /// Bytes that may be the start of the trailer.
+    pending: Vec<u8>,
+}
+
+impl<'a> ExitMarkerWriter<'a> {
+    pub fn new(inner: &'a mut dyn Write) -> Self {
+        ExitMarkerWriter {
+            inner,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Writes out anything held back and returns the exit code, if the
+    /// trailer arrived.
+    pub fn finish(self) -> io::Result<Option<u8>> {
+        let marker = EXIT_MARKER.as_bytes();
+        if let Some(code) = self.pending.strip_prefix(marker) {
+            return Ok(std::str::from_utf8(code)
+                .ok()
+                .and_then(|code| code.trim().parse().ok()));
+        }
+
+        // The connection went away before the trailer ran
+        self.inner.write_all(&self.pending)?;
+        self.inner.flush()?;
+        Ok(None)
+    }
+}
+
+impl Write for ExitMarkerWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+
+        // The trailer is at most the marker and three digits, so only a marker
+        // start that close to the end needs holding back
+        let window = self.pending.len().saturating_sub(EXIT_MARKER.len() + 3);
+        let hold_from = self.pending[window..]
+            .iter()
+            .position(|&byte| byte == EXIT_MARKER.as_bytes()[0])
+            .map_or(self.pending.len(), |position| window + position);
+
+        self.inner.write_all(&self.pending[..hold_from])?;
+        self.pending.drain(..hold_from);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub(crate) fn run_shell_command(
+    device: &mut dyn AdbDevice,
+    command: &str,
+) -> Result<ShellOutput, DroidKitError> {
+    if command.trim().is_empty() {
+        return Err(DroidKitError::InvalidInput("Empty command".to_string()));
+    }
+
+    device
+        .shell_output(command)
+        .map_err(|e| e.context("Command execution failed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::FakeDevice;
+
+    fn strip_exit_marker(chunks: &[&[u8]]) -> (Vec<u8>, Option<u8>) {
+        let mut stdout = Vec::new();
+        let mut writer = ExitMarkerWriter::new(&mut stdout);
+        for chunk in chunks {
+            writer.write_all(chunk).unwrap();
+        }
+        let exit_code = writer.finish().unwrap();
+        (stdout, exit_code)
+    }
+
+    #[test]
+    fn exit_marker_round_trip() {
+        let command = with_exit_marker("ls /nope # comment");
+        assert!(command.starts_with("ls /nope # comment\n"));
+
+        let output = format!("ls: /nope: No such file or directory\n{}1", EXIT_MARKER);
+        let (stdout, exit_code) = strip_exit_marker(&[output.as_bytes()]);
+        assert_eq!(stdout, b"ls: /nope: No such file or directory\n");
+        assert_eq!(exit_code, Some(1));
+    }
+
+    #[test]
+    fn exit_marker_split_across_chunks() {
+        let output = format!("abc{}130", EXIT_MARKER);
+        let (head, tail) = output.as_bytes().split_at(6);
+        let (stdout, exit_code) = strip_exit_marker(&[head, tail]);
+        assert_eq!(stdout, b"abc");
+        assert_eq!(exit_code, Some(130));
+    }
+
+    #[test]
+    fn output_is_passed_through_before_the_trailer() {
+        let mut stdout = Vec::new();
+        let mut writer = ExitMarkerWriter::new(&mut stdout);
+        writer.write_all(b"64 bytes from 8.8.8.8\n").unwrap();
+        drop(writer);
+        assert_eq!(stdout, b"64 bytes from 8.8.8.8\n");
+    }
+
+    #[test]
+    fn missing_exit_marker_keeps_output() {
+        // The connection went away before the trailer ran
+        let (stdout, exit_code) = strip_exit_marker(&[b"partial\x1e"]);
+        assert_eq!(stdout, b"partial\x1e");
+        assert_eq!(exit_code, None);
+    }
+
+    #[test]
+    fn failed_command_keeps_streams_apart() {
+        let mut device = FakeDevice::new().with_shell_failure(
+            "cat /data/system/packages.xml",
+            "cat: /data/system/packages.xml: Permission denied\n",
+            1,
+        );
+        let output = run_shell_command(&mut device, "cat /data/system/packages.xml").unwrap();
+        assert!(output.stdout.is_empty());
+        assert_eq!(output.exit_code, Some(1));
+
+        match output.into_result("cat /data/system/packages.xml") {
+            Err(DroidKitError::CommandFailed {
+                exit_code, stderr, ..
+            }) => {
+                assert_eq!(exit_code, Some(1));
+                assert!(stderr.contains("Permission denied"));
+            }
+            other => panic!("expected CommandFailed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn serializes_streams_as_text() {
+        let output = ShellOutput {
+            stdout: b"hello\n".to_vec(),
+            stderr: Vec::new(),
+            exit_code: Some(0),
+        };
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!({ "stdout": "hello\n", "stderr": "", "exit_code": 0 })
+        );
+    }
+}
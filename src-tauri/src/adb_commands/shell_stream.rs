@@ -0,0 +1,441 @@
+use adb_client::tcp::ADBTcpDevice;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::adb_device::AdbDevice;
+use super::device::{Device, open_server_device};
+use crate::error::DroidKitError;
+
+/// Printed ahead of the command, followed by the pid of the shell running it.
+const PID_MARKER: &str = "\u{1e}droidkit-pid:";
+
+/// Written every second by commands that have no second channel to be killed
+/// over, so a cancelled stream notices even when the command is silent.
+/// Never forwarded.
+const HEARTBEAT: u8 = 0x1f;
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub(crate) enum ShellEvent {
+    Stdout {
+        data: String,
+    },
+    Stderr {
+        data: String,
+    },
+    /// Always the last event of a stream that did not fail.
+    Exit {
+        exit_code: Option<u8>,
+        cancelled: bool,
+    },
+}
+
+pub(crate) struct StreamControl {
+    serial_no: String,
+    cancelled: AtomicBool,
+    remote_pid: Mutex<Option<u32>>,
+    heartbeat: AtomicBool,
+}
+
+impl StreamControl {
+    /// Runs the command alongside a [`HEARTBEAT`] ticker. For direct USB
+    /// connections, whose only channel the stream itself is holding.
+    pub fn use_heartbeat(&self) {
+        self.heartbeat.store(true, Ordering::SeqCst);
+    }
+
+    fn has_heartbeat(&self) -> bool {
+        self.heartbeat.load(Ordering::SeqCst)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn remote_pid(&self) -> Option<u32> {
+        *self
+            .remote_pid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Running streamed commands keyed by the id handed to the frontend, held in
+/// Tauri state so they can be cancelled.
+#[derive(Clone, Default)]
+pub(crate) struct ShellStreams {
+    next_id: Arc<AtomicU32>,
+    running: Arc<Mutex<HashMap<u32, Arc<StreamControl>>>>,
+}
+
+impl ShellStreams {
+    pub fn start(&self, serial_no: &str) -> (u32, Arc<StreamControl>) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let control = Arc::new(StreamControl {
+            serial_no: serial_no.to_string(),
+            cancelled: AtomicBool::new(false),
+            remote_pid: Mutex::new(None),
+            heartbeat: AtomicBool::new(false),
+        });
+        self.running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, control.clone());
+        (id, control)
+    }
+
+    pub fn finish(&self, id: u32) {
+        self.running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&id);
+    }
+
+    /// Stops forwarding output for `id` and kills the remote process.
+    ///
+    /// The kill goes over a second channel so it does not queue behind the
+    /// stream on the device's session: the adb server, or a connection of its
+    /// own to a direct TCP device. A direct USB device cannot be opened twice;
+    /// its streams carry a heartbeat instead, and are stopped, and the process
+    /// killed on the stream's own channel, within a second.
+    pub fn cancel(&self, id: u32) {
+        let control = self
+            .running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&id)
+            .cloned();
+        let Some(control) = control else {
+            return;
+        };
+
+        control.cancelled.store(true, Ordering::SeqCst);
+        if let Some(pid) = control.remote_pid() {
+            std::thread::spawn(move || {
+                if let Some(mut device) = open_second_channel(&control.serial_no) {
+                    let _ = kill_remote_process(&mut device, pid);
+                }
+            });
+        }
+    }
+}
+
+/// A connection to `serial_no` besides the one held by its session, where
+/// the transport allows one.
+fn open_second_channel(serial_no: &str) -> Option<Device> {
+    if let Ok(device) = open_server_device(serial_no) {
+        return Some(Device::Server(device));
+    }
+    let socket_addr = serial_no.parse::<SocketAddr>().ok()?;
+    ADBTcpDevice::new(socket_addr).ok().map(Device::TCP)
+}
+
+/// Prefixes `command` with a line reporting the pid of the shell running it,
+/// and with `heartbeat`, a background loop writing [`HEARTBEAT`] to stderr
+/// until that shell exits. adbd starts each shell in its own session, so the
+/// pid is also the process group of everything the command spawns.
+fn with_pid_report(command: &str, heartbeat: bool) -> String {
+    let ticker = if heartbeat {
+        format!(
+            "(while kill -0 $$ 2>/dev/null; do sleep 1; printf '\\{:03o}' >&2; done) >/dev/null &\n",
+            HEARTBEAT
+        )
+    } else {
+        String::new()
+    };
+    format!("echo '{}'$$\n{}{}", PID_MARKER, ticker, command)
+}
+
+fn kill_remote_process(device: &mut dyn AdbDevice, pid: u32) -> Result<(), DroidKitError> {
+    device
+        .shell_output(&format!(
+            "kill -TERM -{pid} 2>/dev/null || kill -TERM {pid}",
+            pid = pid
+        ))?
+        .into_result("kill")?;
+    Ok(())
+}
+
+/// How much of `bytes` can be decoded now, holding back a UTF-8 sequence
+/// cut off at the end of a chunk. Invalid bytes are not held back.
+pub(crate) fn complete_utf8_len(bytes: &[u8]) -> usize {
+    match std::str::from_utf8(bytes) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => bytes.len(),
+    }
+}
+
+/// Forwards one output stream as [`ShellEvent`]s, never splitting a UTF-8
+/// sequence across two events.
+struct EventWriter<'a> {
+    control: &'a StreamControl,
+    on_event: &'a dyn Fn(ShellEvent),
+    stderr: bool,
+    /// Stdout starts with the line added by [`with_pid_report`].
+    expect_pid: bool,
+    pending: Vec<u8>,
+}
+
+impl<'a> EventWriter<'a> {
+    fn new(control: &'a StreamControl, on_event: &'a dyn Fn(ShellEvent), stderr: bool) -> Self {
+        EventWriter {
+            control,
+            on_event,
+            stderr,
+            expect_pid: !stderr,
+            pending: Vec::new(),
+        }
+    }
+
+    fn take_pid_line(&mut self) {
+        let Some(newline) = self.pending.iter().position(|&byte| byte == b'\n') else {
+            return;
+        };
+
+        let line = String::from_utf8_lossy(&self.pending[..newline]).to_string();
+        // Anything else is not our line after all; leave it in the output
+        if let Some(Ok(pid)) = line.strip_prefix(PID_MARKER).map(str::parse) {
+            *self
+                .control
+                .remote_pid
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(pid);
+            self.pending.drain(..=newline);
+        }
+        self.expect_pid = false;
+    }
+
+    fn emit(&mut self, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        let data = String::from_utf8_lossy(&self.pending[..bytes]).to_string();
+        self.pending.drain(..bytes);
+        (self.on_event)(if self.stderr {
+            ShellEvent::Stderr { data }
+        } else {
+            ShellEvent::Stdout { data }
+        });
+    }
+
+    fn finish(&mut self) {
+        if !self.control.is_cancelled() {
+            self.emit(self.pending.len());
+        }
+    }
+}
+
+impl Write for EventWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.control.is_cancelled() {
+            // Not `Interrupted`, which `write_all` would retry
+            return Err(io::Error::other("Cancelled"));
+        }
+
+        if self.control.has_heartbeat() {
+            // Legacy shell merges stderr into stdout, so look in both
+            self.pending
+                .extend(buf.iter().filter(|&&byte| byte != HEARTBEAT));
+        } else {
+            self.pending.extend_from_slice(buf);
+        }
+        if self.expect_pid {
+            self.take_pid_line();
+            if self.expect_pid {
+                return Ok(buf.len());
+            }
+        }
+
+        self.emit(complete_utf8_len(&self.pending));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `command`, sending its output through `on_event` as it arrives and
+/// finishing with [`ShellEvent::Exit`].
+pub(crate) fn stream_shell_command(
+    device: &mut dyn AdbDevice,
+    command: &str,
+    control: &StreamControl,
+    on_event: &dyn Fn(ShellEvent),
+) -> Result<(), DroidKitError> {
+    if command.trim().is_empty() {
+        return Err(DroidKitError::InvalidInput("Empty command".to_string()));
+    }
+
+    let mut stdout = EventWriter::new(control, on_event, false);
+    let mut stderr = EventWriter::new(control, on_event, true);
+    let result = device.shell_streams(
+        &with_pid_report(command, control.has_heartbeat()),
+        &mut stdout,
+        &mut stderr,
+    );
+    stdout.finish();
+    stderr.finish();
+
+    match result {
+        Ok(exit_code) => {
+            on_event(ShellEvent::Exit {
+                exit_code,
+                cancelled: control.is_cancelled(),
+            });
+            Ok(())
+        }
+        Err(_) if control.is_cancelled() => {
+            // We stopped reading; make sure the process does not keep running
+            if let Some(pid) = control.remote_pid() {
+                let _ = kill_remote_process(device, pid);
+            }
+            on_event(ShellEvent::Exit {
+                exit_code: None,
+                cancelled: true,
+            });
+            Ok(())
+        }
+        Err(e) => Err(e.context("Command execution failed")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::FakeDevice;
+    use std::cell::RefCell;
+
+    fn run(device: &mut FakeDevice, command: &str, control: &StreamControl) -> Vec<ShellEvent> {
+        let events = RefCell::new(Vec::new());
+        stream_shell_command(device, command, control, &|event| {
+            events.borrow_mut().push(event)
+        })
+        .unwrap();
+        events.into_inner()
+    }
+
+    fn control() -> Arc<StreamControl> {
+        ShellStreams::default().start("emulator-5554").1
+    }
+
+    #[test]
+    fn streams_output_and_ends_with_exit() {
+        let mut device = FakeDevice::new().with_shell(
+            &with_pid_report("ping -c 1 10.0.2.2", false),
+            format!(
+                "{}4242\nPING 10.0.2.2 (10.0.2.2) 56(84) bytes of data.\n",
+                PID_MARKER
+            ),
+        );
+        let control = control();
+        let events = run(&mut device, "ping -c 1 10.0.2.2", &control);
+
+        assert_eq!(control.remote_pid(), Some(4242));
+        assert!(matches!(
+            &events[0],
+            ShellEvent::Stdout { data } if data == "PING 10.0.2.2 (10.0.2.2) 56(84) bytes of data.\n"
+        ));
+        assert!(matches!(
+            events.last(),
+            Some(ShellEvent::Exit {
+                exit_code: Some(0),
+                cancelled: false
+            })
+        ));
+    }
+
+    #[test]
+    fn stderr_and_failures_are_reported() {
+        let mut device = FakeDevice::new().with_shell_failure(
+            &with_pid_report("ls /data", false),
+            "ls: /data: Permission denied\n",
+            1,
+        );
+        let events = run(&mut device, "ls /data", &control());
+
+        assert!(matches!(
+            &events[0],
+            ShellEvent::Stderr { data } if data.contains("Permission denied")
+        ));
+        assert!(matches!(
+            events.last(),
+            Some(ShellEvent::Exit {
+                exit_code: Some(1),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn cancelled_stream_stops_and_kills_the_process() {
+        let mut device = FakeDevice::new()
+            .with_shell_status(
+                &with_pid_report("logcat", false),
+                format!("{}4242\n--------- beginning of main\n", PID_MARKER),
+                None,
+            )
+            .with_shell("kill -TERM -4242 2>/dev/null || kill -TERM 4242", "");
+        let control = control();
+        *control.remote_pid.lock().unwrap() = Some(4242);
+        control.cancelled.store(true, Ordering::SeqCst);
+
+        let events = run(&mut device, "logcat", &control);
+
+        assert!(matches!(
+            events.as_slice(),
+            [ShellEvent::Exit {
+                exit_code: None,
+                cancelled: true
+            }]
+        ));
+        assert!(device.calls.iter().any(|call| call.starts_with("kill ")));
+    }
+
+    #[test]
+    fn heartbeat_is_never_forwarded() {
+        let command = with_pid_report("sleep 2; echo done", true);
+        assert!(command.contains("printf '\\037' >&2"));
+        let mut device = FakeDevice::new().with_shell_status(
+            &command,
+            format!("{}4242\n\u{1f}\u{1f}done\n", PID_MARKER),
+            None,
+        );
+        let control = control();
+        control.use_heartbeat();
+
+        let events = run(&mut device, "sleep 2; echo done", &control);
+
+        assert!(matches!(&events[0], ShellEvent::Stdout { data } if data == "done\n"));
+        assert_eq!(control.remote_pid(), Some(4242));
+    }
+
+    #[test]
+    fn utf8_split_across_chunks_is_kept_whole() {
+        let control = control();
+        let events = RefCell::new(Vec::new());
+        let on_event = |event| events.borrow_mut().push(event);
+        let mut writer = EventWriter::new(&control, &on_event, true);
+
+        let text = "température\n".as_bytes();
+        // Split inside the two-byte 'é'
+        writer.write_all(&text[..5]).unwrap();
+        writer.write_all(&text[5..]).unwrap();
+        writer.finish();
+
+        let events = events.into_inner();
+        let joined: String = events
+            .iter()
+            .map(|event| match event {
+                ShellEvent::Stderr { data } => data.as_str(),
+                _ => "",
+            })
+            .collect();
+        assert_eq!(joined, "température\n");
+        assert!(!joined.contains('\u{fffd}'));
+    }
+}
@@ -0,0 +1,164 @@
+//! The parts of adb's sync protocol that adb_client leaves out, spoken over a
+//! `sync:` stream from the adb server. adb_client's `SEND` always creates
+//! files with mode 0777 and the current time.
+
+use std::io::{Read, Write};
+
+use crate::error::DroidKitError;
+
+/// adbd refuses `DATA` packets larger than this.
+const SYNC_DATA_MAX: usize = 64 * 1024;
+
+fn write_packet(stream: &mut impl Write, id: &[u8; 4], arg: u32) -> std::io::Result<()> {
+    stream.write_all(id)?;
+    stream.write_all(&arg.to_le_bytes())
+}
+
+/// Reads the `OKAY` that ends a request, or turns a `FAIL` into an error.
+fn read_status(stream: &mut impl Read, remote_path: &str) -> Result<(), DroidKitError> {
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header)?;
+    let (id, len) = header.split_at(4);
+    let len = u32::from_le_bytes(len.try_into().expect("four bytes"));
+
+    match id {
+        b"OKAY" => Ok(()),
+        b"FAIL" => {
+            let mut message = vec![0u8; len as usize];
+            stream.read_exact(&mut message)?;
+            Err(DroidKitError::Adb(format!(
+                "Failed to write {}: {}",
+                remote_path,
+                String::from_utf8_lossy(&message)
+            )))
+        }
+        other => Err(DroidKitError::Parse(format!(
+            "Unexpected sync reply: {:?}",
+            String::from_utf8_lossy(other)
+        ))),
+    }
+}
+
+fn write_file(
+    stream: &mut impl Write,
+    input: &mut dyn Read,
+    remote_path: &str,
+    mode: u32,
+    mod_time: u32,
+) -> Result<(), DroidKitError> {
+    // adbd reads the mode with strtoul, so decimal is fine
+    let path_and_mode = format!("{},{}", remote_path, mode);
+    let len = u32::try_from(path_and_mode.len())
+        .map_err(|_| DroidKitError::InvalidInput("Remote path too long".to_string()))?;
+    write_packet(stream, b"SEND", len)?;
+    stream.write_all(path_and_mode.as_bytes())?;
+
+    let mut buf = vec![0u8; SYNC_DATA_MAX];
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        write_packet(stream, b"DATA", n as u32)?;
+        stream.write_all(&buf[..n])?;
+    }
+    write_packet(stream, b"DONE", mod_time)?;
+    Ok(())
+}
+
+/// `SEND`s `input` to `remote_path`, creating it with `mode` (including the
+/// file type bits) and `mod_time`.
+pub(crate) fn send_file(
+    stream: &mut (impl Read + Write),
+    input: &mut dyn Read,
+    remote_path: &str,
+    mode: u32,
+    mod_time: u32,
+) -> Result<(), DroidKitError> {
+    // adbd answers a file it cannot create with a FAIL and hangs up, which
+    // only shows here as a write error; its message says more
+    if let Err(e) = write_file(stream, input, remote_path, mode, mod_time) {
+        return Err(read_status(stream, remote_path).err().unwrap_or(e));
+    }
+    read_status(stream, remote_path)?;
+    write_packet(stream, b"QUIT", 0)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// adbd's side of the stream: scripted replies in, requests out.
+    struct FakeSync {
+        replies: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for FakeSync {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for FakeSync {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fake_sync(replies: &[u8]) -> FakeSync {
+        FakeSync {
+            replies: Cursor::new(replies.to_vec()),
+            sent: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sends_mode_and_time() {
+        let mut stream = fake_sync(b"OKAY\0\0\0\0");
+        let data = vec![7u8; SYNC_DATA_MAX + 10];
+
+        send_file(
+            &mut stream,
+            &mut Cursor::new(&data),
+            "/sdcard/a b.txt",
+            0o100644,
+            1717171717,
+        )
+        .unwrap();
+
+        let mut expected = b"SEND\x15\0\0\0/sdcard/a b.txt,33188".to_vec();
+        expected.extend(b"DATA\0\0\x01\0");
+        expected.extend(&data[..SYNC_DATA_MAX]);
+        expected.extend(b"DATA\x0a\0\0\0");
+        expected.extend(&data[SYNC_DATA_MAX..]);
+        expected.extend(b"DONE");
+        expected.extend(1717171717u32.to_le_bytes());
+        expected.extend(b"QUIT\0\0\0\0");
+        assert_eq!(stream.sent, expected);
+    }
+
+    #[test]
+    fn fail_carries_the_message() {
+        let mut stream = fake_sync(b"FAIL\x27\0\0\0couldn't create file: Permission denied");
+
+        let err = send_file(
+            &mut stream,
+            &mut Cursor::new(b"x"),
+            "/system/x",
+            0o100644,
+            0,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to write /system/x: couldn't create file: Permission denied"
+        );
+    }
+}
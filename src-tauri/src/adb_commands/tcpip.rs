@@ -0,0 +1,136 @@
+//! Moving a cabled device over to Wi-Fi the way `adb tcpip` does, without
+//! the pairing flow.
+
+use std::net::IpAddr;
+use std::thread;
+use std::time::Duration;
+
+use super::device::{Device, DeviceInfo, connect_tcp_device};
+use super::logcat::get_device_info;
+use crate::error::DroidKitError;
+use crate::system_info::{NetworkInfo, get_network_info};
+
+pub(crate) const DEFAULT_TCPIP_PORT: u16 = 5555;
+
+/// adbd takes a moment to come back up listening on the new port.
+const CONNECT_ATTEMPTS: u32 = 10;
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The address of the first Wi-Fi interface that has one, preferring those
+/// that are up.
+fn wifi_address(network: &NetworkInfo) -> Option<IpAddr> {
+    let wifi = || {
+        network
+            .network_interfaces
+            .iter()
+            .filter(|interface| interface.name.starts_with("wlan"))
+    };
+    wifi()
+        .filter(|interface| interface.status.as_deref() == Some("UP"))
+        .chain(wifi())
+        .find_map(|interface| interface.ip_address.as_deref()?.parse().ok())
+}
+
+/// Restarts adbd listening on `port` through its `tcpip:` service, which
+/// needs no root. adb_client only opens that service through the adb server:
+/// it has no way to open arbitrary services on a device it holds directly.
+fn restart_adbd_in_tcp_mode(device: &mut Device, port: u16) -> Result<(), DroidKitError> {
+    match device {
+        Device::Server(server_device) => server_device
+            .tcpip(port)
+            .map_err(|e| DroidKitError::from(e).context("Failed to restart adbd in TCP mode")),
+        Device::USB(_) | Device::TCP(_) => Err(DroidKitError::InvalidInput(
+            "Switching to TCP/IP needs the device to be connected through the adb server; \
+             start `adb start-server` and reconnect it"
+                .to_string(),
+        )),
+    }
+}
+
+/// Switches `device` to listen on `port` over TCP/IP and connects to it at
+/// its Wi-Fi address. The returned info has `ip:port` as its serial, like
+/// devices connected with `connect_wireless_device`.
+pub(crate) fn switch_to_tcpip(
+    device: &mut Device,
+    port: u16,
+) -> Result<(Device, DeviceInfo), DroidKitError> {
+    if let Device::TCP(_) = device {
+        return Err(DroidKitError::InvalidInput(
+            "The device is already connected over TCP/IP".to_string(),
+        ));
+    }
+
+    let ip = wifi_address(&get_network_info(device)).ok_or_else(|| {
+        DroidKitError::InvalidInput(
+            "The device has no Wi-Fi address; connect it to a network first".to_string(),
+        )
+    })?;
+    restart_adbd_in_tcp_mode(device, port)?;
+
+    let mut last_error = None;
+    for _ in 0..CONNECT_ATTEMPTS {
+        thread::sleep(CONNECT_RETRY_INTERVAL);
+        match connect_tcp_device(ip, port) {
+            Ok(mut tcp_device) => {
+                let mut device_info = get_device_info(&mut tcp_device)?;
+                // Same form as connect_wireless_device, for easy reconnection
+                device_info.serial_no = format!("{}:{}", ip, port);
+                return Ok((tcp_device, device_info));
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error
+        .map(|e| e.context("adbd did not come back up over TCP/IP"))
+        .unwrap_or_else(|| DroidKitError::Timeout("No connection attempt was made".to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system_info::NetworkInterface;
+
+    fn interface(name: &str, ip_address: Option<&str>, status: &str) -> NetworkInterface {
+        NetworkInterface {
+            name: name.to_string(),
+            ip_address: ip_address.map(str::to_string),
+            mac_address: None,
+            status: Some(status.to_string()),
+        }
+    }
+
+    fn network(network_interfaces: Vec<NetworkInterface>) -> NetworkInfo {
+        NetworkInfo {
+            wifi_status: None,
+            connection_type: None,
+            signal_strength: None,
+            upload_speed: None,
+            download_speed: None,
+            ip_addresses: Vec::new(),
+            mac_addresses: Vec::new(),
+            network_interfaces,
+        }
+    }
+
+    #[test]
+    fn picks_the_wifi_address() {
+        let network = network(vec![
+            interface("lo", Some("127.0.0.1"), "UP"),
+            interface("rmnet_data0", Some("10.143.22.187"), "UP"),
+            interface("wlan1", Some("192.168.49.1"), "DOWN"),
+            interface("wlan0", Some("192.168.1.37"), "UP"),
+        ]);
+        assert_eq!(wifi_address(&network), "192.168.1.37".parse().ok());
+    }
+
+    #[test]
+    fn no_wifi_address_without_wlan() {
+        let network = network(vec![
+            interface("lo", Some("127.0.0.1"), "UP"),
+            interface("wlan0", None, "UP"),
+            interface("eth0", Some("10.0.2.15"), "UP"),
+        ]);
+        assert_eq!(wifi_address(&network), None);
+    }
+}
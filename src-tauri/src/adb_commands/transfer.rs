@@ -0,0 +1,886 @@
+//! Recursive copies of whole directories to and from the device, one file at
+//! a time over the sync protocol.
+//!
+//! A transfer first walks the source tree into a list of steps, so progress
+//! can be reported against totals, then runs them in order. A file that
+//! fails is recorded in the summary and the rest carry on.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::adb_device::{AdbDevice, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG};
+use super::checksum::verify_copy;
+use super::command::ShellCommand;
+use super::files::{local_mod_time, local_permissions, pull_file, push_file_as};
+use crate::error::DroidKitError;
+
+/// What to do with a symbolic link met inside the tree.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum SymlinkPolicy {
+    /// Leave it out, listing it as skipped.
+    #[default]
+    Skip,
+    /// Copy what it points to. The tree is then walked by resolved paths,
+    /// and a link back into a directory already copied is a failure.
+    Follow,
+    /// Create a link with the same target on the other side.
+    Preserve,
+}
+
+/// Progress of a whole tree, sent as each file advances and once it is done.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct TreeProgress {
+    /// Files finished, including those that failed.
+    pub files_done: u64,
+    pub total_files: u64,
+    /// Bytes of finished files, plus the file in flight so far.
+    pub bytes_done: u64,
+    pub total_bytes: u64,
+    /// Source path of the file in flight.
+    pub current_file: String,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct TransferFailure {
+    /// The file, directory or link that could not be copied.
+    pub path: String,
+    pub error: DroidKitError,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub(crate) struct TreeTransferSummary {
+    pub files_transferred: u64,
+    pub bytes_transferred: u64,
+    pub directories: u64,
+    pub links: u64,
+    /// Symlinks left out by [`SymlinkPolicy::Skip`], and sockets, pipes and
+    /// device nodes, which cannot be copied.
+    pub skipped: Vec<String>,
+    pub failures: Vec<TransferFailure>,
+}
+
+impl TreeTransferSummary {
+    /// Records a failure and carries on, unless it ends the whole transfer.
+    pub(crate) fn fail(&mut self, path: &str, error: DroidKitError) -> Result<(), DroidKitError> {
+        match error {
+            DroidKitError::Cancelled(_)
+            | DroidKitError::Timeout(_)
+            | DroidKitError::NoDevice(_)
+            | DroidKitError::Offline(_) => Err(error),
+            error => {
+                self.failures.push(TransferFailure {
+                    path: path.to_string(),
+                    error,
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+enum Step {
+    /// `mode` holds the permission bits only.
+    Dir {
+        dest: String,
+        mode: u32,
+        mod_time: u32,
+    },
+    File {
+        source: String,
+        dest: String,
+        size: u64,
+    },
+    Link {
+        source: String,
+        dest: String,
+        target: String,
+    },
+}
+
+struct Plan<K> {
+    steps: Vec<Step>,
+    /// Directories already walked, by the paths `K` they were walked by.
+    visited: HashSet<K>,
+    summary: TreeTransferSummary,
+}
+
+impl<K: Eq + Hash> Plan<K> {
+    fn new() -> Self {
+        Plan {
+            steps: Vec::new(),
+            visited: HashSet::new(),
+            summary: TreeTransferSummary::default(),
+        }
+    }
+
+    fn totals(&self) -> TreeProgress {
+        let mut progress = TreeProgress {
+            files_done: 0,
+            total_files: 0,
+            bytes_done: 0,
+            total_bytes: 0,
+            current_file: String::new(),
+        };
+        for step in &self.steps {
+            if let Step::File { size, .. } = step {
+                progress.total_files += 1;
+                progress.total_bytes += size;
+            }
+        }
+        progress
+    }
+}
+
+pub(crate) fn join_remote(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+fn run_remote(device: &mut dyn AdbDevice, command: &str) -> Result<(), DroidKitError> {
+    device.shell_output(command)?.into_result(command)?;
+    Ok(())
+}
+
+/// The target of the link at `remote_path`, or with `canonicalize` the
+/// path it finally resolves to.
+fn read_remote_link(
+    device: &mut dyn AdbDevice,
+    remote_path: &str,
+    canonicalize: bool,
+) -> Result<String, DroidKitError> {
+    let mut command = ShellCommand::new("readlink");
+    if canonicalize {
+        command = command.arg("-f");
+    }
+    let command = command.arg(remote_path).to_string();
+    let output = device.shell_output(&command)?.into_result(&command)?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches('\n')
+        .to_string())
+}
+
+fn plan_remote_dir(
+    device: &mut dyn AdbDevice,
+    plan: &mut Plan<String>,
+    symlinks: SymlinkPolicy,
+    remote_dir: &str,
+    local_dir: &Path,
+    mode: u32,
+    mod_time: u32,
+) -> Result<(), DroidKitError> {
+    plan.visited.insert(remote_dir.to_string());
+    plan.steps.push(Step::Dir {
+        dest: local_dir.to_string_lossy().into_owned(),
+        mode: mode & 0o7777,
+        mod_time,
+    });
+
+    let entries = match device.list(remote_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return plan
+                .summary
+                .fail(remote_dir, e.context("Failed to list directory"));
+        }
+    };
+    for entry in entries {
+        let remote = join_remote(remote_dir, &entry.name);
+        let local = local_dir.join(&entry.name);
+        match entry.mode & S_IFMT {
+            S_IFDIR => plan_remote_dir(
+                device,
+                plan,
+                symlinks,
+                &remote,
+                &local,
+                entry.mode,
+                entry.mod_time,
+            )?,
+            S_IFREG => plan.steps.push(Step::File {
+                source: remote,
+                dest: local.to_string_lossy().into_owned(),
+                size: u64::from(entry.size),
+            }),
+            S_IFLNK => plan_remote_link(device, plan, symlinks, remote, &local)?,
+            _ => plan.summary.skipped.push(remote),
+        }
+    }
+    Ok(())
+}
+
+fn plan_remote_link(
+    device: &mut dyn AdbDevice,
+    plan: &mut Plan<String>,
+    symlinks: SymlinkPolicy,
+    remote: String,
+    local: &Path,
+) -> Result<(), DroidKitError> {
+    let dest = local.to_string_lossy().into_owned();
+    match symlinks {
+        SymlinkPolicy::Skip => plan.summary.skipped.push(remote),
+        SymlinkPolicy::Preserve => match read_remote_link(device, &remote, false) {
+            Ok(target) => plan.steps.push(Step::Link {
+                source: remote,
+                dest,
+                target,
+            }),
+            Err(e) => plan.summary.fail(&remote, e)?,
+        },
+        SymlinkPolicy::Follow => {
+            let resolved = match read_remote_link(device, &remote, true) {
+                Ok(resolved) => resolved,
+                Err(e) => return plan.summary.fail(&remote, e),
+            };
+            let stat = match device.stat(&resolved) {
+                Ok(stat) => stat,
+                Err(e) => return plan.summary.fail(&remote, e),
+            };
+            match stat.file_perm & S_IFMT {
+                S_IFDIR if plan.visited.contains(&resolved) => plan.summary.fail(
+                    &remote,
+                    DroidKitError::InvalidInput(format!(
+                        "{} leads back to {}, which is already being copied",
+                        remote, resolved
+                    )),
+                )?,
+                S_IFDIR => plan_remote_dir(
+                    device,
+                    plan,
+                    symlinks,
+                    &resolved,
+                    local,
+                    stat.file_perm,
+                    stat.mod_time,
+                )?,
+                S_IFREG => plan.steps.push(Step::File {
+                    source: resolved,
+                    dest,
+                    size: u64::from(stat.file_size),
+                }),
+                0 => plan.summary.fail(
+                    &remote,
+                    DroidKitError::InvalidInput(format!(
+                        "{} points to {}, which does not exist",
+                        remote, resolved
+                    )),
+                )?,
+                _ => plan.summary.skipped.push(remote),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn plan_local_dir(
+    plan: &mut Plan<PathBuf>,
+    symlinks: SymlinkPolicy,
+    local_dir: &Path,
+    remote_dir: &str,
+    metadata: &std::fs::Metadata,
+) -> Result<(), DroidKitError> {
+    let source = local_dir.to_string_lossy().into_owned();
+    plan.visited.insert(local_dir.to_path_buf());
+    plan.steps.push(Step::Dir {
+        dest: remote_dir.to_string(),
+        mode: local_permissions(metadata),
+        mod_time: local_mod_time(metadata),
+    });
+
+    let mut entries = match std::fs::read_dir(local_dir)
+        .and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>())
+    {
+        Ok(entries) => entries,
+        Err(e) => {
+            return plan.summary.fail(
+                &source,
+                DroidKitError::from(e).context("Failed to list directory"),
+            );
+        }
+    };
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let remote = join_remote(remote_dir, &entry.file_name().to_string_lossy());
+        // Of the entry itself, not what a link points to
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                plan.summary
+                    .fail(&path.to_string_lossy(), DroidKitError::from(e))?;
+                continue;
+            }
+        };
+
+        if metadata.is_dir() {
+            plan_local_dir(plan, symlinks, &path, &remote, &metadata)?;
+        } else if metadata.is_file() {
+            plan.steps.push(Step::File {
+                source: path.to_string_lossy().into_owned(),
+                dest: remote,
+                size: metadata.len(),
+            });
+        } else if metadata.is_symlink() {
+            plan_local_link(plan, symlinks, &path, remote)?;
+        } else {
+            plan.summary
+                .skipped
+                .push(path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
+
+fn plan_local_link(
+    plan: &mut Plan<PathBuf>,
+    symlinks: SymlinkPolicy,
+    path: &Path,
+    remote: String,
+) -> Result<(), DroidKitError> {
+    let source = path.to_string_lossy().into_owned();
+    match symlinks {
+        SymlinkPolicy::Skip => plan.summary.skipped.push(source),
+        SymlinkPolicy::Preserve => match std::fs::read_link(path) {
+            Ok(target) => plan.steps.push(Step::Link {
+                source,
+                dest: remote,
+                target: target.to_string_lossy().into_owned(),
+            }),
+            Err(e) => plan.summary.fail(&source, DroidKitError::from(e))?,
+        },
+        SymlinkPolicy::Follow => {
+            let resolved = match std::fs::canonicalize(path) {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    return plan.summary.fail(
+                        &source,
+                        DroidKitError::from(e).context(&format!("Failed to follow {}", source)),
+                    );
+                }
+            };
+            let metadata = match std::fs::metadata(&resolved) {
+                Ok(metadata) => metadata,
+                Err(e) => return plan.summary.fail(&source, DroidKitError::from(e)),
+            };
+            if metadata.is_dir() {
+                if plan.visited.contains(&resolved) {
+                    plan.summary.fail(
+                        &source,
+                        DroidKitError::InvalidInput(format!(
+                            "{} leads back to {}, which is already being copied",
+                            source,
+                            resolved.display()
+                        )),
+                    )?;
+                } else {
+                    plan_local_dir(plan, symlinks, &resolved, &remote, &metadata)?;
+                }
+            } else if metadata.is_file() {
+                plan.steps.push(Step::File {
+                    source: resolved.to_string_lossy().into_owned(),
+                    dest: remote,
+                    size: metadata.len(),
+                });
+            } else {
+                plan.summary.skipped.push(source);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn create_local_link(target: &str, link: &str, overwrite: bool) -> Result<(), DroidKitError> {
+    if Path::new(link).symlink_metadata().is_ok() {
+        if !overwrite {
+            return Err(DroidKitError::AlreadyExists(format!(
+                "{} already exists",
+                link
+            )));
+        }
+        std::fs::remove_file(link)
+            .map_err(|e| DroidKitError::from(e).context(&format!("Failed to replace {}", link)))?;
+    }
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+            .map_err(|e| DroidKitError::from(e).context("Failed to create link"))
+    }
+    // Creating links takes Developer Mode or admin rights on Windows
+    #[cfg(not(unix))]
+    {
+        let _ = target;
+        Err(DroidKitError::InvalidInput(
+            "Links can only be recreated on this computer under Unix".to_string(),
+        ))
+    }
+}
+
+/// Gives a local directory the time and mode of the device's. Unix only, as
+/// Windows cannot open a directory to set its time.
+fn copy_dir_metadata(path: &Path, mode: u32, mod_time: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // Time first, in case the mode takes away the right to open it
+        File::open(path)?.set_modified(UNIX_EPOCH + Duration::from_secs(u64::from(mod_time)))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode, mod_time);
+    Ok(())
+}
+
+/// Copies the directory `remote_dir` to `local_dir`, which is created if
+/// needed, keeping structure, mode and modification times. Existing local
+/// files are only replaced when `overwrite`. With `verify`, each file is
+/// hashed on both sides once copied. Fails as a whole only when the device
+/// goes away or the operation is cancelled or times out.
+pub(crate) fn pull_dir(
+    device: &mut dyn AdbDevice,
+    remote_dir: &str,
+    local_dir: &str,
+    overwrite: bool,
+    symlinks: SymlinkPolicy,
+    verify: bool,
+    mut on_progress: impl FnMut(TreeProgress),
+) -> Result<TreeTransferSummary, DroidKitError> {
+    // `STAT` does not follow links, except through a trailing slash
+    let stat = device.stat(&format!("{}/", remote_dir.trim_end_matches('/')))?;
+    if stat.file_perm & S_IFMT != S_IFDIR {
+        return Err(DroidKitError::InvalidInput(format!(
+            "{} is not a directory on the device",
+            remote_dir
+        )));
+    }
+    let root = match symlinks {
+        SymlinkPolicy::Follow => read_remote_link(device, remote_dir, true)?,
+        _ => remote_dir.to_string(),
+    };
+
+    let mut plan = Plan::new();
+    plan_remote_dir(
+        device,
+        &mut plan,
+        symlinks,
+        &root,
+        Path::new(local_dir),
+        stat.file_perm,
+        stat.mod_time,
+    )?;
+    let mut progress = plan.totals();
+    let mut summary = plan.summary;
+    let mut created = Vec::new();
+
+    for step in &plan.steps {
+        match step {
+            Step::Dir {
+                dest,
+                mode,
+                mod_time,
+            } => match std::fs::create_dir_all(dest) {
+                Ok(()) => {
+                    summary.directories += 1;
+                    created.push((dest, *mode, *mod_time));
+                }
+                Err(e) => summary.fail(
+                    dest,
+                    DroidKitError::from(e).context("Failed to create directory"),
+                )?,
+            },
+            Step::File { source, dest, size } => {
+                progress.current_file = source.clone();
+                let result = if !overwrite && Path::new(dest).symlink_metadata().is_ok() {
+                    Err(DroidKitError::AlreadyExists(format!(
+                        "{} already exists",
+                        dest
+                    )))
+                } else {
+                    pull_file(device, source, dest, |file| {
+                        on_progress(TreeProgress {
+                            bytes_done: progress.bytes_done + file.bytes_transferred,
+                            ..progress.clone()
+                        })
+                    })
+                    .and_then(|()| {
+                        if verify {
+                            verify_copy(device, dest, source)
+                        } else {
+                            Ok(())
+                        }
+                    })
+                };
+                progress.files_done += 1;
+                progress.bytes_done += size;
+                match result {
+                    Ok(()) => {
+                        summary.files_transferred += 1;
+                        summary.bytes_transferred += size;
+                    }
+                    Err(e) => summary.fail(source, e)?,
+                }
+                on_progress(progress.clone());
+            }
+            Step::Link {
+                source,
+                dest,
+                target,
+            } => match create_local_link(target, dest, overwrite) {
+                Ok(()) => summary.links += 1,
+                Err(e) => summary.fail(source, e)?,
+            },
+        }
+    }
+
+    // Deepest first and after their contents, which change a directory's time
+    for (dest, mode, mod_time) in created.into_iter().rev() {
+        if let Err(e) = copy_dir_metadata(Path::new(dest), mode, mod_time) {
+            summary.fail(
+                dest,
+                DroidKitError::Io(format!("Failed to set directory time and mode: {}", e)),
+            )?;
+        }
+    }
+    Ok(summary)
+}
+
+/// Copies the directory `local_dir` to `remote_dir` on the device, which is
+/// created if needed, keeping structure, mode and modification times. The
+/// counterpart of [`pull_dir`].
+pub(crate) fn push_dir(
+    device: &mut dyn AdbDevice,
+    local_dir: &str,
+    remote_dir: &str,
+    overwrite: bool,
+    symlinks: SymlinkPolicy,
+    verify: bool,
+    mut on_progress: impl FnMut(TreeProgress),
+) -> Result<TreeTransferSummary, DroidKitError> {
+    let root = match symlinks {
+        SymlinkPolicy::Follow => std::fs::canonicalize(local_dir).map_err(|e| {
+            DroidKitError::from(e).context(&format!("Failed to read {}", local_dir))
+        })?,
+        _ => PathBuf::from(local_dir),
+    };
+    let metadata = std::fs::metadata(&root)
+        .map_err(|e| DroidKitError::from(e).context(&format!("Failed to read {}", local_dir)))?;
+    if !metadata.is_dir() {
+        return Err(DroidKitError::InvalidInput(format!(
+            "{} is not a directory",
+            local_dir
+        )));
+    }
+
+    let mut plan = Plan::new();
+    plan_local_dir(&mut plan, symlinks, &root, remote_dir, &metadata)?;
+    let mut progress = plan.totals();
+    let mut summary = plan.summary;
+    let mut created = Vec::new();
+
+    for step in &plan.steps {
+        match step {
+            Step::Dir {
+                dest,
+                mode,
+                mod_time,
+            } => {
+                let command = ShellCommand::new("mkdir").arg("-p").arg(dest).to_string();
+                match run_remote(device, &command) {
+                    Ok(()) => {
+                        summary.directories += 1;
+                        created.push((dest, *mode, *mod_time));
+                    }
+                    Err(e) => summary.fail(dest, e)?,
+                }
+            }
+            Step::File { source, dest, size } => {
+                progress.current_file = source.clone();
+                let result = std::fs::metadata(source)
+                    .map_err(|e| {
+                        DroidKitError::from(e).context(&format!("Failed to read {}", source))
+                    })
+                    .and_then(|metadata| {
+                        push_file_as(device, source, &metadata, dest, overwrite, |file| {
+                            on_progress(TreeProgress {
+                                bytes_done: progress.bytes_done + file.bytes_transferred,
+                                ..progress.clone()
+                            })
+                        })
+                    })
+                    .and_then(|()| {
+                        if verify {
+                            verify_copy(device, source, dest)
+                        } else {
+                            Ok(())
+                        }
+                    });
+                progress.files_done += 1;
+                progress.bytes_done += size;
+                match result {
+                    Ok(()) => {
+                        summary.files_transferred += 1;
+                        summary.bytes_transferred += size;
+                    }
+                    Err(e) => summary.fail(source, e)?,
+                }
+                on_progress(progress.clone());
+            }
+            Step::Link {
+                source,
+                dest,
+                target,
+            } => {
+                let result = device.stat(dest).and_then(|stat| {
+                    if stat.file_perm & S_IFMT != 0 && !overwrite {
+                        return Err(DroidKitError::AlreadyExists(format!(
+                            "{} already exists on the device",
+                            dest
+                        )));
+                    }
+                    let command = ShellCommand::new("ln")
+                        .arg("-sf")
+                        .arg(target)
+                        .arg(dest)
+                        .to_string();
+                    run_remote(device, &command)
+                });
+                match result {
+                    Ok(()) => summary.links += 1,
+                    Err(e) => summary.fail(source, e)?,
+                }
+            }
+        }
+    }
+
+    // Deepest first and after their contents, which change a directory's time
+    for (dest, mode, mod_time) in created.into_iter().rev() {
+        let command = format!(
+            "{} && {}",
+            ShellCommand::new("chmod")
+                .arg(format!("{:o}", mode))
+                .arg(dest),
+            ShellCommand::new("touch")
+                .args(["-m", "-d"])
+                .arg(format!("@{}", mod_time))
+                .arg(dest)
+        );
+        if let Err(e) = run_remote(device, &command) {
+            summary.fail(dest, e)?;
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::{FakeDevice, unique_temp_dir};
+
+    fn fresh_dir(name: &str) -> PathBuf {
+        let dir = unique_temp_dir(name);
+        let _ = std::fs::remove_dir(&dir);
+        dir
+    }
+
+    fn modified(path: &Path) -> u64 {
+        std::fs::metadata(path)
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn pull_dir_copies_the_tree_and_collects_failures() {
+        let mut device = FakeDevice::new()
+            .with_file("/sdcard/Music", 0o040775, 4096, 1700000000)
+            .with_file_data("/sdcard/Music/a.mp3", 0o644, b"aaa".to_vec(), 1700000001)
+            .with_file("/sdcard/Music/Album", 0o040700, 4096, 1700000002)
+            .with_file_data(
+                "/sdcard/Music/Album/b.mp3",
+                0o600,
+                b"bbbb".to_vec(),
+                1700000003,
+            )
+            // Listed, but the fake has nothing to send
+            .with_file("/sdcard/Music/broken.mp3", 0o100644, 10, 0)
+            .with_file("/sdcard/Music/latest", 0o120777, 11, 0)
+            .with_shell("readlink /sdcard/Music/latest", "Album/b.mp3\n")
+            .with_file("/sdcard/Music/.socket", 0o140777, 0, 0);
+        let local = fresh_dir("pull-dir");
+
+        let mut progress = Vec::new();
+        let summary = pull_dir(
+            &mut device,
+            "/sdcard/Music",
+            &local.to_string_lossy(),
+            false,
+            SymlinkPolicy::Preserve,
+            false,
+            |p| progress.push(p),
+        )
+        .unwrap();
+
+        assert_eq!(summary.files_transferred, 2);
+        assert_eq!(summary.bytes_transferred, 7);
+        assert_eq!(summary.directories, 2);
+        assert_eq!(summary.skipped, ["/sdcard/Music/.socket"]);
+        let paths: Vec<&str> = summary.failures.iter().map(|f| f.path.as_str()).collect();
+        #[cfg(unix)]
+        assert_eq!(paths, ["/sdcard/Music/broken.mp3"]);
+        #[cfg(not(unix))]
+        assert_eq!(paths, ["/sdcard/Music/broken.mp3", "/sdcard/Music/latest"]);
+
+        let last = progress.last().unwrap();
+        assert_eq!((last.files_done, last.total_files), (3, 3));
+        assert_eq!((last.bytes_done, last.total_bytes), (17, 17));
+
+        assert_eq!(std::fs::read(local.join("a.mp3")).unwrap(), b"aaa");
+        assert_eq!(std::fs::read(local.join("Album/b.mp3")).unwrap(), b"bbbb");
+        assert!(!local.join("broken.mp3").exists());
+        assert_eq!(modified(&local), 1700000000);
+        assert_eq!(modified(&local.join("Album")), 1700000002);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&local), 0o775);
+            assert_eq!(mode(&local.join("Album")), 0o700);
+            assert_eq!(
+                std::fs::read_link(local.join("latest")).unwrap(),
+                Path::new("Album/b.mp3")
+            );
+        }
+        let _ = std::fs::remove_dir_all(&local);
+    }
+
+    #[test]
+    fn pull_dir_follows_links_without_looping() {
+        let mut device = FakeDevice::new()
+            .with_file("/sdcard/Music", 0o040775, 4096, 0)
+            .with_shell("readlink -f /sdcard/Music/", "/sdcard/Music\n")
+            .with_file("/sdcard/Music/loop", 0o120777, 13, 0)
+            .with_shell("readlink -f /sdcard/Music/loop", "/sdcard/Music\n")
+            .with_file("/sdcard/Music/song", 0o120777, 18, 0)
+            .with_shell("readlink -f /sdcard/Music/song", "/sdcard/Other/c.mp3\n")
+            .with_file_data("/sdcard/Other/c.mp3", 0o644, b"ccccc".to_vec(), 0);
+        let local = fresh_dir("pull-dir-follow");
+
+        let summary = pull_dir(
+            &mut device,
+            "/sdcard/Music/",
+            &local.to_string_lossy(),
+            false,
+            SymlinkPolicy::Follow,
+            false,
+            |_| {},
+        )
+        .unwrap();
+
+        assert_eq!(summary.files_transferred, 1);
+        assert_eq!(std::fs::read(local.join("song")).unwrap(), b"ccccc");
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(summary.failures[0].path, "/sdcard/Music/loop");
+        assert_eq!(summary.failures[0].error.kind(), "InvalidInput");
+        let _ = std::fs::remove_dir_all(&local);
+    }
+
+    #[test]
+    fn pull_dir_rejects_files() {
+        let mut device =
+            FakeDevice::new().with_file_data("/sdcard/notes.txt", 0o660, b"x".to_vec(), 0);
+        let err = pull_dir(
+            &mut device,
+            "/sdcard/notes.txt",
+            "unused",
+            false,
+            SymlinkPolicy::Skip,
+            false,
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), "InvalidInput");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn push_dir_recreates_the_tree() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let local = fresh_dir("push-dir");
+        std::fs::create_dir_all(local.join("sub")).unwrap();
+        std::fs::write(local.join("x.txt"), b"xx").unwrap();
+        std::fs::write(local.join("sub/y.txt"), b"yyy").unwrap();
+        std::os::unix::fs::symlink("x.txt", local.join("link")).unwrap();
+        for (dir, mod_time) in [(local.join("sub"), 1700000001), (local.clone(), 1700000000)] {
+            File::open(&dir)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(mod_time))
+                .unwrap();
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o750)).unwrap();
+        }
+
+        let mut device = FakeDevice::new()
+            .with_file_data("/sdcard/Up/x.txt", 0o660, b"old".to_vec(), 0)
+            .with_shell("mkdir -p /sdcard/Up", "")
+            .with_shell("mkdir -p /sdcard/Up/sub", "")
+            .with_shell("ln -sf x.txt /sdcard/Up/link", "")
+            .with_shell(
+                "chmod 750 /sdcard/Up/sub && touch -m -d @1700000001 /sdcard/Up/sub",
+                "",
+            )
+            .with_shell(
+                "chmod 750 /sdcard/Up && touch -m -d @1700000000 /sdcard/Up",
+                "",
+            );
+
+        let mut progress = Vec::new();
+        let summary = push_dir(
+            &mut device,
+            &local.to_string_lossy(),
+            "/sdcard/Up",
+            false,
+            SymlinkPolicy::Preserve,
+            false,
+            |p| progress.push(p.current_file),
+        )
+        .unwrap();
+        let _ = std::fs::remove_dir_all(&local);
+
+        assert_eq!(summary.directories, 2);
+        assert_eq!(summary.links, 1);
+        assert_eq!(summary.files_transferred, 1);
+        assert_eq!(summary.bytes_transferred, 3);
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(summary.failures[0].error.kind(), "AlreadyExists");
+        assert!(summary.failures[0].path.ends_with("x.txt"));
+        assert!(progress.last().unwrap().ends_with("x.txt"));
+
+        // Directory times last, deepest first
+        let tail: Vec<&str> = device
+            .calls
+            .iter()
+            .rev()
+            .take(2)
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            tail,
+            [
+                "chmod 750 /sdcard/Up && touch -m -d @1700000000 /sdcard/Up",
+                "chmod 750 /sdcard/Up/sub && touch -m -d @1700000001 /sdcard/Up/sub",
+            ]
+        );
+
+        let mut data = Vec::new();
+        device.pull("/sdcard/Up/sub/y.txt", &mut data).unwrap();
+        assert_eq!(data, b"yyy");
+        data.clear();
+        device.pull("/sdcard/Up/x.txt", &mut data).unwrap();
+        assert_eq!(data, b"old");
+    }
+}
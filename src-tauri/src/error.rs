@@ -0,0 +1,198 @@
+use adb_client::RustADBError;
+use serde::Serialize;
+use serde::ser::{SerializeMap, Serializer};
+
+/// Error returned by every device command.
+///
+/// Serializes as `{ "kind": "...", "message": "...", ...fields }` so the UI can
+/// branch on `kind` and show `message` as-is.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum DroidKitError {
+    /// No device with the requested serial is reachable.
+    #[error("{0}")]
+    NoDevice(String),
+    /// The device is attached but has not accepted this computer's RSA key.
+    #[error("{0}")]
+    Unauthorized(String),
+    /// The device is known but not answering (e.g. still booting, cable pulled).
+    #[error("{0}")]
+    Offline(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    /// Stopped on request before it finished.
+    #[error("{0}")]
+    Cancelled(String),
+    /// Root was asked for but the device offers no way to it.
+    #[error("{0}")]
+    NotRooted(String),
+    /// The destination exists and overwriting it was not asked for.
+    #[error("{0}")]
+    AlreadyExists(String),
+    /// A shell command ran but reported failure.
+    #[error(
+        "`{command}` failed{}{}",
+        .exit_code.map(|code| format!(" with exit code {}", code)).unwrap_or_default(),
+        if .stderr.trim().is_empty() { String::new() } else { format!(": {}", .stderr.trim()) }
+    )]
+    CommandFailed {
+        command: String,
+        exit_code: Option<u8>,
+        stderr: String,
+    },
+    #[error("Pairing failed: {reason}")]
+    PairingFailed { reason: String },
+    /// A copy was verified and its digest differs from the original's.
+    #[error("{path} does not match after the transfer: {local} here, {remote} on the device")]
+    ChecksumMismatch {
+        path: String,
+        local: String,
+        remote: String,
+    },
+    #[error("{0}")]
+    InvalidInput(String),
+    #[error("I/O error: {0}")]
+    Io(String),
+    #[error("Unexpected output: {0}")]
+    Parse(String),
+    /// The bootloader answered a fastboot command with FAIL.
+    #[error("{0}")]
+    Fastboot(String),
+    /// Any other failure reported by the adb transport.
+    #[error("{0}")]
+    Adb(String),
+}
+
+impl DroidKitError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DroidKitError::NoDevice(_) => "NoDevice",
+            DroidKitError::Unauthorized(_) => "Unauthorized",
+            DroidKitError::Offline(_) => "Offline",
+            DroidKitError::Timeout(_) => "Timeout",
+            DroidKitError::Cancelled(_) => "Cancelled",
+            DroidKitError::NotRooted(_) => "NotRooted",
+            DroidKitError::AlreadyExists(_) => "AlreadyExists",
+            DroidKitError::CommandFailed { .. } => "CommandFailed",
+            DroidKitError::PairingFailed { .. } => "PairingFailed",
+            DroidKitError::ChecksumMismatch { .. } => "ChecksumMismatch",
+            DroidKitError::InvalidInput(_) => "InvalidInput",
+            DroidKitError::Io(_) => "Io",
+            DroidKitError::Parse(_) => "Parse",
+            DroidKitError::Fastboot(_) => "Fastboot",
+            DroidKitError::Adb(_) => "Adb",
+        }
+    }
+
+    /// Prefixes the message with what we were doing, keeping the kind.
+    pub fn context(self, what: &str) -> Self {
+        let prefix = |message: String| format!("{}: {}", what, message);
+        match self {
+            DroidKitError::NoDevice(m) => DroidKitError::NoDevice(prefix(m)),
+            DroidKitError::Unauthorized(m) => DroidKitError::Unauthorized(prefix(m)),
+            DroidKitError::Offline(m) => DroidKitError::Offline(prefix(m)),
+            DroidKitError::Timeout(m) => DroidKitError::Timeout(prefix(m)),
+            DroidKitError::Cancelled(m) => DroidKitError::Cancelled(prefix(m)),
+            DroidKitError::NotRooted(m) => DroidKitError::NotRooted(prefix(m)),
+            DroidKitError::AlreadyExists(m) => DroidKitError::AlreadyExists(prefix(m)),
+            DroidKitError::InvalidInput(m) => DroidKitError::InvalidInput(prefix(m)),
+            DroidKitError::Io(m) => DroidKitError::Io(prefix(m)),
+            DroidKitError::Parse(m) => DroidKitError::Parse(prefix(m)),
+            DroidKitError::Fastboot(m) => DroidKitError::Fastboot(prefix(m)),
+            DroidKitError::Adb(m) => DroidKitError::Adb(prefix(m)),
+            // Already self-describing
+            other @ (DroidKitError::CommandFailed { .. }
+            | DroidKitError::PairingFailed { .. }
+            | DroidKitError::ChecksumMismatch { .. }) => other,
+        }
+    }
+}
+
+impl Serialize for DroidKitError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", self.kind())?;
+        map.serialize_entry("message", &self.to_string())?;
+        match self {
+            DroidKitError::CommandFailed {
+                command,
+                exit_code,
+                stderr,
+            } => {
+                map.serialize_entry("command", command)?;
+                map.serialize_entry("exit_code", exit_code)?;
+                map.serialize_entry("stderr", stderr)?;
+            }
+            DroidKitError::PairingFailed { reason } => {
+                map.serialize_entry("reason", reason)?;
+            }
+            DroidKitError::ChecksumMismatch {
+                path,
+                local,
+                remote,
+            } => {
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("local", local)?;
+                map.serialize_entry("remote", remote)?;
+            }
+            _ => {}
+        }
+        map.end()
+    }
+}
+
+impl From<std::io::Error> for DroidKitError {
+    fn from(e: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+
+        match e.kind() {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => DroidKitError::Timeout(e.to_string()),
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof => DroidKitError::Offline(e.to_string()),
+            _ => DroidKitError::Io(e.to_string()),
+        }
+    }
+}
+
+impl From<RustADBError> for DroidKitError {
+    fn from(e: RustADBError) -> Self {
+        match e {
+            RustADBError::IOError(e) => e.into(),
+            RustADBError::DeviceNotFound(_) | RustADBError::USBDeviceNotFound(..) => {
+                DroidKitError::NoDevice(e.to_string())
+            }
+            RustADBError::ADBDeviceNotPaired => DroidKitError::Unauthorized(e.to_string()),
+            // The server reports device state problems as plain FAIL messages
+            RustADBError::ADBRequestFailed(ref message) => {
+                let lower = message.to_lowercase();
+                if lower.contains("unauthorized") {
+                    DroidKitError::Unauthorized(e.to_string())
+                } else if lower.contains("offline") {
+                    DroidKitError::Offline(e.to_string())
+                } else if lower.contains("not found") || lower.contains("no devices") {
+                    DroidKitError::NoDevice(e.to_string())
+                } else {
+                    DroidKitError::Adb(e.to_string())
+                }
+            }
+            RustADBError::UsbError(rusb::Error::Timeout) => DroidKitError::Timeout(e.to_string()),
+            RustADBError::UsbError(rusb::Error::NoDevice) => DroidKitError::NoDevice(e.to_string()),
+            RustADBError::ADBShellV2ParseError(_)
+            | RustADBError::UnknownResponseType(_)
+            | RustADBError::WrongResponseReceived(..)
+            | RustADBError::UnknownDeviceState(_)
+            | RustADBError::Utf8StrError(_)
+            | RustADBError::Utf8StringError(_)
+            | RustADBError::RegexParsingError
+            | RustADBError::ParseIntError(_)
+            | RustADBError::ConversionError
+            | RustADBError::IntegerConversionError(_)
+            | RustADBError::UnknownFileMode(_) => DroidKitError::Parse(e.to_string()),
+            RustADBError::AddrParseError(_) => DroidKitError::InvalidInput(e.to_string()),
+            _ => DroidKitError::Adb(e.to_string()),
+        }
+    }
+}
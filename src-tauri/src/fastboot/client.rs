@@ -0,0 +1,327 @@
+//! The fastboot protocol itself: ASCII commands answered by OKAY, FAIL, INFO
+//! or DATA, the same over every transport.
+
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use super::sparse::SparseImage;
+use crate::adb_commands::reboot::RebootTarget;
+use crate::error::DroidKitError;
+
+/// Bootloaders since Android 8 take commands this long; older ones only 64 bytes.
+const MAX_COMMAND_LEN: usize = 4096;
+const DOWNLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Moves whole fastboot packets: one command or response, or one piece of a
+/// download.
+pub(crate) trait FastbootTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), DroidKitError>;
+    fn receive(&mut self) -> Result<Vec<u8>, DroidKitError>;
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub(crate) enum FastbootEvent {
+    /// A line the bootloader printed while working on a command.
+    Info {
+        message: String,
+    },
+    Download {
+        bytes_sent: u64,
+        total_bytes: u64,
+    },
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub(crate) struct FastbootVar {
+    /// Includes the argument for per-partition variables, e.g.
+    /// `partition-size:boot_a`.
+    pub name: String,
+    pub value: String,
+}
+
+enum Response {
+    Okay(String),
+    /// Ready to receive this many bytes.
+    Data(u32),
+}
+
+/// Splits a `getvar all` line at its last colon, as names may have their own.
+fn parse_var(line: &str) -> Option<FastbootVar> {
+    let (name, value) = line.rsplit_once(':')?;
+    Some(FastbootVar {
+        name: name.trim().to_string(),
+        value: value.trim().to_string(),
+    })
+}
+
+/// Sizes are reported in hex with a `0x` prefix by most bootloaders, but
+/// some answer in decimal.
+fn parse_size(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// A connection to a device in the bootloader or fastbootd.
+pub(crate) struct Fastboot {
+    transport: Box<dyn FastbootTransport>,
+}
+
+impl Fastboot {
+    pub fn new(transport: Box<dyn FastbootTransport>) -> Self {
+        Fastboot { transport }
+    }
+
+    fn send_command(&mut self, command: &str) -> Result<(), DroidKitError> {
+        if command.len() > MAX_COMMAND_LEN {
+            return Err(DroidKitError::InvalidInput(format!(
+                "Fastboot commands are limited to {} bytes",
+                MAX_COMMAND_LEN
+            )));
+        }
+        self.transport.send(command.as_bytes())
+    }
+
+    fn read_response(
+        &mut self,
+        command: &str,
+        on_event: &mut dyn FnMut(FastbootEvent),
+    ) -> Result<Response, DroidKitError> {
+        loop {
+            let packet = self.transport.receive()?;
+            if packet.len() < 4 {
+                return Err(DroidKitError::Parse(format!(
+                    "Short fastboot response to {}: {:?}",
+                    command,
+                    String::from_utf8_lossy(&packet)
+                )));
+            }
+            let (status, message) = packet.split_at(4);
+            let message = String::from_utf8_lossy(message).to_string();
+
+            match status {
+                b"OKAY" => return Ok(Response::Okay(message)),
+                b"FAIL" => {
+                    return Err(DroidKitError::Fastboot(format!(
+                        "{} failed: {}",
+                        command, message
+                    )));
+                }
+                b"INFO" | b"TEXT" => on_event(FastbootEvent::Info { message }),
+                b"DATA" => {
+                    let size = u32::from_str_radix(&message, 16).map_err(|_| {
+                        DroidKitError::Parse(format!("Invalid DATA size: {:?}", message))
+                    })?;
+                    return Ok(Response::Data(size));
+                }
+                other => {
+                    return Err(DroidKitError::Parse(format!(
+                        "Unknown fastboot response to {}: {:?}",
+                        command,
+                        String::from_utf8_lossy(other)
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Runs `command` to completion and returns the message of its OKAY.
+    fn command(
+        &mut self,
+        command: &str,
+        on_event: &mut dyn FnMut(FastbootEvent),
+    ) -> Result<String, DroidKitError> {
+        self.send_command(command)?;
+        match self.read_response(command, on_event)? {
+            Response::Okay(message) => Ok(message),
+            Response::Data(_) => Err(DroidKitError::Parse(format!(
+                "The bootloader asked for data after {}",
+                command
+            ))),
+        }
+    }
+
+    /// Runs `command` and returns everything it printed, for commands whose
+    /// output is meant for a person.
+    fn command_output(&mut self, command: &str) -> Result<Vec<String>, DroidKitError> {
+        let mut lines = Vec::new();
+        let message = self.command(command, &mut |event| {
+            if let FastbootEvent::Info { message } = event {
+                lines.push(message);
+            }
+        })?;
+        if !message.is_empty() {
+            lines.push(message);
+        }
+        Ok(lines)
+    }
+
+    pub fn getvar(&mut self, name: &str) -> Result<String, DroidKitError> {
+        self.command(&format!("getvar:{}", name), &mut |_| {})
+    }
+
+    pub fn getvar_all(&mut self) -> Result<Vec<FastbootVar>, DroidKitError> {
+        Ok(self
+            .command_output("getvar:all")?
+            .iter()
+            .filter_map(|line| parse_var(line))
+            .collect())
+    }
+
+    fn download(
+        &mut self,
+        data: &mut dyn Read,
+        size: u32,
+        on_event: &mut dyn FnMut(FastbootEvent),
+    ) -> Result<(), DroidKitError> {
+        let command = format!("download:{:08x}", size);
+        self.send_command(&command)?;
+        match self.read_response(&command, on_event)? {
+            Response::Data(accepted) if accepted == size => {}
+            Response::Data(accepted) => {
+                return Err(DroidKitError::Fastboot(format!(
+                    "The bootloader only accepts {} of {} bytes",
+                    accepted, size
+                )));
+            }
+            Response::Okay(_) => {
+                return Err(DroidKitError::Parse(format!(
+                    "The bootloader answered {} without asking for data",
+                    command
+                )));
+            }
+        }
+
+        let total_bytes = u64::from(size);
+        let mut chunk = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+        let mut bytes_sent = 0;
+        while bytes_sent < total_bytes {
+            let len = (total_bytes - bytes_sent).min(DOWNLOAD_CHUNK_SIZE as u64) as usize;
+            data.read_exact(&mut chunk[..len])?;
+            self.transport.send(&chunk[..len])?;
+            bytes_sent += len as u64;
+            on_event(FastbootEvent::Download {
+                bytes_sent,
+                total_bytes,
+            });
+        }
+
+        match self.read_response(&command, on_event)? {
+            Response::Okay(_) => Ok(()),
+            Response::Data(_) => Err(DroidKitError::Parse(format!(
+                "The bootloader asked for more data after {}",
+                command
+            ))),
+        }
+    }
+
+    /// Sends `image` and writes it to `partition`. Images over the
+    /// bootloader's `max-download-size`, raw or sparse, are resparsed into
+    /// pieces that fit, each sent and flashed in turn.
+    pub fn flash(
+        &mut self,
+        partition: &str,
+        image: &Path,
+        on_event: &mut dyn FnMut(FastbootEvent),
+    ) -> Result<(), DroidKitError> {
+        let mut file = File::open(image).map_err(|e| {
+            DroidKitError::from(e).context(&format!("Failed to open {}", image.display()))
+        })?;
+        let size = file.metadata()?.len();
+
+        let max_download_size = self
+            .getvar("max-download-size")
+            .ok()
+            .and_then(|value| parse_size(&value));
+        if let Some(max_download_size) = max_download_size
+            && size > max_download_size
+        {
+            let pieces = SparseImage::read(&mut file, size)
+                .and_then(|sparse| sparse.split(max_download_size.min(u64::from(u32::MAX))))
+                .map_err(|e| e.context(&format!("Failed to resparse {}", image.display())))?;
+            let count = pieces.len();
+            for (index, piece) in pieces.iter().enumerate() {
+                on_event(FastbootEvent::Info {
+                    message: format!(
+                        "Sending sparse '{}' {}/{} ({} bytes)",
+                        partition,
+                        index + 1,
+                        count,
+                        piece.size
+                    ),
+                });
+                self.download(&mut piece.reader(&mut file), piece.size as u32, on_event)?;
+                self.command(&format!("flash:{}", partition), on_event)?;
+            }
+            return Ok(());
+        }
+        let size = u32::try_from(size).map_err(|_| {
+            DroidKitError::InvalidInput(format!("{} is too large to flash", image.display()))
+        })?;
+
+        self.download(&mut file, size, on_event)?;
+        self.command(&format!("flash:{}", partition), on_event)?;
+        Ok(())
+    }
+
+    pub fn erase(
+        &mut self,
+        partition: &str,
+        on_event: &mut dyn FnMut(FastbootEvent),
+    ) -> Result<(), DroidKitError> {
+        self.command(&format!("erase:{}", partition), on_event)?;
+        Ok(())
+    }
+
+    pub fn reboot(&mut self, target: RebootTarget) -> Result<(), DroidKitError> {
+        let command = match target {
+            RebootTarget::System => "reboot",
+            RebootTarget::Bootloader => "reboot-bootloader",
+            RebootTarget::Recovery => "reboot-recovery",
+            RebootTarget::Fastboot => "reboot-fastboot",
+            RebootTarget::Sideload => {
+                return Err(DroidKitError::InvalidInput(
+                    "Fastboot cannot reboot straight into sideload".to_string(),
+                ));
+            }
+        };
+        self.command(command, &mut |_| {})?;
+        Ok(())
+    }
+
+    /// Vendor specific commands, e.g. `device-info`.
+    pub fn oem(&mut self, command: &str) -> Result<Vec<String>, DroidKitError> {
+        self.command_output(&format!("oem {}", command))
+    }
+
+    /// Asks to unlock the bootloader; the user has to confirm on the device,
+    /// which then wipes itself.
+    pub fn flashing_unlock(&mut self) -> Result<Vec<String>, DroidKitError> {
+        self.command_output("flashing unlock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_getvar_lines() {
+        assert_eq!(
+            parse_var("partition-size:boot_a: 0x4000000"),
+            Some(FastbootVar {
+                name: "partition-size:boot_a".to_string(),
+                value: "0x4000000".to_string(),
+            })
+        );
+        assert_eq!(parse_var("unlocked:no").unwrap().value, "no");
+        assert_eq!(parse_var("Done"), None);
+
+        assert_eq!(parse_size("0x10000000"), Some(268_435_456));
+        assert_eq!(parse_size("536870912"), Some(536_870_912));
+    }
+}
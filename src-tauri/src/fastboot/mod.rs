@@ -0,0 +1,27 @@
+//! Talking to devices in the bootloader or fastbootd, for flashing, which
+//! adb cannot do.
+
+pub mod client;
+pub mod sparse;
+pub mod tcp;
+pub mod usb;
+
+use std::net::{IpAddr, SocketAddr};
+
+use crate::error::DroidKitError;
+use client::Fastboot;
+use tcp::{DEFAULT_FASTBOOT_PORT, TcpTransport};
+use usb::UsbTransport;
+
+/// Connects to `serial_no`: over TCP when it is an `ip:port` address, or an
+/// IP on the default port, otherwise to the USB device with that serial number.
+pub(crate) fn connect_fastboot(serial_no: &str) -> Result<Fastboot, DroidKitError> {
+    let socket_addr = serial_no.parse::<SocketAddr>().ok().or_else(|| {
+        let ip = serial_no.parse::<IpAddr>().ok()?;
+        Some(SocketAddr::new(ip, DEFAULT_FASTBOOT_PORT))
+    });
+    if let Some(socket_addr) = socket_addr {
+        return Ok(Fastboot::new(Box::new(TcpTransport::connect(socket_addr)?)));
+    }
+    Ok(Fastboot::new(Box::new(UsbTransport::open(serial_no)?)))
+}
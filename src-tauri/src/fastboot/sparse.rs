@@ -0,0 +1,483 @@
+//! Android sparse images, split into pieces the bootloader can take in one
+//! download, the way `fastboot` resparses images over `max-download-size`.
+//!
+//! Every piece describes the whole partition: the blocks it carries, and
+//! "don't care" chunks for the rest, so the bootloader writes the pieces one
+//! after another without disturbing what the others wrote.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::error::DroidKitError;
+
+const SPARSE_MAGIC: u32 = 0xed26_ff3a;
+const FILE_HEADER_LEN: u64 = 28;
+const CHUNK_HEADER_LEN: u64 = 12;
+/// What `fastboot` uses when it resparses a raw image.
+const RAW_BLOCK_SIZE: u32 = 4096;
+
+const CHUNK_RAW: u16 = 0xcac1;
+const CHUNK_FILL: u16 = 0xcac2;
+const CHUNK_DONT_CARE: u16 = 0xcac3;
+const CHUNK_CRC32: u16 = 0xcac4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SegmentData {
+    /// `len` bytes at `offset` in the file, zero padded to whole blocks.
+    Raw {
+        offset: u64,
+        len: u64,
+    },
+    Fill(u32),
+}
+
+/// Blocks of the partition with something to write to them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Segment {
+    start: u32,
+    blocks: u32,
+    data: SegmentData,
+}
+
+impl Segment {
+    fn end(&self) -> u32 {
+        self.start + self.blocks
+    }
+
+    fn data_len(&self, block_size: u32) -> u64 {
+        match self.data {
+            SegmentData::Raw { .. } => u64::from(self.blocks) * u64::from(block_size),
+            SegmentData::Fill(_) => 4,
+        }
+    }
+
+    /// Splits a raw segment after its first `blocks` blocks.
+    fn split(self, blocks: u32, block_size: u32) -> (Segment, Segment) {
+        let SegmentData::Raw { offset, len } = self.data else {
+            unreachable!("only raw segments are split");
+        };
+        let head_len = len.min(u64::from(blocks) * u64::from(block_size));
+        (
+            Segment {
+                blocks,
+                data: SegmentData::Raw {
+                    offset,
+                    len: head_len,
+                },
+                ..self
+            },
+            Segment {
+                start: self.start + blocks,
+                blocks: self.blocks - blocks,
+                data: SegmentData::Raw {
+                    offset: offset + head_len,
+                    len: len - head_len,
+                },
+            },
+        )
+    }
+}
+
+/// A raw or sparse image file, as the blocks it writes.
+#[derive(Debug, PartialEq)]
+pub(crate) struct SparseImage {
+    block_size: u32,
+    total_blocks: u32,
+    segments: Vec<Segment>,
+}
+
+/// One part of a piece, read in turn.
+#[derive(Debug, PartialEq)]
+enum Part {
+    Bytes(Vec<u8>),
+    File { offset: u64, len: u64 },
+    Zeros(u64),
+}
+
+/// A sparse image small enough for one download.
+#[derive(Debug, PartialEq)]
+pub(crate) struct SparsePiece {
+    parts: Vec<Part>,
+    pub size: u64,
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn invalid(why: &str) -> DroidKitError {
+    DroidKitError::Parse(format!("Invalid sparse image: {}", why))
+}
+
+impl SparseImage {
+    /// Reads the layout of `file`, which is `size` bytes long.
+    pub fn read(file: &mut (impl Read + Seek), size: u64) -> Result<Self, DroidKitError> {
+        let mut header = [0u8; FILE_HEADER_LEN as usize];
+        file.seek(SeekFrom::Start(0))?;
+        let is_sparse = size >= FILE_HEADER_LEN
+            && file.read_exact(&mut header).is_ok()
+            && read_u32(&header, 0) == SPARSE_MAGIC;
+        if !is_sparse {
+            let blocks = size.div_ceil(u64::from(RAW_BLOCK_SIZE));
+            let blocks = u32::try_from(blocks).map_err(|_| invalid("too many blocks"))?;
+            return Ok(SparseImage {
+                block_size: RAW_BLOCK_SIZE,
+                total_blocks: blocks,
+                segments: vec![Segment {
+                    start: 0,
+                    blocks,
+                    data: SegmentData::Raw {
+                        offset: 0,
+                        len: size,
+                    },
+                }],
+            });
+        }
+
+        let file_header_len = u64::from(read_u16(&header, 8));
+        let chunk_header_len = u64::from(read_u16(&header, 10));
+        let block_size = read_u32(&header, 12);
+        let total_blocks = read_u32(&header, 16);
+        let total_chunks = read_u32(&header, 20);
+        if read_u16(&header, 4) != 1
+            || file_header_len < FILE_HEADER_LEN
+            || chunk_header_len < CHUNK_HEADER_LEN
+            || block_size == 0
+            || !block_size.is_multiple_of(4)
+        {
+            return Err(invalid("unsupported header"));
+        }
+
+        let mut segments = Vec::new();
+        let mut offset = file_header_len;
+        let mut block = 0u32;
+        for _ in 0..total_chunks {
+            let mut chunk = [0u8; CHUNK_HEADER_LEN as usize];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut chunk)?;
+            let blocks = read_u32(&chunk, 4);
+            let data_len = u64::from(read_u32(&chunk, 8))
+                .checked_sub(chunk_header_len)
+                .ok_or_else(|| invalid("chunk shorter than its header"))?;
+            let data_offset = offset + chunk_header_len;
+
+            let data = match read_u16(&chunk, 0) {
+                CHUNK_RAW if data_len == u64::from(blocks) * u64::from(block_size) => {
+                    Some(SegmentData::Raw {
+                        offset: data_offset,
+                        len: data_len,
+                    })
+                }
+                CHUNK_FILL if data_len == 4 => {
+                    let mut value = [0u8; 4];
+                    file.seek(SeekFrom::Start(data_offset))?;
+                    file.read_exact(&mut value)?;
+                    Some(SegmentData::Fill(u32::from_le_bytes(value)))
+                }
+                CHUNK_DONT_CARE | CHUNK_CRC32 => None,
+                _ => return Err(invalid("unknown or inconsistent chunk")),
+            };
+            if let Some(data) = data
+                && blocks > 0
+            {
+                segments.push(Segment {
+                    start: block,
+                    blocks,
+                    data,
+                });
+            }
+            block = block
+                .checked_add(blocks)
+                .filter(|&block| block <= total_blocks)
+                .ok_or_else(|| invalid("chunks run past the end"))?;
+            offset = data_offset + data_len;
+        }
+
+        Ok(SparseImage {
+            block_size,
+            total_blocks,
+            segments,
+        })
+    }
+
+    fn chunk_header(chunk_type: u16, blocks: u32, data_len: u64) -> Vec<u8> {
+        let mut header = Vec::with_capacity(CHUNK_HEADER_LEN as usize);
+        header.extend_from_slice(&chunk_type.to_le_bytes());
+        header.extend_from_slice(&[0, 0]);
+        header.extend_from_slice(&blocks.to_le_bytes());
+        header.extend_from_slice(&((CHUNK_HEADER_LEN + data_len) as u32).to_le_bytes());
+        header
+    }
+
+    /// Lays out `segments` as one sparse image covering the whole partition.
+    fn piece(&self, segments: &[Segment]) -> SparsePiece {
+        let mut chunks: Vec<Part> = Vec::new();
+        let mut count = 0u32;
+        let mut block = 0;
+        let skip_to = |chunks: &mut Vec<Part>, count: &mut u32, block: &mut u32, to: u32| {
+            if to > *block {
+                chunks.push(Part::Bytes(Self::chunk_header(
+                    CHUNK_DONT_CARE,
+                    to - *block,
+                    0,
+                )));
+                *count += 1;
+                *block = to;
+            }
+        };
+
+        for segment in segments {
+            skip_to(&mut chunks, &mut count, &mut block, segment.start);
+            match segment.data {
+                SegmentData::Raw { offset, len } => {
+                    let data_len = segment.data_len(self.block_size);
+                    chunks.push(Part::Bytes(Self::chunk_header(
+                        CHUNK_RAW,
+                        segment.blocks,
+                        data_len,
+                    )));
+                    chunks.push(Part::File { offset, len });
+                    if data_len > len {
+                        chunks.push(Part::Zeros(data_len - len));
+                    }
+                }
+                SegmentData::Fill(value) => {
+                    let mut chunk = Self::chunk_header(CHUNK_FILL, segment.blocks, 4);
+                    chunk.extend_from_slice(&value.to_le_bytes());
+                    chunks.push(Part::Bytes(chunk));
+                }
+            }
+            count += 1;
+            block = segment.end();
+        }
+        skip_to(&mut chunks, &mut count, &mut block, self.total_blocks);
+
+        let mut header = Vec::with_capacity(FILE_HEADER_LEN as usize);
+        header.extend_from_slice(&SPARSE_MAGIC.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&(FILE_HEADER_LEN as u16).to_le_bytes());
+        header.extend_from_slice(&(CHUNK_HEADER_LEN as u16).to_le_bytes());
+        header.extend_from_slice(&self.block_size.to_le_bytes());
+        header.extend_from_slice(&self.total_blocks.to_le_bytes());
+        header.extend_from_slice(&count.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut parts = vec![Part::Bytes(header)];
+        parts.extend(chunks);
+        let size = parts
+            .iter()
+            .map(|part| match part {
+                Part::Bytes(bytes) => bytes.len() as u64,
+                Part::File { len, .. } | Part::Zeros(len) => *len,
+            })
+            .sum();
+        SparsePiece { parts, size }
+    }
+
+    /// Splits the image into sparse pieces of at most `max_size` bytes,
+    /// cutting raw data at block boundaries where needed.
+    pub fn split(&self, max_size: u64) -> Result<Vec<SparsePiece>, DroidKitError> {
+        // Room for the skips before and after what a piece carries
+        let overhead = FILE_HEADER_LEN + 2 * CHUNK_HEADER_LEN;
+        let block_size = u64::from(self.block_size);
+        if max_size < overhead + 2 * CHUNK_HEADER_LEN + block_size {
+            return Err(DroidKitError::InvalidInput(format!(
+                "The bootloader takes at most {} bytes, too little for a sparse image",
+                max_size
+            )));
+        }
+
+        let mut pieces = Vec::new();
+        let mut current: Vec<Segment> = Vec::new();
+        let mut used = overhead;
+        let mut pending: Vec<Segment> = self.segments.iter().rev().copied().collect();
+        while let Some(segment) = pending.pop() {
+            let gap = match current.last() {
+                Some(last) if last.end() != segment.start => CHUNK_HEADER_LEN,
+                _ => 0,
+            };
+            let cost = gap + CHUNK_HEADER_LEN + segment.data_len(self.block_size);
+            if used + cost <= max_size {
+                used += cost;
+                current.push(segment);
+                continue;
+            }
+
+            let room = max_size.saturating_sub(used + gap + CHUNK_HEADER_LEN) / block_size;
+            if matches!(segment.data, SegmentData::Raw { .. }) && room > 0 {
+                let (head, tail) = segment.split(room as u32, self.block_size);
+                current.push(head);
+                pending.push(tail);
+            } else {
+                pending.push(segment);
+            }
+            pieces.push(self.piece(&current));
+            current.clear();
+            used = overhead;
+        }
+        if !current.is_empty() || pieces.is_empty() {
+            pieces.push(self.piece(&current));
+        }
+        Ok(pieces)
+    }
+}
+
+/// Reads a [`SparsePiece`], taking its data from the image file.
+pub(crate) struct PieceReader<'a, F> {
+    file: &'a mut F,
+    parts: std::slice::Iter<'a, Part>,
+    /// The rest of the part being read.
+    current: Option<(&'a Part, u64)>,
+}
+
+impl SparsePiece {
+    pub fn reader<'a, F: Read + Seek>(&'a self, file: &'a mut F) -> PieceReader<'a, F> {
+        PieceReader {
+            file,
+            parts: self.parts.iter(),
+            current: None,
+        }
+    }
+}
+
+impl<F: Read + Seek> Read for PieceReader<'_, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (part, done) = match self.current {
+                Some(current) => current,
+                None => match self.parts.next() {
+                    Some(part) => {
+                        if let Part::File { offset, .. } = part {
+                            self.file.seek(SeekFrom::Start(*offset))?;
+                        }
+                        (part, 0)
+                    }
+                    None => return Ok(0),
+                },
+            };
+
+            let len = match part {
+                Part::Bytes(bytes) => bytes.len() as u64,
+                Part::File { len, .. } | Part::Zeros(len) => *len,
+            };
+            let want = (len - done).min(buf.len() as u64) as usize;
+            if want == 0 {
+                self.current = None;
+                continue;
+            }
+
+            let read = match part {
+                Part::Bytes(bytes) => {
+                    buf[..want].copy_from_slice(&bytes[done as usize..done as usize + want]);
+                    want
+                }
+                Part::File { .. } => match self.file.read(&mut buf[..want])? {
+                    0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    read => read,
+                },
+                Part::Zeros(_) => {
+                    buf[..want].fill(0);
+                    want
+                }
+            };
+            self.current = Some((part, done + read as u64));
+            return Ok(read);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Writes `image` out the way a bootloader would, on top of `partition`.
+    fn apply(partition: &mut [u8], image: &[u8]) {
+        let block_size = read_u32(image, 12) as usize;
+        let total_chunks = read_u32(image, 20);
+        let mut offset = FILE_HEADER_LEN as usize;
+        let mut block = 0;
+        for _ in 0..total_chunks {
+            let blocks = read_u32(image, offset + 4) as usize;
+            let total_len = read_u32(image, offset + 8) as usize;
+            let data = &image[offset + 12..offset + total_len];
+            let target = &mut partition[block * block_size..(block + blocks) * block_size];
+            match read_u16(image, offset) {
+                CHUNK_RAW => target.copy_from_slice(data),
+                CHUNK_FILL => target
+                    .chunks_mut(4)
+                    .for_each(|word| word.copy_from_slice(data)),
+                _ => {}
+            }
+            block += blocks;
+            offset += total_len;
+        }
+        assert_eq!(offset, image.len());
+    }
+
+    fn read_piece(piece: &SparsePiece, file: &mut Cursor<Vec<u8>>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        piece.reader(file).read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes.len() as u64, piece.size);
+        bytes
+    }
+
+    #[test]
+    fn resparses_raw_images_into_pieces_that_fit() {
+        // Ten and a half blocks, so the last one is padded
+        let data: Vec<u8> = (0..4096 * 10 + 2048).map(|i| (i % 251) as u8).collect();
+        let mut file = Cursor::new(data.clone());
+        let image = SparseImage::read(&mut file, data.len() as u64).unwrap();
+        assert_eq!(image.total_blocks, 11);
+
+        let max_size = 3 * 4096 + 100;
+        let pieces = image.split(max_size).unwrap();
+        assert_eq!(pieces.len(), 4);
+
+        let mut partition = vec![0xffu8; 11 * 4096];
+        for piece in &pieces {
+            assert!(piece.size <= max_size);
+            apply(&mut partition, &read_piece(piece, &mut file));
+        }
+        assert_eq!(partition[..data.len()], data[..]);
+        assert!(partition[data.len()..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn splits_sparse_images_keeping_fills_and_gaps() {
+        let mut sparse = Vec::new();
+        sparse.extend_from_slice(&SPARSE_MAGIC.to_le_bytes());
+        sparse.extend_from_slice(&[1, 0, 0, 0, 28, 0, 12, 0]);
+        sparse.extend_from_slice(&4096u32.to_le_bytes());
+        sparse.extend_from_slice(&8u32.to_le_bytes());
+        sparse.extend_from_slice(&3u32.to_le_bytes());
+        sparse.extend_from_slice(&0u32.to_le_bytes());
+        let raw: Vec<u8> = (0..2 * 4096).map(|i| (i % 7) as u8).collect();
+        sparse.extend(SparseImage::chunk_header(CHUNK_RAW, 2, raw.len() as u64));
+        sparse.extend_from_slice(&raw);
+        sparse.extend(SparseImage::chunk_header(CHUNK_DONT_CARE, 4, 0));
+        sparse.extend(SparseImage::chunk_header(CHUNK_FILL, 2, 4));
+        sparse.extend_from_slice(&0xdead_beefu32.to_le_bytes());
+
+        let mut file = Cursor::new(sparse.clone());
+        let image = SparseImage::read(&mut file, sparse.len() as u64).unwrap();
+        assert_eq!(image.segments.len(), 2);
+
+        let pieces = image.split(4096 + 100).unwrap();
+        assert_eq!(pieces.len(), 2);
+        let mut partition = vec![0u8; 8 * 4096];
+        for piece in &pieces {
+            apply(&mut partition, &read_piece(piece, &mut file));
+        }
+        assert_eq!(partition[..2 * 4096], raw[..]);
+        assert!(partition[2 * 4096..6 * 4096].iter().all(|&byte| byte == 0));
+        assert_eq!(
+            partition[6 * 4096..6 * 4096 + 4],
+            0xdead_beefu32.to_le_bytes()
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Fastboot over TCP, as fastbootd and some bootloaders offer on port 5554:
+//! a version handshake, then every packet prefixed with its length.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use super::client::FastbootTransport;
+use crate::error::DroidKitError;
+
+pub(crate) const DEFAULT_FASTBOOT_PORT: u16 = 5554;
+
+const HANDSHAKE: &[u8; 4] = b"FB01";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Flashing or erasing a large partition can take minutes before the OKAY.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(600);
+/// Responses are small; anything bigger is not fastboot talking.
+const MAX_RESPONSE_LEN: u64 = 64 * 1024;
+
+pub(crate) struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    pub fn connect(addr: SocketAddr) -> Result<Self, DroidKitError> {
+        let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).map_err(|e| {
+            DroidKitError::from(e).context(&format!("Failed to connect to {}", addr))
+        })?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+
+        stream.write_all(HANDSHAKE)?;
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply)?;
+        // Any later version still speaks version 1 to us
+        let version = std::str::from_utf8(&reply[2..])
+            .ok()
+            .and_then(|version| version.parse::<u8>().ok());
+        if &reply[..2] != b"FB" || version.is_none_or(|version| version < 1) {
+            return Err(DroidKitError::Parse(format!(
+                "{} did not answer the fastboot handshake: {:?}",
+                addr,
+                String::from_utf8_lossy(&reply)
+            )));
+        }
+
+        Ok(TcpTransport { stream })
+    }
+}
+
+impl FastbootTransport for TcpTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), DroidKitError> {
+        self.stream
+            .write_all(&(packet.len() as u64).to_be_bytes())?;
+        self.stream.write_all(packet)?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, DroidKitError> {
+        let mut len = [0u8; 8];
+        self.stream.read_exact(&mut len)?;
+        let len = u64::from_be_bytes(len);
+        if len > MAX_RESPONSE_LEN {
+            return Err(DroidKitError::Parse(format!(
+                "Fastboot response of {} bytes",
+                len
+            )));
+        }
+
+        let mut packet = vec![0u8; len as usize];
+        self.stream.read_exact(&mut packet)?;
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::unique_temp_dir;
+    use crate::adb_commands::reboot::RebootTarget;
+    use crate::fastboot::client::{Fastboot, FastbootEvent};
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::thread::{self, JoinHandle};
+
+    /// The device's side of one fastboot-over-TCP connection.
+    struct StandIn {
+        stream: TcpStream,
+    }
+
+    impl StandIn {
+        fn receive(&mut self) -> Vec<u8> {
+            let mut len = [0u8; 8];
+            self.stream.read_exact(&mut len).unwrap();
+            let mut packet = vec![0u8; u64::from_be_bytes(len) as usize];
+            self.stream.read_exact(&mut packet).unwrap();
+            packet
+        }
+
+        fn expect(&mut self, command: &str) {
+            assert_eq!(String::from_utf8(self.receive()).unwrap(), command);
+        }
+
+        fn reply(&mut self, packet: &str) {
+            self.stream
+                .write_all(&(packet.len() as u64).to_be_bytes())
+                .unwrap();
+            self.stream.write_all(packet.as_bytes()).unwrap();
+        }
+
+        /// Reads downloaded data until `len` bytes have arrived.
+        fn receive_data(&mut self, len: usize) -> Vec<u8> {
+            let mut data = Vec::new();
+            while data.len() < len {
+                data.extend(self.receive());
+            }
+            data
+        }
+    }
+
+    /// Serves a single connection with `script`, returning what it returns.
+    fn stand_in<T: Send + 'static>(
+        script: impl FnOnce(&mut StandIn) -> T + Send + 'static,
+    ) -> (Fastboot, JoinHandle<T>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut handshake = [0u8; 4];
+            stream.read_exact(&mut handshake).unwrap();
+            assert_eq!(&handshake, HANDSHAKE);
+            stream.write_all(HANDSHAKE).unwrap();
+            script(&mut StandIn { stream })
+        });
+
+        let transport = TcpTransport::connect(addr).unwrap();
+        (Fastboot::new(Box::new(transport)), handle)
+    }
+
+    #[test]
+    fn getvar_all() {
+        let (mut fastboot, stand_in) = stand_in(|device| {
+            device.expect("getvar:all");
+            device.reply("INFOversion-bootloader:slider-1.2-9152140");
+            device.reply("INFOpartition-size:boot_a: 0x4000000");
+            device.reply("INFOcurrent-slot:a");
+            device.reply("INFOunlocked:no");
+            device.reply("OKAY");
+        });
+
+        let vars = fastboot.getvar_all().unwrap();
+        stand_in.join().unwrap();
+
+        let names: Vec<&str> = vars.iter().map(|var| var.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "version-bootloader",
+                "partition-size:boot_a",
+                "current-slot",
+                "unlocked"
+            ]
+        );
+        assert_eq!(vars[1].value, "0x4000000");
+    }
+
+    #[test]
+    fn flash_with_progress() {
+        let image_data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        let dir = unique_temp_dir("fastboot-flash");
+        let image = dir.join("boot.img");
+        std::fs::write(&image, &image_data).unwrap();
+
+        let (mut fastboot, stand_in) = stand_in(|device| {
+            device.expect("getvar:max-download-size");
+            device.reply("OKAY0x10000000");
+            device.expect("download:00000bb8");
+            device.reply("DATA00000bb8");
+            let data = device.receive_data(3000);
+            device.reply("OKAY");
+            device.expect("flash:boot_a");
+            device.reply("INFOWriting 'boot_a'");
+            device.reply("OKAY");
+            data
+        });
+
+        let mut events = Vec::new();
+        fastboot
+            .flash("boot_a", &image, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!(stand_in.join().unwrap(), image_data);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(matches!(
+            events[..],
+            [
+                FastbootEvent::Download {
+                    bytes_sent: 3000,
+                    total_bytes: 3000
+                },
+                FastbootEvent::Info { ref message }
+            ] if message == "Writing 'boot_a'"
+        ));
+    }
+
+    #[test]
+    fn images_over_the_download_limit_are_refused() {
+        let dir = unique_temp_dir("fastboot-too-large");
+        let image = dir.join("boot.img");
+        std::fs::write(&image, vec![0u8; 3000]).unwrap();
+
+        let (mut fastboot, stand_in) = stand_in(|device| {
+            device.expect("getvar:max-download-size");
+            device.reply("OKAY0x800");
+        });
+
+        let err = fastboot.flash("boot_a", &image, &mut |_| {}).unwrap_err();
+        stand_in.join().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(err.kind(), "InvalidInput");
+    }
+
+    #[test]
+    fn erase_oem_and_reboot() {
+        let (mut fastboot, stand_in) = stand_in(|device| {
+            device.expect("erase:userdata");
+            device.reply("OKAY");
+            device.expect("oem device-info");
+            device.reply("INFODevice tampered: false");
+            device.reply("INFODevice unlocked: true");
+            device.reply("OKAY");
+            device.expect("reboot-bootloader");
+            device.reply("OKAY");
+        });
+
+        fastboot.erase("userdata", &mut |_| {}).unwrap();
+        assert_eq!(
+            fastboot.oem("device-info").unwrap(),
+            ["Device tampered: false", "Device unlocked: true"]
+        );
+        fastboot.reboot(RebootTarget::Bootloader).unwrap();
+        stand_in.join().unwrap();
+    }
+
+    #[test]
+    fn fail_is_an_error() {
+        let (mut fastboot, stand_in) = stand_in(|device| {
+            device.expect("flashing unlock");
+            device.reply("FAILFlashing Unlock is not allowed");
+        });
+
+        let err = fastboot.flashing_unlock().unwrap_err();
+        stand_in.join().unwrap();
+        assert_eq!(err.kind(), "Fastboot");
+        assert_eq!(
+            err.to_string(),
+            "flashing unlock failed: Flashing Unlock is not allowed"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Fastboot over USB: one bulk transfer per packet on the bootloader's
+//! fastboot interface.
+
+use rusb::{
+    DeviceHandle, Direction, GlobalContext, TransferType, UsbContext,
+    constants::LIBUSB_CLASS_VENDOR_SPEC,
+};
+use std::time::Duration;
+
+use super::client::FastbootTransport;
+use crate::adb_commands::device::{UsbDeviceEntry, read_usb_entry};
+use crate::error::DroidKitError;
+
+const FASTBOOT_SUBCLASS: u8 = 0x42;
+const FASTBOOT_PROTOCOL: u8 = 0x03;
+
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Flashing or erasing a large partition can take minutes before the OKAY.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(600);
+/// A multiple of every bulk packet size, so a response never overflows it.
+const RESPONSE_BUFFER_LEN: usize = 4096;
+
+struct FastbootInterface {
+    number: u8,
+    endpoint_in: u8,
+    endpoint_out: u8,
+}
+
+fn usb_error(e: rusb::Error) -> DroidKitError {
+    match e {
+        rusb::Error::Timeout => DroidKitError::Timeout(e.to_string()),
+        rusb::Error::NoDevice => DroidKitError::NoDevice(e.to_string()),
+        _ => DroidKitError::Io(e.to_string()),
+    }
+}
+
+fn find_fastboot_interface(device: &rusb::Device<GlobalContext>) -> Option<FastbootInterface> {
+    let config = device.active_config_descriptor().ok()?;
+    config.interfaces().find_map(|interface| {
+        interface.descriptors().find_map(|interface_des| {
+            if interface_des.class_code() != LIBUSB_CLASS_VENDOR_SPEC
+                || interface_des.sub_class_code() != FASTBOOT_SUBCLASS
+                || interface_des.protocol_code() != FASTBOOT_PROTOCOL
+            {
+                return None;
+            }
+
+            let bulk_endpoint = |direction| {
+                interface_des
+                    .endpoint_descriptors()
+                    .find(|endpoint| {
+                        endpoint.transfer_type() == TransferType::Bulk
+                            && endpoint.direction() == direction
+                    })
+                    .map(|endpoint| endpoint.address())
+            };
+            Some(FastbootInterface {
+                number: interface_des.interface_number(),
+                endpoint_in: bulk_endpoint(Direction::In)?,
+                endpoint_out: bulk_endpoint(Direction::Out)?,
+            })
+        })
+    })
+}
+
+fn find_fastboot_usb_devices()
+-> Result<Vec<(rusb::Device<GlobalContext>, UsbDeviceEntry)>, DroidKitError> {
+    let devices = GlobalContext::default()
+        .devices()
+        .map_err(|e| DroidKitError::Io(format!("Failed to enumerate USB devices: {}", e)))?;
+
+    Ok(devices
+        .iter()
+        .filter_map(|device| {
+            let des = device.device_descriptor().ok()?;
+            find_fastboot_interface(&device)?;
+            let entry = read_usb_entry(&device, &des);
+            Some((device, entry))
+        })
+        .collect())
+}
+
+/// Lists every attached USB device sitting in the bootloader or fastbootd.
+pub(crate) fn list_fastboot_usb_devices() -> Result<Vec<UsbDeviceEntry>, DroidKitError> {
+    find_fastboot_usb_devices().map(|devices| devices.into_iter().map(|(_, entry)| entry).collect())
+}
+
+pub(crate) struct UsbTransport {
+    handle: DeviceHandle<GlobalContext>,
+    interface: FastbootInterface,
+}
+
+impl UsbTransport {
+    /// Claims the fastboot interface of the device whose serial number is
+    /// exactly `serial_no`.
+    pub fn open(serial_no: &str) -> Result<Self, DroidKitError> {
+        let device = find_fastboot_usb_devices()?
+            .into_iter()
+            .find(|(_, entry)| entry.serial_no.as_deref() == Some(serial_no))
+            .map(|(device, _)| device)
+            .ok_or_else(|| {
+                DroidKitError::NoDevice(format!("No device in fastboot with serial {}", serial_no))
+            })?;
+        let interface = find_fastboot_interface(&device)
+            .ok_or_else(|| DroidKitError::NoDevice(format!("{} left fastboot", serial_no)))?;
+
+        let handle = device.open().map_err(usb_error)?;
+        // Not supported everywhere, and only needed where a driver is bound
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        handle
+            .claim_interface(interface.number)
+            .map_err(|e| usb_error(e).context(&format!("Failed to claim {}", serial_no)))?;
+
+        Ok(UsbTransport { handle, interface })
+    }
+}
+
+impl Drop for UsbTransport {
+    fn drop(&mut self) {
+        let _ = self.handle.release_interface(self.interface.number);
+    }
+}
+
+impl FastbootTransport for UsbTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), DroidKitError> {
+        let mut sent = 0;
+        while sent < packet.len() {
+            sent += self
+                .handle
+                .write_bulk(self.interface.endpoint_out, &packet[sent..], WRITE_TIMEOUT)
+                .map_err(usb_error)?;
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, DroidKitError> {
+        let mut packet = vec![0u8; RESPONSE_BUFFER_LEN];
+        let len = self
+            .handle
+            .read_bulk(self.interface.endpoint_in, &mut packet, RESPONSE_TIMEOUT)
+            .map_err(usb_error)?;
+        packet.truncate(len);
+        Ok(packet)
+    }
+}
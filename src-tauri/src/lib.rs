@@ -1,34 +1,74 @@
+use crate::adb_commands::adb_device::AdbDevice;
+use crate::adb_commands::capabilities::{DeviceCapabilities, probe_capabilities};
+use crate::adb_commands::checksum::{ChecksumComparison, compare_file, verify_copy};
+use crate::adb_commands::deadline::{CommandCategory, CommandTimeouts, DeviceOperations};
 use crate::adb_commands::device::{
-    DeviceInfo, DiscoveredDevice, connect_tcp_device, connect_to_discovered_device,
-    get_connected_device, list_discovered_devices, pair_device_with_code, reconnect_device,
+    Device, DeviceInfo, DiscoveredDevice, ServerDeviceEntry, UsbDeviceEntry, connect_tcp_device,
+    connect_to_discovered_device, get_connected_device, list_discovered_devices,
+    list_server_devices, list_usb_devices, open_server_device, pair_device_with_code,
 };
 use crate::adb_commands::discovery::{
     DiscoveredWirelessDevice, discover_wireless_devices, discover_wireless_devices_detailed,
     get_connection_port_for_device,
 };
-use crate::adb_commands::files::{FileInfo, list_files, pull_file};
+use crate::adb_commands::file_ops::{
+    FileOpResult, change_mode, copy_path, delete_path, make_directory, move_path, touch_path,
+};
+use crate::adb_commands::files::{FileInfo, TransferProgress, list_files, pull_file, push_file};
+use crate::adb_commands::forward::PortRule;
+use crate::adb_commands::hotplug::start_device_watcher;
+use crate::adb_commands::listing::{
+    BrowseKey, ListingBatch, ListingCache, ListingPage, ListingQuery, list_page,
+};
 use crate::adb_commands::logcat::{execute_shell_command, get_device_info, get_logcat_output};
+use crate::adb_commands::mirror::{
+    CompareBy, MirrorPlan, MirrorPlans, MirrorSummary, SyncDirection, plan_mirror, run_mirror,
+};
 use crate::adb_commands::packages::get_installed_packages;
 use crate::adb_commands::pairing::{
     PairingData, PairingResult, generate_pairing_data, start_pairing_listener,
 };
+use crate::adb_commands::pty::{PtyEvent, PtySessionInfo, PtySessions};
+use crate::adb_commands::reboot::{
+    RebootEvent, RebootTarget, SideloadProgress, reboot_and_wait, sideload_package,
+};
+use crate::adb_commands::root::{Elevated, RootMode};
+use crate::adb_commands::session::DeviceSessions;
+use crate::adb_commands::shell::{ShellOutput, run_shell_command};
+use crate::adb_commands::shell_stream::{ShellEvent, ShellStreams, stream_shell_command};
+use crate::adb_commands::tcpip::{DEFAULT_TCPIP_PORT, switch_to_tcpip};
+use crate::adb_commands::transfer::{
+    SymlinkPolicy, TreeProgress, TreeTransferSummary, pull_dir, push_dir,
+};
 use crate::emulator::{get_android_home, launch_avd, list_avds};
+use crate::error::DroidKitError;
+use crate::fastboot::client::{FastbootEvent, FastbootVar};
+use crate::fastboot::connect_fastboot;
+use crate::fastboot::usb::list_fastboot_usb_devices;
 use crate::system_info::{
     BatteryInfo, BuildInfo, DisplayInfo, HardwareInfo, NetworkInfo, get_battery_info,
     get_build_info, get_display_info, get_hardware_info, get_network_info,
 };
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use tauri::{Manager, State};
 
 mod adb_commands;
 mod emulator;
+mod error;
+mod fastboot;
 mod system_info;
 mod utils;
 
 #[tauri::command]
-fn device_info() -> Result<DeviceInfo, ()> {
+fn device_info() -> Result<DeviceInfo, DroidKitError> {
     get_connected_device()
-        .and_then(|mut device| get_device_info(&mut device).ok())
-        .ok_or(())
+        .ok_or_else(no_device_connected)
+        .and_then(|mut device| get_device_info(&mut device))
+}
+
+fn no_device_connected() -> DroidKitError {
+    DroidKitError::NoDevice("No device connected".to_string())
 }
 
 #[tauri::command]
@@ -47,42 +87,596 @@ fn start_avd(avd_name: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-fn browse_files(path: String) -> Result<Vec<FileInfo>, String> {
+fn browse_files(path: String) -> Result<Vec<FileInfo>, DroidKitError> {
+    get_connected_device()
+        .ok_or_else(no_device_connected)
+        .and_then(|mut device| {
+            let capabilities = probe_capabilities(&mut device)?;
+            list_files(&mut device, &capabilities, &path)
+        })
+}
+
+/// Runs `f` against `serial_no` on a blocking thread, under the deadline for
+/// `category` and as root when `elevated` asks for it and the device allows.
+async fn with_device_op<T: Send + 'static>(
+    sessions: &DeviceSessions,
+    operations: &DeviceOperations,
+    serial_no: String,
+    category: CommandCategory,
+    elevated: Option<bool>,
+    f: impl FnOnce(&mut dyn AdbDevice, &DeviceCapabilities) -> Result<T, DroidKitError> + Send + 'static,
+) -> Result<Elevated<T>, DroidKitError> {
+    let sessions = sessions.clone();
+    let deadline = operations.deadline(&serial_no, category);
+    tokio::task::spawn_blocking(move || {
+        sessions.try_with_device(&serial_no, |device| {
+            deadline.try_run(device, |device| {
+                let capabilities = sessions.capabilities(&serial_no, device)?;
+                RootMode::choose(&capabilities, elevated.unwrap_or(false))?
+                    .run(device, |device| f(device, &capabilities))
+            })
+        })
+    })
+    .await
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+/// [`with_device_op`] for a command that changes files on the device. The
+/// directory browsed there is listed afresh afterwards.
+async fn with_file_change<T: Send + 'static>(
+    sessions: &DeviceSessions,
+    operations: &DeviceOperations,
+    listings: &ListingCache,
+    serial_no: String,
+    elevated: Option<bool>,
+    f: impl FnOnce(&mut dyn AdbDevice, &DeviceCapabilities) -> Result<T, DroidKitError> + Send + 'static,
+) -> Result<Elevated<T>, DroidKitError> {
+    let result = with_device_op(
+        sessions,
+        operations,
+        serial_no.clone(),
+        CommandCategory::Files,
+        elevated,
+        f,
+    )
+    .await;
+    listings.forget(&serial_no);
+    result
+}
+
+#[tauri::command]
+fn browse_files_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+    path: String,
+    elevated: Option<bool>,
+) -> Result<Elevated<Vec<FileInfo>>, DroidKitError> {
+    let deadline = operations.deadline(&device_serial, CommandCategory::Files);
+    sessions.try_with_device(&device_serial, |device| {
+        deadline.try_run(device, |device| {
+            let capabilities = sessions.capabilities(&device_serial, device)?;
+            RootMode::choose(&capabilities, elevated.unwrap_or(false))?
+                .run(device, |device| list_files(device, &capabilities, &path))
+        })
+    })
+}
+
+/// Lists one page of a directory, sorted and filtered on the backend, and
+/// sends its entries through `on_batch` a few hundred at a time.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn browse_files_page_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    listings: State<'_, ListingCache>,
+    device_serial: String,
+    path: String,
+    query: Option<ListingQuery>,
+    elevated: Option<bool>,
+    on_batch: tauri::ipc::Channel<ListingBatch>,
+) -> Result<Elevated<ListingPage>, DroidKitError> {
+    let query = query.unwrap_or_default();
+    let listings = listings.inner().clone();
+    let key = BrowseKey {
+        serial_no: device_serial.clone(),
+        elevated: elevated.unwrap_or(false),
+    };
+    with_device_op(
+        &sessions,
+        &operations,
+        device_serial,
+        CommandCategory::Files,
+        elevated,
+        move |device, capabilities| {
+            list_page(
+                device,
+                capabilities,
+                &listings,
+                &key,
+                &path,
+                &query,
+                |batch| {
+                    let _ = on_batch.send(batch);
+                },
+            )
+        },
+    )
+    .await
+}
+
+/// Compares a local directory with one on a specific device and returns
+/// what mirroring `direction` would add, update and, with `delete`, remove.
+/// Nothing is changed until the plan's id is passed to
+/// [`run_mirror_for_device`].
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn plan_mirror_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    plans: State<'_, MirrorPlans>,
+    device_serial: String,
+    local_path: String,
+    remote_path: String,
+    direction: SyncDirection,
+    compare: Option<CompareBy>,
+    delete: Option<bool>,
+    elevated: Option<bool>,
+) -> Result<Elevated<MirrorPlan>, DroidKitError> {
+    let planned = with_device_op(
+        &sessions,
+        &operations,
+        device_serial.clone(),
+        CommandCategory::Files,
+        elevated,
+        move |device, _| {
+            plan_mirror(
+                device,
+                &local_path,
+                &remote_path,
+                direction,
+                compare.unwrap_or_default(),
+                delete.unwrap_or(false),
+            )
+        },
+    )
+    .await?;
+    Ok(Elevated {
+        root_mode: planned.root_mode,
+        value: plans.insert(&device_serial, planned.value),
+    })
+}
+
+/// Carries out plan `plan_id` from [`plan_mirror_for_device`], reporting
+/// progress through `on_progress` and per-path failures in the summary.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn run_mirror_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    listings: State<'_, ListingCache>,
+    plans: State<'_, MirrorPlans>,
+    device_serial: String,
+    plan_id: u32,
+    elevated: Option<bool>,
+    on_progress: tauri::ipc::Channel<TreeProgress>,
+) -> Result<Elevated<MirrorSummary>, DroidKitError> {
+    let plan = plans.take(&device_serial, plan_id)?;
+    with_file_change(
+        &sessions,
+        &operations,
+        &listings,
+        device_serial,
+        elevated,
+        move |device, _| {
+            run_mirror(device, &plan, |progress| {
+                let _ = on_progress.send(progress);
+            })
+        },
+    )
+    .await
+}
+
+#[tauri::command]
+fn download_file(remote_path: String, local_path: String) -> Result<(), DroidKitError> {
     get_connected_device()
-        .ok_or_else(|| "No device connected".to_string())
-        .and_then(|mut device| list_files(&mut device, &path))
+        .ok_or_else(no_device_connected)
+        .and_then(|mut device| pull_file(&mut device, &remote_path, &local_path, |_| {}))
 }
 
+/// Copies a file off the device, reporting progress through `on_progress`.
+/// With `verify`, both copies are hashed afterwards and any difference is a
+/// `ChecksumMismatch` error.
 #[tauri::command]
-fn browse_files_for_device(device_serial: String, path: String) -> Result<Vec<FileInfo>, String> {
-    reconnect_device(&device_serial)
-        .ok_or_else(|| "Failed to connect to device".to_string())
-        .and_then(|mut device| list_files(&mut device, &path))
+#[allow(clippy::too_many_arguments)]
+async fn download_file_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+    remote_path: String,
+    local_path: String,
+    verify: Option<bool>,
+    elevated: Option<bool>,
+    on_progress: tauri::ipc::Channel<TransferProgress>,
+) -> Result<Elevated<()>, DroidKitError> {
+    with_device_op(
+        &sessions,
+        &operations,
+        device_serial,
+        CommandCategory::Files,
+        elevated,
+        move |device, _| {
+            pull_file(device, &remote_path, &local_path, |progress| {
+                let _ = on_progress.send(progress);
+            })?;
+            if verify.unwrap_or(false) {
+                verify_copy(device, &local_path, &remote_path)?;
+            }
+            Ok(())
+        },
+    )
+    .await
 }
 
+/// Copies a local file to the connected device, returning where it went.
 #[tauri::command]
-fn download_file(remote_path: String, local_path: String) -> Result<(), String> {
+fn upload_file(
+    local_path: String,
+    remote_path: String,
+    overwrite: Option<bool>,
+) -> Result<String, DroidKitError> {
     get_connected_device()
-        .ok_or_else(|| "No device connected".to_string())
-        .and_then(|mut device| pull_file(&mut device, &remote_path, &local_path))
+        .ok_or_else(no_device_connected)
+        .and_then(|mut device| {
+            push_file(
+                &mut device,
+                &local_path,
+                &remote_path,
+                overwrite.unwrap_or(false),
+                |_| {},
+            )
+        })
+}
+
+/// Copies a local file to the device, reporting progress through
+/// `on_progress`. Existing files are only replaced when `overwrite` is set.
+/// With `verify`, both copies are hashed afterwards and any difference is a
+/// `ChecksumMismatch` error.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn upload_file_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    listings: State<'_, ListingCache>,
+    device_serial: String,
+    local_path: String,
+    remote_path: String,
+    overwrite: Option<bool>,
+    verify: Option<bool>,
+    elevated: Option<bool>,
+    on_progress: tauri::ipc::Channel<TransferProgress>,
+) -> Result<Elevated<String>, DroidKitError> {
+    with_file_change(
+        &sessions,
+        &operations,
+        &listings,
+        device_serial,
+        elevated,
+        move |device, _| {
+            let target = push_file(
+                device,
+                &local_path,
+                &remote_path,
+                overwrite.unwrap_or(false),
+                |progress| {
+                    let _ = on_progress.send(progress);
+                },
+            )?;
+            if verify.unwrap_or(false) {
+                verify_copy(device, &local_path, &target)?;
+            }
+            Ok(target)
+        },
+    )
+    .await
+}
+
+/// Hashes a local file and one on a specific device, with SHA-256 or, on
+/// devices without it, MD5.
+#[tauri::command]
+async fn compare_file_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+    local_path: String,
+    remote_path: String,
+    elevated: Option<bool>,
+) -> Result<Elevated<ChecksumComparison>, DroidKitError> {
+    with_device_op(
+        &sessions,
+        &operations,
+        device_serial,
+        CommandCategory::Files,
+        elevated,
+        move |device, _| compare_file(device, &local_path, &remote_path),
+    )
+    .await
+}
+
+/// Copies a directory from a specific device, reporting per-file failures in
+/// the summary rather than stopping at the first. With `verify`, a file whose
+/// copy hashes differently is one of those failures.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn download_directory_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+    remote_path: String,
+    local_path: String,
+    overwrite: Option<bool>,
+    symlinks: Option<SymlinkPolicy>,
+    verify: Option<bool>,
+    elevated: Option<bool>,
+    on_progress: tauri::ipc::Channel<TreeProgress>,
+) -> Result<Elevated<TreeTransferSummary>, DroidKitError> {
+    with_device_op(
+        &sessions,
+        &operations,
+        device_serial,
+        CommandCategory::Files,
+        elevated,
+        move |device, _| {
+            pull_dir(
+                device,
+                &remote_path,
+                &local_path,
+                overwrite.unwrap_or(false),
+                symlinks.unwrap_or_default(),
+                verify.unwrap_or(false),
+                |progress| {
+                    let _ = on_progress.send(progress);
+                },
+            )
+        },
+    )
+    .await
+}
+
+/// Copies a local directory to a specific device, reporting per-file
+/// failures in the summary rather than stopping at the first.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn upload_directory_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    listings: State<'_, ListingCache>,
+    device_serial: String,
+    local_path: String,
+    remote_path: String,
+    overwrite: Option<bool>,
+    symlinks: Option<SymlinkPolicy>,
+    verify: Option<bool>,
+    elevated: Option<bool>,
+    on_progress: tauri::ipc::Channel<TreeProgress>,
+) -> Result<Elevated<TreeTransferSummary>, DroidKitError> {
+    with_file_change(
+        &sessions,
+        &operations,
+        &listings,
+        device_serial,
+        elevated,
+        move |device, _| {
+            push_dir(
+                device,
+                &local_path,
+                &remote_path,
+                overwrite.unwrap_or(false),
+                symlinks.unwrap_or_default(),
+                verify.unwrap_or(false),
+                |progress| {
+                    let _ = on_progress.send(progress);
+                },
+            )
+        },
+    )
+    .await
 }
 
+/// Deletes a file or link, or a whole directory when `recursive`. With
+/// `dry_run` nothing is deleted and the result lists what would be.
 #[tauri::command]
-fn get_apps() -> Result<Vec<String>, String> {
+#[allow(clippy::too_many_arguments)]
+async fn delete_path_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    listings: State<'_, ListingCache>,
+    device_serial: String,
+    path: String,
+    recursive: Option<bool>,
+    dry_run: Option<bool>,
+    elevated: Option<bool>,
+) -> Result<Elevated<FileOpResult>, DroidKitError> {
+    with_file_change(
+        &sessions,
+        &operations,
+        &listings,
+        device_serial,
+        elevated,
+        move |device, _| {
+            delete_path(
+                device,
+                &path,
+                recursive.unwrap_or(false),
+                dry_run.unwrap_or(false),
+            )
+        },
+    )
+    .await
+}
+
+/// Renames or moves a path. A directory destination receives it under its
+/// own name; an existing file is only replaced when `overwrite`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn move_path_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    listings: State<'_, ListingCache>,
+    device_serial: String,
+    source: String,
+    dest: String,
+    overwrite: Option<bool>,
+    dry_run: Option<bool>,
+    elevated: Option<bool>,
+) -> Result<Elevated<FileOpResult>, DroidKitError> {
+    with_file_change(
+        &sessions,
+        &operations,
+        &listings,
+        device_serial,
+        elevated,
+        move |device, _| {
+            move_path(
+                device,
+                &source,
+                &dest,
+                overwrite.unwrap_or(false),
+                dry_run.unwrap_or(false),
+            )
+        },
+    )
+    .await
+}
+
+/// Copies a file or directory on the device, keeping modes and times.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn copy_path_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    listings: State<'_, ListingCache>,
+    device_serial: String,
+    source: String,
+    dest: String,
+    overwrite: Option<bool>,
+    dry_run: Option<bool>,
+    elevated: Option<bool>,
+) -> Result<Elevated<FileOpResult>, DroidKitError> {
+    with_file_change(
+        &sessions,
+        &operations,
+        &listings,
+        device_serial,
+        elevated,
+        move |device, _| {
+            copy_path(
+                device,
+                &source,
+                &dest,
+                overwrite.unwrap_or(false),
+                dry_run.unwrap_or(false),
+            )
+        },
+    )
+    .await
+}
+
+/// `mkdir -p` on the device.
+#[tauri::command]
+async fn make_directory_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    listings: State<'_, ListingCache>,
+    device_serial: String,
+    path: String,
+    elevated: Option<bool>,
+) -> Result<Elevated<FileOpResult>, DroidKitError> {
+    with_file_change(
+        &sessions,
+        &operations,
+        &listings,
+        device_serial,
+        elevated,
+        move |device, _| make_directory(device, &path),
+    )
+    .await
+}
+
+/// `chmod` with an octal or symbolic `mode`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn change_mode_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    listings: State<'_, ListingCache>,
+    device_serial: String,
+    path: String,
+    mode: String,
+    recursive: Option<bool>,
+    elevated: Option<bool>,
+) -> Result<Elevated<FileOpResult>, DroidKitError> {
+    with_file_change(
+        &sessions,
+        &operations,
+        &listings,
+        device_serial,
+        elevated,
+        move |device, _| change_mode(device, &path, &mode, recursive.unwrap_or(false)),
+    )
+    .await
+}
+
+/// `touch`: creates the file if missing and sets its times to `mod_time`
+/// (Unix seconds), or to now.
+#[tauri::command]
+async fn touch_path_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    listings: State<'_, ListingCache>,
+    device_serial: String,
+    path: String,
+    mod_time: Option<u32>,
+    elevated: Option<bool>,
+) -> Result<Elevated<FileOpResult>, DroidKitError> {
+    with_file_change(
+        &sessions,
+        &operations,
+        &listings,
+        device_serial,
+        elevated,
+        move |device, _| touch_path(device, &path, mod_time),
+    )
+    .await
+}
+
+#[tauri::command]
+fn get_apps() -> Result<Vec<String>, DroidKitError> {
     get_connected_device()
-        .ok_or_else(|| "No device connected".to_string())
-        .and_then(|mut device| get_installed_packages(&mut device))
+        .ok_or_else(no_device_connected)
+        .and_then(|mut device| {
+            let capabilities = probe_capabilities(&mut device)?;
+            get_installed_packages(&mut device, &capabilities)
+        })
 }
 
 #[tauri::command]
-fn get_apps_for_device(device_serial: String) -> Result<Vec<String>, String> {
-    reconnect_device(&device_serial)
-        .ok_or_else(|| "Failed to connect to device".to_string())
-        .and_then(|mut device| get_installed_packages(&mut device))
+fn get_apps_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+) -> Result<Vec<String>, DroidKitError> {
+    let deadline = operations.deadline(&device_serial, CommandCategory::Packages);
+    sessions.try_with_device(&device_serial, |device| {
+        deadline.try_run(device, |device| {
+            let capabilities = sessions.capabilities(&device_serial, device)?;
+            get_installed_packages(device, &capabilities)
+        })
+    })
 }
 
 #[tauri::command]
-fn get_logcat(lines: u32, on_event: tauri::ipc::Channel<Result<String, String>>) {
+fn get_logcat(lines: u32, on_event: tauri::ipc::Channel<Result<String, DroidKitError>>) {
     match get_connected_device() {
         Some(mut device) => {
             // Run logcat in a separate thread to avoid blocking
@@ -92,54 +686,82 @@ fn get_logcat(lines: u32, on_event: tauri::ipc::Channel<Result<String, String>>)
             });
         }
         None => {
-            let _ = on_event.send(Err("No device connected".to_string()));
+            let _ = on_event.send(Err(no_device_connected()));
         }
     }
 }
 
 #[tauri::command]
 fn get_logcat_for_device(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
     device_serial: String,
     lines: u32,
     log_level: Option<String>,
-    on_event: tauri::ipc::Channel<Result<String, String>>,
+    on_event: tauri::ipc::Channel<Result<String, DroidKitError>>,
 ) {
-    match reconnect_device(&device_serial) {
-        Some(mut device) => {
-            // Run logcat in a separate thread to avoid blocking
-            std::thread::spawn(move || {
-                let result = get_logcat_output(&mut device, lines, log_level);
-                let _ = on_event.send(result);
-            });
-        }
-        None => {
-            let _ = on_event.send(Err("Failed to connect to device".to_string()));
-        }
-    }
+    let sessions = sessions.inner().clone();
+    let deadline = operations.deadline(&device_serial, CommandCategory::Logcat);
+    // Run logcat in a separate thread to avoid blocking
+    std::thread::spawn(move || {
+        let result = sessions.try_with_device(&device_serial, |device| {
+            deadline.try_run(device, |device| get_logcat_output(device, lines, log_level))
+        });
+        let _ = on_event.send(result);
+    });
 }
 
 #[tauri::command]
-fn connect_wireless_device(ip: String, port: u16) -> Result<DeviceInfo, String> {
+fn connect_wireless_device(
+    sessions: State<'_, DeviceSessions>,
+    ip: String,
+    port: u16,
+) -> Result<DeviceInfo, DroidKitError> {
     let ip_addr: IpAddr = ip
         .parse()
-        .map_err(|_| "Invalid IP address format".to_string())?;
+        .map_err(|_| DroidKitError::InvalidInput("Invalid IP address format".to_string()))?;
 
-    connect_tcp_device(ip_addr, port)
-        .ok_or_else(|| "Failed to connect to device".to_string())
-        .and_then(|mut device| {
-            let mut device_info = get_device_info(&mut device)
-                .map_err(|_| "Failed to get device info".to_string())?;
-            // Override the serial number with IP:port format for easy reconnection
-            device_info.serial_no = format!("{}:{}", ip, port);
-            Ok(device_info)
-        })
+    connect_tcp_device(ip_addr, port).and_then(|mut device| {
+        let mut device_info = get_device_info(&mut device)?;
+        // Override the serial number with IP:port format for easy reconnection
+        device_info.serial_no = format!("{}:{}", ip, port);
+        sessions.insert(&device_info.serial_no, device);
+        Ok(device_info)
+    })
 }
 
+/// `adb tcpip`: moves a cabled device over to Wi-Fi and opens a session for
+/// it under its `ip:port` serial. The cabled session is closed, since adbd
+/// restarts.
 #[tauri::command]
-fn pair_wireless_device(ip: String, port: u16, pairing_code: String) -> Result<DeviceInfo, String> {
+async fn switch_to_tcpip_cmd(
+    sessions: State<'_, DeviceSessions>,
+    device_serial: String,
+    port: Option<u16>,
+) -> Result<DeviceInfo, DroidKitError> {
+    let sessions = sessions.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let (device, device_info) = sessions.try_with_device(&device_serial, |device| {
+            switch_to_tcpip(device, port.unwrap_or(DEFAULT_TCPIP_PORT))
+        })?;
+        sessions.evict(&device_serial);
+        sessions.insert(&device_info.serial_no, device);
+        Ok(device_info)
+    })
+    .await
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+#[tauri::command]
+fn pair_wireless_device(
+    sessions: State<'_, DeviceSessions>,
+    ip: String,
+    port: u16,
+    pairing_code: String,
+) -> Result<DeviceInfo, DroidKitError> {
     let ip_addr: IpAddr = ip
         .parse()
-        .map_err(|_| "Invalid IP address format".to_string())?;
+        .map_err(|_| DroidKitError::InvalidInput("Invalid IP address format".to_string()))?;
 
     // First, pair the device using the pairing port
     pair_device_with_code(ip_addr, port, &pairing_code)?;
@@ -153,22 +775,23 @@ fn pair_wireless_device(ip: String, port: u16, pairing_code: String) -> Result<D
     );
 
     connect_tcp_device(ip_addr, connection_port)
-        .ok_or_else(|| format!("Failed to connect to paired device on port {}. The device may not be advertising a connection service or wireless debugging may have been disabled.", connection_port))
+        .map_err(|e| e.context(&format!("Failed to connect to paired device on port {}. The device may not be advertising a connection service or wireless debugging may have been disabled", connection_port)))
         .and_then(|mut device| {
-            let mut device_info = get_device_info(&mut device).map_err(|_| "Failed to get device info".to_string())?;
+            let mut device_info = get_device_info(&mut device)?;
             // Override the serial number with IP:connection_port format for easy reconnection
             device_info.serial_no = format!("{}:{}", ip, connection_port);
+            sessions.insert(&device_info.serial_no, device);
             Ok(device_info)
         })
 }
 
 #[tauri::command]
-fn get_pairing_qr_data() -> Result<PairingData, String> {
+fn get_pairing_qr_data() -> Result<PairingData, DroidKitError> {
     generate_pairing_data()
 }
 
 #[tauri::command]
-fn start_qr_pairing(pairing_code: String) -> Result<PairingResult, String> {
+fn start_qr_pairing(pairing_code: String) -> Result<PairingResult, DroidKitError> {
     let result = start_pairing_listener(pairing_code, 60)?;
     Ok(PairingResult {
         success: result.success,
@@ -179,7 +802,7 @@ fn start_qr_pairing(pairing_code: String) -> Result<PairingResult, String> {
 }
 
 #[tauri::command]
-fn discover_devices() -> Result<Vec<String>, String> {
+fn discover_devices() -> Result<Vec<String>, DroidKitError> {
     discover_wireless_devices().map(|devices| {
         devices
             .into_iter()
@@ -189,87 +812,465 @@ fn discover_devices() -> Result<Vec<String>, String> {
 }
 
 #[tauri::command]
-fn list_discovered_devices_cmd() -> Result<Vec<DiscoveredDevice>, String> {
+fn list_discovered_devices_cmd() -> Result<Vec<DiscoveredDevice>, DroidKitError> {
     list_discovered_devices()
 }
 
 #[tauri::command]
-fn discover_wireless_devices_detailed_cmd() -> Result<Vec<DiscoveredWirelessDevice>, String> {
+fn list_usb_devices_cmd() -> Result<Vec<UsbDeviceEntry>, DroidKitError> {
+    list_usb_devices()
+}
+
+#[tauri::command]
+fn list_fastboot_devices_cmd() -> Result<Vec<UsbDeviceEntry>, DroidKitError> {
+    list_fastboot_usb_devices()
+}
+
+#[tauri::command]
+async fn fastboot_getvar_all_cmd(device_serial: String) -> Result<Vec<FastbootVar>, DroidKitError> {
+    tokio::task::spawn_blocking(move || connect_fastboot(&device_serial)?.getvar_all())
+        .await
+        .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+/// Flashes the image at `path` to `partition`, reporting download progress
+/// and the bootloader's messages through `on_event`.
+#[tauri::command]
+async fn fastboot_flash_cmd(
+    device_serial: String,
+    partition: String,
+    path: String,
+    on_event: tauri::ipc::Channel<FastbootEvent>,
+) -> Result<(), DroidKitError> {
+    tokio::task::spawn_blocking(move || {
+        connect_fastboot(&device_serial)?.flash(&partition, Path::new(&path), &mut |event| {
+            let _ = on_event.send(event);
+        })
+    })
+    .await
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+#[tauri::command]
+async fn fastboot_erase_cmd(
+    device_serial: String,
+    partition: String,
+    on_event: tauri::ipc::Channel<FastbootEvent>,
+) -> Result<(), DroidKitError> {
+    tokio::task::spawn_blocking(move || {
+        connect_fastboot(&device_serial)?.erase(&partition, &mut |event| {
+            let _ = on_event.send(event);
+        })
+    })
+    .await
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+#[tauri::command]
+async fn fastboot_reboot_cmd(
+    device_serial: String,
+    target: RebootTarget,
+) -> Result<(), DroidKitError> {
+    tokio::task::spawn_blocking(move || connect_fastboot(&device_serial)?.reboot(target))
+        .await
+        .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+/// Runs `fastboot oem <command>` and returns what the bootloader printed.
+#[tauri::command]
+async fn fastboot_oem_cmd(
+    device_serial: String,
+    command: String,
+) -> Result<Vec<String>, DroidKitError> {
+    tokio::task::spawn_blocking(move || connect_fastboot(&device_serial)?.oem(&command))
+        .await
+        .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+/// `fastboot flashing unlock`; resolves once the user has answered on the
+/// device, which wipes it on unlocking.
+#[tauri::command]
+async fn fastboot_flashing_unlock_cmd(device_serial: String) -> Result<Vec<String>, DroidKitError> {
+    tokio::task::spawn_blocking(move || connect_fastboot(&device_serial)?.flashing_unlock())
+        .await
+        .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+#[tauri::command]
+fn list_server_devices_cmd() -> Result<Vec<ServerDeviceEntry>, DroidKitError> {
+    list_server_devices()
+}
+
+#[tauri::command]
+fn discover_wireless_devices_detailed_cmd() -> Result<Vec<DiscoveredWirelessDevice>, DroidKitError>
+{
     discover_wireless_devices_detailed()
 }
 
 #[tauri::command]
-fn connect_to_discovered_device_cmd(device: DiscoveredDevice) -> Result<DeviceInfo, String> {
-    connect_to_discovered_device(&device).and_then(|mut device| {
-        get_device_info(&mut device).map_err(|_| "Failed to get device info".to_string())
+fn connect_to_discovered_device_cmd(device: DiscoveredDevice) -> Result<DeviceInfo, DroidKitError> {
+    connect_to_discovered_device(&device).and_then(|mut device| get_device_info(&mut device))
+}
+
+#[tauri::command]
+fn close_device_session(sessions: State<'_, DeviceSessions>, device_serial: String) {
+    sessions.evict(&device_serial);
+}
+
+/// What the device supports, probed on first use and kept while it stays connected.
+#[tauri::command]
+async fn get_device_capabilities_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+) -> Result<DeviceCapabilities, DroidKitError> {
+    let sessions = sessions.inner().clone();
+    let deadline = operations.deadline(&device_serial, CommandCategory::SystemInfo);
+    tokio::task::spawn_blocking(move || {
+        sessions.try_with_device(&device_serial, |device| {
+            deadline.try_run(device, |device| {
+                sessions.capabilities(&device_serial, device)
+            })
+        })
     })
+    .await
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
 }
 
+/// `adb root`: restarts adbd as root on userdebug and eng builds. The device
+/// drops off and reconnects, so its session is closed.
 #[tauri::command]
-fn execute_shell_command_cmd(device_serial: String, command: String) -> Result<String, String> {
-    reconnect_device(&device_serial)
-        .ok_or_else(|| "Failed to connect to device".to_string())
-        .and_then(|mut device| execute_shell_command(&mut device, &command))
+async fn restart_adbd_as_root_cmd(
+    sessions: State<'_, DeviceSessions>,
+    device_serial: String,
+) -> Result<(), DroidKitError> {
+    let sessions = sessions.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        sessions.try_with_device(&device_serial, |device| {
+            let capabilities = sessions.capabilities(&device_serial, device)?;
+            if capabilities.adbd_root {
+                return Ok(());
+            }
+            if !capabilities.adb_root_available {
+                return Err(DroidKitError::NotRooted(
+                    "adbd cannot run as root in production builds".to_string(),
+                ));
+            }
+            device
+                .as_ext()
+                .root()
+                .map_err(|e| DroidKitError::from(e).context("Failed to restart adbd as root"))
+        })?;
+        sessions.evict(&device_serial);
+        Ok(())
+    })
+    .await
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
 }
 
+/// Reboots into `target`, reporting progress through `on_event`. Resolves
+/// once a device rebooting into the system has finished booting.
 #[tauri::command]
-async fn get_device_hardware_info_cmd(device_serial: String) -> Result<HardwareInfo, String> {
-    let device_serial_clone = device_serial.clone();
+async fn reboot_device_cmd(
+    sessions: State<'_, DeviceSessions>,
+    device_serial: String,
+    target: RebootTarget,
+    on_event: tauri::ipc::Channel<RebootEvent>,
+) -> Result<(), DroidKitError> {
+    let sessions = sessions.inner().clone();
     tokio::task::spawn_blocking(move || {
-        reconnect_device(&device_serial_clone)
-            .ok_or_else(|| "Failed to connect to device".to_string())
-            .map(|mut device| get_hardware_info(&mut device))
+        reboot_and_wait(&sessions, &device_serial, target, |event| {
+            let _ = on_event.send(event);
+        })
     })
     .await
-    .map_err(|e| format!("Task execution failed: {}", e))?
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
 }
 
+/// `adb sideload`: installs the OTA package at `path` on a device waiting in
+/// recovery's sideload mode.
 #[tauri::command]
-async fn get_device_display_info_cmd(device_serial: String) -> Result<DisplayInfo, String> {
-    let device_serial_clone = device_serial.clone();
+async fn sideload_package_cmd(
+    sessions: State<'_, DeviceSessions>,
+    device_serial: String,
+    path: String,
+    on_progress: tauri::ipc::Channel<SideloadProgress>,
+) -> Result<(), DroidKitError> {
+    let sessions = sessions.inner().clone();
     tokio::task::spawn_blocking(move || {
-        reconnect_device(&device_serial_clone)
-            .ok_or_else(|| "Failed to connect to device".to_string())
-            .map(|mut device| get_display_info(&mut device))
+        // Recovery has no shell, so any open handle is stale
+        sessions.evict(&device_serial);
+        sideload_package(&device_serial, Path::new(&path), |progress| {
+            let _ = on_progress.send(progress);
+        })
     })
     .await
-    .map_err(|e| format!("Task execution failed: {}", e))?
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+/// Creates a forward or reverse rule and remembers it, so it is re-applied
+/// whenever the device reconnects. Returns the rule with `tcp:0` resolved.
+#[tauri::command]
+fn add_port_rule_cmd(
+    sessions: State<'_, DeviceSessions>,
+    device_serial: String,
+    rule: PortRule,
+) -> Result<PortRule, DroidKitError> {
+    let rule = sessions.try_with_device(&device_serial, |device| device.add_port_rule(&rule))?;
+    sessions.port_rules().record(&device_serial, rule.clone());
+    Ok(rule)
 }
 
 #[tauri::command]
-async fn get_device_battery_info_cmd(device_serial: String) -> Result<Option<BatteryInfo>, String> {
-    let device_serial_clone = device_serial.clone();
+fn list_port_rules_cmd(
+    sessions: State<'_, DeviceSessions>,
+    device_serial: String,
+) -> Result<Vec<PortRule>, DroidKitError> {
+    sessions.try_with_device(&device_serial, |device| device.list_port_rules())
+}
+
+#[tauri::command]
+fn remove_port_rule_cmd(
+    sessions: State<'_, DeviceSessions>,
+    device_serial: String,
+    rule: PortRule,
+) -> Result<(), DroidKitError> {
+    sessions.port_rules().forget(&device_serial, &rule);
+    sessions.try_with_device(&device_serial, |device| device.remove_port_rule(&rule))
+}
+
+/// Stops every operation in flight on a device, e.g. when the user navigates away.
+#[tauri::command]
+fn cancel_device_operations_cmd(operations: State<'_, DeviceOperations>, device_serial: String) {
+    operations.cancel_all(&device_serial);
+}
+
+#[tauri::command]
+fn get_command_timeouts_cmd(operations: State<'_, DeviceOperations>) -> CommandTimeouts {
+    operations.timeouts()
+}
+
+#[tauri::command]
+fn set_command_timeouts_cmd(operations: State<'_, DeviceOperations>, timeouts: CommandTimeouts) {
+    operations.set_timeouts(timeouts);
+}
+
+#[tauri::command]
+fn execute_shell_command_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+    command: String,
+    elevated: Option<bool>,
+) -> Result<Elevated<String>, DroidKitError> {
+    let deadline = operations.deadline(&device_serial, CommandCategory::Shell);
+    sessions.try_with_device(&device_serial, |device| {
+        deadline.try_run(device, |device| {
+            let capabilities = sessions.capabilities(&device_serial, device)?;
+            RootMode::choose(&capabilities, elevated.unwrap_or(false))?
+                .run(device, |device| execute_shell_command(device, &command))
+        })
+    })
+}
+
+/// Like `execute_shell_command_cmd`, but keeps stderr and the exit code.
+#[tauri::command]
+async fn run_shell_command_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+    command: String,
+) -> Result<ShellOutput, DroidKitError> {
+    let sessions = sessions.inner().clone();
+    let deadline = operations.deadline(&device_serial, CommandCategory::Shell);
     tokio::task::spawn_blocking(move || {
-        reconnect_device(&device_serial_clone)
-            .ok_or_else(|| "Failed to connect to device".to_string())
-            .map(|mut device| get_battery_info(&mut device))
+        sessions.try_with_device(&device_serial, |device| {
+            deadline.try_run(device, |device| run_shell_command(device, &command))
+        })
     })
     .await
-    .map_err(|e| format!("Task execution failed: {}", e))?
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+/// Streams the output of `command` through `on_event`; returns an id for
+/// `cancel_shell_stream_cmd`.
+#[tauri::command]
+fn stream_shell_command_cmd(
+    sessions: State<'_, DeviceSessions>,
+    streams: State<'_, ShellStreams>,
+    device_serial: String,
+    command: String,
+    on_event: tauri::ipc::Channel<Result<ShellEvent, DroidKitError>>,
+) -> u32 {
+    let sessions = sessions.inner().clone();
+    let streams = streams.inner().clone();
+    let (stream_id, control) = streams.start(&device_serial);
+
+    std::thread::spawn(move || {
+        let send = |event| {
+            let _ = on_event.send(Ok(event));
+        };
+        let result = match open_server_device(&device_serial) {
+            // A connection of its own, so the device stays usable meanwhile
+            Ok(device) => {
+                stream_shell_command(&mut Device::Server(device), &command, &control, &send)
+            }
+            Err(_) => {
+                // Only a direct TCP device can be reached a second time to cancel
+                if device_serial.parse::<SocketAddr>().is_err() {
+                    control.use_heartbeat();
+                }
+                sessions.try_with_device(&device_serial, |device| {
+                    stream_shell_command(device, &command, &control, &send)
+                })
+            }
+        };
+        if let Err(e) = result {
+            let _ = on_event.send(Err(e));
+        }
+        streams.finish(stream_id);
+    });
+
+    stream_id
+}
+
+#[tauri::command]
+fn cancel_shell_stream_cmd(streams: State<'_, ShellStreams>, stream_id: u32) {
+    streams.cancel(stream_id);
+}
+
+/// Opens an interactive shell on a PTY; returns the session id.
+#[tauri::command]
+fn open_pty_session_cmd(
+    ptys: State<'_, PtySessions>,
+    device_serial: String,
+    rows: u16,
+    cols: u16,
+    on_event: tauri::ipc::Channel<PtyEvent>,
+) -> Result<u32, DroidKitError> {
+    ptys.open(&device_serial, rows, cols, move |event| {
+        let _ = on_event.send(event);
+    })
+}
+
+#[tauri::command]
+fn write_pty_session_cmd(
+    ptys: State<'_, PtySessions>,
+    session_id: u32,
+    data: String,
+) -> Result<(), DroidKitError> {
+    ptys.write(session_id, data.as_bytes())
+}
+
+#[tauri::command]
+fn resize_pty_session_cmd(
+    ptys: State<'_, PtySessions>,
+    session_id: u32,
+    rows: u16,
+    cols: u16,
+) -> Result<(), DroidKitError> {
+    ptys.resize(session_id, rows, cols)
+}
+
+#[tauri::command]
+fn close_pty_session_cmd(
+    ptys: State<'_, PtySessions>,
+    session_id: u32,
+) -> Result<(), DroidKitError> {
+    ptys.close(session_id)
 }
 
 #[tauri::command]
-async fn get_device_build_info_cmd(device_serial: String) -> Result<BuildInfo, String> {
-    let device_serial_clone = device_serial.clone();
+fn list_pty_sessions_cmd(ptys: State<'_, PtySessions>) -> Vec<PtySessionInfo> {
+    ptys.list()
+}
+
+#[tauri::command]
+async fn get_device_hardware_info_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+) -> Result<HardwareInfo, DroidKitError> {
+    let sessions = sessions.inner().clone();
+    let deadline = operations.deadline(&device_serial, CommandCategory::SystemInfo);
+    tokio::task::spawn_blocking(move || {
+        sessions.try_with_device(&device_serial, |device| {
+            deadline.try_run(device, |device| {
+                let capabilities = sessions.capabilities(&device_serial, device)?;
+                Ok(get_hardware_info(device, &capabilities))
+            })
+        })
+    })
+    .await
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+#[tauri::command]
+async fn get_device_display_info_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+) -> Result<DisplayInfo, DroidKitError> {
+    let sessions = sessions.inner().clone();
+    let deadline = operations.deadline(&device_serial, CommandCategory::SystemInfo);
+    tokio::task::spawn_blocking(move || {
+        sessions.try_with_device(&device_serial, |device| {
+            deadline.run(device, get_display_info)
+        })
+    })
+    .await
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+#[tauri::command]
+async fn get_device_battery_info_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+) -> Result<Option<BatteryInfo>, DroidKitError> {
+    let sessions = sessions.inner().clone();
+    let deadline = operations.deadline(&device_serial, CommandCategory::SystemInfo);
+    tokio::task::spawn_blocking(move || {
+        sessions.try_with_device(&device_serial, |device| {
+            deadline.run(device, get_battery_info)
+        })
+    })
+    .await
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
+}
+
+#[tauri::command]
+async fn get_device_build_info_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+) -> Result<BuildInfo, DroidKitError> {
+    let sessions = sessions.inner().clone();
+    let deadline = operations.deadline(&device_serial, CommandCategory::SystemInfo);
     tokio::task::spawn_blocking(move || {
-        reconnect_device(&device_serial_clone)
-            .ok_or_else(|| "Failed to connect to device".to_string())
-            .map(|mut device| get_build_info(&mut device))
+        sessions.try_with_device(&device_serial, |device| {
+            deadline.run(device, get_build_info)
+        })
     })
     .await
-    .map_err(|e| format!("Task execution failed: {}", e))?
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
 }
 
 #[tauri::command]
-async fn get_device_network_info_cmd(device_serial: String) -> Result<NetworkInfo, String> {
-    let device_serial_clone = device_serial.clone();
+async fn get_device_network_info_cmd(
+    sessions: State<'_, DeviceSessions>,
+    operations: State<'_, DeviceOperations>,
+    device_serial: String,
+) -> Result<NetworkInfo, DroidKitError> {
+    let sessions = sessions.inner().clone();
+    let deadline = operations.deadline(&device_serial, CommandCategory::Network);
     tokio::task::spawn_blocking(move || {
-        reconnect_device(&device_serial_clone)
-            .ok_or_else(|| "Failed to connect to device".to_string())
-            .map(|mut device| get_network_info(&mut device))
+        sessions.try_with_device(&device_serial, |device| {
+            deadline.run(device, get_network_info)
+        })
     })
     .await
-    .map_err(|e| format!("Task execution failed: {}", e))?
+    .map_err(|e| DroidKitError::Io(format!("Task execution failed: {}", e)))?
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -277,6 +1278,17 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_store::Builder::new().build())
         .plugin(tauri_plugin_opener::init())
+        .setup(|app| {
+            let sessions = DeviceSessions::default();
+            app.manage(sessions.clone());
+            app.manage(ShellStreams::default());
+            app.manage(PtySessions::default());
+            app.manage(DeviceOperations::default());
+            app.manage(ListingCache::default());
+            app.manage(MirrorPlans::default());
+            start_device_watcher(app.handle().clone(), sessions);
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             device_info,
             get_android_sdk_path,
@@ -284,20 +1296,64 @@ pub fn run() {
             start_avd,
             browse_files,
             browse_files_for_device,
+            browse_files_page_for_device,
             download_file,
+            download_file_for_device,
+            upload_file,
+            upload_file_for_device,
+            compare_file_for_device,
+            download_directory_for_device,
+            upload_directory_for_device,
+            plan_mirror_for_device,
+            run_mirror_for_device,
+            delete_path_cmd,
+            move_path_cmd,
+            copy_path_cmd,
+            make_directory_cmd,
+            change_mode_cmd,
+            touch_path_cmd,
             get_apps,
             get_apps_for_device,
             get_logcat,
             get_logcat_for_device,
             connect_wireless_device,
+            switch_to_tcpip_cmd,
             pair_wireless_device,
             get_pairing_qr_data,
             start_qr_pairing,
             discover_devices,
             list_discovered_devices_cmd,
+            list_usb_devices_cmd,
+            list_fastboot_devices_cmd,
+            fastboot_getvar_all_cmd,
+            fastboot_flash_cmd,
+            fastboot_erase_cmd,
+            fastboot_reboot_cmd,
+            fastboot_oem_cmd,
+            fastboot_flashing_unlock_cmd,
+            list_server_devices_cmd,
             discover_wireless_devices_detailed_cmd,
             connect_to_discovered_device_cmd,
+            close_device_session,
+            get_device_capabilities_cmd,
+            restart_adbd_as_root_cmd,
+            reboot_device_cmd,
+            sideload_package_cmd,
+            add_port_rule_cmd,
+            list_port_rules_cmd,
+            remove_port_rule_cmd,
+            cancel_device_operations_cmd,
+            get_command_timeouts_cmd,
+            set_command_timeouts_cmd,
             execute_shell_command_cmd,
+            run_shell_command_cmd,
+            stream_shell_command_cmd,
+            cancel_shell_stream_cmd,
+            open_pty_session_cmd,
+            write_pty_session_cmd,
+            resize_pty_session_cmd,
+            close_pty_session_cmd,
+            list_pty_sessions_cmd,
             get_device_hardware_info_cmd,
             get_device_display_info_cmd,
             get_device_battery_info_cmd,
@@ -1,4 +1,6 @@
-use crate::adb_commands::device::Device;
+use crate::adb_commands::adb_device::AdbDevice;
+use crate::adb_commands::capabilities::{DeviceCapabilities, LsFlavor};
+use crate::adb_commands::command::ShellCommand;
 use serde::{Deserialize, Serialize};
 use std::str::from_utf8;
 
@@ -78,9 +80,9 @@ pub struct NetworkInterface {
     pub status: Option<String>,
 }
 
-fn execute_adb_command(device: &mut Device, command: &str) -> Option<String> {
+fn execute_adb_command(device: &mut dyn AdbDevice, command: &str) -> Option<String> {
     let mut buf: Vec<u8> = Vec::new();
-    match device.shell_command(&command, &mut buf) {
+    match device.shell(command, &mut buf) {
         Ok(_) => match from_utf8(&buf) {
             Ok(output) => Some(output.trim().to_string()),
             Err(_) => None,
@@ -89,18 +91,27 @@ fn execute_adb_command(device: &mut Device, command: &str) -> Option<String> {
     }
 }
 
-fn get_property(device: &mut Device, property: &str) -> Option<String> {
-    execute_adb_command(device, &format!("getprop {}", property))
+fn get_property(device: &mut dyn AdbDevice, property: &str) -> Option<String> {
+    execute_adb_command(
+        device,
+        &ShellCommand::new("getprop").arg(property).to_string(),
+    )
 }
 
-pub fn get_hardware_info(device: &mut Device) -> HardwareInfo {
+pub fn get_hardware_info(
+    device: &mut dyn AdbDevice,
+    capabilities: &DeviceCapabilities,
+) -> HardwareInfo {
     HardwareInfo {
-        cpu_architecture: get_property(device, "ro.product.cpu.abi"),
+        cpu_architecture: capabilities
+            .abi
+            .clone()
+            .or_else(|| get_property(device, "ro.product.cpu.abi")),
         cpu_abi_list: get_property(device, "ro.product.cpu.abilist"),
         total_memory: parse_memory_info(device, "MemTotal"),
         available_memory: parse_memory_info(device, "MemAvailable"),
-        internal_storage_total: get_storage_info(device, "/data", "total"),
-        internal_storage_available: get_storage_info(device, "/data", "available"),
+        internal_storage_total: get_storage_info(device, capabilities, "/data", "total"),
+        internal_storage_available: get_storage_info(device, capabilities, "/data", "available"),
         manufacturer: get_property(device, "ro.product.manufacturer"),
         brand: get_property(device, "ro.product.brand"),
         board: get_property(device, "ro.product.board"),
@@ -108,7 +119,7 @@ pub fn get_hardware_info(device: &mut Device) -> HardwareInfo {
     }
 }
 
-fn parse_memory_info(device: &mut Device, field: &str) -> Option<String> {
+fn parse_memory_info(device: &mut dyn AdbDevice, field: &str) -> Option<String> {
     if let Some(meminfo) = execute_adb_command(device, "cat /proc/meminfo") {
         for line in meminfo.lines() {
             if line.starts_with(field) {
@@ -130,8 +141,19 @@ fn parse_memory_info(device: &mut Device, field: &str) -> Option<String> {
     None
 }
 
-fn get_storage_info(device: &mut Device, path: &str, info_type: &str) -> Option<String> {
-    if let Some(df_output) = execute_adb_command(device, &format!("df -h {}", path)) {
+fn get_storage_info(
+    device: &mut dyn AdbDevice,
+    capabilities: &DeviceCapabilities,
+    path: &str,
+    info_type: &str,
+) -> Option<String> {
+    // Devices with toolbox `ls` have toolbox `df` too, which has no -h but
+    // prints human readable sizes anyway, in the same columns
+    let command = match capabilities.ls {
+        LsFlavor::Toybox => ShellCommand::new("df").arg("-h").arg(path),
+        LsFlavor::Toolbox => ShellCommand::new("df").arg(path),
+    };
+    if let Some(df_output) = execute_adb_command(device, &command.to_string()) {
         for line in df_output.lines() {
             if line.contains(path) || line.starts_with("/dev/") {
                 let parts: Vec<&str> = line.split_whitespace().collect();
@@ -148,7 +170,7 @@ fn get_storage_info(device: &mut Device, path: &str, info_type: &str) -> Option<
     None
 }
 
-pub fn get_display_info(device: &mut Device) -> DisplayInfo {
+pub fn get_display_info(device: &mut dyn AdbDevice) -> DisplayInfo {
     DisplayInfo {
         resolution: get_screen_resolution(device),
         density: get_screen_density(device),
@@ -158,7 +180,7 @@ pub fn get_display_info(device: &mut Device) -> DisplayInfo {
     }
 }
 
-fn get_screen_resolution(device: &mut Device) -> Option<String> {
+fn get_screen_resolution(device: &mut dyn AdbDevice) -> Option<String> {
     if let Some(output) = execute_adb_command(device, "wm size") {
         for line in output.lines() {
             if line.contains("Physical size:") {
@@ -171,7 +193,7 @@ fn get_screen_resolution(device: &mut Device) -> Option<String> {
     None
 }
 
-fn get_screen_density(device: &mut Device) -> Option<String> {
+fn get_screen_density(device: &mut dyn AdbDevice) -> Option<String> {
     if let Some(output) = execute_adb_command(device, "wm density") {
         for line in output.lines() {
             if line.contains("Physical density:") {
@@ -184,45 +206,65 @@ fn get_screen_density(device: &mut Device) -> Option<String> {
     None
 }
 
-fn get_physical_size(device: &mut Device) -> Option<String> {
+fn get_physical_size(device: &mut dyn AdbDevice) -> Option<String> {
     get_property(device, "ro.sf.lcd_density").map(|density| format!("{} dpi", density))
 }
 
-fn get_refresh_rate(device: &mut Device) -> Option<String> {
-    if let Some(output) = execute_adb_command(device, "dumpsys display") {
-        for line in output.lines() {
-            if line.contains("refreshRate=") {
-                if let Some(rate_part) = line.split("refreshRate=").nth(1) {
-                    if let Some(rate) = rate_part.split(',').next() {
-                        return Some(format!("{} Hz", rate.trim()));
-                    }
-                }
-            }
-        }
-    }
-    None
+fn get_refresh_rate(device: &mut dyn AdbDevice) -> Option<String> {
+    let output = execute_adb_command(device, "dumpsys display")?;
+    parse_refresh_rate(&output).map(|rate| format!("{:.1} Hz", rate))
 }
 
-fn get_orientation(device: &mut Device) -> Option<String> {
-    if let Some(output) = execute_adb_command(device, "dumpsys input") {
-        for line in output.lines() {
-            if line.contains("SurfaceOrientation:") {
-                if let Some(orientation) = line.split("SurfaceOrientation:").nth(1) {
-                    return match orientation.trim() {
-                        "0" => Some("Portrait".to_string()),
-                        "1" => Some("Landscape".to_string()),
-                        "2" => Some("Reverse Portrait".to_string()),
-                        "3" => Some("Reverse Landscape".to_string()),
-                        _ => Some(orientation.trim().to_string()),
-                    };
-                }
-            }
-        }
-    }
-    None
+fn parse_refresh_rate(output: &str) -> Option<f32> {
+    let value_after = |line: &str, key: &str| -> Option<f32> {
+        let rest = line.split(key).nth(1)?;
+        rest.split([',', '}']).next()?.trim().parse().ok()
+    };
+
+    // Android 12+ lists every mode before naming the active one
+    let active = output
+        .lines()
+        .find(|line| line.contains("mActiveSfDisplayMode="))
+        .and_then(|line| value_after(line, "refreshRate="));
+
+    active
+        .or_else(|| {
+            output
+                .lines()
+                .find_map(|line| value_after(line, "refreshRate="))
+        })
+        // Android 9 and older only print the supported modes
+        .or_else(|| output.lines().find_map(|line| value_after(line, "fps=")))
+}
+
+fn get_orientation(device: &mut dyn AdbDevice) -> Option<String> {
+    let output = execute_adb_command(device, "dumpsys input")?;
+
+    let surface_orientation = output
+        .lines()
+        .find_map(|line| line.split("SurfaceOrientation:").nth(1))
+        .map(|orientation| orientation.trim().to_string());
+    // Android 13 dropped SurfaceOrientation; the touch viewport still has it
+    let viewport_orientation = || {
+        output
+            .lines()
+            .filter(|line| line.contains("Viewport INTERNAL:"))
+            .find_map(|line| line.split("orientation=").nth(1))
+            .and_then(|rest| rest.split(',').next())
+            .map(|orientation| orientation.trim().to_string())
+    };
+
+    let orientation = surface_orientation.or_else(viewport_orientation)?;
+    Some(match orientation.as_str() {
+        "0" => "Portrait".to_string(),
+        "1" => "Landscape".to_string(),
+        "2" => "Reverse Portrait".to_string(),
+        "3" => "Reverse Landscape".to_string(),
+        _ => orientation,
+    })
 }
 
-pub fn get_battery_info(device: &mut Device) -> Option<BatteryInfo> {
+pub fn get_battery_info(device: &mut dyn AdbDevice) -> Option<BatteryInfo> {
     if let Some(output) = execute_adb_command(device, "dumpsys battery") {
         let mut battery_info = BatteryInfo {
             level: None,
@@ -262,7 +304,7 @@ pub fn get_battery_info(device: &mut Device) -> Option<BatteryInfo> {
     }
 }
 
-pub fn get_build_info(device: &mut Device) -> BuildInfo {
+pub fn get_build_info(device: &mut dyn AdbDevice) -> BuildInfo {
     BuildInfo {
         fingerprint: get_property(device, "ro.build.fingerprint"),
         build_date: get_property(device, "ro.build.date"),
@@ -277,7 +319,7 @@ pub fn get_build_info(device: &mut Device) -> BuildInfo {
     }
 }
 
-pub fn get_network_info(device: &mut Device) -> NetworkInfo {
+pub fn get_network_info(device: &mut dyn AdbDevice) -> NetworkInfo {
     let mut network_info = NetworkInfo {
         wifi_status: get_wifi_status(device),
         connection_type: get_connection_type(device),
@@ -304,7 +346,7 @@ pub fn get_network_info(device: &mut Device) -> NetworkInfo {
     network_info
 }
 
-fn get_wifi_status(device: &mut Device) -> Option<String> {
+fn get_wifi_status(device: &mut dyn AdbDevice) -> Option<String> {
     if let Some(output) = execute_adb_command(device, "dumpsys wifi") {
         for line in output.lines() {
             if line.contains("Wi-Fi is ") {
@@ -319,7 +361,7 @@ fn get_wifi_status(device: &mut Device) -> Option<String> {
     None
 }
 
-fn get_connection_type(device: &mut Device) -> Option<String> {
+fn get_connection_type(device: &mut dyn AdbDevice) -> Option<String> {
     // Check for WiFi connection first
     if let Some(wifi_info) = execute_adb_command(device, "dumpsys wifi") {
         if wifi_info.contains("mWifiInfo") && wifi_info.contains("state: COMPLETED") {
@@ -360,7 +402,7 @@ fn get_connection_type(device: &mut Device) -> Option<String> {
     Some("Unknown".to_string())
 }
 
-fn get_signal_strength(device: &mut Device) -> Option<i32> {
+fn get_signal_strength(device: &mut dyn AdbDevice) -> Option<i32> {
     if let Some(output) = execute_adb_command(device, "dumpsys telephony.registry") {
         for line in output.lines() {
             if line.contains("mSignalStrength") {
@@ -410,9 +452,10 @@ fn get_signal_strength(device: &mut Device) -> Option<i32> {
     None
 }
 
-fn get_network_speed(device: &mut Device, speed_type: &str) -> Option<String> {
-    // Simple connectivity test using ping
-    if let Some(ping_output) = execute_adb_command(device, "ping -c 3 8.8.8.8") {
+fn get_network_speed(device: &mut dyn AdbDevice, speed_type: &str) -> Option<String> {
+    // Simple connectivity test using ping, bounded so an unreachable host
+    // does not eat the whole operation's deadline
+    if let Some(ping_output) = execute_adb_command(device, "ping -c 3 -w 5 8.8.8.8") {
         // Parse ping results for basic connectivity info
         for line in ping_output.lines() {
             if line.contains("avg") {
@@ -447,67 +490,296 @@ fn get_network_speed(device: &mut Device, speed_type: &str) -> Option<String> {
     None
 }
 
-fn get_network_interfaces(device: &mut Device) -> Option<Vec<NetworkInterface>> {
-    if let Some(output) = execute_adb_command(device, "ip addr show") {
-        let mut interfaces = Vec::new();
-        let mut current_interface: Option<NetworkInterface> = None;
+fn get_network_interfaces(device: &mut dyn AdbDevice) -> Option<Vec<NetworkInterface>> {
+    let output = execute_adb_command(device, "ip addr show")?;
+    let interfaces = parse_ip_addr(&output);
 
-        for line in output.lines() {
-            let line = line.trim();
+    if !interfaces.is_empty() {
+        Some(interfaces)
+    } else {
+        None
+    }
+}
 
-            if line.contains(": ")
-                && (line.contains("wlan")
-                    || line.contains("eth")
-                    || line.contains("lo")
-                    || line.contains("rmnet")
-                    || line.contains("ccmni"))
-            {
-                if let Some(interface) = current_interface.take() {
-                    interfaces.push(interface);
-                }
+fn is_relevant_interface(name: &str) -> bool {
+    name == "lo"
+        || ["wlan", "eth", "rmnet", "ccmni"]
+            .iter()
+            .any(|prefix| name.contains(prefix))
+}
 
-                if let Some(name_part) = line.split(": ").nth(1) {
-                    let name = name_part
-                        .split_whitespace()
-                        .next()
-                        .unwrap_or("unknown")
-                        .to_string();
-                    current_interface = Some(NetworkInterface {
-                        name,
-                        ip_address: None,
-                        mac_address: None,
-                        status: if line.contains("UP") {
-                            Some("UP".to_string())
-                        } else {
-                            Some("DOWN".to_string())
-                        },
-                    });
-                }
-            } else if let Some(ref mut interface) = current_interface {
-                if line.starts_with("inet ") {
-                    if let Some(ip_part) = line.split_whitespace().nth(1) {
-                        if let Some(ip) = ip_part.split('/').next() {
-                            interface.ip_address = Some(ip.to_string());
-                        }
-                    }
-                } else if line.starts_with("link/ether ") {
-                    if let Some(mac) = line.split_whitespace().nth(1) {
-                        interface.mac_address = Some(mac.to_string());
+fn parse_ip_addr(output: &str) -> Vec<NetworkInterface> {
+    let mut interfaces = Vec::new();
+    let mut current_interface: Option<NetworkInterface> = None;
+
+    for line in output.lines() {
+        // Interface headers look like `21: wlan0: <BROADCAST,MULTICAST,UP> mtu ...`
+        let header = line
+            .split_once(": ")
+            .filter(|(index, _)| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()));
+
+        if let Some((_, rest)) = header {
+            if let Some(interface) = current_interface.take() {
+                interfaces.push(interface);
+            }
+
+            let name = rest
+                .split(':')
+                .next()
+                .unwrap_or("unknown")
+                .split('@')
+                .next()
+                .unwrap_or("unknown")
+                .to_string();
+            let is_up = rest
+                .split_once('<')
+                .and_then(|(_, flags)| flags.split_once('>'))
+                .is_some_and(|(flags, _)| flags.split(',').any(|flag| flag == "UP"));
+
+            // Skip tunnels and dummies, but still start a new block so their
+            // addresses don't end up on the previous interface
+            if is_relevant_interface(&name) {
+                current_interface = Some(NetworkInterface {
+                    name,
+                    ip_address: None,
+                    mac_address: None,
+                    status: Some(if is_up { "UP" } else { "DOWN" }.to_string()),
+                });
+            }
+        } else if let Some(ref mut interface) = current_interface {
+            let line = line.trim();
+            if line.starts_with("inet ") {
+                if let Some(ip_part) = line.split_whitespace().nth(1) {
+                    if let Some(ip) = ip_part.split('/').next() {
+                        interface.ip_address = Some(ip.to_string());
                     }
                 }
+            } else if line.starts_with("link/ether ") {
+                if let Some(mac) = line.split_whitespace().nth(1) {
+                    interface.mac_address = Some(mac.to_string());
+                }
             }
         }
+    }
 
-        if let Some(interface) = current_interface {
-            interfaces.push(interface);
-        }
+    if let Some(interface) = current_interface {
+        interfaces.push(interface);
+    }
 
-        if !interfaces.is_empty() {
-            Some(interfaces)
-        } else {
-            None
-        }
-    } else {
-        None
+    interfaces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb_commands::fake_device::{FakeDevice, fixture};
+
+    fn recorded(android_version: &str) -> FakeDevice {
+        FakeDevice::new()
+            .with_shell(
+                "dumpsys battery",
+                fixture(android_version, "dumpsys_battery.txt"),
+            )
+            .with_shell("ip addr show", fixture(android_version, "ip_addr_show.txt"))
+            .with_shell("wm size", fixture(android_version, "wm_size.txt"))
+            .with_shell("wm density", fixture(android_version, "wm_density.txt"))
+            .with_shell(
+                "getprop ro.sf.lcd_density",
+                fixture(android_version, "getprop_ro.sf.lcd_density.txt"),
+            )
+            .with_shell(
+                "dumpsys display",
+                fixture(android_version, "dumpsys_display.txt"),
+            )
+            .with_shell(
+                "dumpsys input",
+                fixture(android_version, "dumpsys_input.txt"),
+            )
+    }
+
+    #[test]
+    fn storage_info_toybox_and_toolbox() {
+        let mut device =
+            FakeDevice::new().with_shell("df -h /data", fixture("android-14", "df_-h_data.txt"));
+        let toybox = DeviceCapabilities::default();
+        assert_eq!(
+            get_storage_info(&mut device, &toybox, "/data", "total").as_deref(),
+            Some("111G")
+        );
+        assert_eq!(
+            get_storage_info(&mut device, &toybox, "/data", "available").as_deref(),
+            Some("73G")
+        );
+
+        let mut device =
+            FakeDevice::new().with_shell("df /data", fixture("android-5", "df_data.txt"));
+        let toolbox = DeviceCapabilities {
+            ls: LsFlavor::Toolbox,
+            ..Default::default()
+        };
+        assert_eq!(
+            get_storage_info(&mut device, &toolbox, "/data", "total").as_deref(),
+            Some("12.5G")
+        );
+        assert_eq!(
+            get_storage_info(&mut device, &toolbox, "/data", "available").as_deref(),
+            Some("3.4G")
+        );
+    }
+
+    #[test]
+    fn battery_info_android_9_charging_over_usb() {
+        let battery = get_battery_info(&mut recorded("android-9")).unwrap();
+        assert_eq!(battery.level, Some(64));
+        assert_eq!(battery.status.as_deref(), Some("2"));
+        assert_eq!(battery.health.as_deref(), Some("2"));
+        assert_eq!(battery.temperature, Some(30.5));
+        assert_eq!(battery.voltage, Some(4011));
+        assert_eq!(battery.technology.as_deref(), Some("Li-ion"));
+    }
+
+    #[test]
+    fn battery_info_android_11_emulator() {
+        let battery = get_battery_info(&mut recorded("android-11")).unwrap();
+        assert_eq!(battery.level, Some(100));
+        assert_eq!(battery.temperature, Some(25.0));
+        assert_eq!(battery.voltage, Some(5000));
+    }
+
+    #[test]
+    fn battery_info_android_14_ignores_newer_fields() {
+        // "Charging state" and "Capacity level" must not be taken for status or level
+        let battery = get_battery_info(&mut recorded("android-14")).unwrap();
+        assert_eq!(battery.level, Some(71));
+        assert_eq!(battery.status.as_deref(), Some("3"));
+        assert_eq!(battery.temperature, Some(28.7));
+        assert_eq!(battery.voltage, Some(3952));
+    }
+
+    #[test]
+    fn battery_info_missing_without_dumpsys_output() {
+        let mut device = FakeDevice::new().with_shell_status("dumpsys battery", "", None);
+        let battery = get_battery_info(&mut device).unwrap();
+        assert_eq!(battery.level, None);
+        assert_eq!(battery.technology, None);
+    }
+
+    fn interface<'a>(interfaces: &'a [NetworkInterface], name: &str) -> &'a NetworkInterface {
+        interfaces
+            .iter()
+            .find(|interface| interface.name == name)
+            .unwrap_or_else(|| panic!("no interface {}", name))
+    }
+
+    fn names(interfaces: &[NetworkInterface]) -> Vec<&str> {
+        interfaces
+            .iter()
+            .map(|interface| interface.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn network_interfaces_android_9() {
+        let interfaces = get_network_interfaces(&mut recorded("android-9")).unwrap();
+        assert_eq!(
+            names(&interfaces),
+            [
+                "lo",
+                "rmnet_ipa0",
+                "r_rmnet_data0",
+                "rmnet_data0",
+                "rmnet_data1",
+                "wlan0"
+            ]
+        );
+
+        let lo = interface(&interfaces, "lo");
+        assert_eq!(lo.ip_address.as_deref(), Some("127.0.0.1"));
+        // dummy0's link/ether line follows lo but belongs to dummy0
+        assert_eq!(lo.mac_address, None);
+
+        let wlan0 = interface(&interfaces, "wlan0");
+        assert_eq!(wlan0.ip_address.as_deref(), Some("192.168.1.37"));
+        // ...and p2p0's must not overwrite wlan0's
+        assert_eq!(wlan0.mac_address.as_deref(), Some("40:4e:36:8a:12:c5"));
+        assert_eq!(wlan0.status.as_deref(), Some("UP"));
+
+        let rmnet_data0 = interface(&interfaces, "rmnet_data0");
+        assert_eq!(rmnet_data0.ip_address.as_deref(), Some("10.143.22.187"));
+        assert_eq!(rmnet_data0.mac_address, None);
+        assert_eq!(
+            interface(&interfaces, "rmnet_data1").status.as_deref(),
+            Some("DOWN")
+        );
+    }
+
+    #[test]
+    fn network_interfaces_android_11_emulator() {
+        let interfaces = get_network_interfaces(&mut recorded("android-11")).unwrap();
+        assert_eq!(names(&interfaces), ["lo", "eth0", "wlan0"]);
+
+        let eth0 = interface(&interfaces, "eth0");
+        assert_eq!(eth0.ip_address.as_deref(), Some("10.0.2.15"));
+        assert_eq!(eth0.mac_address.as_deref(), Some("52:54:00:12:34:56"));
+
+        let wlan0 = interface(&interfaces, "wlan0");
+        assert_eq!(wlan0.ip_address.as_deref(), Some("10.0.2.16"));
+    }
+
+    #[test]
+    fn network_interfaces_android_14() {
+        let interfaces = get_network_interfaces(&mut recorded("android-14")).unwrap();
+        assert_eq!(
+            names(&interfaces),
+            ["lo", "rmnet0", "rmnet1", "wlan0", "wlan1"]
+        );
+
+        let wlan0 = interface(&interfaces, "wlan0");
+        assert_eq!(wlan0.ip_address.as_deref(), Some("192.168.1.42"));
+        assert_eq!(wlan0.mac_address.as_deref(), Some("9c:5a:81:2b:44:d0"));
+
+        // Administratively up without a carrier is still "UP" in the flags
+        let wlan1 = interface(&interfaces, "wlan1");
+        assert_eq!(wlan1.status.as_deref(), Some("UP"));
+        assert_eq!(wlan1.ip_address, None);
+
+        let rmnet1 = interface(&interfaces, "rmnet1");
+        assert_eq!(rmnet1.ip_address.as_deref(), Some("100.77.12.9"));
+    }
+
+    #[test]
+    fn network_interfaces_none_when_ip_is_missing() {
+        assert!(get_network_interfaces(&mut FakeDevice::new()).is_none());
+    }
+
+    #[test]
+    fn display_info_android_9() {
+        let display = get_display_info(&mut recorded("android-9"));
+        assert_eq!(display.resolution.as_deref(), Some("1080x1920"));
+        assert_eq!(display.density.as_deref(), Some("420"));
+        assert_eq!(display.physical_size.as_deref(), Some("420 dpi"));
+        // Only the supported modes list carries the rate on Android 9
+        assert_eq!(display.refresh_rate.as_deref(), Some("60.0 Hz"));
+        assert_eq!(display.orientation.as_deref(), Some("Portrait"));
+    }
+
+    #[test]
+    fn display_info_android_11_emulator_landscape() {
+        let display = get_display_info(&mut recorded("android-11"));
+        assert_eq!(display.resolution.as_deref(), Some("1080x1920"));
+        assert_eq!(display.refresh_rate.as_deref(), Some("60.0 Hz"));
+        assert_eq!(display.orientation.as_deref(), Some("Landscape"));
+    }
+
+    #[test]
+    fn display_info_android_14() {
+        let display = get_display_info(&mut recorded("android-14"));
+        assert_eq!(display.resolution.as_deref(), Some("1080x2400"));
+        // The physical density, not the user's display size override
+        assert_eq!(display.density.as_deref(), Some("420"));
+        // The active 90 Hz mode rather than the first listed 60 Hz one
+        assert_eq!(display.refresh_rate.as_deref(), Some("90.0 Hz"));
+        // From the touch viewport, as SurfaceOrientation no longer exists
+        assert_eq!(display.orientation.as_deref(), Some("Portrait"));
     }
 }
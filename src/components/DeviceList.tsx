@@ -263,7 +263,11 @@ export function DeviceList({
   // Add USB devices (not connected)
   if (deviceSettings.autoDiscoverUSB) {
     usbDevices.filter(d => !d.is_connected).forEach(device => {
-      const serialNumber = 'USB' in device.connection_method ? device.connection_method.USB.serial_number : 'Unknown'
+      const serialNumber = 'USB' in device.connection_method
+        ? device.connection_method.USB.serial_number
+        : 'Server' in device.connection_method
+          ? device.connection_method.Server.serial_number
+          : 'Unknown'
       unifiedDevices.push({
         id: `usb-${serialNumber}`,
         type: 'usb',
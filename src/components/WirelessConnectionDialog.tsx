@@ -6,7 +6,7 @@ import { Dialog, DialogContent, DialogDescription, DialogFooter, DialogHeader, D
 import { Tabs, TabsContent, TabsList, TabsTrigger } from "@/components/ui/tabs"
 import { Card, CardContent, CardDescription, CardHeader, CardTitle } from "@/components/ui/card"
 import { Badge } from "@/components/ui/badge"
-import { getPairingQrData, pairWirelessDevice, connectWirelessDevice, startQrPairing, type DeviceInfo, type PairingData, type PairingResult } from "@/tauri-commands"
+import { getPairingQrData, pairWirelessDevice, connectWirelessDevice, startQrPairing, type DeviceInfo, type PairingData, type PairingResult, errorMessage } from "@/tauri-commands"
 import { PairedDevice } from "@/types/paired-device"
 import { usePairedDevices } from "@/hooks/usePairedDevices"
 import { QRCodeSVG } from "qrcode.react"
@@ -51,7 +51,7 @@ export function WirelessConnectionDialog({ onDeviceConnected, children }: Wirele
       const data = await getPairingQrData()
       setPairingData(data)
     } catch (err) {
-      setError(err as string || "Failed to generate QR code")
+      setError(errorMessage(err, "Failed to generate QR code"))
     } finally {
       setIsGeneratingQR(false)
     }
@@ -86,7 +86,7 @@ export function WirelessConnectionDialog({ onDeviceConnected, children }: Wirele
         }
       }
     } catch (err) {
-      setError(err as string || "Failed to pair device via QR")
+      setError(errorMessage(err, "Failed to pair device via QR"))
     } finally {
       setIsPairingViaQr(false)
     }
@@ -133,7 +133,7 @@ export function WirelessConnectionDialog({ onDeviceConnected, children }: Wirele
       setOpen(false)
       resetForm()
     } catch (err) {
-      setError(err as string || "Failed to pair device")
+      setError(errorMessage(err, "Failed to pair device"))
     } finally {
       setIsPairing(false)
     }
@@ -153,7 +153,7 @@ export function WirelessConnectionDialog({ onDeviceConnected, children }: Wirele
       onDeviceConnected(deviceInfo)
       setOpen(false)
     } catch (err) {
-      setError(err as string || "Failed to connect to device")
+      setError(errorMessage(err, "Failed to connect to device"))
     } finally {
       setIsConnecting(false)
     }
@@ -3,7 +3,7 @@ import { Button } from "@/components/ui/button"
 import { Badge } from "@/components/ui/badge"
 import { Input } from "@/components/ui/input"
 import { ScrollArea } from "@/components/ui/scroll-area"
-import { type DeviceInfo } from "@/tauri-commands"
+import { type DeviceInfo, errorMessage } from "@/tauri-commands"
 import { useDeviceApps, useRefreshDeviceApps } from "@/hooks/useDeviceDataQueries"
 import {
   Package,
@@ -145,7 +145,7 @@ export function AppManager({ selectedDevice }: AppManagerProps) {
             <Package className="h-8 w-8 mx-auto mb-2 opacity-50" />
             <p>Failed to load applications</p>
             <p className="text-xs mt-1">
-              {error instanceof Error ? error.message : "Unknown error"}
+              {errorMessage(error)}
             </p>
           </div>
         ) : filteredApps.length === 0 ? (
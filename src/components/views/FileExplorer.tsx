@@ -50,8 +50,8 @@ function FileItem({ file, onNavigate, onDownload }: FileItemProps) {
         return `${file.dir}/${file.name}`
       case 'Symlink':
         return file.file_type.target
-      case 'File':
-        return null // Files are not navigatable
+      default:
+        return null // Files and special files are not navigatable
     }
   }
 
@@ -65,7 +65,7 @@ function FileItem({ file, onNavigate, onDownload }: FileItemProps) {
         return <Folder className="h-4 w-4 text-blue-500 flex-shrink-0" />
       case 'Symlink':
         return <Link className="h-4 w-4 text-green-500 flex-shrink-0" />
-      case 'File':
+      default:
         return <File className="h-4 w-4 text-gray-500 flex-shrink-0" />
     }
   }
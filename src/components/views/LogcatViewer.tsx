@@ -10,7 +10,7 @@ import {
   SelectTrigger,
   SelectValue,
 } from "@/components/ui/select"
-import { type DeviceInfo } from "@/tauri-commands"
+import { type DeviceInfo, errorMessage } from "@/tauri-commands"
 import { useDeviceLogs } from "@/hooks/useDeviceDataQueries"
 import { 
   Terminal, 
@@ -209,7 +209,7 @@ export function LogcatViewer({ selectedDevice }: LogcatViewerProps) {
                 <Terminal className="h-8 w-8 mx-auto mb-2 opacity-50" />
                 <p>Failed to load logs</p>
                 <p className="text-xs mt-1">
-                  {error instanceof Error ? error.message : "Unknown error"}
+                  {errorMessage(error)}
                 </p>
               </div>
             ) : filteredLogs.length === 0 ? (
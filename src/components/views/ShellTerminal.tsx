@@ -1,6 +1,6 @@
 import { useState, useRef, useEffect, KeyboardEvent } from "react"
-import { DeviceInfo, executeShellCommand } from "@/tauri-commands"
-import { Terminal, Trash2, Loader2 } from "lucide-react"
+import { DeviceInfo, DroidKitError, ShellEvent, cancelShellStream, errorMessage, streamShellCommand } from "@/tauri-commands"
+import { Terminal, Trash2, Loader2, Square } from "lucide-react"
 import { Button } from "@/components/ui/button"
 
 interface TerminalEntry {
@@ -26,6 +26,10 @@ export function ShellTerminal({ selectedDevice }: ShellTerminalProps) {
   const [historyIndex, setHistoryIndex] = useState(-1)
   const [isExecuting, setIsExecuting] = useState(false)
   
+  const streamIdRef = useRef<number | null>(null)
+  // Output arrives in chunks; hold partial lines until they are complete
+  const partialLinesRef = useRef({ output: '', error: '' })
+
   const inputRef = useRef<HTMLInputElement>(null)
   const terminalRef = useRef<HTMLDivElement>(null)
 
@@ -72,24 +76,68 @@ export function ShellTerminal({ selectedDevice }: ShellTerminalProps) {
 
     setIsExecuting(true)
     
-    try {
-      const output = await executeShellCommand(selectedDevice.serial_no, command)
-      if (output.trim()) {
-        // Handle newlines by splitting output into separate entries
-        const lines = output.trim().split('\n')
-        lines.forEach(line => {
-          addEntry('output', line)
-        })
-      } else {
-        addEntry('output', '(no output)')
+    let sawOutput = false
+    const flushPartialLines = () => {
+      const partial = partialLinesRef.current
+      if (partial.output) addEntry('output', partial.output)
+      if (partial.error) addEntry('error', partial.error)
+      partialLinesRef.current = { output: '', error: '' }
+    }
+    const addChunk = (type: 'output' | 'error', data: string) => {
+      sawOutput = true
+      const lines = (partialLinesRef.current[type] + data).split('\n')
+      partialLinesRef.current[type] = lines.pop() ?? ''
+      lines.forEach(line => addEntry(type, line))
+    }
+    const finish = () => {
+      streamIdRef.current = null
+      setIsExecuting(false)
+      setCurrentCommand('')
+    }
+
+    const onEvent = (result: { Ok?: ShellEvent; Err?: DroidKitError }) => {
+      if (result.Err) {
+        flushPartialLines()
+        addEntry('error', `Error: ${errorMessage(result.Err)}`)
+        finish()
+        return
       }
+      const event = result.Ok
+      if (!event) return
+
+      switch (event.type) {
+        case 'Stdout':
+          addChunk('output', event.data)
+          break
+        case 'Stderr':
+          addChunk('error', event.data)
+          break
+        case 'Exit':
+          flushPartialLines()
+          if (event.cancelled) {
+            addEntry('error', '^C')
+          } else if (event.exit_code !== null && event.exit_code !== 0) {
+            addEntry('error', `(exit code ${event.exit_code})`)
+          } else if (!sawOutput) {
+            addEntry('output', '(no output)')
+          }
+          finish()
+          break
+      }
+    }
+
+    try {
+      streamIdRef.current = await streamShellCommand(selectedDevice.serial_no, command, onEvent)
     } catch (error) {
-      addEntry('error', `Error: ${error}`)
-    } finally {
-      setIsExecuting(false)
+      addEntry('error', `Error: ${errorMessage(error)}`)
+      finish()
+    }
+  }
+
+  const stopCommand = () => {
+    if (streamIdRef.current !== null) {
+      cancelShellStream(streamIdRef.current)
     }
-    
-    setCurrentCommand('')
   }
 
   const handleKeyDown = (e: KeyboardEvent<HTMLInputElement>) => {
@@ -185,7 +233,20 @@ export function ShellTerminal({ selectedDevice }: ShellTerminalProps) {
             autoComplete="off"
             spellCheck={false}
           />
-          {isExecuting && <Loader2 className="h-4 w-4 ml-2 animate-spin text-gray-400" />}
+          {isExecuting && (
+            <>
+              <Loader2 className="h-4 w-4 ml-2 animate-spin text-gray-400" />
+              <Button
+                variant="ghost"
+                size="sm"
+                className="ml-2 h-6 text-gray-400"
+                onClick={stopCommand}
+              >
+                <Square className="h-3 w-3 mr-1" />
+                Stop
+              </Button>
+            </>
+          )}
         </div>
       </div>
     </div>
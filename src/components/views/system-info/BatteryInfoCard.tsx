@@ -1,7 +1,7 @@
 import { Battery, Loader2 } from "lucide-react"
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card"
 import { Skeleton } from "@/components/ui/skeleton"
-import type { DeviceInfo } from "@/tauri-commands"
+import { type DeviceInfo, errorMessage } from "@/tauri-commands"
 import { useDeviceBatteryInfo } from "@/hooks/useSystemInfo"
 
 interface BatteryInfoCardProps {
@@ -46,7 +46,7 @@ export function BatteryInfoCard({ selectedDevice }: BatteryInfoCardProps) {
           </div>
         ) : batteryError ? (
           <div className="text-sm text-destructive">
-            {batteryError instanceof Error ? batteryError.message : 'Failed to fetch battery info'}
+            {errorMessage(batteryError, 'Failed to fetch battery info')}
           </div>
         ) : !batteryInfo ? (
           <div className="text-sm text-muted-foreground">
@@ -1,7 +1,7 @@
 import { Settings, Loader2 } from "lucide-react"
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card"
 import { Skeleton } from "@/components/ui/skeleton"
-import type { DeviceInfo } from "@/tauri-commands"
+import { type DeviceInfo, errorMessage } from "@/tauri-commands"
 import { useDeviceBuildInfo } from "@/hooks/useSystemInfo"
 
 interface BuildInfoCardProps {
@@ -57,7 +57,7 @@ export function BuildInfoCard({ selectedDevice }: BuildInfoCardProps) {
           </>
         ) : buildError ? (
           <div className="text-sm text-destructive">
-            {buildError instanceof Error ? buildError.message : 'Failed to fetch build info'}
+            {errorMessage(buildError, 'Failed to fetch build info')}
           </div>
         ) : !buildInfo ? (
           <div className="text-sm text-muted-foreground">
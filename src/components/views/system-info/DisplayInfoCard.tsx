@@ -1,7 +1,7 @@
 import { Monitor, Loader2 } from "lucide-react"
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card"
 import { Skeleton } from "@/components/ui/skeleton"
-import type { DeviceInfo } from "@/tauri-commands"
+import { type DeviceInfo, errorMessage } from "@/tauri-commands"
 import { useDeviceDisplayInfo } from "@/hooks/useSystemInfo"
 
 interface DisplayInfoCardProps {
@@ -45,7 +45,7 @@ export function DisplayInfoCard({ selectedDevice }: DisplayInfoCardProps) {
           </div>
         ) : displayError ? (
           <div className="text-sm text-destructive">
-            {displayError instanceof Error ? displayError.message : 'Failed to fetch display info'}
+            {errorMessage(displayError, 'Failed to fetch display info')}
           </div>
         ) : (
           <div className="space-y-2 text-sm">
@@ -1,7 +1,7 @@
 import { Cpu, Loader2 } from "lucide-react"
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card"
 import { Skeleton } from "@/components/ui/skeleton"
-import type { DeviceInfo } from "@/tauri-commands"
+import { type DeviceInfo, errorMessage } from "@/tauri-commands"
 import { useDeviceHardwareInfo } from "@/hooks/useSystemInfo"
 
 interface HardwareInfoCardProps {
@@ -45,7 +45,7 @@ export function HardwareInfoCard({ selectedDevice }: HardwareInfoCardProps) {
           </div>
         ) : hardwareError ? (
           <div className="text-sm text-destructive">
-            {hardwareError instanceof Error ? hardwareError.message : 'Failed to fetch hardware info'}
+            {errorMessage(hardwareError, 'Failed to fetch hardware info')}
           </div>
         ) : (
           <div className="space-y-2 text-sm">
@@ -1,7 +1,7 @@
 import { Wifi, Loader2 } from "lucide-react"
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card"
 import { Skeleton } from "@/components/ui/skeleton"
-import type { DeviceInfo } from "@/tauri-commands"
+import { type DeviceInfo, errorMessage } from "@/tauri-commands"
 import { useDeviceNetworkInfo } from "@/hooks/useSystemInfo"
 
 interface NetworkInfoCardProps {
@@ -58,7 +58,7 @@ export function NetworkInfoCard({ selectedDevice }: NetworkInfoCardProps) {
           </div>
         ) : networkError ? (
           <div className="text-sm text-destructive">
-            {networkError instanceof Error ? networkError.message : 'Failed to fetch network info'}
+            {errorMessage(networkError, 'Failed to fetch network info')}
           </div>
         ) : !networkInfo ? (
           <div className="text-sm text-muted-foreground text-center py-4">
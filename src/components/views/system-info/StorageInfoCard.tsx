@@ -1,7 +1,7 @@
 import { HardDrive } from "lucide-react"
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card"
 import { Skeleton } from "@/components/ui/skeleton"
-import type { DeviceInfo } from "@/tauri-commands"
+import { type DeviceInfo, errorMessage } from "@/tauri-commands"
 import { useDeviceHardwareInfo } from "@/hooks/useSystemInfo"
 
 interface StorageInfoCardProps {
@@ -33,7 +33,7 @@ export function StorageInfoCard({ selectedDevice }: StorageInfoCardProps) {
           </div>
         ) : error ? (
           <div className="text-sm text-destructive">
-            {error instanceof Error ? error.message : 'Failed to fetch storage info'}
+            {errorMessage(error, 'Failed to fetch storage info')}
           </div>
         ) : !hardwareInfo?.internal_storage_total && !hardwareInfo?.internal_storage_available ? (
           <div className="text-sm text-muted-foreground">
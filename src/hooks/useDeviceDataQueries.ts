@@ -1,11 +1,13 @@
 import { useQuery, useMutation, useQueryClient } from '@tanstack/react-query'
 import { useState, useCallback, useEffect } from 'react'
-import type { DeviceInfo } from '@/tauri-commands'
+import type { DeviceInfo, DroidKitError, FileInfo } from '@/tauri-commands'
 import {
-  browseFilesForDevice,
+  browseFilesPageForDevice,
   getAppsForDevice,
   downloadFile,
   getLogcatForDevice,
+  errorMessage,
+  isDroidKitError,
 } from '@/tauri-commands'
 
 // Query Keys for file and app operations
@@ -29,12 +31,24 @@ export const logKeys = {
 export function useDeviceFiles(device: DeviceInfo | undefined, path: string) {
   return useQuery({
     queryKey: fileKeys.devicePath(device?.serial_no || '', path),
-    queryFn: () => browseFilesForDevice(device!.serial_no, path),
+    queryFn: async () => {
+      // Sorted on the backend and sent in batches, so big directories
+      // never cross to the webview in one message
+      const files: FileInfo[] = []
+      await browseFilesPageForDevice(device!.serial_no, path, {}, batch => {
+        files.splice(batch.offset, batch.entries.length, ...batch.entries)
+      })
+      return files
+    },
     enabled: !!device,
     staleTime: 30 * 1000, // Files change less frequently than device lists
     retry: (failureCount, error) => {
+      // Don't retry when the device is gone, refused us or we gave up on purpose
+      if (isDroidKitError(error) && ['NoDevice', 'Unauthorized', 'Cancelled', 'NotRooted'].includes(error.kind)) {
+        return false
+      }
       // Don't retry on permission errors
-      if (error instanceof Error && error.message.includes('permission denied')) {
+      if (errorMessage(error).toLowerCase().includes('permission denied')) {
         return false
       }
       return failureCount < 2
@@ -74,12 +88,12 @@ export function useDeviceLogs(
       device.serial_no, 
       lines, 
       logLevel,
-      (result: { Ok?: string; Err?: string }) => {
+      (result: { Ok?: string; Err?: DroidKitError }) => {
         if (result.Ok) {
           setLogs(result.Ok)
           setError(null)
         } else if (result.Err) {
-          setError(new Error(result.Err))
+          setError(new Error(result.Err.message))
         }
         setIsLoading(false)
       }
@@ -21,7 +21,7 @@ export function useUSBDevices() {
     queryKey: ['usb-devices'],
     queryFn: async (): Promise<DiscoveredDevice[]> => {
       const discoveredDevices = await listDiscoveredDevices()
-      return discoveredDevices.filter(d => 'USB' in d.connection_method)
+      return discoveredDevices.filter(d => 'USB' in d.connection_method || 'Server' in d.connection_method)
     },
     enabled: deviceSettings.autoDiscoverUSB,
     refetchInterval: deviceSettings.autoRefresh ? deviceSettings.pollingInterval * 1000 : false,
@@ -1,3 +1,4 @@
+import { useEffect } from 'react'
 import { useQuery, useMutation, useQueryClient } from '@tanstack/react-query'
 import { useAppSettings } from '@/hooks/useAppSettings'
 import { PairedDevice } from '@/types/paired-device'
@@ -8,12 +9,16 @@ import {
   connectWirelessDevice, 
   pairWirelessDevice,
   deviceInfo,
-  DeviceInfo
+  DeviceInfo,
+  onDeviceConnected,
+  onDeviceDisconnected,
+  onDeviceStateChanged,
 } from '@/tauri-commands'
 
 export type ConnectionMethod = 
   | { USB: { serial_number: string } }
   | { TCP: { socket_address: string } }
+  | { Server: { serial_number: string } }
 
 export interface DiscoveredDevice {
   connection_method: ConnectionMethod
@@ -60,7 +65,7 @@ export function useUSBDevices() {
   
   return {
     ...discoveredDevices,
-    data: discoveredDevices.data?.filter(d => 'USB' in d.connection_method) ?? [],
+    data: discoveredDevices.data?.filter(d => 'USB' in d.connection_method || 'Server' in d.connection_method) ?? [],
   }
 }
 
@@ -170,6 +175,28 @@ export function useConnectedDevices() {
     )
   }
 
+  // Keep the list in sync with the backend device watcher between polls
+  useEffect(() => {
+    const upsert = (device: DeviceInfo) => {
+      addDevice(device)
+      queryClient.invalidateQueries({ queryKey: deviceKeys.discovered() })
+    }
+    const remove = (device: DeviceInfo) => {
+      removeDevice(device.serial_no)
+      queryClient.invalidateQueries({ queryKey: deviceKeys.discovered() })
+    }
+
+    const unlisteners = [
+      onDeviceConnected(upsert),
+      onDeviceStateChanged(upsert),
+      onDeviceDisconnected(remove),
+    ]
+
+    return () => {
+      unlisteners.forEach(unlisten => unlisten.then(fn => fn()))
+    }
+  }, [queryClient])
+
   return {
     ...query,
     addDevice,
@@ -1,7 +1,8 @@
 import { invoke, Channel } from '@tauri-apps/api/core';
+import { listen, UnlistenFn } from '@tauri-apps/api/event';
 
 // Type definitions based on Rust structs
-export type DeviceTransport = 'USB' | 'TCP';
+export type DeviceTransport = 'USB' | 'TCP' | 'Server';
 
 export interface DeviceInfo {
   transport: DeviceTransport;
@@ -9,19 +10,30 @@ export interface DeviceInfo {
   model: string;
   android_version: string;
   sdk_version: string;
+  /** adb connection state: "device" once usable, otherwise e.g. "offline" or "unauthorized" */
+  state: string;
 }
 
 export type FileType = 
   | { type: 'File' }
   | { type: 'Directory' }
-  | { type: 'Symlink'; target: string };
+  | { type: 'Symlink'; target: string }
+  | { type: 'BlockDevice' }
+  | { type: 'CharDevice' }
+  | { type: 'Socket' }
+  | { type: 'Fifo' };
 
 export interface FileInfo {
   name: string;
   dir: string;
   file_type: FileType;
-  size?: number;
+  /** Set for files and links only. */
+  size?: number | null;
   permissions: string;
+  /** Unix seconds; null when the device's `ls` gives no usable time. */
+  modified?: number | null;
+  owner: string;
+  group: string;
 }
 
 export interface PairingData {
@@ -50,7 +62,8 @@ export interface DiscoveredWirelessDevice {
 
 export type ConnectionMethod = 
   | { USB: { serial_number: string } }
-  | { TCP: { socket_address: string } };
+  | { TCP: { socket_address: string } }
+  | { Server: { serial_number: string } };
 
 export interface DiscoveredDevice {
   connection_method: ConnectionMethod;
@@ -60,6 +73,50 @@ export interface DiscoveredDevice {
   is_connected: boolean;
 }
 
+export interface UsbDeviceEntry {
+  serial_no?: string;
+  vendor_id: number;
+  product_id: number;
+  bus_number: number;
+  address: number;
+  description: string;
+}
+
+export interface ServerDeviceEntry {
+  serial_no: string;
+  state: string;
+  usb: string;
+  product: string;
+  model: string;
+  device: string;
+  transport_id: number;
+}
+
+/**
+ * Error returned by device commands; branch on `kind`, show `message`
+ */
+export type DroidKitError =
+  | {
+      kind: 'NoDevice' | 'Unauthorized' | 'Offline' | 'Timeout' | 'Cancelled' | 'NotRooted' | 'AlreadyExists' | 'InvalidInput' | 'Io' | 'Parse' | 'Fastboot' | 'Adb';
+      message: string;
+    }
+  | { kind: 'CommandFailed'; message: string; command: string; exit_code?: number; stderr: string }
+  | { kind: 'PairingFailed'; message: string; reason: string }
+  | { kind: 'ChecksumMismatch'; message: string; path: string; local: string; remote: string };
+
+export const isDroidKitError = (error: unknown): error is DroidKitError =>
+  typeof error === 'object' && error !== null && 'kind' in error && 'message' in error;
+
+/**
+ * Human-readable message for anything a command promise rejected with
+ */
+export const errorMessage = (error: unknown, fallback = 'Unknown error'): string => {
+  if (isDroidKitError(error)) return error.message;
+  if (error instanceof Error) return error.message;
+  if (typeof error === 'string' && error) return error;
+  return fallback;
+};
+
 // Type-safe command functions
 /**
  * Get information about the currently connected device
@@ -92,10 +149,72 @@ export const browseFiles = (path: string): Promise<FileInfo[]> =>
   invoke('browse_files', { path });
 
 /**
- * Browse files on a specific device
+ * Who a command ran as: the shell user, root adbd, Magisk-style `su -c` or AOSP `su 0`
  */
-export const browseFilesForDevice = (deviceSerial: string, path: string): Promise<FileInfo[]> => 
-  invoke('browse_files_for_device', { deviceSerial, path });
+export type RootMode = 'Shell' | 'AdbdRoot' | 'SuCommand' | 'SuUid';
+
+export interface Elevated<T> {
+  root_mode: RootMode;
+  value: T;
+}
+
+/**
+ * Browse files on a specific device, as root when `elevated`
+ */
+export const browseFilesForDevice = (deviceSerial: string, path: string, elevated = false): Promise<Elevated<FileInfo[]>> =>
+  invoke('browse_files_for_device', { deviceSerial, path, elevated });
+
+export type SortKey = 'Name' | 'Size' | 'Modified' | 'Type';
+
+export interface ListingQuery {
+  sort?: SortKey;
+  descending?: boolean;
+  /** Defaults to true. */
+  directories_first?: boolean;
+  /** Case-insensitive part of the name. */
+  filter?: string | null;
+  offset?: number;
+  limit?: number | null;
+}
+
+export interface ListingBatch {
+  offset: number;
+  entries: FileInfo[];
+}
+
+export interface ListingPage {
+  /** Entries matching the filter. */
+  total: number;
+  unfiltered: number;
+  offset: number;
+  returned: number;
+  has_more: boolean;
+}
+
+/**
+ * List one page of a directory on a specific device, sorted and filtered by
+ * the backend. Entries arrive through `onBatch` a few hundred at a time.
+ * A page at offset 0 lists the directory afresh; later pages reuse that listing
+ * until a file command changes the device or `elevated` differs
+ */
+export const browseFilesPageForDevice = (
+  deviceSerial: string,
+  path: string,
+  query: ListingQuery = {},
+  onBatch: (batch: ListingBatch) => void = () => {},
+  elevated = false
+): Promise<Elevated<ListingPage>> => {
+  const channel = new Channel<ListingBatch>();
+  channel.onmessage = onBatch;
+
+  return invoke('browse_files_page_for_device', {
+    deviceSerial,
+    path,
+    query,
+    elevated,
+    onBatch: channel,
+  });
+};
 
 /**
  * Download a file from the device to local storage
@@ -103,6 +222,346 @@ export const browseFilesForDevice = (deviceSerial: string, path: string): Promis
 export const downloadFile = (remotePath: string, localPath: string): Promise<void> => 
   invoke('download_file', { remotePath, localPath });
 
+/**
+ * Progress of one file being copied to or from the device
+ */
+export interface TransferProgress {
+  remote_path: string;
+  bytes_transferred: number;
+  total_bytes: number;
+}
+
+/**
+ * Download a file from a specific device over the sync protocol, as root when `elevated`.
+ * With `verify`, both copies are hashed afterwards and a difference rejects with 'ChecksumMismatch'
+ */
+export const downloadFileForDevice = (
+  deviceSerial: string,
+  remotePath: string,
+  localPath: string,
+  verify = false,
+  elevated = false,
+  onProgress: (progress: TransferProgress) => void = () => {}
+): Promise<Elevated<null>> => {
+  const channel = new Channel<TransferProgress>();
+  channel.onmessage = onProgress;
+
+  return invoke('download_file_for_device', { deviceSerial, remotePath, localPath, verify, elevated, onProgress: channel });
+};
+
+/**
+ * Upload a file to the connected device; returns where it went. Existing files are kept unless `overwrite`
+ */
+export const uploadFile = (localPath: string, remotePath: string, overwrite = false): Promise<string> =>
+  invoke('upload_file', { localPath, remotePath, overwrite });
+
+/**
+ * Upload a file to a specific device with its mode and mtime, as root when `elevated`.
+ * A directory destination receives the file under its own name; fails with 'AlreadyExists'
+ * rather than replacing a file unless `overwrite`. With `verify`, both copies are hashed
+ * afterwards and a difference rejects with 'ChecksumMismatch'
+ */
+export const uploadFileForDevice = (
+  deviceSerial: string,
+  localPath: string,
+  remotePath: string,
+  overwrite = false,
+  verify = false,
+  elevated = false,
+  onProgress: (progress: TransferProgress) => void = () => {}
+): Promise<Elevated<string>> => {
+  const channel = new Channel<TransferProgress>();
+  channel.onmessage = onProgress;
+
+  return invoke('upload_file_for_device', {
+    deviceSerial,
+    localPath,
+    remotePath,
+    overwrite,
+    verify,
+    elevated,
+    onProgress: channel,
+  });
+};
+
+/**
+ * What a directory transfer does with symbolic links inside the tree
+ */
+export type SymlinkPolicy = 'Skip' | 'Follow' | 'Preserve';
+
+/**
+ * Progress of a whole directory transfer; failed files count as done
+ */
+export interface TreeProgress {
+  files_done: number;
+  total_files: number;
+  bytes_done: number;
+  total_bytes: number;
+  current_file: string;
+}
+
+export interface TransferFailure {
+  path: string;
+  error: DroidKitError;
+}
+
+export interface TreeTransferSummary {
+  files_transferred: number;
+  bytes_transferred: number;
+  directories: number;
+  links: number;
+  skipped: string[];
+  failures: TransferFailure[];
+}
+
+/**
+ * Download a directory from a specific device with its structure, modes and times.
+ * Files that fail, including with `verify` any whose copy hashes differently, are listed
+ * in the summary instead of stopping the transfer
+ */
+export const downloadDirectoryForDevice = (
+  deviceSerial: string,
+  remotePath: string,
+  localPath: string,
+  overwrite = false,
+  symlinks: SymlinkPolicy = 'Skip',
+  verify = false,
+  elevated = false,
+  onProgress: (progress: TreeProgress) => void = () => {}
+): Promise<Elevated<TreeTransferSummary>> => {
+  const channel = new Channel<TreeProgress>();
+  channel.onmessage = onProgress;
+
+  return invoke('download_directory_for_device', {
+    deviceSerial,
+    remotePath,
+    localPath,
+    overwrite,
+    symlinks,
+    verify,
+    elevated,
+    onProgress: channel,
+  });
+};
+
+/**
+ * Upload a local directory to a specific device with its structure, modes and times.
+ * Files that fail, including with `verify` any whose copy hashes differently, are listed
+ * in the summary instead of stopping the transfer
+ */
+export const uploadDirectoryForDevice = (
+  deviceSerial: string,
+  localPath: string,
+  remotePath: string,
+  overwrite = false,
+  symlinks: SymlinkPolicy = 'Skip',
+  verify = false,
+  elevated = false,
+  onProgress: (progress: TreeProgress) => void = () => {}
+): Promise<Elevated<TreeTransferSummary>> => {
+  const channel = new Channel<TreeProgress>();
+  channel.onmessage = onProgress;
+
+  return invoke('upload_directory_for_device', {
+    deviceSerial,
+    localPath,
+    remotePath,
+    overwrite,
+    symlinks,
+    verify,
+    elevated,
+    onProgress: channel,
+  });
+};
+
+export type ChecksumAlgorithm = 'Sha256' | 'Md5';
+
+export interface ChecksumComparison {
+  local_path: string;
+  remote_path: string;
+  algorithm: ChecksumAlgorithm;
+  /** Lowercase hex. */
+  local_digest: string;
+  remote_digest: string;
+  matches: boolean;
+  /** The device had no hashing tool, so its copy was read back and hashed here. */
+  read_back: boolean;
+}
+
+/**
+ * Hash a local file and one on a specific device, with SHA-256 or, on devices
+ * without it, MD5
+ */
+export const compareFileForDevice = (
+  deviceSerial: string,
+  localPath: string,
+  remotePath: string,
+  elevated = false
+): Promise<Elevated<ChecksumComparison>> =>
+  invoke('compare_file_for_device', { deviceSerial, localPath, remotePath, elevated });
+
+export type SyncDirection = 'Push' | 'Pull';
+
+export type CompareBy = 'Time' | 'Checksum';
+
+export type MirrorAction = 'Add' | 'Update' | 'Delete';
+
+export interface MirrorEntry {
+  /** Relative to both roots, with `/` between names. */
+  path: string;
+  action: MirrorAction;
+  is_dir: boolean;
+  size: number;
+}
+
+export interface MirrorPlan {
+  /** What to pass to `runMirrorForDevice`. */
+  id: number;
+  direction: SyncDirection;
+  local_dir: string;
+  remote_dir: string;
+  /** Deletions first, then each directory before its contents. */
+  entries: MirrorEntry[];
+  unchanged: number;
+  bytes_to_copy: number;
+  skipped: string[];
+  /** A file on one side and a directory on the other; left alone. */
+  conflicts: string[];
+}
+
+export interface MirrorSummary extends TreeTransferSummary {
+  deleted: number;
+}
+
+/**
+ * Compare a local directory with one on a specific device and work out what
+ * mirroring in `direction` would add, update and, with `deleteExtra`, remove.
+ * Nothing changes until the plan's id is passed to `runMirrorForDevice`; the
+ * plan itself stays on the backend, and a newer one for the device replaces it
+ */
+export const planMirrorForDevice = (
+  deviceSerial: string,
+  localPath: string,
+  remotePath: string,
+  direction: SyncDirection,
+  compare: CompareBy = 'Time',
+  deleteExtra = false,
+  elevated = false
+): Promise<Elevated<MirrorPlan>> =>
+  invoke('plan_mirror_for_device', {
+    deviceSerial,
+    localPath,
+    remotePath,
+    direction,
+    compare,
+    delete: deleteExtra,
+    elevated,
+  });
+
+/**
+ * Carry out plan `planId` from `planMirrorForDevice`, once. Paths that fail,
+ * or changed on the destination since the plan, are listed in the summary
+ * instead of stopping the rest
+ */
+export const runMirrorForDevice = (
+  deviceSerial: string,
+  planId: number,
+  elevated = false,
+  onProgress: (progress: TreeProgress) => void = () => {}
+): Promise<Elevated<MirrorSummary>> => {
+  const channel = new Channel<TreeProgress>();
+  channel.onmessage = onProgress;
+
+  return invoke('run_mirror_for_device', {
+    deviceSerial,
+    planId,
+    elevated,
+    onProgress: channel,
+  });
+};
+
+/**
+ * What a file operation did or, with `dryRun`, would do
+ */
+export interface FileOpResult {
+  command: string;
+  dry_run: boolean;
+  /** A dry-run delete of a directory lists everything in it, deepest first */
+  paths: string[];
+  replaced: boolean;
+}
+
+/**
+ * Delete a file or link, or a whole directory when `recursive`. Paths must be absolute
+ */
+export const deletePath = (
+  deviceSerial: string,
+  path: string,
+  recursive = false,
+  dryRun = false,
+  elevated = false
+): Promise<Elevated<FileOpResult>> =>
+  invoke('delete_path_cmd', { deviceSerial, path, recursive, dryRun, elevated });
+
+/**
+ * Rename or move a path; a directory destination receives it under its own name
+ */
+export const movePath = (
+  deviceSerial: string,
+  source: string,
+  dest: string,
+  overwrite = false,
+  dryRun = false,
+  elevated = false
+): Promise<Elevated<FileOpResult>> =>
+  invoke('move_path_cmd', { deviceSerial, source, dest, overwrite, dryRun, elevated });
+
+/**
+ * Copy a file or directory on the device, keeping modes and times
+ */
+export const copyPath = (
+  deviceSerial: string,
+  source: string,
+  dest: string,
+  overwrite = false,
+  dryRun = false,
+  elevated = false
+): Promise<Elevated<FileOpResult>> =>
+  invoke('copy_path_cmd', { deviceSerial, source, dest, overwrite, dryRun, elevated });
+
+/**
+ * Create a directory and any missing parents
+ */
+export const makeDirectory = (
+  deviceSerial: string,
+  path: string,
+  elevated = false
+): Promise<Elevated<FileOpResult>> =>
+  invoke('make_directory_cmd', { deviceSerial, path, elevated });
+
+/**
+ * chmod with an octal (`0755`) or symbolic (`u+x,go-w`) mode
+ */
+export const changeMode = (
+  deviceSerial: string,
+  path: string,
+  mode: string,
+  recursive = false,
+  elevated = false
+): Promise<Elevated<FileOpResult>> =>
+  invoke('change_mode_cmd', { deviceSerial, path, mode, recursive, elevated });
+
+/**
+ * Create a file if missing and set its times to `modTime` (Unix seconds), or to now
+ */
+export const touchPath = (
+  deviceSerial: string,
+  path: string,
+  modTime?: number,
+  elevated = false
+): Promise<Elevated<FileOpResult>> =>
+  invoke('touch_path_cmd', { deviceSerial, path, modTime, elevated });
+
 /**
  * Get list of installed apps on the connected device
  */
@@ -128,9 +587,9 @@ export const getLogcatForDevice = (
   deviceSerial: string, 
   lines: number, 
   logLevel: string | undefined,
-  onLogReceived: (result: { Ok?: string; Err?: string }) => void
+  onLogReceived: (result: { Ok?: string; Err?: DroidKitError }) => void
 ): void => {
-  const channel = new Channel<{ Ok?: string; Err?: string }>();
+  const channel = new Channel<{ Ok?: string; Err?: DroidKitError }>();
   channel.onmessage = onLogReceived;
   
   invoke('get_logcat_for_device', { 
@@ -147,6 +606,13 @@ export const getLogcatForDevice = (
 export const connectWirelessDevice = (ip: string, port: number): Promise<DeviceInfo> => 
   invoke('connect_wireless_device', { ip, port });
 
+/**
+ * Switch a USB device into TCP/IP mode (port 5555 by default) and connect to it over Wi-Fi.
+ * Needs the device to be reached through the adb server; otherwise rejects with `InvalidInput`
+ */
+export const switchToTcpip = (deviceSerial: string, port?: number): Promise<DeviceInfo> =>
+  invoke('switch_to_tcpip_cmd', { deviceSerial, port });
+
 /**
  * Pair with a wireless device using IP, port, and pairing code
  */
@@ -177,6 +643,18 @@ export const discoverDevices = (): Promise<string[]> =>
 export const listDiscoveredDevices = (): Promise<DiscoveredDevice[]> => 
   invoke('list_discovered_devices_cmd');
 
+/**
+ * List every attached USB device exposing an ADB interface
+ */
+export const listUsbDevices = (): Promise<UsbDeviceEntry[]> => 
+  invoke('list_usb_devices_cmd');
+
+/**
+ * List devices known to a running adb server (emulators, devices claimed by Android Studio)
+ */
+export const listServerDevices = (): Promise<ServerDeviceEntry[]> => 
+  invoke('list_server_devices_cmd');
+
 /**
  * Discover wireless devices with detailed information
  */
@@ -190,10 +668,245 @@ export const connectToDiscoveredDevice = (device: DiscoveredDevice): Promise<Dev
   invoke('connect_to_discovered_device_cmd', { device });
 
 /**
- * Execute a shell command on a specific device
+ * Subscribe to devices being plugged in or showing up on the adb server
+ */
+export const onDeviceConnected = (handler: (device: DeviceInfo) => void): Promise<UnlistenFn> =>
+  listen<DeviceInfo>('device-connected', (event) => handler(event.payload));
+
+/**
+ * Subscribe to devices going away
+ */
+export const onDeviceDisconnected = (handler: (device: DeviceInfo) => void): Promise<UnlistenFn> =>
+  listen<DeviceInfo>('device-disconnected', (event) => handler(event.payload));
+
+/**
+ * Subscribe to connection state changes (e.g. unauthorized -> device)
+ */
+export const onDeviceStateChanged = (handler: (device: DeviceInfo) => void): Promise<UnlistenFn> =>
+  listen<DeviceInfo>('device-state-changed', (event) => handler(event.payload));
+
+/**
+ * Drop the cached connection to a device; the next command reconnects
+ */
+export const closeDeviceSession = (deviceSerial: string): Promise<void> => 
+  invoke('close_device_session', { deviceSerial });
+
+/**
+ * Deadline per kind of device operation in seconds; 0 means none
+ */
+export interface DeviceCapabilities {
+  sdk: number | null;
+  abi: string | null;
+  toybox_version: string | null;
+  ls: 'Toybox' | 'Toolbox';
+  adbd_root: boolean;
+  adb_root_available: boolean;
+  su: boolean;
+  su_command: boolean;
+  shell_v2: boolean;
+  cmd_package: boolean;
+  ls_full_time: boolean;
+  stat_format: boolean;
+}
+
+/**
+ * What the device's shell and adbd support; probed once while it stays connected
+ */
+export const getDeviceCapabilities = (deviceSerial: string): Promise<DeviceCapabilities> =>
+  invoke('get_device_capabilities_cmd', { deviceSerial });
+
+/**
+ * `adb root`: restart adbd as root on userdebug/eng builds; the device reconnects
+ */
+export const restartAdbdAsRoot = (deviceSerial: string): Promise<void> =>
+  invoke('restart_adbd_as_root_cmd', { deviceSerial });
+
+export type RebootTarget = 'System' | 'Bootloader' | 'Recovery' | 'Sideload' | 'Fastboot';
+
+export type RebootEvent =
+  | { type: 'Rebooting'; target: RebootTarget }
+  | { type: 'Offline' }
+  | { type: 'Booting' }
+  | { type: 'Booted' }
+  | { type: 'Left'; target: RebootTarget };
+
+/**
+ * Reboot into `target`; resolves once the system has finished booting, or once the device
+ * shows up in recovery, sideload or fastboot. Rejects with `Timeout` if it never does
+ */
+export const rebootDevice = (
+  deviceSerial: string,
+  target: RebootTarget,
+  onEvent: (event: RebootEvent) => void
+): Promise<void> => {
+  const channel = new Channel<RebootEvent>();
+  channel.onmessage = onEvent;
+
+  return invoke('reboot_device_cmd', { deviceSerial, target, onEvent: channel });
+};
+
+/**
+ * `bytes_sent` may pass `total_bytes`, as recovery reads parts of the package twice
+ */
+export interface SideloadProgress {
+  bytes_sent: number;
+  total_bytes: number;
+}
+
+/**
+ * `adb sideload`: install an OTA zip on a device waiting in recovery's sideload mode.
+ * Needs the adb server running; rejects with `InvalidInput` otherwise
+ */
+export const sideloadPackage = (
+  deviceSerial: string,
+  path: string,
+  onProgress: (progress: SideloadProgress) => void
+): Promise<void> => {
+  const channel = new Channel<SideloadProgress>();
+  channel.onmessage = onProgress;
+
+  return invoke('sideload_package_cmd', { deviceSerial, path, onProgress: channel });
+};
+
+/**
+ * `local` is on this computer, `remote` on the device; both are adb socket
+ * specs like `tcp:8081` or `localabstract:chrome_devtools_remote`
+ */
+export interface PortRule {
+  direction: 'Forward' | 'Reverse';
+  local: string;
+  remote: string;
+}
+
+/**
+ * Create a forward/reverse rule; it is re-applied whenever the device reconnects.
+ * Resolves with the rule, `tcp:0` replaced by the port picked. Needs the device to be
+ * reached through the adb server; direct USB/TCP connections reject with `InvalidInput`
+ */
+export const addPortRule = (deviceSerial: string, rule: PortRule): Promise<PortRule> =>
+  invoke('add_port_rule_cmd', { deviceSerial, rule });
+
+export const listPortRules = (deviceSerial: string): Promise<PortRule[]> =>
+  invoke('list_port_rules_cmd', { deviceSerial });
+
+export const removePortRule = (deviceSerial: string, rule: PortRule): Promise<void> =>
+  invoke('remove_port_rule_cmd', { deviceSerial, rule });
+
+export interface CommandTimeouts {
+  system_info_secs: number;
+  network_secs: number;
+  packages_secs: number;
+  files_secs: number;
+  logcat_secs: number;
+  shell_secs: number;
+}
+
+export const getCommandTimeouts = (): Promise<CommandTimeouts> =>
+  invoke('get_command_timeouts_cmd');
+
+export const setCommandTimeouts = (timeouts: CommandTimeouts): Promise<void> =>
+  invoke('set_command_timeouts_cmd', { timeouts });
+
+/**
+ * Stop every operation in flight on a device; they fail with kind 'Cancelled'
+ */
+export const cancelDeviceOperations = (deviceSerial: string): Promise<void> =>
+  invoke('cancel_device_operations_cmd', { deviceSerial });
+
+/**
+ * Execute a shell command on a specific device, as root when `elevated`
+ */
+export const executeShellCommand = (deviceSerial: string, command: string, elevated = false): Promise<Elevated<string>> =>
+  invoke('execute_shell_command_cmd', { deviceSerial, command, elevated });
+
+export interface ShellOutput {
+  stdout: string;
+  /** Empty when the device cannot separate it from stdout (no shell v2) */
+  stderr: string;
+  exit_code: number | null;
+}
+
+/**
+ * Run a shell command on a specific device, keeping stderr and the exit code
+ */
+export const runShellCommand = (deviceSerial: string, command: string): Promise<ShellOutput> =>
+  invoke('run_shell_command_cmd', { deviceSerial, command });
+
+export type ShellEvent =
+  | { type: 'Stdout'; data: string }
+  | { type: 'Stderr'; data: string }
+  | { type: 'Exit'; exit_code: number | null; cancelled: boolean };
+
+/**
+ * Run a shell command on a specific device, receiving its output as it arrives.
+ * Resolves to an id for cancelShellStream.
+ */
+export const streamShellCommand = (
+  deviceSerial: string,
+  command: string,
+  onEvent: (result: { Ok?: ShellEvent; Err?: DroidKitError }) => void
+): Promise<number> => {
+  const channel = new Channel<{ Ok?: ShellEvent; Err?: DroidKitError }>();
+  channel.onmessage = onEvent;
+
+  return invoke('stream_shell_command_cmd', { deviceSerial, command, onEvent: channel });
+};
+
+/**
+ * Stop a streamed shell command and kill it on the device
+ */
+export const cancelShellStream = (streamId: number): Promise<void> =>
+  invoke('cancel_shell_stream_cmd', { streamId });
+
+export type PtyEvent =
+  | { type: 'Output'; data: string }
+  | { type: 'Exit'; exit_code: number | null };
+
+export interface PtySessionInfo {
+  id: number;
+  serial_no: string;
+  rows: number;
+  cols: number;
+}
+
+/**
+ * Open an interactive shell on a PTY of the given size. Resolves to a session id.
+ */
+export const openPtySession = (
+  deviceSerial: string,
+  rows: number,
+  cols: number,
+  onEvent: (event: PtyEvent) => void
+): Promise<number> => {
+  const channel = new Channel<PtyEvent>();
+  channel.onmessage = onEvent;
+
+  return invoke('open_pty_session_cmd', { deviceSerial, rows, cols, onEvent: channel });
+};
+
+/**
+ * Send keystrokes or pasted text to a PTY session
+ */
+export const writePtySession = (sessionId: number, data: string): Promise<void> =>
+  invoke('write_pty_session_cmd', { sessionId, data });
+
+/**
+ * Tell a PTY session the terminal was resized
+ */
+export const resizePtySession = (sessionId: number, rows: number, cols: number): Promise<void> =>
+  invoke('resize_pty_session_cmd', { sessionId, rows, cols });
+
+/**
+ * Hang up a PTY session
+ */
+export const closePtySession = (sessionId: number): Promise<void> =>
+  invoke('close_pty_session_cmd', { sessionId });
+
+/**
+ * List open PTY sessions
  */
-export const executeShellCommand = (deviceSerial: string, command: string): Promise<string> => 
-  invoke('execute_shell_command_cmd', { deviceSerial, command });
+export const listPtySessions = (): Promise<PtySessionInfo[]> =>
+  invoke('list_pty_sessions_cmd');
 
 // System Information Types
 export interface SystemInfo {
@@ -294,3 +1007,74 @@ export const getDeviceBuildInfo = (deviceSerial: string): Promise<BuildInfo> =>
  */
 export const getDeviceNetworkInfo = (deviceSerial: string): Promise<NetworkInfo> => 
   invoke('get_device_network_info_cmd', { deviceSerial });
+
+// Fastboot
+
+export type FastbootEvent =
+  | { type: 'Info'; message: string }
+  | { type: 'Download'; bytes_sent: number; total_bytes: number };
+
+/**
+ * `name` includes the argument of per-partition variables, e.g. `partition-size:boot_a`
+ */
+export interface FastbootVar {
+  name: string;
+  value: string;
+}
+
+/**
+ * List every attached USB device sitting in the bootloader or fastbootd
+ */
+export const listFastbootDevices = (): Promise<UsbDeviceEntry[]> =>
+  invoke('list_fastboot_devices_cmd');
+
+/**
+ * `fastboot getvar all`; `deviceSerial` is a USB serial, or `ip[:port]` for fastboot over TCP
+ */
+export const fastbootGetvarAll = (deviceSerial: string): Promise<FastbootVar[]> =>
+  invoke('fastboot_getvar_all_cmd', { deviceSerial });
+
+/**
+ * Flash the image at `path` to `partition`, with download progress. Images over the
+ * bootloader's `max-download-size` are sent as sparse pieces, each announced by an `Info` event
+ */
+export const fastbootFlash = (
+  deviceSerial: string,
+  partition: string,
+  path: string,
+  onEvent: (event: FastbootEvent) => void
+): Promise<void> => {
+  const channel = new Channel<FastbootEvent>();
+  channel.onmessage = onEvent;
+
+  return invoke('fastboot_flash_cmd', { deviceSerial, partition, path, onEvent: channel });
+};
+
+export const fastbootErase = (
+  deviceSerial: string,
+  partition: string,
+  onEvent: (event: FastbootEvent) => void
+): Promise<void> => {
+  const channel = new Channel<FastbootEvent>();
+  channel.onmessage = onEvent;
+
+  return invoke('fastboot_erase_cmd', { deviceSerial, partition, onEvent: channel });
+};
+
+/**
+ * Reboot out of fastboot; 'Sideload' is not a fastboot target
+ */
+export const fastbootReboot = (deviceSerial: string, target: RebootTarget): Promise<void> =>
+  invoke('fastboot_reboot_cmd', { deviceSerial, target });
+
+/**
+ * `fastboot oem <command>`, returning what the bootloader printed
+ */
+export const fastbootOem = (deviceSerial: string, command: string): Promise<string[]> =>
+  invoke('fastboot_oem_cmd', { deviceSerial, command });
+
+/**
+ * `fastboot flashing unlock`; resolves once the user answers on the device, which wipes it
+ */
+export const fastbootFlashingUnlock = (deviceSerial: string): Promise<string[]> =>
+  invoke('fastboot_flashing_unlock_cmd', { deviceSerial });